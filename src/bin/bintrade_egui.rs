@@ -1,12 +1,30 @@
 use anyhow::Result;
-use bintrade_egui::client::cli_run;
+use bintrade_egui::client::{cli_run, headless_run};
+use bintrade_egui::paths;
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
 
 fn main() -> Result<()> {
     let _subscriber = FmtSubscriber::builder()
         .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
         .pretty()
         .init();
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let paths = paths::init(paths::take_data_dir_arg(&mut args));
+    let res = paths.migrate_legacy(std::path::Path::new("."));
+    match res {
+        Ok(moved) if !moved.is_empty() => tracing::info![
+            "Moved {} legacy files to {}",
+            moved.len(),
+            paths.data_dir.display()
+        ],
+        Ok(_) => (),
+        Err(e) => tracing::error!["Unable to move the legacy data: {:?}", e],
+    };
+    if !args.is_empty() {
+        //NOTE headless mode, used for cron jobs etc. no window is opened
+        std::process::exit(headless_run(&args));
+    };
     cli_run()?;
     Ok(())
 }
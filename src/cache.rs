@@ -0,0 +1,362 @@
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Datelike, TimeZone, Utc};
+use sqlx::{Pool, Sqlite};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use crate::data::{Intv, Kline, KlineRow, Klines, kfrom_sql_intv, resample_kline};
+use strum::IntoEnumIterator;
+
+///Candles per cached chunk, chunks start at multiples of intv * CACHE_CHUNK_CANDLES, month
+///chunks at every CACHE_CHUNK_CANDLES months since 1970
+pub const CACHE_CHUNK_CANDLES: i64 = 1000;
+pub const DEFAULT_CACHE_LIMIT_MB: u64 = 512;
+const ROW_BYTES: usize = std::mem::size_of::<KlineRow>();
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CacheUsage {
+    pub bytes: usize,
+    pub limit: usize,
+    pub chunks: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evicted: u64,
+}
+impl CacheUsage {
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            n => self.hits as f64 / n as f64 * 100.0,
+        }
+    }
+}
+
+struct CacheChunk {
+    rows: Vec<KlineRow>,
+    last_used: u64,
+}
+
+type ChunkKey = (String, Intv, i64);
+
+///Historical klines by symbol, interval and chunk, the least recently used chunks are evicted
+///once the rows take more than limit bytes
+pub struct KlineCache {
+    chunks: HashMap<ChunkKey, CacheChunk>,
+    tick: u64,
+    usage: CacheUsage,
+}
+impl std::fmt::Debug for KlineCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "KlineCache {:?}", self.usage)
+    }
+}
+impl Default for KlineCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CACHE_LIMIT_MB as usize * 1024 * 1024)
+    }
+}
+impl KlineCache {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            chunks: HashMap::new(),
+            tick: 0,
+            usage: CacheUsage {
+                limit,
+                ..Default::default()
+            },
+        }
+    }
+    pub fn usage(&self) -> CacheUsage {
+        self.usage
+    }
+    pub fn set_limit(&mut self, limit: usize) {
+        self.usage.limit = limit;
+        self.evict(&HashSet::new());
+    }
+    ///Drops every chunk of symbol, called after its DB was written to
+    pub fn invalidate(&mut self, symbol: &str) {
+        self.chunks.retain(|(s, _, _), _| s != symbol);
+        self.recount();
+    }
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+        self.recount();
+    }
+    fn chunk_span(intv: &Intv) -> i64 {
+        intv.to_ms().max(1) * CACHE_CHUNK_CANDLES
+    }
+    fn chunk_index(intv: &Intv, t: i64) -> i64 {
+        match intv {
+            Intv::Month1 => month_index(t).div_euclid(CACHE_CHUNK_CANDLES),
+            _ => t.div_euclid(Self::chunk_span(intv)),
+        }
+    }
+    ///Open times covered by chunk n of intv
+    fn chunk_bounds(intv: &Intv, n: i64) -> (i64, i64) {
+        match intv {
+            Intv::Month1 => (
+                month_start(n * CACHE_CHUNK_CANDLES),
+                month_start((n + 1) * CACHE_CHUNK_CANDLES) - 1,
+            ),
+            _ => {
+                let span = Self::chunk_span(intv);
+                (n * span, (n + 1) * span - 1)
+            }
+        }
+    }
+    fn recount(&mut self) {
+        self.usage.chunks = self.chunks.len();
+        self.usage.bytes = self.chunks.values().map(|c| c.rows.len() * ROW_BYTES).sum();
+    }
+    fn evict(&mut self, keep: &HashSet<ChunkKey>) {
+        while self.usage.bytes > self.usage.limit {
+            let lru = self
+                .chunks
+                .iter()
+                .filter(|(k, _)| !keep.contains(*k))
+                .min_by_key(|(_, c)| c.last_used)
+                .map(|(k, _)| k.clone());
+            let Some(key) = lru else {
+                //NOTE the chunks of the current request stay even if they're over the limit
+                break;
+            };
+            if let Some(c) = self.chunks.remove(&key) {
+                self.usage.bytes -= c.rows.len() * ROW_BYTES;
+                self.usage.evicted += 1;
+            };
+        }
+        self.usage.chunks = self.chunks.len();
+    }
+    ///Candles of symbol/intv with open times between start and end (inclusive), chunks that
+    ///aren't cached are loaded from pool. Custom intervals are resampled from the cached chunks of
+    ///their base interval, like kfrom_sql_intv every candle overlapping the range is included.
+    pub async fn load_range(
+        cache: &Mutex<Self>,
+        pool: &Pool<Sqlite>,
+        symbol: &str,
+        intv: &Intv,
+        start: i64,
+        end: i64,
+    ) -> Result<Kline> {
+        match intv {
+            Intv::Custom(_) => {
+                let (start, end) = (intv.bucket_start(start), intv.next_open(end) - 1);
+                let base = Self::load_stored(cache, pool, symbol, &intv.base(), start, end).await?;
+                Ok(resample_kline(&base.kline, intv))
+            }
+            _ => Self::load_stored(cache, pool, symbol, intv, start, end).await,
+        }
+    }
+    async fn load_stored(
+        cache: &Mutex<Self>,
+        pool: &Pool<Sqlite>,
+        symbol: &str,
+        intv: &Intv,
+        start: i64,
+        end: i64,
+    ) -> Result<Kline> {
+        let keys: Vec<ChunkKey> = (Self::chunk_index(intv, start)..=Self::chunk_index(intv, end))
+            .map(|n| (symbol.to_string(), *intv, n))
+            .collect();
+        let missing: Vec<ChunkKey> = {
+            let c = cache.lock().expect("Poisoned kline cache mutex");
+            keys.iter()
+                .filter(|k| !c.chunks.contains_key(*k))
+                .cloned()
+                .collect()
+        };
+        //NOTE loaded without holding the lock, other tasks can use the cache meanwhile
+        let mut loaded = vec![];
+        for key in missing.iter() {
+            let t = Self::chunk_bounds(intv, key.2);
+            let k = kfrom_sql_intv(pool, intv, Some(t)).await?;
+            loaded.push((key.clone(), k.kline));
+        }
+        let mut c = cache.lock().expect("Poisoned kline cache mutex");
+        c.usage.misses += missing.len() as u64;
+        c.usage.hits += (keys.len() - missing.len()) as u64;
+        c.tick += 1;
+        let tick = c.tick;
+        for (key, rows) in loaded {
+            c.usage.bytes += rows.len() * ROW_BYTES;
+            if let Some(old) = c.chunks.insert(
+                key,
+                CacheChunk {
+                    rows,
+                    last_used: tick,
+                },
+            ) {
+                c.usage.bytes -= old.rows.len() * ROW_BYTES;
+            };
+        }
+        let mut kline = vec![];
+        for key in keys.iter() {
+            let chunk = c
+                .chunks
+                .get_mut(key)
+                .ok_or(anyhow!["Kline cache chunk {:?} missing", key])?;
+            chunk.last_used = tick;
+            kline.extend(chunk.rows.iter().filter(|r| {
+                let t = r.0.timestamp_millis();
+                t >= start && t <= end
+            }));
+        }
+        c.evict(&keys.into_iter().collect());
+        Ok(Kline { kline })
+    }
+}
+
+fn month_index(t: i64) -> i64 {
+    match DateTime::<Utc>::from_timestamp_millis(t) {
+        Some(t) => (t.year() as i64 - 1970) * 12 + t.month0() as i64,
+        None => t.div_euclid(Intv::Month1.to_ms()),
+    }
+}
+
+///Open time of the month m months after 1970-01
+fn month_start(m: i64) -> i64 {
+    let (year, month) = (1970 + m.div_euclid(12), m.rem_euclid(12) as u32 + 1);
+    i32::try_from(year)
+        .ok()
+        .and_then(|y| Utc.with_ymd_and_hms(y, month, 1, 0, 0, 0).single())
+        .map_or(m * Intv::Month1.to_ms(), |t| t.timestamp_millis())
+}
+
+///The newest candles (up to wicks) of every interval of symbol through the cache, a whole
+///asset can be larger than the cache limit so it's never loaded at once
+pub async fn load_latest_cached(
+    cache: &Mutex<KlineCache>,
+    pool: &Pool<Sqlite>,
+    symbol: &str,
+    wicks: i64,
+) -> Result<Klines> {
+    let mut klines = Klines::new_empty();
+    for intv in Intv::iter() {
+        let q = format!["SELECT MAX([Timestamp MS]) FROM {};", intv.table()?];
+        let (last,): (Option<i64>,) = sqlx::query_as(&q).fetch_one(pool).await?;
+        if let Some(last) = last {
+            let first = last - intv.to_ms() * (wicks - 1);
+            let k = KlineCache::load_range(cache, pool, symbol, &intv, first, last).await?;
+            klines.insert(&intv, k);
+        };
+    }
+    Ok(klines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::test_db::TempDb;
+    use crate::data::{get_data_source, kfrom_sql};
+    use crate::jobs::DlJob;
+    use crate::source::MockSource;
+
+    #[tokio::test]
+    async fn lru_chunks_are_evicted_over_the_limit() {
+        let db = TempDb::asset("cache").await;
+        let hour = Intv::Hour1.to_ms();
+        let span = hour * CACHE_CHUNK_CANDLES;
+        let start = span * 475;
+        let source = MockSource::new("MOCK", start, 0);
+        let job = DlJob::default();
+        get_data_source(
+            &source,
+            "MOCK",
+            Intv::Hour1,
+            &db.pool,
+            start - 1,
+            start + 3 * span,
+            &job,
+        )
+        .await
+        .unwrap();
+
+        //room for 2 full chunks
+        let cache = Mutex::new(KlineCache::new(
+            2 * CACHE_CHUNK_CANDLES as usize * ROW_BYTES,
+        ));
+        let window = (start + span - 100 * hour, start + span + 100 * hour);
+        let k = KlineCache::load_range(&cache, &db.pool, "MOCK", &Intv::Hour1, window.0, window.1)
+            .await
+            .unwrap();
+        let from_db = kfrom_sql(&db.pool, "kline_1hour", Some(window))
+            .await
+            .unwrap();
+        let first = cache.lock().unwrap().usage();
+        //same chunks again
+        KlineCache::load_range(
+            &cache,
+            &db.pool,
+            "MOCK",
+            &Intv::Hour1,
+            window.0,
+            window.0 + hour,
+        )
+        .await
+        .unwrap();
+        let second = cache.lock().unwrap().usage();
+        //the chunk after the window was last used by the first load and goes first
+        let last = start + 3 * span - hour;
+        KlineCache::load_range(&cache, &db.pool, "MOCK", &Intv::Hour1, last - hour, last)
+            .await
+            .unwrap();
+        let third = cache.lock().unwrap().usage();
+        let latest = load_latest_cached(&cache, &db.pool, "MOCK", 100)
+            .await
+            .unwrap();
+        let after_latest = cache.lock().unwrap().usage();
+        //3h candles are resampled from the cached 1h chunks
+        let h3 = KlineCache::load_range(
+            &cache,
+            &db.pool,
+            "MOCK",
+            &Intv::Custom(180),
+            window.0,
+            window.1,
+        )
+        .await
+        .unwrap();
+        let h3_db = kfrom_sql_intv(&db.pool, &Intv::Custom(180), Some(window))
+            .await
+            .unwrap();
+        let after_custom = cache.lock().unwrap().usage();
+        cache.lock().unwrap().invalidate("MOCK");
+        let cleared = cache.lock().unwrap().usage();
+        db.pool.close().await;
+
+        assert_eq!(k.kline, from_db.kline);
+        assert_eq!(k.kline.len(), 201);
+        assert_eq!((first.chunks, first.misses, first.hits), (2, 2, 0));
+        assert_eq!((second.misses, second.hits), (2, 1));
+        assert_eq!(third.chunks, 2);
+        assert_eq!(third.evicted, 1);
+        assert!(third.bytes <= third.limit);
+        //the newest candles are loaded even though all 3000 1h candles don't fit in 2 chunks
+        let latest_1h = &latest.dat.get(&Intv::Hour1).unwrap().kline;
+        assert_eq!(latest_1h.len(), 100);
+        assert_eq!(
+            latest_1h.last().unwrap().0.timestamp_millis(),
+            start + 3 * span
+        );
+        assert!(after_latest.chunks <= 2);
+        assert_eq!(h3.kline, h3_db.kline);
+        let lookups = |u: &CacheUsage| u.hits + u.misses;
+        assert!(lookups(&after_custom) > lookups(&after_latest));
+        assert!(after_custom.chunks <= 2);
+        assert_eq!((cleared.chunks, cleared.bytes), (0, 0));
+    }
+
+    #[test]
+    fn month_chunks_follow_the_calendar() {
+        //2024-02-01 and 2024-02-29 23:59
+        let (feb, feb_end) = (1706745600000, 1709251140000);
+        assert_eq!(month_index(feb), 54 * 12 + 1);
+        assert_eq!(month_index(feb_end), month_index(feb));
+        assert_eq!(month_start(month_index(feb)), feb);
+        assert_eq!(KlineCache::chunk_index(&Intv::Month1, feb), 0);
+        //the first chunk ends right before 2053-05-01
+        let (start, end) = KlineCache::chunk_bounds(&Intv::Month1, 0);
+        assert_eq!((start, end + 1), (0, 2629670400000));
+        assert_eq!(KlineCache::chunk_index(&Intv::Month1, end + 1), 1);
+    }
+}
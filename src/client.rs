@@ -1,8 +1,11 @@
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
-use crate::data::{METADATA_DB_PATH, create_metadata_db};
-use sqlx::migrate::MigrateDatabase;
+use crate::data::{
+    CsvImportConfig, ExportFormat, RemErrors, ensure_metadata_db, migrate_databases,
+    write_kline_columnar,
+};
+use chrono::{DateTime, Utc};
 
 use tokio::select;
 use tokio::sync::mpsc::{Receiver, Sender};
@@ -29,6 +32,8 @@ use winit::platform::wayland::EventLoopBuilderExtWayland;
 use crate::conn::{BinanceClient, SymbolOutput, get_exchange_info};
 use crate::data::{AssetData, Intv, SQLConn};
 use crate::gui::{DesktopApp, KeysStatus, LiveInfo, Settings};
+use crate::ticks::{import_agg_trades, load_ticks};
+use crate::trade::{BacktestConfig, HistTrade};
 
 #[derive(Clone, Debug)]
 pub struct ApiKeys {
@@ -768,15 +773,52 @@ impl ClientTask {
     ) {
         let (send_to_client, mut recv_from_client) =
             unpack_channels!(task_chans, SRSend, SQLResponse, SRecv, SQLInstructs);
-        let mut sql_client = SQLConn::new(hist_asset_data);
+        //NOTE shared with the download jobs which stream their progress through it
+        let send_to_client = Arc::new(send_to_client);
+        let mut sql_client = SQLConn::new(hist_asset_data.clone());
+        let dl_jobs = sql_client.dl_jobs.clone();
+        dl_jobs.stream_to(send_to_client.clone());
         tracing::info!("SQL started");
+        //NOTE retry chunks that failed on the last run and run scheduled updates without blocking
+        //the SQL task
+        let scheduler_cancel = cancel_token.clone();
+        let mut retry_client = SQLConn::new(hist_asset_data);
+        //NOTE same jobs as the SQL task, a symbol is never retried and downloaded at once
+        retry_client.dl_jobs = dl_jobs.clone();
+        retry_client.cache = sql_client.cache.clone();
+        tokio::spawn(async move {
+            let res = retry_client.retry_all_dl_errors().await;
+            match res {
+                Ok(_) => (),
+                Err(e) => tracing::error!["Retrying failed downloads: {:?}", e],
+            };
+            ClientTask::update_scheduler(retry_client, scheduler_cancel).await;
+        });
         loop {
             loop {
                 select! {
                     _ = recv_from_client.changed() =>{
-                        let instruct=recv_from_client.borrow_and_update().clone();
-                        let response=sql_client.parse_sql_instructs(instruct).await;
-                        let _=send_to_client.send(response);
+                        let mut next=Some(recv_from_client.borrow_and_update().clone());
+                        while let Some(instruct)=next.take(){
+                            let parse=sql_client.parse_sql_instructs(instruct);
+                            tokio::pin!(parse);
+                            //NOTE cancel/pause reach running downloads, anything else runs after
+                            let response=loop {
+                                select! {
+                                    r = &mut parse => break r,
+                                    res = recv_from_client.changed() => {
+                                        if res.is_err() {
+                                            break (&mut parse).await;
+                                        };
+                                        let i=recv_from_client.borrow_and_update().clone();
+                                        if !dl_jobs.control(&i) {
+                                            next=Some(i);
+                                        };
+                                    }
+                                }
+                            };
+                            let _=send_to_client.send(response);
+                        }
                     }
                     _ = cancel_token.cancelled() => {
                         tracing::debug!("SQL task cancelled");
@@ -792,6 +834,32 @@ impl ClientTask {
             tracing::debug!("SQL task awake");
         }
     }
+    ///Checks every minute for assets that are due for a scheduled update, assets are only due an
+    ///interval after their last attempt so failing ones aren't retried every minute
+    async fn update_scheduler(mut sql_client: SQLConn, cancel_token: CancellationToken) {
+        let res = sql_client.load_update_schedule().await;
+        match res {
+            Ok(s) => tracing::debug!["Scheduled updates every {:?} min", s],
+            Err(e) => tracing::error!["Unable to load the update schedule: {:?}", e],
+        };
+        loop {
+            //NOTE the SQL task sets the interval when it's changed from the GUI
+            if let Some(m) = sql_client.shared_update_schedule() {
+                let res = sql_client.scheduled_update(m).await;
+                match res {
+                    Ok(_) => (),
+                    Err(e) => tracing::error!["Scheduled update failed: {:?}", e],
+                };
+            };
+            select! {
+                _ = tokio::time::sleep(tokio::time::Duration::from_secs(60)) => {}
+                _ = cancel_token.cancelled() => {
+                    tracing::debug!("Update scheduler cancelled");
+                    return;
+                }
+            }
+        }
+    }
     pub fn make_chans(&mut self, t: &Tasks) -> Vec<ChanType> {
         let mut cv: Vec<ChanType> = std::vec::Vec::new();
         match t {
@@ -917,17 +985,10 @@ pub fn cli_run() -> Result<()> {
     let frontend = Frontend::Desktop;
     let tasks: Vec<Tasks> = frontend.init(&settings, true)?;
     let _res = rt.block_on(async {
-        let res = sqlx::Sqlite::database_exists(&METADATA_DB_PATH).await;
-        let db_exists = match res {
-            Ok(e) => e,
-            Err(e) => {
-                tracing::error!["Check db exists ERROR: {}", e];
-                false
-            }
-        };
-        if !db_exists {
-            let _res = tokio::fs::create_dir("./databases").await;
-            let _res = create_metadata_db().await;
+        let res = migrate_databases().await;
+        match res {
+            Ok(_) => (),
+            Err(e) => tracing::error!["Unable to migrate databases: {:?}", e],
         };
 
         let frontend = Frontend::Desktop;
@@ -944,3 +1005,557 @@ pub fn cli_run() -> Result<()> {
     });
     Ok(())
 }
+
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_ERR: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_NO_DATA: i32 = 3;
+pub const EXIT_AUDIT_FAILED: i32 = 4;
+
+const HEADLESS_USAGE: &str = "Usage: bintrade_egui [--data-dir <dir>] [COMMAND]
+Without a command the GUI is started.
+Data is kept in the XDG data dir (~/.local/share/bintrade), settings in the XDG config dir,
+--data-dir or BINTRADE_DATA_DIR keeps both in <dir> instead.
+
+Commands:
+    download <symbol> [--derive]         Add a Binance symbol to the download list and download it
+                                         <symbol>.P downloads the USDⓈ-M futures klines and funding rates
+    update [--derive]                    Update all downloaded assets
+                                         --derive only downloads 1m and builds the other intervals from it
+    derive <symbol> [--repair]           Build missing intervals from 1m and report candles that didn't match,
+                                         --repair replaces them with the derived ones
+    list                                 List downloaded assets and their time ranges
+    export <symbol> <intv> [out.csv] [--from <t>] [--to <t>]
+                                         Export an interval as CSV, to stdout if no file is given.
+                                         <t> is unix ms, YYYY-MM-DD or RFC 3339, the range is inclusive
+                                         .parquet or .arrow files are written with all kline columns
+    audit <symbol> [--repair]            Check the data for gaps, duplicates, bad OHLC values and 1m consistency,
+                                         --repair downloads the gaps again
+    import-archive <dir>                 Import data.binance.vision kline zips, checksums are verified if present
+    import-ticks <symbol> <file>         Import data.binance.vision aggTrades (.csv or .zip) into the tick store
+    import <symbol> <intv> <in.csv> [config.json]
+                                         Import OHLCV klines from a CSV file, see README for the config
+    backtest <config.json> [out.json]    Run a backtest, the report is printed to stdout if no file is given.
+                                         \"ticks\": true in the config runs it on recorded/imported aggTrades
+                                         funding is charged on .P symbols
+    help                                 Print this message
+
+Exit codes: 0 ok, 1 error, 2 usage error, 3 symbol or data not found, 4 audit found problems";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeadlessCmd {
+    Download {
+        symbol: String,
+        derive: bool,
+    },
+    Update {
+        derive: bool,
+    },
+    Derive {
+        symbol: String,
+        repair: bool,
+    },
+    List,
+    Export {
+        symbol: String,
+        intv: Intv,
+        out: Option<String>,
+        ///(start, end) in ms
+        range: Option<(i64, i64)>,
+    },
+    Audit {
+        symbol: String,
+        repair: bool,
+    },
+    ImportArchive {
+        dir: String,
+    },
+    ImportTicks {
+        symbol: String,
+        path: String,
+    },
+    Import {
+        symbol: String,
+        intv: Intv,
+        csv: String,
+        config: Option<String>,
+    },
+    Backtest {
+        config: String,
+        out: Option<String>,
+    },
+    Help,
+}
+impl HeadlessCmd {
+    pub fn parse(args: &[String]) -> Result<Self> {
+        let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+        match args.as_slice() {
+            ["download", symbol] => Ok(HeadlessCmd::Download {
+                symbol: symbol.to_uppercase(),
+                derive: false,
+            }),
+            ["download", symbol, "--derive"] => Ok(HeadlessCmd::Download {
+                symbol: symbol.to_uppercase(),
+                derive: true,
+            }),
+            ["update"] => Ok(HeadlessCmd::Update { derive: false }),
+            ["update", "--derive"] => Ok(HeadlessCmd::Update { derive: true }),
+            ["derive", symbol] => Ok(HeadlessCmd::Derive {
+                symbol: symbol.to_uppercase(),
+                repair: false,
+            }),
+            ["derive", symbol, "--repair"] => Ok(HeadlessCmd::Derive {
+                symbol: symbol.to_uppercase(),
+                repair: true,
+            }),
+            ["list"] => Ok(HeadlessCmd::List),
+            ["export", symbol, intv, rest @ ..] => {
+                let (out, range) = parse_export_args(rest)?;
+                Ok(HeadlessCmd::Export {
+                    symbol: symbol.to_uppercase(),
+                    intv: Intv::parse(intv)?,
+                    out,
+                    range,
+                })
+            }
+            ["audit", symbol] => Ok(HeadlessCmd::Audit {
+                symbol: symbol.to_uppercase(),
+                repair: false,
+            }),
+            ["audit", symbol, "--repair"] => Ok(HeadlessCmd::Audit {
+                symbol: symbol.to_uppercase(),
+                repair: true,
+            }),
+            ["import-archive", dir] => Ok(HeadlessCmd::ImportArchive {
+                dir: dir.to_string(),
+            }),
+            ["import-ticks", symbol, path] => Ok(HeadlessCmd::ImportTicks {
+                symbol: symbol.to_uppercase(),
+                path: path.to_string(),
+            }),
+            ["import", symbol, intv, csv, config @ ..] if config.len() <= 1 => {
+                Ok(HeadlessCmd::Import {
+                    symbol: symbol.to_uppercase(),
+                    intv: Intv::parse(intv)?,
+                    csv: csv.to_string(),
+                    config: config.first().map(|c| c.to_string()),
+                })
+            }
+            ["backtest", config, out @ ..] if out.len() <= 1 => Ok(HeadlessCmd::Backtest {
+                config: config.to_string(),
+                out: out.first().map(|o| o.to_string()),
+            }),
+            ["help"] | ["--help"] | ["-h"] => Ok(HeadlessCmd::Help),
+            _ => Err(anyhow::anyhow!["Invalid command: {}", args.join(" ")]),
+        }
+    }
+    async fn run(&self) -> Result<i32> {
+        let mut sql_conn = SQLConn::default();
+        if !matches!(self, HeadlessCmd::Help) {
+            migrate_databases().await?;
+        };
+        match self {
+            HeadlessCmd::Download { symbol, derive } => {
+                sql_conn.derive_intervals = *derive;
+                if sql_conn.add_and_download(symbol).await? {
+                    Ok(EXIT_OK)
+                } else {
+                    eprintln!["Symbol {} not found on Binance", symbol];
+                    Ok(EXIT_NO_DATA)
+                }
+            }
+            HeadlessCmd::Update { derive } => {
+                sql_conn.derive_intervals = *derive;
+                ensure_metadata_db().await?;
+                sql_conn.update_data().await?;
+                Ok(EXIT_OK)
+            }
+            HeadlessCmd::List => {
+                let assets = sql_conn.list_dl_assets().await?;
+                let fmt_t =
+                    |t: &Option<i64>| match t.and_then(DateTime::<Utc>::from_timestamp_millis) {
+                        Some(t) => t.to_rfc3339(),
+                        None => "-".to_string(),
+                    };
+                for (symbol, exchange, start, end) in assets.iter() {
+                    let missing = match RemErrors::load(symbol)? {
+                        Some(e) => format!["\tincomplete, {} chunks missing", e.chunks()],
+                        None => String::new(),
+                    };
+                    println![
+                        "{}\t{}\t{}\t{}{}",
+                        symbol,
+                        exchange,
+                        fmt_t(start),
+                        fmt_t(end),
+                        missing
+                    ];
+                }
+                Ok(EXIT_OK)
+            }
+            HeadlessCmd::Export {
+                symbol,
+                intv,
+                out,
+                range,
+            } => {
+                let columnar = out.as_ref().and_then(|o| {
+                    let ext = std::path::Path::new(o).extension()?.to_str()?;
+                    ExportFormat::parse(ext).ok()
+                });
+                if let (Some(format), Some(path)) = (columnar, out) {
+                    let rows = match sql_conn.load_fat_kline(symbol, intv, *range).await? {
+                        Some(k) if !k.kline.is_empty() => {
+                            let f = std::io::BufWriter::new(std::fs::File::create(path)?);
+                            write_kline_columnar(&k, &format, f)?
+                        }
+                        _ => {
+                            eprintln!["No {} data found for {}", intv.label(), symbol];
+                            return Ok(EXIT_NO_DATA);
+                        }
+                    };
+                    tracing::info!["Exported {} rows", rows];
+                    return Ok(EXIT_OK);
+                };
+                let kline = match sql_conn.load_kline(symbol, intv, *range).await? {
+                    Some(k) if !k.kline.is_empty() => k,
+                    _ => {
+                        eprintln!["No {} data found for {}", intv.label(), symbol];
+                        return Ok(EXIT_NO_DATA);
+                    }
+                };
+                let rows = match out {
+                    Some(path) => {
+                        let mut f = std::io::BufWriter::new(std::fs::File::create(path)?);
+                        SQLConn::write_kline_csv(&kline, &mut f)?
+                    }
+                    None => SQLConn::write_kline_csv(&kline, &mut std::io::stdout().lock())?,
+                };
+                tracing::info!["Exported {} rows", rows];
+                Ok(EXIT_OK)
+            }
+            HeadlessCmd::Derive { symbol, repair } => {
+                let report = match sql_conn.derive_asset_intervals(symbol, *repair).await? {
+                    Some(r) => r,
+                    None => {
+                        eprintln!["No data found for {}", symbol];
+                        return Ok(EXIT_NO_DATA);
+                    }
+                };
+                for (intv, t) in report.mismatched.iter() {
+                    let t = DateTime::<Utc>::from_timestamp_millis(*t).unwrap_or_default();
+                    println!["mismatch\t{}\t{}", intv.to_str(), t.to_rfc3339()];
+                }
+                println![
+                    "Derived {} candles, {} of {} existing candles didn't match",
+                    report.written,
+                    report.mismatched.len(),
+                    report.compared
+                ];
+                Ok(EXIT_OK)
+            }
+            HeadlessCmd::Audit { symbol, repair } => {
+                let report = match sql_conn.audit_asset(symbol, *repair).await? {
+                    Some(r) => r,
+                    None => {
+                        eprintln!["No data found for {}", symbol];
+                        return Ok(EXIT_NO_DATA);
+                    }
+                };
+                let fmt_t = |t: i64| match DateTime::<Utc>::from_timestamp_millis(t) {
+                    Some(t) => t.to_rfc3339(),
+                    None => t.to_string(),
+                };
+                for a in report.intvs.iter() {
+                    println![
+                        "{}\t{} rows\t{} gaps ({} candles)\t{} duplicates\t{} bad OHLC\t{} mismatched",
+                        a.intv.to_str(),
+                        a.rows,
+                        a.gaps.len(),
+                        a.missing(),
+                        a.duplicates,
+                        a.bad_ohlc.len(),
+                        a.mismatched.len()
+                    ];
+                    for (st, et) in a.gaps.iter() {
+                        println!["\tgap {} - {}", fmt_t(*st), fmt_t(*et)];
+                    }
+                }
+                match report.is_ok() {
+                    true => Ok(EXIT_OK),
+                    false => Ok(EXIT_AUDIT_FAILED),
+                }
+            }
+            HeadlessCmd::ImportArchive { dir } => {
+                let report = sql_conn.import_binance_archive(dir).await?;
+                for (file, e) in report.failed.iter() {
+                    eprintln!["Skipped {}: {}", file, e];
+                }
+                println![
+                    "Imported {} rows from {} files ({} checksums verified) for {}",
+                    report.rows,
+                    report.files,
+                    report.verified,
+                    report.symbols.join(", ")
+                ];
+                match report.files {
+                    0 => Ok(EXIT_NO_DATA),
+                    _ => Ok(EXIT_OK),
+                }
+            }
+            HeadlessCmd::ImportTicks { symbol, path } => {
+                let dir = crate::paths::get().ticks();
+                let n = import_agg_trades(std::path::Path::new(path), symbol, &dir)?;
+                if n == 0 {
+                    eprintln!["No aggTrades found in {}", path];
+                    return Ok(EXIT_NO_DATA);
+                };
+                println![
+                    "Imported {} aggTrades for {} to {}",
+                    n,
+                    symbol,
+                    dir.display()
+                ];
+                Ok(EXIT_OK)
+            }
+            HeadlessCmd::Import {
+                symbol,
+                intv,
+                csv,
+                config,
+            } => {
+                let config: CsvImportConfig = match config {
+                    Some(path) => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+                    None => CsvImportConfig::default(),
+                };
+                let rows = sql_conn.import_csv(symbol, intv, csv, &config).await?;
+                if rows == 0 {
+                    eprintln!["No rows found in {}", csv];
+                    return Ok(EXIT_NO_DATA);
+                };
+                println!["Imported {} {} rows for {}", rows, intv.to_str(), symbol];
+                Ok(EXIT_OK)
+            }
+            HeadlessCmd::Backtest { config, out } => {
+                let config: BacktestConfig =
+                    serde_json::from_str(&std::fs::read_to_string(config)?)?;
+                let intv = Intv::parse(&config.intv)?;
+                let mut h_trade = HistTrade::new(config.symbol.clone());
+                h_trade.asset1 = config.asset1;
+                h_trade.asset2 = config.asset2;
+                h_trade.current_intv = intv;
+                h_trade.start_time = config.start_time;
+                h_trade.funding = sql_conn
+                    .load_funding(&config.symbol, Some((config.start_time, config.end_time)))
+                    .await?;
+                if config.ticks {
+                    let ticks = load_ticks(
+                        &crate::paths::get().ticks(),
+                        &config.symbol,
+                        config.start_time,
+                        config.end_time,
+                    )?;
+                    if ticks.is_empty() {
+                        eprintln!["No aggTrades found for {}", config.symbol];
+                        return Ok(EXIT_NO_DATA);
+                    };
+                    let report = h_trade.backtest_ticks(&ticks, &config.queue, &config.orders);
+                    let report = serde_json::to_string_pretty(&report)?;
+                    match out {
+                        Some(path) => std::fs::write(path, report)?,
+                        None => println!["{}", report],
+                    };
+                    return Ok(EXIT_OK);
+                };
+                let kline = match sql_conn
+                    .load_kline(
+                        &config.symbol,
+                        &intv,
+                        Some((config.start_time, config.end_time)),
+                    )
+                    .await?
+                {
+                    Some(k) if !k.kline.is_empty() => k,
+                    _ => {
+                        eprintln!["No {} data found for {}", intv.label(), config.symbol];
+                        return Ok(EXIT_NO_DATA);
+                    }
+                };
+                let report = h_trade.backtest(&kline.kline, &config.eval_mode, &config.orders);
+                let report = serde_json::to_string_pretty(&report)?;
+                match out {
+                    Some(path) => std::fs::write(path, report)?,
+                    None => println!["{}", report],
+                };
+                Ok(EXIT_OK)
+            }
+            HeadlessCmd::Help => {
+                println!["{}", HEADLESS_USAGE];
+                Ok(EXIT_OK)
+            }
+        }
+    }
+}
+
+///Unix ms, a YYYY-MM-DD date (midnight UTC) or an RFC 3339 time
+fn parse_time_arg(s: &str) -> Result<i64> {
+    if let Ok(ms) = s.parse::<i64>() {
+        return Ok(ms);
+    };
+    if let Ok(d) = chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(d
+            .and_time(chrono::NaiveTime::MIN)
+            .and_utc()
+            .timestamp_millis());
+    };
+    let t = DateTime::parse_from_rfc3339(s)
+        .with_context(|| format!["Invalid time {}, use unix ms, YYYY-MM-DD or RFC 3339", s])?;
+    Ok(t.timestamp_millis())
+}
+
+///(out, (start, end))
+type ExportArgs = (Option<String>, Option<(i64, i64)>);
+
+///[out] [--from <t>] [--to <t>] of export, a missing end of the range is open
+fn parse_export_args(args: &[&str]) -> Result<ExportArgs> {
+    let (mut out, mut from, mut to) = (None, None, None);
+    let mut args = args.iter();
+    while let Some(a) = args.next() {
+        match *a {
+            "--from" | "--to" => {
+                let t = args.next().ok_or(anyhow::anyhow!["{} needs a time", a])?;
+                let t = parse_time_arg(t)?;
+                match *a {
+                    "--from" => from = Some(t),
+                    _ => to = Some(t),
+                };
+            }
+            o if out.is_none() && !o.starts_with("--") => out = Some(o.to_string()),
+            o => return Err(anyhow::anyhow!["Unexpected export argument: {}", o]),
+        };
+    }
+    let range = match (from, to) {
+        (None, None) => None,
+        (from, to) => Some((
+            from.unwrap_or(0),
+            to.unwrap_or(Utc::now().timestamp_millis()),
+        )),
+    };
+    if range.is_some_and(|(st, et)| st > et) {
+        return Err(anyhow::anyhow!["--from is after --to"]);
+    };
+    Ok((out, range))
+}
+
+///Runs a single command without starting the GUI and returns the process exit code
+pub fn headless_run(args: &[String]) -> i32 {
+    let cmd = match HeadlessCmd::parse(args) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!["{}\n\n{}", e, HEADLESS_USAGE];
+            return EXIT_USAGE;
+        }
+    };
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build();
+    let rt = match rt {
+        Ok(rt) => rt,
+        Err(e) => {
+            eprintln!["Unable to build tokio runtime: {}", e];
+            return EXIT_ERR;
+        }
+    };
+    let res = rt.block_on(cmd.run());
+    match res {
+        Ok(code) => code,
+        Err(e) => {
+            tracing::error!["{:?} failed: {:?}", cmd, e];
+            eprintln!["Error: {:#}", e];
+            EXIT_ERR
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(a: &str) -> Vec<String> {
+        a.split_whitespace().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn headless_args_parse() {
+        assert_eq!(
+            HeadlessCmd::parse(&args("download btcusdt --derive")).unwrap(),
+            HeadlessCmd::Download {
+                symbol: "BTCUSDT".to_string(),
+                derive: true
+            }
+        );
+        assert_eq!(
+            HeadlessCmd::parse(&args("update")).unwrap(),
+            HeadlessCmd::Update { derive: false }
+        );
+        assert_eq!(
+            HeadlessCmd::parse(&args("export ethusdt 1h")).unwrap(),
+            HeadlessCmd::Export {
+                symbol: "ETHUSDT".to_string(),
+                intv: Intv::Hour1,
+                out: None,
+                range: None,
+            }
+        );
+        assert_eq!(
+            HeadlessCmd::parse(&args(
+                "export BTCUSDT 15m --from 2024-01-01 out.csv --to 1704153600000"
+            ))
+            .unwrap(),
+            HeadlessCmd::Export {
+                symbol: "BTCUSDT".to_string(),
+                intv: Intv::Min15,
+                out: Some("out.csv".to_string()),
+                range: Some((1704067200000, 1704153600000)),
+            }
+        );
+        assert_eq!(
+            HeadlessCmd::parse(&args("export BTCUSDT 1d --to 2024-01-01T01:00:00+01:00")).unwrap(),
+            HeadlessCmd::Export {
+                symbol: "BTCUSDT".to_string(),
+                intv: Intv::Day1,
+                out: None,
+                range: Some((0, 1704067200000)),
+            }
+        );
+        assert_eq!(
+            HeadlessCmd::parse(&args("backtest config.json out.json")).unwrap(),
+            HeadlessCmd::Backtest {
+                config: "config.json".to_string(),
+                out: Some("out.json".to_string())
+            }
+        );
+        assert_eq!(HeadlessCmd::parse(&args("-h")).unwrap(), HeadlessCmd::Help);
+        for bad in [
+            "",
+            "download",
+            "download BTCUSDT --foo",
+            "export BTCUSDT 7x",
+            "export BTCUSDT 1h a.csv b.csv",
+            "export BTCUSDT 1h --from",
+            "export BTCUSDT 1h --from yesterday",
+            "export BTCUSDT 1h --from 2024-02-01 --to 2024-01-01",
+            "backtest a.json b.json c.json",
+            "frobnicate",
+        ] {
+            assert!(HeadlessCmd::parse(&args(bad)).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn headless_exit_codes() {
+        assert_eq!(headless_run(&args("frobnicate")), EXIT_USAGE);
+        assert_eq!(headless_run(&args("export BTCUSDT 1h --to")), EXIT_USAGE);
+        assert_eq!(headless_run(&args("help")), EXIT_OK);
+    }
+}
@@ -4,8 +4,10 @@ use binance::rest_model::{
     AccountInformation, Order as BinanceOrder, OrderSide, OrderStatus, OrderType, TimeInForce,
 };
 use tokio::time::{Duration, sleep};
+use tokio_util::sync::CancellationToken;
 
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
 #[cfg(debug_assertions)]
@@ -29,9 +31,16 @@ use core::pin::pin;
 use futures::stream::FuturesUnordered;
 
 use crate::data::{
-    AssetData, Intv, Kline as KlineMine, Klines, get_asset_bases_binance, validate_asset_binance,
+    AssetData, Intv, Kline as KlineMine, KlineRow, Klines, get_asset_bases_binance,
+    validate_asset_binance,
 };
 use crate::gui::{KeysStatus, LiveInfo, Settings};
+use crate::ratelimit::{
+    WEIGHT_ACCOUNT, WEIGHT_EXCHANGE_INFO, WEIGHT_FUT_EXCHANGE_INFO, WEIGHT_KLINES,
+    WEIGHT_OPEN_ORDERS_ALL, WEIGHT_ORDER, binance_call, binance_fut_limiter, binance_limiter,
+};
+use crate::replay::{WsFrame, WsRecorder, load_frames, replay_delay};
+use crate::ticks::{AggTick, TickRecorder};
 use crate::trade::{LimitStatus, Order, Quant, StopStatus};
 use crate::{BinInstructs, BinResponse, GeneralError};
 
@@ -78,9 +87,12 @@ pub struct FutSymbolInfo {
 }
 
 pub async fn fut_get_exchange_info() -> Result<Vec<FutSymbolInfo>> {
-    let body = reqwest::get("https://fapi.binance.com/fapi/v1/exchangeInfo")
-        .await?
-        .text()
+    let body = binance_fut_limiter()
+        .get(
+            &reqwest::Client::new(),
+            "https://fapi.binance.com/fapi/v1/exchangeInfo",
+            WEIGHT_FUT_EXCHANGE_INFO,
+        )
         .await?;
     let json_body: Value = serde_json::from_str(&body)?;
     let sym = json_body["symbols"].clone();
@@ -90,9 +102,12 @@ pub async fn fut_get_exchange_info() -> Result<Vec<FutSymbolInfo>> {
 }
 
 pub async fn get_exchange_info() -> Result<Vec<SymbolInfo>> {
-    let body = reqwest::get("https://api.binance.com/api/v3/exchangeInfo")
-        .await?
-        .text()
+    let body = binance_limiter()
+        .get(
+            &reqwest::Client::new(),
+            "https://api.binance.com/api/v3/exchangeInfo",
+            WEIGHT_EXCHANGE_INFO,
+        )
         .await?;
     let json_body: Value = serde_json::from_str(&body)?;
     let sym = json_body["symbols"].clone();
@@ -139,7 +154,7 @@ struct GetKline {
 }
 impl GetKline {
     fn to_kline(input: &[GetKline]) -> KlineMine {
-        let res: Vec<(DateTime<Utc>, f64, f64, f64, f64, f64)> = input
+        let res: Vec<KlineRow> = input
             .iter()
             .map(|n| {
                 (
@@ -190,15 +205,11 @@ async fn get_latest_wicks(
     symbol: &str,
     interval: &str,
 ) -> Result<Vec<GetKline>> {
-    let res: String = client
-        .get(&format![
-            "https://api.binance.com/api/v3/klines?symbol={}&interval={}",
-            symbol, interval
-        ])
-        .send()
-        .await?
-        .text()
-        .await?;
+    let url = format![
+        "https://api.binance.com/api/v3/klines?symbol={}&interval={}",
+        symbol, interval
+    ];
+    let res: String = binance_limiter().get(client, &url, WEIGHT_KLINES).await?;
     if res.is_empty() != true {
         //tracing::trace!["GET response:{}", &res];
         let res = serde_json::from_str(&res);
@@ -230,6 +241,19 @@ struct AggTradeWS {
     q: f64,
     //s: String,
 }
+impl From<&AggTradeWS> for AggTick {
+    fn from(t: &AggTradeWS) -> Self {
+        Self {
+            id: t.a,
+            price: t.p,
+            qty: t.q,
+            first_id: t.f,
+            last_id: t.l,
+            time: t.T,
+            buyer_maker: t.m,
+        }
+    }
+}
 #[allow(non_snake_case)]
 #[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
 pub struct KlineTick {
@@ -252,7 +276,7 @@ pub struct KlineTick {
     B: i64, //ignore
 }
 impl KlineTick {
-    pub fn to_kline_vec(input: &[KlineTick]) -> Vec<(DateTime<Utc>, f64, f64, f64, f64, f64)> {
+    pub fn to_kline_vec(input: &[KlineTick]) -> Vec<KlineRow> {
         input
             .iter()
             .map(|i| {
@@ -418,6 +442,9 @@ impl BinWSResponse {
     }
 }
 
+///aggTrades kept per symbol, older ones are only kept by the tick recorder
+const AGG_TRADE_KEEP: usize = 1000;
+
 #[derive(Debug, Default, Clone)]
 pub struct SymbolOutput {
     agg_trade: Vec<AggTradeWS>,
@@ -444,6 +471,9 @@ pub struct WSTick {
     reconnect: bool,
     change_symbol: Option<String>,
     symbol: String,
+    ///Shared with the running WS task, None while not recording
+    recorder: Arc<Mutex<Option<TickRecorder>>>,
+    ws_recorder: Arc<Mutex<Option<WsRecorder>>>,
 }
 impl WSTick {
     fn new(
@@ -456,6 +486,170 @@ impl WSTick {
             ..Default::default()
         }
     }
+    ///Starts recording into dir, stops with None
+    pub fn set_recording(&self, dir: Option<&Path>) -> Result<()> {
+        let mut rec = self
+            .recorder
+            .lock()
+            .expect("(BINCLIENT) poisoned tick recorder mutex");
+        if let Some(old) = rec.as_mut() {
+            old.flush()?;
+            tracing::info![
+                "Stopped recording ticks, {} recorded to {}",
+                old.recorded(),
+                old.dir().display()
+            ];
+        };
+        *rec = match dir {
+            Some(d) => Some(TickRecorder::new(d)?),
+            None => None,
+        };
+        Ok(())
+    }
+    ///Starts recording every raw WS message to a new file in dir, stops with None. Returns the
+    ///file recorded to.
+    pub fn set_ws_recording(&self, dir: Option<&Path>) -> Result<Option<PathBuf>> {
+        let mut rec = self
+            .ws_recorder
+            .lock()
+            .expect("(BINCLIENT) poisoned WS recorder mutex");
+        if let Some(old) = rec.as_mut() {
+            old.flush()?;
+            tracing::info![
+                "Stopped recording WS, {} messages recorded to {}",
+                old.recorded(),
+                old.path().display()
+            ];
+        };
+        *rec = match dir {
+            Some(d) => Some(WsRecorder::new(d)?),
+            None => None,
+        };
+        Ok(rec.as_ref().map(|r| r.path().to_path_buf()))
+    }
+    fn record_ws(&self, msg: &Value) {
+        let mut rec = self
+            .ws_recorder
+            .lock()
+            .expect("(BINCLIENT) poisoned WS recorder mutex");
+        if let Some(r) = rec.as_mut() {
+            match r.record(msg) {
+                Ok(_) => (),
+                Err(e) => {
+                    tracing::error!["WS recording stopped: {}", e];
+                    *rec = None;
+                }
+            };
+        };
+    }
+    fn record_tick(&self, symbol: &str, t: &AggTradeWS) {
+        let mut rec = self
+            .recorder
+            .lock()
+            .expect("(BINCLIENT) poisoned tick recorder mutex");
+        if let Some(r) = rec.as_mut() {
+            match r.record(symbol, &AggTick::from(t)) {
+                Ok(_) => (),
+                Err(e) => {
+                    //NOTE stop instead of failing on every tick after e.g. a full disk
+                    tracing::error!["Tick recording of {} stopped: {}", symbol, e];
+                    *rec = None;
+                }
+            };
+        };
+    }
+    fn flush_recorders(&self) {
+        let mut rec = self
+            .recorder
+            .lock()
+            .expect("(BINCLIENT) poisoned tick recorder mutex");
+        if let Some(r) = rec.as_mut() {
+            match r.flush() {
+                Ok(_) => (),
+                Err(e) => {
+                    tracing::error!["Unable to flush recorded ticks: {}", e];
+                }
+            };
+        };
+        let mut rec = self
+            .ws_recorder
+            .lock()
+            .expect("(BINCLIENT) poisoned WS recorder mutex");
+        if let Some(r) = rec.as_mut() {
+            match r.flush() {
+                Ok(_) => (),
+                Err(e) => {
+                    tracing::error!["Unable to flush recorded WS messages: {}", e];
+                }
+            };
+        };
+    }
+    ///Places the messages appended since the last batch
+    async fn place_buffer(&mut self) -> Result<()> {
+        let mut buffer = self.sort().await;
+        while let Some((symbol, response)) = buffer.pop() {
+            tracing::trace!["\x1b[93m WS placing raw output: \x1b[0m {:?}", response];
+            self.place_unsorted(symbol, response)
+                .await
+                .context("Unable to sort WS output")?;
+        }
+        self.flush_recorders();
+        Ok(())
+    }
+    ///Feeds recorded frames through append_tick and place_buffer in the same batches as a live
+    ///connection, waiting between frames at speed (0 doesn't wait). Stops once stop is
+    ///cancelled, returns the number of frames replayed. Replay into a WSTick of its own
+    ///(replay_tick) so the recorders and the live buffer aren't fed stale trades.
+    pub async fn replay<F: FnMut(usize)>(
+        &mut self,
+        frames: Vec<WsFrame>,
+        speed: u32,
+        buffer_size: usize,
+        stop: CancellationToken,
+        mut on_batch: F,
+    ) -> Result<usize> {
+        let mut last = frames.first().map(|f| f.time).unwrap_or_default();
+        let mut n = 0;
+        let mut replayed = 0;
+        for frame in frames {
+            let delay = replay_delay(last, frame.time, speed);
+            if !delay.is_zero() {
+                tokio::select! {
+                    _ = sleep(delay) => {}
+                    _ = stop.cancelled() => {}
+                }
+            };
+            if stop.is_cancelled() {
+                //NOTE the output was cleared by the stop, don't place the rest of the batch
+                return Ok(replayed);
+            };
+            last = frame.time;
+            //NOTE the disconnect flag belongs to the live connection, replays only stop on stop
+            let _flags = self
+                .append_tick(frame.msg)
+                .await
+                .context("Failed to append replayed tick")?;
+            n += 1;
+            replayed += 1;
+            if n >= buffer_size {
+                self.place_buffer().await?;
+                on_batch(replayed);
+                n = 0;
+            };
+        }
+        self.place_buffer().await?;
+        on_batch(replayed);
+        Ok(replayed)
+    }
+    ///A WSTick placing into the same output and live price as self, without its recorders or
+    ///unplaced messages
+    fn replay_tick(&self, symbol: &str) -> Self {
+        Self {
+            watch_price_ty: self.watch_price_ty,
+            symbol: symbol.to_string(),
+            ..Self::new(self.symbol_sorted_output.clone(), self.live_price1.clone())
+        }
+    }
     async fn sort(&mut self) -> Vec<(String, BinWSResponse)> {
         let mut uo = self
             .unsorted_output
@@ -473,6 +667,10 @@ impl WSTick {
                     .expect("(BINCLIENT) poisoned data collection mutex");
                 if let Some(queue) = cum_queue.get_mut(&symbol) {
                     queue.agg_trade.push(o);
+                    if queue.agg_trade.len() > 2 * AGG_TRADE_KEEP {
+                        let n = queue.agg_trade.len() - AGG_TRADE_KEEP;
+                        queue.agg_trade.drain(..n);
+                    };
                     tracing::trace!["Agg trade queue {:?}", &queue];
                 } else {
                     let mut queue = SymbolOutput::default();
@@ -539,6 +737,10 @@ impl WSTick {
         tracing::trace!["\x1b[93m Raw ws output\x1b[93m : {:?}", input];
         let (symbol, sorted) =
             BinWSResponse::parse_into_by_str(input).context("Failed to parse into str")?;
+        //NOTE recorded here, the buffer is placed in reverse order
+        if let BinWSResponse::AggTrade(ref t) = sorted {
+            self.record_tick(&symbol, t);
+        };
         match (sorted, self.watch_price_ty) {
             (BinWSResponse::AggTrade(val), BinWSResponse::AggTrade(_)) => {
                 if self.symbol == symbol || self.symbol.is_empty() {
@@ -626,20 +828,14 @@ impl WSTick {
                         tracing::trace!["\x1b[93m WS output (NOT APPEND): \x1b[0m {:?}", msg];
                     } else {
                         tracing::trace!["\x1b[93m WS output (APPEND): \x1b[0m {:?}", msg];
+                        self.record_ws(&v);
                         (self.disconnect, self.reconnect, self.change_symbol) =
                             self.append_tick(v).await.context("Failed to append tick")?;
                         n += 1;
                     }
                 }
             }
-            let mut buffer = self.sort().await;
-            while let Some((symbol, response)) = buffer.pop() {
-                tracing::trace!["\x1b[93m WS placing raw output: \x1b[0m {:?}", response];
-                let _res = self
-                    .place_unsorted(symbol, response)
-                    .await
-                    .context("Unable to sort WS output")?;
-            }
+            self.place_buffer().await?;
             n = 0;
             tracing::trace!["\x1b[93m Unsorted  messages placed \x1b[0m"];
         }
@@ -694,6 +890,8 @@ pub struct BinanceClient {
 
     pub default_symbol: String,
     pub default_intv: Intv,
+    ///Stops the running WS replay
+    pub ws_replay: Option<CancellationToken>,
 }
 
 impl Default for BinanceClient {
@@ -714,6 +912,7 @@ impl Default for BinanceClient {
             current_symbol: String::default(),
             default_symbol: String::default(),
             default_intv: Intv::default(),
+            ws_replay: None,
             base_balances: (0.0, 0.0),
             qoute_balances: (0.0, 0.0),
             balances: HashMap::new(),
@@ -751,7 +950,7 @@ impl BinanceClient {
     }
     async fn add_replace_api_keys(&mut self, pub_key: &str, priv_key: &str) -> Result<()> {
         let client: Account = Binance::new(Some(pub_key.to_string()), Some(priv_key.to_string()));
-        let res = client.get_account().await;
+        let res = binance_call(WEIGHT_ACCOUNT, client.get_account()).await;
 
         let live_inf = self.live_info.clone();
         let mut live_info = live_inf.lock().expect("live_info poisoned mutex");
@@ -774,14 +973,15 @@ impl BinanceClient {
     async fn send_new_order(&mut self, sym: &str, o: &Order) -> Result<u64> {
         let order_request: OrderRequest =
             parse_to_binance(sym, &o, self.qoute_balances.0, self.base_balances.0);
-        let transaction = self.binance_client.place_order(order_request).await?;
+        let transaction =
+            binance_call(WEIGHT_ORDER, self.binance_client.place_order(order_request)).await?;
         let _res = self.get_all_balances().await;
         Ok(transaction.order_id)
     }
     //NOTE this is a horrible way to do this but for now it's fine
     pub async fn check_live_orders_change(live_info: Arc<Mutex<LiveInfo>>, binance: Account) {
         loop {
-            let res = binance.get_all_open_orders().await;
+            let res = binance_call(WEIGHT_OPEN_ORDERS_ALL, binance.get_all_open_orders()).await;
             match res {
                 Ok(orders) => {
                     let (
@@ -801,7 +1001,9 @@ impl BinanceClient {
                     match keys_status {
                         KeysStatus::Valid => {
                             let (a1_locked, a1_free) = if !&a1_string.is_empty() {
-                                let res_a1 = binance.get_balance(&a1_string).await;
+                                let res_a1 =
+                                    binance_call(WEIGHT_ACCOUNT, binance.get_balance(&a1_string))
+                                        .await;
                                 match res_a1 {
                                     Ok(balance) => (balance.locked, balance.free),
                                     Err(e) => {
@@ -818,7 +1020,9 @@ impl BinanceClient {
                                 (a1_l_old, a1_f_old)
                             };
                             let (a2_locked, a2_free) = if !&a2_string.is_empty() {
-                                let res_a2 = binance.get_balance(&a2_string).await;
+                                let res_a2 =
+                                    binance_call(WEIGHT_ACCOUNT, binance.get_balance(&a2_string))
+                                        .await;
                                 match res_a2 {
                                     Ok(balance) => (balance.locked, balance.free),
                                     Err(e) => {
@@ -893,7 +1097,11 @@ impl BinanceClient {
             new_client_order_id: None,
             recv_window: None,
         };
-        let transaction = self.binance_client.cancel_order(order_cancelation).await?;
+        let transaction = binance_call(
+            WEIGHT_ORDER,
+            self.binance_client.cancel_order(order_cancelation),
+        )
+        .await?;
         tracing::trace!["{:?}", transaction];
         Ok(())
     }
@@ -905,6 +1113,92 @@ impl BinanceClient {
         tracing::trace!["Cancelled orders: {:?}", transaction];
         Ok(())
     }
+    fn set_tick_recording(&mut self, record: bool) -> Result<()> {
+        let ws_tick = self
+            .ws_tick
+            .as_ref()
+            .ok_or(anyhow!["No WS to record ticks from"])?;
+        let dir = crate::paths::get().ticks();
+        ws_tick.set_recording(record.then_some(dir.as_path()))?;
+        let mut live_info = self.live_info.lock().expect("live_info poisoned mutex");
+        live_info.tick_recording = record;
+        live_info.live_info_changed = true;
+        Ok(())
+    }
+    fn set_ws_recording(&mut self, record: bool) -> Result<()> {
+        let ws_tick = self.ws_tick.as_ref().ok_or(anyhow!["No WS to record"])?;
+        let dir = crate::paths::get().ws_sessions();
+        let path = ws_tick.set_ws_recording(record.then_some(dir.as_path()))?;
+        let mut live_info = self.live_info.lock().expect("live_info poisoned mutex");
+        live_info.ws_recording = path.map(|p| p.display().to_string());
+        live_info.live_info_changed = true;
+        Ok(())
+    }
+    ///Stops the running WS replay and drops its output so it isn't mixed with a live session
+    fn stop_ws_replay(&mut self) {
+        if let Some(stop) = self.ws_replay.take() {
+            stop.cancel();
+            if let Some(ws_tick) = self.ws_tick.as_ref() {
+                ws_tick
+                    .symbol_sorted_output
+                    .lock()
+                    .expect("(BINCLIENT) poisoned data collection mutex")
+                    .clear();
+            };
+        };
+    }
+    ///Replays a recorded WS session into the live chart instead of the live connection, needs
+    ///no network
+    async fn replay_ws(&mut self, path: &str, speed: u32) -> Result<()> {
+        let frames = load_frames(Path::new(path))?;
+        let symbol = frames
+            .iter()
+            .find_map(|f| f.symbol())
+            .map(|s| s.to_string())
+            .ok_or(anyhow!["No symbol in the WS session {}", path])?;
+        let mut ws_tick = self
+            .ws_tick
+            .as_ref()
+            .map(|w| w.replay_tick(&symbol))
+            .ok_or(anyhow!["No WS to replay into"])?;
+        self.stop_ws_replay();
+        let _res = self.disconnect_ws().await;
+        ws_tick
+            .symbol_sorted_output
+            .lock()
+            .expect("(BINCLIENT) poisoned data collection mutex")
+            .clear();
+        self.current_symbol = symbol.clone();
+        self.live_ad
+            .lock()
+            .expect("Poisoned live AD mutex")
+            .live_asset_symbol_changed = (true, symbol);
+        let total = frames.len();
+        let stop = CancellationToken::new();
+        self.ws_replay = Some(stop.clone());
+        let live_info = self.live_info.clone();
+        live_info
+            .lock()
+            .expect("live_info poisoned mutex")
+            .ws_replay = Some((0, total));
+        let path = path.to_string();
+        tokio::task::spawn(async move {
+            let progress = live_info.clone();
+            let res = ws_tick
+                .replay(frames, speed, DEFAULT_BUFFER_SIZE, stop, |n| {
+                    let mut li = progress.lock().expect("live_info poisoned mutex");
+                    li.ws_replay = Some((n, total));
+                })
+                .await;
+            match res {
+                Ok(n) => tracing::info!["Replayed {} of {} WS messages from {}", n, total, path],
+                Err(e) => tracing::error!["WS replay of {} failed: {}", path, e],
+            };
+            let mut li = live_info.lock().expect("live_info poisoned mutex");
+            li.ws_replay = None;
+        });
+        Ok(())
+    }
     pub async fn connect_ws(
         mut ws_tick: WSTick,
         params: HashMap<String, Vec<String>>,
@@ -1078,7 +1372,11 @@ impl BinanceClient {
         Ok(())
     }
     async fn get_open_orders_binance(&mut self) -> Result<()> {
-        let orders = self.binance_client.get_all_open_orders().await?;
+        let orders = binance_call(
+            WEIGHT_OPEN_ORDERS_ALL,
+            self.binance_client.get_all_open_orders(),
+        )
+        .await?;
         let _res: Vec<_> = orders
             .iter()
             .map(|order_binance| {
@@ -1104,7 +1402,7 @@ impl BinanceClient {
         Ok(())
     }
     async fn get_balances(&mut self, symbol: &str) -> Result<()> {
-        let res = self.binance_client.get_account().await;
+        let res = binance_call(WEIGHT_ACCOUNT, self.binance_client.get_account()).await;
         let mut balances: HashMap<String, (f64, f64)> = HashMap::new();
         match res {
             Ok(acc) => {
@@ -1148,7 +1446,7 @@ impl BinanceClient {
         Ok(())
     }
     async fn get_all_balances(&mut self) -> Result<()> {
-        let res = self.binance_client.get_account().await;
+        let res = binance_call(WEIGHT_ACCOUNT, self.binance_client.get_account()).await;
         let mut balances: HashMap<String, (f64, f64)> = HashMap::new();
         match res {
             Ok(acc) => {
@@ -1223,7 +1521,9 @@ impl BinanceClient {
         Ok(())
     }
     pub async fn get_user_data(&mut self) -> Result<()> {
-        let resp = self.binance_client.get_account().await.context(ERR_CTX)?;
+        let resp = binance_call(WEIGHT_ACCOUNT, self.binance_client.get_account())
+            .await
+            .context(ERR_CTX)?;
         tracing::trace!("{:?}", resp);
         self.account_info = Some(resp);
         Ok(())
@@ -1426,6 +1726,7 @@ impl BinanceClient {
             }
             BinInstructs::ReConnectWS => {
                 tracing::trace!["reconnect WS called!"];
+                self.stop_ws_replay();
                 let _res = self.disconnect_ws().await;
                 let _res = self.reconnect_ws().await;
                 let symbol = self.current_symbol.clone();
@@ -1441,6 +1742,7 @@ impl BinanceClient {
             }
             BinInstructs::Disconnect => {
                 tracing::trace!["Disconnect WS called!"];
+                self.stop_ws_replay();
                 let _res = self.disconnect_ws().await;
                 tracing::trace!["Disconnect WS finished!"];
                 BinResponse::Success
@@ -1640,6 +1942,55 @@ impl BinanceClient {
                 tracing::trace!["reconnect WS finished!"];
                 BinResponse::Success
             }
+            BinInstructs::RecordTicks { record } => {
+                let res = self.set_tick_recording(record);
+                let resp: BinResponse = match res {
+                    Ok(_) => BinResponse::Success,
+                    Err(e) => {
+                        let string_error = format!["{}", e];
+                        tracing::error!(
+                            "{}",
+                            anyhow!["Unable to set tick recording:{}", e.context(ERR_CTX)]
+                        );
+                        BinResponse::Failure((string_error, GeneralError::Generic))
+                    }
+                };
+                resp
+            }
+            BinInstructs::RecordWS { record } => {
+                let res = self.set_ws_recording(record);
+                let resp: BinResponse = match res {
+                    Ok(_) => BinResponse::Success,
+                    Err(e) => {
+                        let string_error = format!["{}", e];
+                        tracing::error!(
+                            "{}",
+                            anyhow!["Unable to set WS recording:{}", e.context(ERR_CTX)]
+                        );
+                        BinResponse::Failure((string_error, GeneralError::Generic))
+                    }
+                };
+                resp
+            }
+            BinInstructs::ReplayWS { ref path, speed } => {
+                let res = self.replay_ws(path, speed).await;
+                let resp: BinResponse = match res {
+                    Ok(_) => BinResponse::Success,
+                    Err(e) => {
+                        let string_error = format!["{}", e];
+                        tracing::error!(
+                            "{}",
+                            anyhow!["Unable to replay WS session:{}", e.context(ERR_CTX)]
+                        );
+                        BinResponse::Failure((string_error, GeneralError::Generic))
+                    }
+                };
+                resp
+            }
+            BinInstructs::StopReplayWS => {
+                self.stop_ws_replay();
+                BinResponse::Success
+            }
             BinInstructs::None => BinResponse::None,
         }
     }
@@ -1933,4 +2284,93 @@ mod tests {
     #[tokio::test]
     //TODO  make binance api tests
     async fn example() {}
+
+    fn agg_trade_msg(n: i64) -> Value {
+        json!({
+            "e": "aggTrade", "E": 1704067200000 + n, "s": "BTCUSDT", "a": n,
+            "p": format!["{}.5", 42000 + n], "q": "0.1", "f": n, "l": n,
+            "T": 1704067200000 + n, "m": false, "M": true
+        })
+    }
+    fn kline_msg(n: i64, closed: bool) -> Value {
+        json!({
+            "e": "kline", "E": 1704067200000 + n, "s": "BTCUSDT",
+            "k": {
+                "t": 1704067200000_i64, "T": 1704067259999_i64, "s": "BTCUSDT", "i": "1m",
+                "f": 0, "L": n, "o": "42000", "c": "42001", "h": "42002", "l": "41999",
+                "v": "10", "n": n, "x": closed, "q": "420000", "V": "5", "Q": "210000", "B": "0"
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn replayed_session_is_placed_like_a_live_one() {
+        let frames: Vec<WsFrame> = (0..7)
+            .map(|n| WsFrame {
+                time: n * 10,
+                msg: match n % 3 {
+                    2 => kline_msg(n, n == 5),
+                    _ => agg_trade_msg(n),
+                },
+            })
+            .collect();
+        let collect = Arc::new(Mutex::new(HashMap::new()));
+        let price = Arc::new(Mutex::new(0.0));
+        let mut ws = WSTick::new(collect.clone(), price.clone());
+        let mut batches = vec![];
+        let replayed = ws
+            .replay(frames.clone(), 0, 3, CancellationToken::new(), |n| {
+                batches.push(n)
+            })
+            .await
+            .unwrap();
+        let stop = CancellationToken::new();
+        stop.cancel();
+        let mut ws2 = WSTick::new(Arc::default(), Arc::default());
+        let stopped = ws2.replay(frames, 1, 3, stop, |_| ()).await.unwrap();
+
+        assert_eq!(replayed, 7);
+        assert_eq!(batches, vec![3, 6, 7]);
+        assert_eq!(stopped, 0);
+        assert_eq!(*price.lock().unwrap(), 42006.5);
+        let out = collect.lock().unwrap();
+        let btc = out.get("BTCUSDT").unwrap();
+        assert_eq!(btc.agg_trade.len(), 5);
+        assert_eq!(btc.closed_klines.get(&Intv::Min1).map(|k| k.len()), Some(1));
+    }
+
+    #[tokio::test]
+    async fn replay_bypasses_the_live_recorders() {
+        let dir = std::env::temp_dir().join(format!["bintrade_replay_rec_{}", std::process::id()]);
+        let frames: Vec<WsFrame> = (0..4)
+            .map(|n| WsFrame {
+                time: n * 10,
+                msg: agg_trade_msg(n),
+            })
+            .collect();
+        let live = WSTick::new(Arc::default(), Arc::default());
+        live.set_recording(Some(&dir)).unwrap();
+        live.set_ws_recording(Some(&dir)).unwrap();
+        let mut replay = live.replay_tick("BTCUSDT");
+        let replayed = replay
+            .replay(frames, 0, 3, CancellationToken::new(), |_| ())
+            .await
+            .unwrap();
+        let ticks = live.recorder.lock().unwrap().as_ref().unwrap().recorded();
+        let msgs = live
+            .ws_recorder
+            .lock()
+            .unwrap()
+            .as_ref()
+            .unwrap()
+            .recorded();
+        live.set_recording(None).unwrap();
+        live.set_ws_recording(None).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(replayed, 4);
+        assert_eq!((ticks, msgs), (0, 0));
+        let out = live.symbol_sorted_output.lock().unwrap();
+        assert_eq!(out.get("BTCUSDT").map(|o| o.agg_trade.len()), Some(4));
+    }
 }
@@ -1,8 +1,8 @@
-use chrono::{DateTime, Datelike, Utc};
+use chrono::{DateTime, Datelike, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, path::Path};
 
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, OnceLock};
 
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
@@ -12,6 +12,14 @@ use futures::StreamExt;
 use futures::stream::FuturesUnordered;
 
 use anyhow::{Context, Result, anyhow};
+use sha2::{Digest, Sha256};
+
+use arrow_array::{ArrayRef, Float64Array, RecordBatch, TimestampMillisecondArray, UInt64Array};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
 
 use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -19,55 +27,74 @@ use sqlx::{
     Pool, QueryBuilder, Sqlite, SqlitePool, migrate::MigrateDatabase, sqlite::SqliteConnectOptions,
 };
 
-use binance::api::Binance;
-use binance::market::Market;
-use binance::rest_model::{KlineSummaries, KlineSummary};
-
 const ERR_CTX: &str = "SQL Data loader";
 const SINGLE_ASSET_DL_TASKS_MAX: usize = 8;
-pub const METADATA_DB_PATH: &str = "./databases/metadata.db";
 const BIN_TIMESTAMP: i64 = 1577836800000;
+//NOTE 2000-01-01, daily stock data goes back further but it's enough for trading practice
+const YF_TIMESTAMP: i64 = 946684800000;
+///SQLite allows 32766 bound variables per query, append_kline binds 13 per row
+const APPEND_KLINE_MAX_ROWS: usize = 32766 / 13;
 
 use bincode::{Decode, Encode, config};
-use linya::{Bar, Progress};
 use std::io::{Read, Write};
 use std::time::Instant;
 use tracing::instrument;
 
+use crate::cache::{CACHE_CHUNK_CANDLES, CacheUsage, KlineCache, load_latest_cached};
 use crate::conn::{fut_get_exchange_info, get_exchange_info};
 use crate::gui::Settings;
+use crate::jobs::{DlJob, DlJobs};
+use crate::paths::{asset_db_path, databases_dir, metadata_db_path};
+use crate::pattern::{
+    PATTERN_MATCHES, PatternMatch, query_pattern, scan_patterns, update_pattern_hashes,
+    update_pattern_hashes_intv,
+};
+#[cfg(feature = "yfinance")]
+use crate::source::YahooSource;
+use crate::source::{
+    BinanceSource, DataSource, FUT_PAGE_LIMIT, exchange_symbol, fut_symbol, is_fut_symbol,
+};
+use crate::stats::{ColStat, compute_col_stats, load_col_stats, save_col_stats};
 use crate::{GeneralError, SQLInstructs, SQLResponse};
 
 #[cfg(feature = "yfinance")]
 use yfinance_rs::core::models::Interval;
 #[cfg(feature = "yfinance")]
-use yfinance_rs::{Candle, HistoryBuilder, YfClient, YfError};
+use yfinance_rs::{Candle, HistoryBuilder, Range, YfClient, YfError};
 
 #[cfg(feature = "yfinance")]
-async fn get_yfinance_data(symbol: String, intv: Intv) -> Result<Kline> {
-    let client = YfClient::default();
-    let interval: Interval = yfintv_conv(intv);
-    let hist_download = HistoryBuilder::new(&client, symbol).interval(interval);
+pub(crate) async fn get_yfinance_data(
+    client: &YfClient,
+    symbol: &str,
+    intv: Intv,
+    start_ms: i64,
+    end_ms: i64,
+) -> Result<Kline> {
+    let interval: Interval =
+        yfintv_conv(intv).ok_or(anyhow!["Yahoo has no {} interval", intv.label()])?;
+    let (start, end) = match (
+        DateTime::<Utc>::from_timestamp_millis(start_ms),
+        DateTime::<Utc>::from_timestamp_millis(end_ms),
+    ) {
+        (Some(s), Some(e)) => (s, e),
+        _ => return Err(anyhow!["Invalid time range {} - {}", start_ms, end_ms]),
+    };
+    let hist_download = HistoryBuilder::new(client, symbol)
+        .interval(interval)
+        .between(start, end)
+        .actions(false);
     let kl = hist_download.fetch().await?;
-    let mut kline: Vec<(DateTime<Utc>, f64, f64, f64, f64, f64)> = vec![];
-    kl.iter().map(|k| {
-        let s = yfcandle_conv(&k);
-        match s {
-            Ok(slice) => {
-                kline.push(slice.clone());
-                Ok(())
-            }
-            Err(e) => Err(e),
-        };
-    });
+    let kline = kl
+        .iter()
+        .map(yfcandle_conv)
+        .collect::<Result<Vec<KlineRow>>>()?;
     Ok(Kline::new_sql(kline))
 }
 
 #[cfg(feature = "yfinance")]
-fn yfcandle_conv(input: &Candle) -> Result<(DateTime<Utc>, f64, f64, f64, f64, f64)> {
+fn yfcandle_conv(input: &Candle) -> Result<KlineRow> {
     let time: DateTime<Utc> = DateTime::from_timestamp(input.ts, 0)
-        .ok_or(anyhow!["Unable to parse time from timestamp"])?
-        .naive_utc();
+        .ok_or(anyhow!["Unable to parse time from timestamp"])?;
     let open = input.open;
     let high = input.high;
     let low = input.low;
@@ -80,6 +107,147 @@ fn yfcandle_conv(input: &Candle) -> Result<(DateTime<Utc>, f64, f64, f64, f64, f
     Ok((time, open, high, low, close, volume))
 }
 
+///Intervals Yahoo serves, the others are resampled from yf_source_intv
+#[cfg(feature = "yfinance")]
+fn yfintv_conv(intv: Intv) -> Option<Interval> {
+    match intv {
+        Intv::Min1 => Some(Interval::I1m),
+        Intv::Min5 => Some(Interval::I5m),
+        Intv::Min15 => Some(Interval::I15m),
+        Intv::Min30 => Some(Interval::I30m),
+        Intv::Hour1 => Some(Interval::I1h),
+        Intv::Day1 => Some(Interval::D1),
+        Intv::Week1 => Some(Interval::W1),
+        Intv::Month1 => Some(Interval::M1),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "yfinance")]
+fn yf_source_intv(intv: Intv) -> Intv {
+    match intv {
+        Intv::Min3 => Intv::Min1,
+        Intv::Hour2 | Intv::Hour4 | Intv::Hour6 | Intv::Hour8 | Intv::Hour12 => Intv::Hour1,
+        Intv::Day3 => Intv::Day1,
+        i => i.base(),
+    }
+}
+
+///How far back Yahoo serves intraday data
+#[cfg(feature = "yfinance")]
+fn yf_max_lookback(intv: Intv) -> Option<chrono::TimeDelta> {
+    //NOTE a day less than the documented limits, requests right at the limit get rejected
+    match intv {
+        Intv::Min1 => Some(chrono::TimeDelta::days(6)),
+        Intv::Min5 | Intv::Min15 | Intv::Min30 => Some(chrono::TimeDelta::days(59)),
+        Intv::Hour1 => Some(chrono::TimeDelta::days(729)),
+        _ => None,
+    }
+}
+
+///Downloads every interval Yahoo has into the asset DB and resamples the rest from them.
+///Intraday intervals only go back as far as Yahoo serves them.
+#[cfg(feature = "yfinance")]
+async fn yahoo_asset_dl(
+    source: &YahooSource,
+    pool: &Pool<Sqlite>,
+    symbol: &str,
+    start_time: i64,
+    end_time: i64,
+    job: &DlJob,
+) -> Result<usize> {
+    let mut stored = 0;
+    let intvs: Vec<Intv> = Intv::iter().filter(|i| yfintv_conv(*i).is_some()).collect();
+    job.add_chunks(intvs.len() as u32);
+    for intv in intvs {
+        job.checkpoint().await?;
+        let start = match yf_max_lookback(intv) {
+            Some(d) => start_time.max(end_time - d.num_milliseconds()),
+            None => start_time,
+        };
+        if start >= end_time {
+            job.chunk_done(intv);
+            continue;
+        };
+        let kline = match source.fetch_klines(symbol, (start, end_time), intv).await {
+            Ok(k) => k,
+            Err(e) => {
+                tracing::error![
+                    "Yahoo {} {} download failed: {:?}",
+                    symbol,
+                    intv.to_str(),
+                    e
+                ];
+                job.chunk_failed(intv, &e);
+                continue;
+            }
+        };
+        for chunk in kline.kline.chunks(APPEND_KLINE_MAX_ROWS) {
+            append_kline(pool, &intv.table()?, chunk).await?;
+        }
+        stored += kline.kline.len();
+        job.chunk_done(intv);
+    }
+    if stored == 0 {
+        return Err(anyhow!["No data downloaded from Yahoo for {}", symbol]);
+    };
+    //NOTE resampled from the DB so candles the update starts in are complete
+    for intv in Intv::iter().filter(|i| yfintv_conv(*i).is_none()) {
+        let source = yf_source_intv(intv);
+        let t = Some((intv.bucket_start(start_time), end_time));
+        let base = kfrom_sql(pool, &source.table()?, t).await?;
+        let kline = resample_kline(&base.kline, &intv);
+        append_thin_kline(pool, &intv, &kline.kline).await?;
+    }
+    Ok(stored)
+}
+
+///Stores OHLCV only candles, the columns Yahoo doesn't have are 0
+#[cfg(feature = "yfinance")]
+async fn append_thin_kline(pool: &Pool<Sqlite>, intv: &Intv, kline: &[KlineRow]) -> Result<usize> {
+    let rows = thin_to_fat(intv, kline);
+    for chunk in rows.chunks(APPEND_KLINE_MAX_ROWS) {
+        append_kline(pool, &intv.table()?, chunk).await?;
+    }
+    Ok(rows.len())
+}
+
+///Fills in the kline table layout for OHLCV only candles, the missing columns are 0
+pub fn thin_to_fat(intv: &Intv, kline: &[KlineRow]) -> Vec<FatKlineRow> {
+    kline
+        .iter()
+        .map(|(open_time, o, h, l, c, v)| {
+            let t = open_time.timestamp_millis();
+            //NOTE Yahoo candles open when the market does, not at midnight
+            let close_t = match intv {
+                Intv::Month1 => intv.next_open(t) - 1,
+                _ => t + intv.to_ms() - 1,
+            };
+            let close_time = DateTime::<Utc>::from_timestamp_millis(close_t).unwrap_or(*open_time);
+            (
+                t, *open_time, *o, *h, *l, *c, *v, close_t, close_time, 0.0, 0, 0.0, 0.0,
+            )
+        })
+        .collect()
+}
+
+///Symbol exists if Yahoo has daily candles for it in the last month
+#[cfg(feature = "yfinance")]
+async fn validate_asset_yahoo(client: &YfClient, symbol: &str) -> Result<bool> {
+    let res = HistoryBuilder::new(client, symbol)
+        .interval(Interval::D1)
+        .range(Range::M1)
+        .actions(false)
+        .fetch()
+        .await;
+    match res {
+        Ok(k) => Ok(!k.is_empty()),
+        Err(YfError::Status { status: 404, .. }) => Ok(false),
+        Err(YfError::Data(e)) if e.contains("Not Found") => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
 pub async fn exec_query(pool: &Pool<Sqlite>, q: &str) -> Result<()> {
     let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(q);
     let query = query_builder.build();
@@ -99,6 +267,7 @@ pub async fn connect_sqlite<P: AsRef<Path>>(filename: P) -> Result<Pool<Sqlite>>
     Ok(pool)
 }
 #[instrument(level = "trace")]
+///Candles ordered by open time, AssetData's slice lookups rely on it
 pub async fn kfrom_sql(pool: &Pool<Sqlite>, intv: &str, t: Option<(i64, i64)>) -> Result<Kline> {
     let q: &str = match t {
         None => {
@@ -106,7 +275,7 @@ pub async fn kfrom_sql(pool: &Pool<Sqlite>, intv: &str, t: Option<(i64, i64)>) -
             &format!(
                 "
                 BEGIN TRANSACTION;
-                SELECT [Open Time], Open, High, Low, Close, Volume  FROM {};
+                SELECT [Open Time], Open, High, Low, Close, Volume  FROM {} ORDER BY [Timestamp MS];
                 COMMIT;
                 ",
                 intv
@@ -117,19 +286,199 @@ pub async fn kfrom_sql(pool: &Pool<Sqlite>, intv: &str, t: Option<(i64, i64)>) -
             &format!(
                 "
                 BEGIN TRANSACTION;
-                SELECT [Open Time], Open, High, Low, Close, Volume  FROM '{}' WHERE [Timestamp ms] BETWEEN '{}' AND '{}';
+                SELECT [Open Time], Open, High, Low, Close, Volume  FROM '{}' WHERE [Timestamp ms] BETWEEN '{}' AND '{}' ORDER BY [Timestamp MS];
                 COMMIT;
                 ",
                 intv, ts, te,
             )
         }
     };
-    let k: Vec<(DateTime<Utc>, f64, f64, f64, f64, f64)> =
-        sqlx::query_as(q).fetch_all(pool).await?;
+    let k: Vec<KlineRow> = sqlx::query_as(q).fetch_all(pool).await?;
     let kline = Kline::new_sql(k);
     Ok(kline)
 }
 
+///Loads intv from its table, custom intervals are resampled from their base table and include
+///every candle overlapping the range, the last one can be partial
+pub async fn kfrom_sql_intv(
+    pool: &Pool<Sqlite>,
+    intv: &Intv,
+    t: Option<(i64, i64)>,
+) -> Result<Kline> {
+    match intv {
+        Intv::Custom(_) => {
+            let t = t.map(|(ts, te)| (intv.bucket_start(ts), intv.next_open(te) - 1));
+            let base = kfrom_sql(pool, &intv.base().table()?, t).await?;
+            Ok(resample_kline(&base.kline, intv))
+        }
+        _ => kfrom_sql(pool, &intv.table()?, t).await,
+    }
+}
+
+///Resamples finer candles into intv, candles open at Intv::bucket_start
+pub fn resample_kline(rows: &[KlineRow], intv: &Intv) -> Kline {
+    let mut sorted;
+    let rows = if rows.is_sorted_by_key(|r| r.0) {
+        rows
+    } else {
+        sorted = rows.to_vec();
+        sorted.sort_by_key(|r| r.0);
+        &sorted[..]
+    };
+    let mut out: Vec<KlineRow> = vec![];
+    for row in rows.iter() {
+        let bucket = intv.bucket_start(row.0.timestamp_millis());
+        match out.last_mut() {
+            Some(cur) if cur.0.timestamp_millis() == bucket => {
+                cur.2 = cur.2.max(row.2);
+                cur.3 = cur.3.min(row.3);
+                cur.4 = row.4;
+                cur.5 += row.5;
+            }
+            _ => {
+                let open_t = DateTime::<Utc>::from_timestamp_millis(bucket).unwrap_or(row.0);
+                out.push((open_t, row.1, row.2, row.3, row.4, row.5));
+            }
+        };
+    }
+    Kline::new_sql(out)
+}
+
+///Same as kfrom_sql but loads every column of the kline table
+pub async fn fat_kfrom_sql(
+    pool: &Pool<Sqlite>,
+    intv: &str,
+    t: Option<(i64, i64)>,
+) -> Result<FatKline> {
+    let (ts, te) = t.unwrap_or((i64::MIN, i64::MAX));
+    let q = format![
+        "SELECT [Timestamp MS], [Open Time], Open, High, Low, Close, Volume, [Close Timestamp MS], [Close Time], [Quote Asset Volume], [Number of Trades], [Taker Buy Base Asset Volume], [Taker Buy Quote Asset Volume] FROM '{}' WHERE [Timestamp MS] BETWEEN ? AND ? ORDER BY [Timestamp MS];",
+        intv
+    ];
+    //NOTE SQLite has no u64, the number of trades is read as i64
+    let k: Vec<FatKlineSqlRow> = sqlx::query_as(&q).bind(ts).bind(te).fetch_all(pool).await?;
+    let kline = k
+        .into_iter()
+        .map(|(t, tt, o, h, l, c, v, ctt, ct, qav, no, tbbav, tbqav)| {
+            (t, tt, o, h, l, c, v, ctt, ct, qav, no as u64, tbbav, tbqav)
+        })
+        .collect();
+    Ok(FatKline { kline })
+}
+
+///Same as kfrom_sql_intv but loads every column of the kline table
+pub async fn fat_kfrom_sql_intv(
+    pool: &Pool<Sqlite>,
+    intv: &Intv,
+    t: Option<(i64, i64)>,
+) -> Result<FatKline> {
+    match intv {
+        Intv::Custom(_) => {
+            let t = t.map(|(ts, te)| (intv.bucket_start(ts), intv.next_open(te) - 1));
+            let base = fat_kfrom_sql(pool, &intv.base().table()?, t).await?;
+            let mut agg = FatAggregator::new(*intv);
+            let mut kline: Vec<_> = base.kline.iter().filter_map(|r| agg.push(r)).collect();
+            kline.extend(agg.finish());
+            Ok(FatKline {
+                kline: kline.into_iter().map(|(k, _)| k).collect(),
+            })
+        }
+        _ => fat_kfrom_sql(pool, &intv.table()?, t).await,
+    }
+}
+
+#[derive(
+    EnumIter, Debug, Clone, Copy, PartialEq, Eq, Default, Decode, Encode, Serialize, Deserialize,
+)]
+pub enum ExportFormat {
+    #[default]
+    Parquet,
+    ArrowIpc,
+}
+impl ExportFormat {
+    pub fn to_str(&self) -> &str {
+        match &self {
+            ExportFormat::Parquet => "Parquet",
+            ExportFormat::ArrowIpc => "Arrow IPC",
+        }
+    }
+    pub fn extension(&self) -> &str {
+        match &self {
+            ExportFormat::Parquet => "parquet",
+            ExportFormat::ArrowIpc => "arrow",
+        }
+    }
+    pub fn parse(input: &str) -> Result<Self> {
+        match input.to_lowercase().as_str() {
+            "parquet" => Ok(ExportFormat::Parquet),
+            "arrow" | "ipc" | "feather" => Ok(ExportFormat::ArrowIpc),
+            _ => Err(anyhow!["Invalid export format: {}", input]),
+        }
+    }
+}
+
+fn fat_kline_batch(kline: &FatKline) -> Result<RecordBatch> {
+    let ts_type = DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into()));
+    let schema = Schema::new(vec![
+        Field::new("open_time", ts_type.clone(), false),
+        Field::new("open", DataType::Float64, false),
+        Field::new("high", DataType::Float64, false),
+        Field::new("low", DataType::Float64, false),
+        Field::new("close", DataType::Float64, false),
+        Field::new("volume", DataType::Float64, false),
+        Field::new("close_time", ts_type, false),
+        Field::new("quote_volume", DataType::Float64, false),
+        Field::new("trades", DataType::UInt64, false),
+        Field::new("taker_buy_base_volume", DataType::Float64, false),
+        Field::new("taker_buy_quote_volume", DataType::Float64, false),
+    ]);
+    let k = &kline.kline;
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(
+            TimestampMillisecondArray::from_iter_values(k.iter().map(|r| r.0)).with_timezone("UTC"),
+        ),
+        Arc::new(Float64Array::from_iter_values(k.iter().map(|r| r.2))),
+        Arc::new(Float64Array::from_iter_values(k.iter().map(|r| r.3))),
+        Arc::new(Float64Array::from_iter_values(k.iter().map(|r| r.4))),
+        Arc::new(Float64Array::from_iter_values(k.iter().map(|r| r.5))),
+        Arc::new(Float64Array::from_iter_values(k.iter().map(|r| r.6))),
+        Arc::new(
+            TimestampMillisecondArray::from_iter_values(k.iter().map(|r| r.7)).with_timezone("UTC"),
+        ),
+        Arc::new(Float64Array::from_iter_values(k.iter().map(|r| r.9))),
+        Arc::new(UInt64Array::from_iter_values(k.iter().map(|r| r.10))),
+        Arc::new(Float64Array::from_iter_values(k.iter().map(|r| r.11))),
+        Arc::new(Float64Array::from_iter_values(k.iter().map(|r| r.12))),
+    ];
+    Ok(RecordBatch::try_new(Arc::new(schema), columns)?)
+}
+
+///Writes all kline columns as Parquet or Arrow IPC with snake_case column names and UTC ms
+///timestamps, returns the number of rows written
+pub fn write_kline_columnar<W: Write + Send>(
+    kline: &FatKline,
+    format: &ExportFormat,
+    out: W,
+) -> Result<usize> {
+    let batch = fat_kline_batch(kline)?;
+    match format {
+        ExportFormat::Parquet => {
+            let props = WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .build();
+            let mut writer = ArrowWriter::try_new(out, batch.schema(), Some(props))?;
+            writer.write(&batch)?;
+            writer.close()?;
+        }
+        ExportFormat::ArrowIpc => {
+            let mut writer = FileWriter::try_new(out, &batch.schema())?;
+            writer.write(&batch)?;
+            writer.finish()?;
+        }
+    };
+    Ok(batch.num_rows())
+}
+
 pub async fn kfrom_sql_wcheck(
     symbol: &str,
     meta_pool: &Pool<Sqlite>,
@@ -137,6 +486,7 @@ pub async fn kfrom_sql_wcheck(
     intv: &Intv,
     trade_time: i64,
     no_wicks: usize,
+    cache: &Mutex<KlineCache>,
 ) -> Result<Option<Kline>> {
     let q = &format![
         "SELECT [Start time], [End time] FROM assets_dl WHERE Asset = '{}';",
@@ -157,12 +507,8 @@ pub async fn kfrom_sql_wcheck(
             tracing::trace!["kform_sql_wcheck fetching kline"];
             let bb = trade_time - intv.to_ms() * (no_wicks as i64);
             let back_time = if bb > 0 { bb } else { 0 };
-            let kline = kfrom_sql(
-                pool,
-                &format!["kline_{}", &intv.to_str()],
-                Some((back_time, trade_time)),
-            )
-            .await?;
+            let kline =
+                KlineCache::load_range(cache, pool, symbol, intv, back_time, trade_time).await?;
             return Ok(Some(kline));
         } else {
             tracing::trace!["kform_sql_wcheck time_outside_fetch_range"];
@@ -173,25 +519,7 @@ pub async fn kfrom_sql_wcheck(
     }
 }
 
-async fn append_kline(
-    pool: &Pool<Sqlite>,
-    table: &str,
-    input: &[(
-        i64,
-        DateTime<Utc>,
-        f64,
-        f64,
-        f64,
-        f64,
-        f64,
-        i64,
-        DateTime<Utc>,
-        f64,
-        u64,
-        f64,
-        f64,
-    )],
-) -> Result<()> {
+async fn append_kline(pool: &Pool<Sqlite>, table: &str, input: &[FatKlineRow]) -> Result<()> {
     let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(format!["
         INSERT OR REPLACE INTO {} ( [Timestamp MS], [Open Time], Open, High, Low, Close, Volume, [Close Timestamp MS], [Close Time], [Quote Asset Volume], [Number of Trades], [Taker Buy Base Asset Volume], [Taker Buy Quote Asset Volume] )
         ",
@@ -221,7 +549,20 @@ async fn append_kline(
     Ok(())
 }
 
-#[derive(EnumIter, Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Decode, Encode)]
+#[derive(
+    EnumIter,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Default,
+    Decode,
+    Encode,
+    Serialize,
+    Deserialize,
+)]
 pub enum Intv {
     #[default]
     Min1,
@@ -239,6 +580,10 @@ pub enum Intv {
     Day3,
     Week1,
     Month1,
+    ///Minutes, resampled from the largest stored interval that divides it, candles are aligned
+    ///to the unix epoch. Never stored and skipped by Intv::iter()
+    #[strum(disabled)]
+    Custom(u32),
 }
 impl Intv {
     pub fn from_str(input: &str) -> Self {
@@ -261,6 +606,7 @@ impl Intv {
             _ => panic!("Invalid interval string, Intv::from_str()"),
         }
     }
+    ///Name of a stored interval, use table() for queries and label() for custom intervals
     pub fn to_str(&self) -> &str {
         match &self {
             Intv::Min1 => "1min",
@@ -278,6 +624,18 @@ impl Intv {
             Intv::Day3 => "3day",
             Intv::Week1 => "1week",
             Intv::Month1 => "1month",
+            Intv::Custom(_) => "custom",
+        }
+    }
+    ///Kline table of a stored interval, custom intervals have none and are loaded with
+    ///kfrom_sql_intv
+    pub fn table(&self) -> Result<String> {
+        match &self {
+            Intv::Custom(_) => Err(anyhow![
+                "{} is resampled on load and has no kline table",
+                self.label()
+            ]),
+            _ => Ok(format!["kline_{}", self.to_str()]),
         }
     }
     pub fn to_bin_str(&self) -> &str {
@@ -297,6 +655,7 @@ impl Intv {
             Intv::Day3 => "3d",
             Intv::Week1 => "1w",
             Intv::Month1 => "1M",
+            Intv::Custom(_) => "custom",
         }
     }
     pub fn from_bin_str(input: &str) -> Self {
@@ -319,6 +678,63 @@ impl Intv {
             _ => panic!("Invalid string parsed Intv::from_bin_str()"),
         }
     }
+    ///Non panicking parse, accepts both the table ("15min") and the Binance ("15m") names, any
+    ///other number of minutes, hours, days or weeks ("10m", "90min", "2D") is a custom interval
+    pub fn parse(input: &str) -> Result<Self> {
+        if let Some(i) = Intv::iter().find(|i| i.to_str() == input || i.to_bin_str() == input) {
+            return Ok(i);
+        };
+        let split = input
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or(anyhow!["Interval without unit: {}", input])?;
+        let (n, unit) = input.split_at(split);
+        let n: u32 = n
+            .parse()
+            .map_err(|_| anyhow!["Invalid interval string: {}", input])?;
+        let mult = match unit {
+            "m" | "min" => 1,
+            "h" | "H" | "hour" => 60,
+            "d" | "D" | "day" => 60 * 24,
+            "w" | "W" | "week" => 60 * 24 * 7,
+            _ => return Err(anyhow!["Invalid interval unit: {}", input]),
+        };
+        let minutes = n
+            .checked_mul(mult)
+            .filter(|m| *m > 0)
+            .ok_or(anyhow!["Invalid interval length: {}", input])?;
+        Ok(Intv::from_min(minutes))
+    }
+    ///Stored interval of that length or a custom one
+    pub fn from_min(minutes: u32) -> Self {
+        Intv::iter()
+            .filter(|i| *i != Intv::Month1)
+            .find(|i| i.to_min() == minutes as u64)
+            .unwrap_or(Intv::Custom(minutes))
+    }
+    pub fn is_custom(&self) -> bool {
+        matches!(self, Intv::Custom(_))
+    }
+    ///Stored interval custom intervals are resampled from
+    pub fn base(&self) -> Intv {
+        match &self {
+            //NOTE only intervals aligned to the epoch, Binance weeks start on Monday
+            Intv::Custom(m) => Intv::iter()
+                .filter(|i| i.to_min() <= Intv::Day1.to_min())
+                .rfind(|i| (*m as u64).is_multiple_of(i.to_min()))
+                .unwrap_or(Intv::Min1),
+            _ => *self,
+        }
+    }
+    ///Display name, the table name for stored intervals
+    pub fn label(&self) -> String {
+        match &self {
+            Intv::Custom(m) if m % (60 * 24 * 7) == 0 => format!["{}w", m / (60 * 24 * 7)],
+            Intv::Custom(m) if m % (60 * 24) == 0 => format!["{}D", m / (60 * 24)],
+            Intv::Custom(m) if m % 60 == 0 => format!["{}h", m / 60],
+            Intv::Custom(m) => format!["{}m", m],
+            _ => self.to_str().to_string(),
+        }
+    }
     pub fn to_timedelta(&self) -> chrono::TimeDelta {
         match &self {
             Intv::Min1 => chrono::TimeDelta::minutes(1),
@@ -336,6 +752,7 @@ impl Intv {
             Intv::Day3 => chrono::TimeDelta::days(3),
             Intv::Week1 => chrono::TimeDelta::weeks(1),
             Intv::Month1 => self.month_to_timedelta(),
+            Intv::Custom(m) => chrono::TimeDelta::minutes(*m as i64),
         }
     }
     pub fn to_view_window(&self) -> usize {
@@ -355,6 +772,9 @@ impl Intv {
             Intv::Day3 => 300,
             Intv::Week1 => 300,
             Intv::Month1 => 300,
+            Intv::Custom(m) if *m <= 15 => 1440,
+            Intv::Custom(m) if *m < 60 * 24 => 775,
+            Intv::Custom(_) => 300,
         }
     }
     pub fn month_to_timedelta(&self) -> chrono::TimeDelta {
@@ -401,11 +821,43 @@ impl Intv {
             Intv::Day3 => 3 * 60 * 60 * 24,
             Intv::Week1 => 1 * 60 * 60 * 24 * 7,
             Intv::Month1 => 1 * 60 * 60 * 24 * 30, //NOTE this is a hack... but it works for now
+            Intv::Custom(m) => *m as i64 * 60,
         }
     }
     pub fn to_ms(&self) -> i64 {
         &self.to_sec() * 1000 as i64
     }
+    ///Open time of the candle containing t_ms, Binance weeks start on Monday and months on the 1st
+    pub fn bucket_start(&self, t_ms: i64) -> i64 {
+        const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+        match &self {
+            //NOTE 1970-01-05 was the first Monday
+            Intv::Week1 => t_ms - (t_ms - 4 * DAY_MS).rem_euclid(7 * DAY_MS),
+            Intv::Month1 => match DateTime::<Utc>::from_timestamp_millis(t_ms) {
+                Some(t) => Utc
+                    .with_ymd_and_hms(t.year(), t.month(), 1, 0, 0, 0)
+                    .single()
+                    .map_or(t_ms, |t| t.timestamp_millis()),
+                None => t_ms,
+            },
+            _ => t_ms - t_ms.rem_euclid(self.to_ms()),
+        }
+    }
+    ///Open time of the candle after the one containing t_ms
+    pub fn next_open(&self, t_ms: i64) -> i64 {
+        match &self {
+            Intv::Month1 => {
+                let start = self.bucket_start(t_ms);
+                match DateTime::<Utc>::from_timestamp_millis(start)
+                    .and_then(|t| t.checked_add_months(chrono::Months::new(1)))
+                {
+                    Some(t) => t.timestamp_millis(),
+                    None => start + self.to_ms(),
+                }
+            }
+            _ => self.bucket_start(t_ms) + self.to_ms(),
+        }
+    }
     pub fn to_min(&self) -> u64 {
         match &self {
             Intv::Min1 => 1,
@@ -423,28 +875,60 @@ impl Intv {
             Intv::Day3 => 3 * 60 * 24,
             Intv::Week1 => 1 * 60 * 24 * 7,
             Intv::Month1 => 1 * 60 * 24 * 30,
+            Intv::Custom(m) => *m as u64,
         }
     }
 }
 
+///One funding event of a USDⓈ-M perpetual, longs pay shorts rate * mark price * qty when the
+///rate is positive
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct FundingRate {
+    pub time: i64,
+    pub rate: f64,
+    pub mark_price: Option<f64>,
+}
+
+///(open time, o, h, l, c, volume)
+pub type KlineRow = (DateTime<Utc>, f64, f64, f64, f64, f64);
+///(open time ms, open time, o, h, l, c, volume, close time ms, close time, quote volume, trades,
+///taker buy base volume, taker buy quote volume)
+pub type FatKlineRow = (
+    i64,
+    DateTime<Utc>,
+    f64,
+    f64,
+    f64,
+    f64,
+    f64,
+    i64,
+    DateTime<Utc>,
+    f64,
+    u64,
+    f64,
+    f64,
+);
+
+type FatKlineSqlRow = (
+    i64,
+    DateTime<Utc>,
+    f64,
+    f64,
+    f64,
+    f64,
+    f64,
+    i64,
+    DateTime<Utc>,
+    f64,
+    i64,
+    f64,
+    f64,
+);
+
 #[derive(Serialize, Deserialize)]
 pub struct FatKline {
     //(time o h l c volume,)
-    pub kline: Vec<(
-        i64,
-        DateTime<Utc>,
-        f64,
-        f64,
-        f64,
-        f64,
-        f64,
-        i64,
-        DateTime<Utc>,
-        f64,
-        u64,
-        f64,
-        f64,
-    )>,
+    pub kline: Vec<FatKlineRow>,
 }
 impl std::fmt::Display for FatKline {
     fn fmt(
@@ -466,28 +950,27 @@ impl std::fmt::Debug for FatKline {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Kline {
     //NOTE (time o h l c volume)
-    pub kline: Vec<(DateTime<Utc>, f64, f64, f64, f64, f64)>,
+    pub kline: Vec<KlineRow>,
 }
 impl Kline {
-    pub fn new_sql(k: Vec<(DateTime<Utc>, f64, f64, f64, f64, f64)>) -> Self {
+    pub fn new_sql(k: Vec<KlineRow>) -> Self {
         Self { kline: k }
     }
-    pub fn split(&self, no_splits: usize) -> Vec<&[(DateTime<Utc>, f64, f64, f64, f64, f64)]> {
+    pub fn split(&self, no_splits: usize) -> Vec<&[KlineRow]> {
         self.kline.chunks(no_splits).collect()
     }
 }
 
 #[derive(Debug, Default)]
 pub struct Klines {
-    start_time: DateTime<Utc>,
-    end_time: DateTime<Utc>,
-
     pub asset_pair: String,
     pub s1_string: String,
     pub s2_string: String,
 
     full_dat: HashMap<Intv, FatKline>,
     pub dat: HashMap<Intv, Kline>,
+    ///Funding of a perpetual sorted by time, empty for spot assets
+    pub funding: Vec<FundingRate>,
 }
 impl Klines {
     pub fn new_empty() -> Self {
@@ -509,8 +992,17 @@ pub struct DLAsset {
     pub exchange: String,
     pub dat_start_t: i64,
     pub dat_end_t: i64,
+    ///Number of chunks that failed to download, the asset is incomplete until they are retried
+    pub missing_chunks: usize,
+    ///Time of the last update that finished without errors, 0 if there wasn't one
+    pub last_update: i64,
+    pub update_status: String,
 }
 
+///Symbols whose klines are kept in the historical AssetData, loading another one drops the least
+///recently loaded
+pub const LOADED_SYMBOLS_MAX: usize = 8;
+
 #[derive(Debug, Default)]
 pub struct AssetData {
     pub id: usize,
@@ -519,6 +1011,20 @@ pub struct AssetData {
     pub downloaded_assets: Vec<DLAsset>,
     pub temp_kline: Option<Klines>,
     pub load_status: HashMap<String, bool>,
+    pub dl_status: String,
+    pub audit_reports: HashMap<String, AuditReport>,
+    ///Scheduled update interval in minutes, set by the SQL task
+    pub update_schedule: Option<u64>,
+    ///Memory used by the SQL task's kline cache
+    pub cache_usage: CacheUsage,
+    ///Column statistics of the downloaded assets that were loaded or updated
+    pub col_stats: HashMap<String, Vec<ColStat>>,
+    ///Results of the last pattern search, most similar first
+    pub pattern_matches: Vec<PatternMatch>,
+    ///Downloads of the SQL task, polled by the Data Manager
+    pub dl_jobs: DlJobs,
+    ///Symbols of kline_data inserted with insert_loaded, least recently loaded first
+    pub load_order: Vec<String>,
 
     //NOTE live only
     pub live_asset_symbol_changed: (bool, String),
@@ -548,12 +1054,20 @@ impl AssetData {
             ..Default::default()
         }
     }
+    ///Replaces the klines of symbol, symbols over LOADED_SYMBOLS_MAX are dropped least recently
+    ///loaded first
+    pub fn insert_loaded(&mut self, symbol: &str, klines: Klines) {
+        self.load_order.retain(|s| s != symbol);
+        self.load_order.push(symbol.to_string());
+        while self.load_order.len() > LOADED_SYMBOLS_MAX {
+            let old = self.load_order.remove(0);
+            tracing::debug!["Dropping the loaded klines of {}", old];
+            self.kline_data.remove(&old);
+        }
+        self.kline_data.insert(symbol.to_string(), klines);
+    }
     //#[instrument(level="trace")]
-    pub fn load_full_intv(
-        &self,
-        symbol: &str,
-        intv: &Intv,
-    ) -> Result<&[(DateTime<Utc>, f64, f64, f64, f64, f64)]> {
+    pub fn load_full_intv(&self, symbol: &str, intv: &Intv) -> Result<&[KlineRow]> {
         let klines = self
             .kline_data
             .get(symbol)
@@ -561,7 +1075,7 @@ impl AssetData {
         let kline = klines.dat.get(intv).ok_or(anyhow![
             "Unable to find data for: {}, interval: {}",
             symbol,
-            intv.to_str()
+            intv.label()
         ])?;
         Ok(&kline.kline)
     }
@@ -575,99 +1089,68 @@ impl AssetData {
             })
             .collect();
     }
-    //#[instrument(level="trace")]
+    ///next_wicks candles from the first one opening at or after start_time, fewer if the data
+    ///ends before. Gaps are skipped, candles are counted and not time.
     pub fn find_slice_n(
         &self,
         symbol: &str,
         intv: &Intv,
         start_time: &i64,
         next_wicks: u16,
-    ) -> Option<&[(DateTime<Utc>, f64, f64, f64, f64, f64)]> {
-        let klines = self.kline_data.get(symbol);
-        let kk = match klines {
-            Some(k) => k,
-            None => {
-                tracing::error!["Find slice error: out of bounds!"];
-                return None;
-            }
-        };
-        let kline = kk.dat.get(intv);
-        let kl = match kline {
-            Some(ki) => ki,
-            None => {
-                tracing::error!["Kline not found for interval!"];
-                return None;
-            }
+    ) -> Result<&[KlineRow]> {
+        let kline = self.load_full_intv(symbol, intv)?;
+        let (Some(first), Some(last)) = (kline.first(), kline.last()) else {
+            return Err(anyhow!["No {} data for {}", intv.label(), symbol]);
         };
-        let step: i64 = intv.to_ms();
-        let end_time = step * (next_wicks as i64) + start_time;
-
-        assert!(
-            end_time > *start_time,
-            "Start time must be greater than end time!"
-        );
-        let (start_index, end_index): (usize, usize) = if kl.kline.is_empty() == false {
-            //NOTE this is to avoid using let index = vec.iter().position(|&r| r == "n").unwrap();
-            let kline_start_t = kl.kline[0].0.timestamp_millis();
-            let kline_end_t = kl.kline[kl.kline.len() - 1].0.timestamp_millis();
-            let kline_length = kl.kline.len();
-
-            let si = (start_time / (kline_end_t - kline_start_t)) as usize;
-            let start_index: usize = si * kline_length;
-            let confirm: i64 = kl.kline[start_index].0.timestamp_millis();
-            let start_index = if confirm as usize != start_index {
-                let ss = if start_index < confirm as usize {
-                    let s = start_index + 1;
-                    s
-                } else {
-                    let s = start_index - 1;
-                    s
-                };
-                ss
-            } else {
-                start_index
-            };
-            let end_index = start_index + (next_wicks as usize);
-            (start_index, end_index)
-        } else {
-            tracing::error!["Kline empty!"];
-            return None;
+        let (first, last) = (first.0.timestamp_millis(), last.0.timestamp_millis());
+        if *start_time < first || *start_time > last {
+            return Err(anyhow![
+                "Start time {} outside of the {} {} data ({} to {})",
+                start_time,
+                symbol,
+                intv.label(),
+                first,
+                last
+            ]);
         };
-
-        return Some(&kl.kline[start_index..end_index]);
+        let start_index = kline.partition_point(|k| k.0.timestamp_millis() < *start_time);
+        let end_index = (start_index + next_wicks as usize).min(kline.len());
+        Ok(&kline[start_index..end_index])
     }
+    ///Candles with open times between start_time and end_time (inclusive), a range in a gap
+    ///gives an empty slice
     pub fn find_slice(
         &self,
         symbol: &str,
         intv: &Intv,
         start_time: &DateTime<Utc>,
         end_time: &DateTime<Utc>,
-    ) -> Option<&[(DateTime<Utc>, f64, f64, f64, f64, f64)]> {
-        let klines = self.kline_data.get(symbol);
-        let kk = match klines {
-            Some(kk) => kk,
-            None => return None,
+    ) -> Result<&[KlineRow]> {
+        if start_time > end_time {
+            return Err(anyhow![
+                "Start time {} after end time {}",
+                start_time,
+                end_time
+            ]);
         };
-        if start_time < &kk.start_time {
-            return None;
-        }
-        if end_time > &kk.end_time {
-            return None;
-        }
-        let kline = kk.dat.get(intv);
-        let k = match kline {
-            Some(k) => k,
-            None => return None,
+        let kline = self.load_full_intv(symbol, intv)?;
+        let (Some(first), Some(last)) = (kline.first(), kline.last()) else {
+            return Err(anyhow!["No {} data for {}", intv.label(), symbol]);
         };
-        let step: i64 = intv.to_sec();
-        let kst = kk.start_time.timestamp();
-        let ket = kk.end_time.timestamp();
-        let dur = (kst - ket) / step;
-        let start_t = start_time.timestamp();
-        let end_t = end_time.timestamp();
-        let start_index = ((kst - start_t) / dur) as usize;
-        let end_index = ((kst - end_t) / dur) as usize;
-        return Some(&k.kline[start_index..end_index]);
+        if *end_time < first.0 || *start_time > last.0 {
+            return Err(anyhow![
+                "{} to {} outside of the {} {} data ({} to {})",
+                start_time,
+                end_time,
+                symbol,
+                intv.label(),
+                first.0,
+                last.0
+            ]);
+        };
+        let start_index = kline.partition_point(|k| k.0 < *start_time);
+        let end_index = kline.partition_point(|k| k.0 <= *end_time);
+        Ok(&kline[start_index..end_index])
     }
     #[instrument(level = "trace")]
     pub fn find_slice_index(
@@ -676,7 +1159,7 @@ impl AssetData {
         intv: &Intv,
         start_index: usize,
         end_index: usize,
-    ) -> Option<&[(DateTime<Utc>, f64, f64, f64, f64, f64)]> {
+    ) -> Option<&[KlineRow]> {
         //TODO fix boilerplate maybe
         let klines = self.kline_data.get(symbol);
         let kk = match klines {
@@ -691,74 +1174,59 @@ impl AssetData {
         return Some(&k.kline[start_index..end_index]);
     }
     #[instrument(level = "trace")]
-    pub async fn load_all(&mut self, symbol: &str, sql_pool: &Pool<Sqlite>) -> Result<()> {
+    pub async fn load_all(
+        &mut self,
+        symbol: &str,
+        sql_pool: &Pool<Sqlite>,
+        cache: &Mutex<KlineCache>,
+    ) -> Result<()> {
         let _search = self
             .kline_data
             .get(symbol)
             .ok_or(anyhow!["Unable to find data for symbol {}", symbol])?;
-        let mut klines = Klines::new_empty();
-        for intv in Intv::iter() {
-            let s: &str = intv.to_str();
-            tracing::trace!("Loading data for:{} interval:{}", symbol, s);
-            let k: Kline = kfrom_sql(&sql_pool, &format!["kline_{}", &s], None).await?;
-            klines.dat.insert(intv, k);
-        }
-        self.kline_data.insert(symbol.to_string(), klines);
+        tracing::trace!("Loading all data for:{}", symbol);
+        let klines = load_latest_cached(cache, sql_pool, symbol, CACHE_CHUNK_CANDLES).await?;
+        self.insert_loaded(symbol, klines);
         Ok(())
     }
 }
-fn kline_conv(
-    input: &KlineSummary,
-) -> Result<(
-    i64,
-    DateTime<Utc>,
-    f64,
-    f64,
-    f64,
-    f64,
-    f64,
-    i64,
-    DateTime<Utc>,
-    f64,
-    u64,
-    f64,
-    f64,
-)> {
-    let based_time = input.open_time;
-    let time = DateTime::<Utc>::from_timestamp_millis(input.open_time)
-        .ok_or(anyhow!["Unable to parse ctime"])?;
-    let open = input.open;
-    let high = input.high;
-    let low = input.low;
-    let close = input.close;
-    let volume = input.volume;
-    let based_ctime = input.close_time;
-    let ctime = DateTime::<Utc>::from_timestamp_millis(input.close_time)
-        .ok_or(anyhow!["Unable to parse ctime"])?;
-    let qav = input.quote_asset_volume;
-    let no = input.number_of_trades as u64;
-    let tbbav = input.taker_buy_base_asset_volume;
-    let tbqav = input.taker_buy_quote_asset_volume;
-
-    Ok((
-        based_time,
-        time,
-        open,
-        high,
-        low,
-        close,
-        volume,
-        based_ctime,
-        ctime,
-        qav,
-        no,
-        tbbav,
-        tbqav,
-    ))
-}
-
-async fn single_asset_dl(symbol: &str, start_time: i64) -> Result<()> {
+async fn single_asset_dl<S: DataSource + Clone + 'static>(
+    source: &S,
+    symbol: &str,
+    start_time: i64,
+    intvs: &[Intv],
+    job: &DlJob,
+) -> Result<()> {
     let end_time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+    let db_path = asset_db_path(symbol);
+    let errors = download_chunks(source, &db_path, symbol, start_time, end_time, intvs, job).await;
+    if !errors.is_empty() {
+        //NOTE a cancelled job skips the retries, the chunks it didn't get to are saved below
+        let errors = match job.is_cancelled() {
+            true => errors,
+            false => retry_chunks(source, &db_path, symbol, errors, job).await?,
+        };
+        if errors.is_empty() {
+            return Ok(());
+        };
+        //NOTE keep the failed chunks so the next update/app start can retry them
+        RemErrors::merge_saved(symbol, errors).await?;
+    };
+    job.checkpoint().await
+}
+
+///Downloads intvs between start_time and end_time into the asset DB at db_path in
+///SINGLE_ASSET_DL_TASKS_MAX parallel tasks, returns the chunks that failed. Progress goes to
+///job, chunks left when it's cancelled count as failed.
+pub async fn download_chunks<S: DataSource + Clone + 'static>(
+    source: &S,
+    db_path: &str,
+    symbol: &str,
+    start_time: i64,
+    end_time: i64,
+    intvs: &[Intv],
+    job: &DlJob,
+) -> Vec<(Intv, Vec<(u64, u64)>)> {
     tracing::debug![
         "single_asset_dl {}",
         DateTime::<Utc>::from_timestamp_millis(start_time).unwrap()
@@ -792,66 +1260,49 @@ async fn single_asset_dl(symbol: &str, start_time: i64) -> Result<()> {
             })
             .collect::<Vec<(DateTime::<Utc>, DateTime::<Utc>)>>()
     ];
-    let mut progress_bar = Progress::new();
-    let no_iterations = Intv::iter().len() * times.len();
-    let mut n: usize = 0;
+    for i in intvs.iter() {
+        for (st, et) in times.iter() {
+            job.add_chunks(source_chunks(source, *i, *st, *et));
+        }
+    }
     let mut errors: Vec<(Intv, Vec<(u64, u64)>)> = vec![];
-    for i in Intv::iter() {
+    for i in intvs.iter().copied() {
         let ss = symbol.to_string();
         let klines_unordered = times
             .iter()
             .map(|(st, et)| {
                 let s = ss.clone();
                 let i = i.clone();
-                let client: Market = Binance::new(None, None);
+                let source = source.clone();
+                let db_path = db_path.to_string();
                 let st = st.clone();
                 let et = et.clone();
-
-                let st_disp = match DateTime::<Utc>::from_timestamp_millis(st) {
-                    Some(s) => s,
-                    None => DateTime::<Utc>::default(),
-                };
-                let et_disp = match DateTime::<Utc>::from_timestamp_millis(et) {
-                    Some(e) => e,
-                    None => DateTime::<Utc>::default(),
-                };
-                let bar: Bar = progress_bar.bar(
-                    no_iterations,
-                    format!(
-                        "Downloading data for {} {} between: {} and: {}: {}/{}",
-                        &s,
-                        &i.to_str(),
-                        st_disp,
-                        et_disp,
-                        n,
-                        no_iterations
-                    ),
-                );
-                let res = tokio::task::spawn(async move {
-                    let res = connect_sqlite(format!["./databases/Asset{}.db", &s])
+                let job = job.clone();
+                tokio::task::spawn(async move {
+                    let res = connect_sqlite(&db_path)
                         .await
                         .context("SQL : unable to connect to asset db");
                     let asset_pool = match res {
                         Ok(ap) => ap,
-                        Err(e) => return Err(e),
+                        Err(e) => return (st, et, Err(e)),
                     };
-                    get_data_binance2(&client, &s, i, &asset_pool, st, et, true).await
-                });
-                progress_bar.inc_and_draw(&bar, n as usize);
-                n += 1;
-                res
+                    let res = get_data_source(&source, &s, i, &asset_pool, st, et, &job).await;
+                    asset_pool.close().await;
+                    (st, et, res)
+                })
             })
             .collect::<FuturesUnordered<_>>();
         let mut ku = pin![klines_unordered];
         while let Some(res) = ku.next().await {
             match res {
-                Ok(res2) => match res2 {
+                Ok((st, et, res2)) => match res2 {
                     Ok(r) => match r {
                         Some(err_vec) => errors.push((i, err_vec)),
                         None => {}
                     },
                     Err(e) => {
                         tracing::error!["Kline download error:{}", e];
+                        errors.push((i, vec![(st as u64, et as u64)]));
                     }
                 },
                 Err(e) => {
@@ -860,51 +1311,82 @@ async fn single_asset_dl(symbol: &str, start_time: i64) -> Result<()> {
             }
         }
     }
-    if !errors.is_empty() {
-        let _e = errors
-            .iter()
-            .map(|(intv, evec)| {
-                let _ = evec
-                    .iter()
-                    .map(|(st, et)| {
-                        tracing::trace![
-                            "Download Errors: Intv: {}  chunk from to {} to {}",
-                            intv.to_str(),
-                            st,
-                            et
-                        ];
-                    })
-                    .collect::<Vec<_>>();
-            })
-            .collect::<Vec<_>>();
-        let mut retries: usize = 0;
-        while retries <= 10 {
-            tracing::debug!["Running iterate over remaining errors"];
-            let res = iterate_over_remaining_errors(errors, symbol).await?;
-            match res {
-                Some(e) => {
-                    tracing::error![
-                        "NO of ERRORS timestamps: {}, retrying ... {}/10",
-                        e.len(),
-                        retries
+    errors
+}
+
+///Retries failed chunks up to 10 times, returns the ones that still failed
+async fn retry_chunks<S: DataSource>(
+    source: &S,
+    db_path: &str,
+    symbol: &str,
+    mut errors: Vec<(Intv, Vec<(u64, u64)>)>,
+    job: &DlJob,
+) -> Result<Vec<(Intv, Vec<(u64, u64)>)>> {
+    let _e = errors
+        .iter()
+        .map(|(intv, evec)| {
+            let _ = evec
+                .iter()
+                .map(|(st, et)| {
+                    tracing::trace![
+                        "Download Errors: Intv: {}  chunk from to {} to {}",
+                        intv.to_str(),
+                        st,
+                        et
                     ];
-                    errors = e;
-                    retries += 1;
-                }
-                None => return Ok(()),
+                })
+                .collect::<Vec<_>>();
+        })
+        .collect::<Vec<_>>();
+    let mut retries: usize = 0;
+    while retries <= 10 && !job.is_cancelled() {
+        tracing::debug!["Running iterate over remaining errors"];
+        let res =
+            iterate_over_remaining_errors(source, db_path, errors.clone(), symbol, job).await?;
+        match res {
+            Some(e) => {
+                tracing::error![
+                    "NO of ERRORS timestamps: {}, retrying ... {}/10",
+                    e.len(),
+                    retries
+                ];
+                errors = e;
+                retries += 1;
             }
+            None => return Ok(vec![]),
         }
-        let rem_errors = RemErrors::new(symbol);
-        rem_errors.save_to_file(&format!["./databases/{}_dl_errors.bin", symbol])?;
-        //FIXME dump missing chunk report as binfile...
+    }
+    Ok(errors)
+}
+
+static DL_ERRORS_LOCKS: OnceLock<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>> =
+    OnceLock::new();
+
+///Locks the dl_errors file of a symbol. Downloads, audit repairs and the retries started on app
+///start run in different tasks, each read-modify-write of the file has to hold this.
+pub async fn lock_dl_errors(symbol: &str) -> tokio::sync::OwnedMutexGuard<()> {
+    let lock = {
+        let mut locks = DL_ERRORS_LOCKS
+            .get_or_init(Default::default)
+            .lock()
+            .expect("Poisoned dl_errors locks mutex");
+        locks.entry(symbol.to_string()).or_default().clone()
     };
-    Ok(())
+    lock.lock_owned().await
+}
+
+pub fn dl_errors_path(symbol: &str) -> String {
+    databases_dir()
+        .join(format!["{}_dl_errors.bin", symbol])
+        .to_string_lossy()
+        .to_string()
 }
 
+///Chunks that still failed after all download retries, persisted per symbol
 #[derive(Debug, Clone, PartialEq, Eq, Default, Decode, Encode)]
-struct RemErrors {
-    symbol: String,
-    errors: Vec<(Intv, Vec<(u64, u64)>)>,
+pub struct RemErrors {
+    pub symbol: String,
+    pub errors: Vec<(Intv, Vec<(u64, u64)>)>,
 }
 impl RemErrors {
     fn new(sym: &str) -> Self {
@@ -913,6 +1395,41 @@ impl RemErrors {
             errors: vec![],
         }
     }
+    pub fn chunks(&self) -> usize {
+        self.errors.iter().map(|(_, e)| e.len()).sum()
+    }
+    fn merge(&mut self, errors: Vec<(Intv, Vec<(u64, u64)>)>) {
+        for (intv, mut chunks) in errors {
+            match self.errors.iter_mut().find(|(i, _)| *i == intv) {
+                Some((_, existing)) => existing.append(&mut chunks),
+                None => self.errors.push((intv, chunks)),
+            };
+        }
+        for (_, chunks) in self.errors.iter_mut() {
+            chunks.sort();
+            chunks.dedup();
+        }
+        self.errors.retain(|(_, chunks)| !chunks.is_empty());
+    }
+    ///Adds errors to the saved errors of symbol
+    pub async fn merge_saved(symbol: &str, errors: Vec<(Intv, Vec<(u64, u64)>)>) -> Result<()> {
+        let _guard = lock_dl_errors(symbol).await;
+        let mut rem_errors = RemErrors::load(symbol)?.unwrap_or(RemErrors::new(symbol));
+        rem_errors.merge(errors);
+        rem_errors.save_to_file(&dl_errors_path(symbol))
+    }
+    ///Loads the saved errors of a symbol, None if there aren't any
+    pub fn load(symbol: &str) -> Result<Option<Self>> {
+        let path = dl_errors_path(symbol);
+        if !Path::new(&path).exists() {
+            return Ok(None);
+        };
+        let rem_errs = Self::load_from_file(&path)?;
+        match rem_errs.chunks() {
+            0 => Ok(None),
+            _ => Ok(Some(rem_errs)),
+        }
+    }
     fn save_to_file(&self, path: &str) -> Result<()> {
         let config = config::standard();
         let res = bincode::encode_to_vec(self.clone(), config)?;
@@ -920,7 +1437,6 @@ impl RemErrors {
         file.write_all(&res)?;
         Ok(())
     }
-    #[allow(unused)]
     fn load_from_file(path: &str) -> Result<Self> {
         let config = config::standard();
         let mut file = std::fs::File::open(path)?;
@@ -931,12 +1447,19 @@ impl RemErrors {
     }
 }
 
-pub async fn iterate_over_remaining_errors(
+pub async fn iterate_over_remaining_errors<S: DataSource>(
+    source: &S,
+    db_path: &str,
     errors: Vec<(Intv, Vec<(u64, u64)>)>,
     symbol: &str,
+    job: &DlJob,
 ) -> Result<Option<Vec<(Intv, Vec<(u64, u64)>)>>> {
-    let asset_pool = connect_sqlite(format!["./databases/Asset{}.db", &symbol]).await?;
-    let client: Market = Binance::new(None, None);
+    let asset_pool = connect_sqlite(db_path).await?;
+    for (intv, errs) in errors.iter() {
+        for (st, et) in errs.iter() {
+            job.add_chunks(source_chunks(source, *intv, *st as i64, *et as i64));
+        }
+    }
     let new_err_vec = Arc::new(Mutex::new(vec![]));
     for (intv, errs) in errors {
         for (st, et) in errs {
@@ -946,14 +1469,14 @@ pub async fn iterate_over_remaining_errors(
                 et
             ];
 
-            let res = get_data_binance2(
-                &client,
+            let res = get_data_source(
+                source,
                 &symbol,
                 intv,
                 &asset_pool,
                 st as i64,
                 et as i64,
-                false,
+                job,
             )
             .await;
             match res {
@@ -968,10 +1491,15 @@ pub async fn iterate_over_remaining_errors(
                 }
                 Err(e) => {
                     tracing::error!["iterate_over_remaining_errors {}", e];
+                    let mut nev_ref = new_err_vec
+                        .lock()
+                        .expect("Unable to unlock errors mutex iterate_over_remaining_errors");
+                    nev_ref.push((intv, vec![(st, et)]));
                 }
             };
         }
     }
+    asset_pool.close().await;
     let nev = new_err_vec
         .lock()
         .expect("Unable to unlock errors mutex iterate_over_remaining_errors");
@@ -982,29 +1510,42 @@ pub async fn iterate_over_remaining_errors(
     }
 }
 
-pub async fn get_data_binance2(
-    client: &Market,
+///Number of requests get_data_source makes for st to et
+fn source_chunks<S: DataSource>(source: &S, intv: Intv, st: i64, et: i64) -> u32 {
+    let chunk = source.max_range(intv).max(1);
+    //NOTE round up, the last partial chunk was dropped before which left gaps in the data
+    let no_iterations = (et - st).saturating_add(chunk - 1) / chunk;
+    no_iterations.clamp(1, u32::MAX as i64) as u32
+}
+
+///Downloads symbol/intv between st and et from source into its kline table in chunks of
+///DataSource::max_range, returns the chunks that failed. Every chunk is counted in job, if it's
+///cancelled the rest of the range is returned as failed.
+pub async fn get_data_source<S: DataSource>(
+    source: &S,
     symbol: &str,
     intv: Intv,
     asset_pool: &Pool<Sqlite>,
     st: i64,
     et: i64,
-    pb: bool,
+    job: &DlJob,
 ) -> Result<Option<Vec<(u64, u64)>>> {
-    let no_datapoints = (et - st) / intv.to_ms();
-    let no_iterations = no_datapoints / 500;
-    let no_it = if no_iterations <= 1 { 1 } else { no_iterations };
-    let mut progress_bar = Progress::new();
+    let chunk = source.max_range(intv).max(1);
+    let no_it = source_chunks(source, intv, st, et) as i64;
     let mut error_timestamps: Vec<(u64, u64)> = vec![];
-    let kline_table = format!["kline_{}", intv.to_str()];
+    let kline_table = intv.table()?;
     for n in 0..no_it {
-        let t1 = (st + intv.to_ms() * n * 500) as u64 + 1;
-        let t2 = (st + intv.to_ms() * (n + 1) * 500) as u64;
+        let t1 = st.saturating_add(chunk.saturating_mul(n)) + 1;
+        let t2 = st
+            .saturating_add(chunk.saturating_mul(n + 1))
+            .min(et.max(t1));
+        if job.checkpoint().await.is_err() {
+            error_timestamps.push(((t1 - 1) as u64, et as u64));
+            break;
+        };
 
-        let res = client
-            .get_klines(symbol, intv.to_bin_str(), None, Some(t1), Some(t2))
-            .await;
-        let kk: Option<KlineSummaries> = match res {
+        let res = source.fetch_klines(symbol, (t1, t2), intv).await;
+        let kk: Option<FatKline> = match res {
             Ok(k) => Some(k),
             Err(err) => {
                 let st_disp = match DateTime::<Utc>::from_timestamp_millis(st) {
@@ -1016,108 +1557,495 @@ pub async fn get_data_binance2(
                     None => DateTime::<Utc>::default(),
                 };
                 tracing::error![
-                    "Binance error ERROR: {} for chunk: {} to {}",
+                    "{} error ERROR: {} for chunk: {} to {}",
+                    source.name(),
                     st_disp,
                     et_disp,
                     err
                 ];
-                error_timestamps.push((t1, t2));
+                job.chunk_failed(intv, &err);
+                error_timestamps.push((t1 as u64, t2 as u64));
                 None
             }
         };
         if let Some(k) = kk {
-            let klines = match k {
-                KlineSummaries::AllKlineSummaries(a) => a,
+            for kline_chunk in k.kline.chunks(APPEND_KLINE_MAX_ROWS) {
+                append_kline(asset_pool, &kline_table, kline_chunk).await?;
+            }
+            job.chunk_done(intv);
+        };
+    }
+    match error_timestamps.is_empty() {
+        true => Ok(None),
+        false => Ok(Some(error_timestamps)),
+    }
+}
+
+///Result of auditing one kline_<intv> table, times are open times in ms
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct IntvAudit {
+    pub intv: Intv,
+    pub rows: usize,
+    pub first: Option<i64>,
+    pub last: Option<i64>,
+    ///(first missing, last missing) candle open times
+    pub gaps: Vec<(i64, i64)>,
+    pub duplicates: usize,
+    ///high < low, open/close outside of high-low, negative volume or NaN values
+    pub bad_ohlc: Vec<i64>,
+    ///candles that don't match the aggregated 1m candles
+    pub mismatched: Vec<i64>,
+}
+impl IntvAudit {
+    pub fn missing(&self) -> i64 {
+        self.gaps
+            .iter()
+            .map(|(st, et)| match self.intv {
+                Intv::Month1 => {
+                    let mut n = 1;
+                    let mut t = *st;
+                    while t < *et {
+                        t = self.intv.next_open(t);
+                        n += 1;
+                    }
+                    n
+                }
+                _ => (et - st) / self.intv.to_ms() + 1,
+            })
+            .sum()
+    }
+    pub fn is_ok(&self) -> bool {
+        self.gaps.is_empty()
+            && self.duplicates == 0
+            && self.bad_ohlc.is_empty()
+            && self.mismatched.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct AuditReport {
+    pub symbol: String,
+    pub intvs: Vec<IntvAudit>,
+}
+impl AuditReport {
+    pub fn is_ok(&self) -> bool {
+        self.intvs.iter().all(|a| a.is_ok())
+    }
+    pub fn summary(&self) -> String {
+        let bad: Vec<String> = self
+            .intvs
+            .iter()
+            .filter(|a| !a.is_ok())
+            .map(|a| {
+                format![
+                    "{}: {} gaps ({} candles), {} duplicates, {} bad OHLC, {} mismatched",
+                    a.intv.to_str(),
+                    a.gaps.len(),
+                    a.missing(),
+                    a.duplicates,
+                    a.bad_ohlc.len(),
+                    a.mismatched.len()
+                ]
+            })
+            .collect();
+        match bad.is_empty() {
+            true => format!["{}: ok", self.symbol],
+            false => format!["{}: {}", self.symbol, bad.join("; ")],
+        }
+    }
+}
+
+///(time ms, o, h, l, c, v) as audited and derived
+type OhlcvRow = (i64, f64, f64, f64, f64, f64);
+///HashMap of OhlcvRow by time per interval
+type OhlcvByIntv = HashMap<Intv, HashMap<i64, OhlcvRow>>;
+
+///Checks a time ordered (time o h l c v) series one candle at a time
+#[derive(Debug, Clone)]
+struct SeriesAudit {
+    audit: IntvAudit,
+}
+impl SeriesAudit {
+    fn new(intv: Intv) -> Self {
+        Self {
+            audit: IntvAudit {
+                intv,
+                ..Default::default()
+            },
+        }
+    }
+    fn push(&mut self, (t, o, h, l, c, v): OhlcvRow) {
+        let a = &mut self.audit;
+        a.rows += 1;
+        if let Some(last) = a.last {
+            if t == last {
+                a.duplicates += 1;
+                return;
             };
-            let kl = klines.iter().map(|k| kline_conv(&k)).collect::<Result<
-                Vec<(
-                    i64,
-                    DateTime<Utc>,
-                    f64,
-                    f64,
-                    f64,
-                    f64,
-                    f64,
-                    i64,
-                    DateTime<Utc>,
-                    f64,
-                    u64,
-                    f64,
-                    f64,
-                )>,
-            >>();
-            let kline_chunk = kl?;
-
-            if !kline_chunk.is_empty() {
-                let _res = append_kline(asset_pool, &kline_table, &kline_chunk).await?;
+            //NOTE only months have different lengths, the rest is checked by step so candles
+            //that aren't aligned to the epoch don't show up as gaps
+            let (expected, last_missing) = match a.intv {
+                Intv::Month1 => (a.intv.next_open(last), a.intv.bucket_start(t - 1)),
+                _ => (last + a.intv.to_ms(), t - a.intv.to_ms()),
             };
+            if t > expected {
+                a.gaps.push((expected, last_missing));
+            };
+        } else {
+            a.first = Some(t);
         };
-        if pb {
-            let st_disp = match DateTime::<Utc>::from_timestamp_millis(st) {
-                Some(s) => s,
-                None => DateTime::<Utc>::default(),
+        a.last = Some(t);
+        let finite = [o, h, l, c, v].iter().all(|x| x.is_finite());
+        if !finite || h < l || o > h || o < l || c > h || c < l || v < 0.0 {
+            a.bad_ohlc.push(t);
+        };
+    }
+}
+
+///Aggregates time ordered 1m candles into intv candles, only full candles are returned
+#[derive(Debug, Clone)]
+struct KlineAggregator {
+    intv: Intv,
+    current: Option<OhlcvRow>,
+    count: i64,
+}
+impl KlineAggregator {
+    fn new(intv: Intv) -> Self {
+        Self {
+            intv,
+            current: None,
+            count: 0,
+        }
+    }
+    fn expected_count(&self, bucket: i64) -> i64 {
+        (self.intv.next_open(bucket) - bucket) / Intv::Min1.to_ms()
+    }
+    fn take_full(&mut self) -> Option<OhlcvRow> {
+        let current = self.current.take()?;
+        match self.count == self.expected_count(current.0) {
+            true => Some(current),
+            false => None,
+        }
+    }
+    fn push(&mut self, (t, o, h, l, c, v): OhlcvRow) -> Option<OhlcvRow> {
+        let bucket = self.intv.bucket_start(t);
+        let mut done = None;
+        if let Some(cur) = self.current.as_mut() {
+            if cur.0 == bucket {
+                cur.2 = cur.2.max(h);
+                cur.3 = cur.3.min(l);
+                cur.4 = c;
+                cur.5 += v;
+                self.count += 1;
+                return None;
             };
-            let et_disp = match DateTime::<Utc>::from_timestamp_millis(et) {
-                Some(e) => e,
-                None => DateTime::<Utc>::default(),
+            done = self.take_full();
+        };
+        self.current = Some((bucket, o, h, l, c, v));
+        self.count = 1;
+        done
+    }
+    fn finish(&mut self) -> Option<OhlcvRow> {
+        self.take_full()
+    }
+}
+
+fn candles_match(a: &OhlcvRow, b: &OhlcvRow) -> bool {
+    let close = |x: f64, y: f64, tol: f64| (x - y).abs() <= tol * x.abs().max(y.abs()).max(1.0);
+    close(a.1, b.1, 1e-9)
+        && close(a.2, b.2, 1e-9)
+        && close(a.3, b.3, 1e-9)
+        && close(a.4, b.4, 1e-9)
+        && close(a.5, b.5, 1e-6)
+}
+
+///Audits every kline table of an asset DB, the 1m table is streamed once and aggregated to
+///check the higher intervals against it
+pub async fn audit_asset_db(pool: &Pool<Sqlite>, symbol: &str) -> Result<AuditReport> {
+    let q = |intv: &Intv| {
+        format![
+            "SELECT [Timestamp MS], Open, High, Low, Close, Volume FROM kline_{} ORDER BY [Timestamp MS];",
+            intv.to_str()
+        ]
+    };
+    let mut aggregated: OhlcvByIntv = HashMap::new();
+    let mut aggregators: Vec<KlineAggregator> = Intv::iter()
+        .filter(|i| *i != Intv::Min1)
+        .map(KlineAggregator::new)
+        .collect();
+    let mut min1 = SeriesAudit::new(Intv::Min1);
+    {
+        let q1 = q(&Intv::Min1);
+        let mut rows = sqlx::query_as::<_, OhlcvRow>(&q1).fetch(pool);
+        while let Some(row) = rows.next().await {
+            let row = row?;
+            min1.push(row);
+            for agg in aggregators.iter_mut() {
+                if let Some(k) = agg.push(row) {
+                    aggregated.entry(agg.intv).or_default().insert(k.0, k);
+                };
+            }
+        }
+    }
+    for agg in aggregators.iter_mut() {
+        if let Some(k) = agg.finish() {
+            aggregated.entry(agg.intv).or_default().insert(k.0, k);
+        };
+    }
+
+    let mut report = AuditReport {
+        symbol: symbol.to_string(),
+        intvs: vec![min1.audit],
+    };
+    for intv in Intv::iter().filter(|i| *i != Intv::Min1) {
+        let mut audit = SeriesAudit::new(intv);
+        let agg = aggregated.remove(&intv).unwrap_or_default();
+        let qi = q(&intv);
+        let mut rows = sqlx::query_as::<_, OhlcvRow>(&qi).fetch(pool);
+        while let Some(row) = rows.next().await {
+            let row = row?;
+            audit.push(row);
+            if agg.get(&row.0).is_some_and(|k| !candles_match(k, &row)) {
+                audit.audit.mismatched.push(row.0);
             };
-            let bar: Bar = progress_bar.bar(
-                no_it as usize,
-                format!(
-                    "Downloading data for {} {} between: {} and: {}: {}/{}",
-                    &symbol,
-                    &intv.to_str(),
-                    st_disp,
-                    et_disp,
-                    n,
-                    no_it
-                ),
-            );
-            progress_bar.inc_and_draw(&bar, n as usize);
+        }
+        report.intvs.push(audit.audit);
+    }
+    Ok(report)
+}
+
+///Aggregates time ordered 1m rows of the full kline layout into intv rows. Unlike
+///KlineAggregator the last candle is returned even if it isn't complete yet, like Binance does.
+#[derive(Clone)]
+struct FatAggregator {
+    intv: Intv,
+    current: Option<FatKlineRow>,
+    count: i64,
+}
+impl FatAggregator {
+    fn new(intv: Intv) -> Self {
+        Self {
+            intv,
+            current: None,
+            count: 0,
+        }
+    }
+    fn is_full(&self) -> bool {
+        match &self.current {
+            Some(cur) => self.count == (self.intv.next_open(cur.0) - cur.0) / Intv::Min1.to_ms(),
+            None => false,
+        }
+    }
+    ///Returns the finished candle and if it was complete
+    fn push(&mut self, row: &FatKlineRow) -> Option<(FatKlineRow, bool)> {
+        let bucket = self.intv.bucket_start(row.0);
+        if let Some(cur) = self.current.as_mut()
+            && cur.0 == bucket
+        {
+            cur.3 = cur.3.max(row.3);
+            cur.4 = cur.4.min(row.4);
+            cur.5 = row.5;
+            cur.6 += row.6;
+            cur.9 += row.9;
+            cur.10 += row.10;
+            cur.11 += row.11;
+            cur.12 += row.12;
+            self.count += 1;
+            return None;
         };
+        let done = self.finish();
+        let close_t = self.intv.next_open(bucket) - 1;
+        self.current = Some((
+            bucket,
+            DateTime::<Utc>::from_timestamp_millis(bucket).unwrap_or(row.1),
+            row.2,
+            row.3,
+            row.4,
+            row.5,
+            row.6,
+            close_t,
+            DateTime::<Utc>::from_timestamp_millis(close_t).unwrap_or(row.8),
+            row.9,
+            row.10,
+            row.11,
+            row.12,
+        ));
+        self.count = 1;
+        done
     }
-    if error_timestamps.is_empty() {
-        return Ok(None);
-    } else {
-        return Ok(Some(error_timestamps));
+    fn finish(&mut self) -> Option<(FatKlineRow, bool)> {
+        let full = self.is_full();
+        self.current.take().map(|c| (c, full))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeriveReport {
+    pub written: usize,
+    ///complete candles that were already in the table and got compared
+    pub compared: usize,
+    pub mismatched: Vec<(Intv, i64)>,
+}
+
+///Builds every interval except 1m from the kline_1min table, starting at the candle containing
+///start_ms. Complete candles that were downloaded before are compared against the derived ones
+///and only replaced with repair. Candles with missing 1m rows are skipped, except the one the
+///1m history starts in and the last one which is still open. With update the 1m rows from
+///start_ms on were just downloaded, the candles from the one containing start_ms on are always
+///rewritten as the last update stored them while they were still open.
+pub async fn derive_intervals(
+    pool: &Pool<Sqlite>,
+    start_ms: i64,
+    update: bool,
+    repair: bool,
+) -> Result<DeriveReport> {
+    let intvs: Vec<Intv> = Intv::iter().filter(|i| *i != Intv::Min1).collect();
+    let (first, last): (Option<i64>, Option<i64>) = sqlx::query_as(
+        "SELECT MIN([Timestamp MS]), MAX([Timestamp MS]) FROM kline_1min WHERE [Timestamp MS] >= ?;",
+    )
+    .bind(start_ms)
+    .fetch_one(pool)
+    .await?;
+    let (Some(first), Some(last)) = (first, last) else {
+        return Ok(DeriveReport::default());
     };
+    let (listed,): (i64,) = sqlx::query_as("SELECT MIN([Timestamp MS]) FROM kline_1min;")
+        .fetch_one(pool)
+        .await?;
+    let ends = (listed, last);
+    let fresh = update.then_some(start_ms);
+    let from = intvs
+        .iter()
+        .map(|i| i.bucket_start(first))
+        .min()
+        .unwrap_or(first);
+    let mut existing: OhlcvByIntv = HashMap::new();
+    for intv in intvs.iter() {
+        let q = format![
+            "SELECT [Timestamp MS], Open, High, Low, Close, Volume FROM {} WHERE [Timestamp MS] >= ?;",
+            intv.table()?
+        ];
+        let rows: Vec<OhlcvRow> = sqlx::query_as(&q).bind(from).fetch_all(pool).await?;
+        existing.insert(*intv, rows.into_iter().map(|r| (r.0, r)).collect());
+    }
+
+    let mut report = DeriveReport::default();
+    let mut aggregators: Vec<FatAggregator> =
+        intvs.iter().map(|i| FatAggregator::new(*i)).collect();
+    let mut pending: HashMap<Intv, Vec<_>> = HashMap::new();
+    let mut done = vec![];
+    //NOTE 1m is loaded in windows of ~35 days so a full history doesn't have to fit in memory
+    const WINDOW_MS: i64 = 50_000 * 60_000;
+    let mut window_start = from;
+    while window_start <= last {
+        let window = (window_start, window_start + WINDOW_MS - 1);
+        let min1 = fat_kfrom_sql(pool, "kline_1min", Some(window)).await?;
+        window_start += WINDOW_MS;
+        for row in min1.kline.iter() {
+            for agg in aggregators.iter_mut() {
+                if let Some(k) = agg.push(row) {
+                    done.push((agg.intv, k));
+                };
+            }
+            for (intv, k) in done.drain(..) {
+                pending.entry(intv).or_default().push(k);
+            }
+        }
+        for (intv, rows) in pending.iter_mut() {
+            write_derived(
+                pool,
+                intv,
+                rows,
+                &existing,
+                ends,
+                fresh,
+                repair,
+                &mut report,
+            )
+            .await?;
+        }
+    }
+    for agg in aggregators.iter_mut() {
+        if let Some(k) = agg.finish() {
+            pending.entry(agg.intv).or_default().push(k);
+        };
+    }
+    for (intv, rows) in pending.iter_mut() {
+        write_derived(
+            pool,
+            intv,
+            rows,
+            &existing,
+            ends,
+            fresh,
+            repair,
+            &mut report,
+        )
+        .await?;
+    }
+    Ok(report)
+}
+
+///Writes the derived rows that are new, the candles containing ends (the first and the newest
+///1m row) and the ones from the candle containing fresh on are always updated, other existing
+///complete candles are only replaced with repair
+#[allow(clippy::too_many_arguments)]
+async fn write_derived(
+    pool: &Pool<Sqlite>,
+    intv: &Intv,
+    rows: &mut Vec<(FatKlineRow, bool)>,
+    existing: &OhlcvByIntv,
+    ends: (i64, i64),
+    fresh: Option<i64>,
+    repair: bool,
+    report: &mut DeriveReport,
+) -> Result<()> {
+    let ex = existing.get(intv);
+    let ends = (intv.bucket_start(ends.0), intv.bucket_start(ends.1));
+    let fresh = fresh.map(|t| intv.bucket_start(t));
+    let mut out = vec![];
+    for (k, full) in rows.drain(..) {
+        let e = ex.and_then(|ex| ex.get(&k.0));
+        if !full {
+            //NOTE any other incomplete candle has a gap in the 1m data
+            if k.0 == ends.0 || k.0 == ends.1 {
+                out.push(k);
+            };
+            continue;
+        };
+        if fresh.is_some_and(|f| k.0 >= f) {
+            out.push(k);
+            continue;
+        };
+        match e {
+            Some(e) => {
+                report.compared += 1;
+                if !candles_match(&(k.0, k.2, k.3, k.4, k.5, k.6), e) {
+                    report.mismatched.push((*intv, k.0));
+                    if repair {
+                        out.push(k);
+                    };
+                };
+            }
+            None => out.push(k),
+        };
+    }
+    report.written += out.len();
+    for chunk in out.chunks(APPEND_KLINE_MAX_ROWS) {
+        append_kline(pool, &intv.table()?, chunk).await?;
+    }
+    Ok(())
 }
 
 pub async fn create_metadata_db() -> Result<()> {
-    create_db(&METADATA_DB_PATH)
+    tokio::fs::create_dir_all(databases_dir()).await?;
+    create_db(&metadata_db_path())
         .await
         .context(anyhow!("SQL::Unable cereate db"))?;
-    let pool = SqlitePool::connect(&METADATA_DB_PATH)
+    let pool = SqlitePool::connect(&metadata_db_path())
         .await
         .context(anyhow!("SQL::Unable to metadata connect to db"))?;
-    let q = format!(
-        "CREATE TABLE assets ( [Asset] TEXT, [Exchange] TEXT, [Status] TEXT, [BaseAsset] TEXT, [QouteAsset] TEXT, [Extras] TEXT, [Extras Multi] TEXT, [Start Time ms] INTEGER, [End Time ms] INTEGER, [Market cap] REAL )"
-    );
-    exec_query(&pool, &q).await?;
-    let q = format!(
-        "CREATE UNIQUE INDEX Asset
-        ON assets ( [Asset] )"
-    );
-    exec_query(&pool, &q).await?;
-
-    let q = format!(
-        "CREATE TABLE assets_fut ( [Asset] TEXT, [Exchange] TEXT, [Status] TEXT, [BaseAsset] TEXT, [QouteAsset] TEXT, [Extras] TEXT, [Extras Multi] TEXT, [onboardDate] INTEGER, [deliveryDate] INTEGER, [Market cap] REAL )"
-    );
-    exec_query(&pool, &q).await?;
-    let q = format!(
-        "CREATE UNIQUE INDEX AssetFut
-        ON assets_fut ( [Asset] )"
-    );
-    exec_query(&pool, &q).await?;
-    let q = format!(
-        "CREATE TABLE assets_dl ( [Asset] TEXT, [Exchange] TEXT, [Start Time] INTEGER, [End Time] INTEGER )" //TODO make "default asset list for release version"
-    );
-    exec_query(&pool, &q).await?;
-    let q = format!(
-        "CREATE UNIQUE INDEX Asset_DL
-        ON assets_dl ( [Asset] )"
-    );
-    exec_query(&pool, &q).await?;
+    migrate_db(&pool, &metadata_migrations()).await?;
     let q = format!(
         "
     INSERT OR REPLACE INTO assets_dl (Asset, Exchange)
@@ -1137,6 +2065,50 @@ pub async fn create_metadata_db() -> Result<()> {
     Ok(())
 }
 
+pub async fn ensure_metadata_db() -> Result<()> {
+    let res = sqlx::Sqlite::database_exists(&metadata_db_path()).await;
+    let db_exists = match res {
+        Ok(e) => e,
+        Err(e) => {
+            tracing::error!["Check db exists ERROR: {}", e];
+            false
+        }
+    };
+    if !db_exists {
+        let _res = tokio::fs::create_dir_all(databases_dir()).await;
+        create_metadata_db().await?;
+    } else {
+        let pool = connect_sqlite(&metadata_db_path()).await?;
+        let res = migrate_db(&pool, &metadata_migrations()).await;
+        pool.close().await;
+        res?;
+    };
+    Ok(())
+}
+
+///Creates/migrates the metadata DB and migrates every Asset*.db in databases_dir, run at startup
+pub async fn migrate_databases() -> Result<()> {
+    ensure_metadata_db().await?;
+    let mut dir = tokio::fs::read_dir(databases_dir()).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        if !(name.starts_with("Asset") && name.ends_with(".db")) {
+            continue;
+        };
+        let pool = connect_sqlite(entry.path()).await?;
+        let res = migrate_db(&pool, &asset_migrations()).await;
+        pool.close().await;
+        match res {
+            Ok(v) => tracing::trace!["{} at schema version {}", name, v],
+            Err(e) => tracing::error!["Unable to migrate {}: {:?}", name, e],
+        };
+    }
+    Ok(())
+}
+
 async fn download_asset_list_binance(metadata_db: &Pool<Sqlite>) -> Result<()> {
     tracing::trace!["Fetching exchange info"];
     let symbol_info_full = get_exchange_info().await?;
@@ -1198,11 +2170,13 @@ async fn dl_load_asset_list(pool: &Pool<Sqlite>) -> Result<Vec<(String, String)>
     .await?;
     Ok(out)
 }
+///Asset, Exchange, Start Time, End Time, Last Update ms, Update Status of assets_dl
+type DLAssetSqlRow = (String, String, i64, i64, Option<i64>, Option<String>);
 async fn load_asset_list_ad(pool: &Pool<Sqlite>) -> Result<Vec<DLAsset>> {
-    let out: Vec<(String, String, i64, i64)> = sqlx::query_as(
+    let out: Vec<DLAssetSqlRow> = sqlx::query_as(
         format!(
             "SELECT 
-[Asset] , [Exchange], [Start Time], [End Time] FROM assets_dl;"
+[Asset] , [Exchange], [Start Time], [End Time], [Last Update ms], [Update Status] FROM assets_dl;"
         )
         .as_str(),
     )
@@ -1210,108 +2184,756 @@ async fn load_asset_list_ad(pool: &Pool<Sqlite>) -> Result<Vec<DLAsset>> {
     .await?;
     let oo: Vec<DLAsset> = out
         .iter()
-        .map(|(symbol, exchange, start_ms, end_ms)| DLAsset {
-            asset: symbol.to_string(),
-            exchange: exchange.to_string(),
-            dat_start_t: *start_ms,
-            dat_end_t: *end_ms,
-        })
+        .map(
+            |(symbol, exchange, start_ms, end_ms, last_update, status)| DLAsset {
+                asset: symbol.to_string(),
+                exchange: exchange.to_string(),
+                dat_start_t: *start_ms,
+                dat_end_t: *end_ms,
+                missing_chunks: match RemErrors::load(symbol) {
+                    Ok(Some(e)) => e.chunks(),
+                    _ => 0,
+                },
+                last_update: last_update.unwrap_or(0),
+                update_status: status.clone().unwrap_or_default(),
+            },
+        )
         .collect();
     Ok(oo)
 }
 
+///Assets whose last update attempt is at least interval_ms old, failed ones are retried after
+///an interval too. Imported data has nothing to update.
+async fn due_assets(
+    pool: &Pool<Sqlite>,
+    now: i64,
+    interval_ms: i64,
+) -> Result<Vec<(String, String)>> {
+    let out: Vec<(String, String, Option<i64>, Option<i64>)> = sqlx::query_as(
+        "SELECT [Asset], [Exchange], [Last Update ms], [Last Attempt ms] FROM assets_dl;",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(out
+        .into_iter()
+        //NOTE TEST is the placeholder row create_metadata_db inserts
+        .filter(|(symbol, exchange, _, _)| symbol != "TEST" && exchange != "Local")
+        .filter(|(_, _, last, attempt)| match last.max(attempt) {
+            Some(t) => now - t >= interval_ms,
+            None => true,
+        })
+        .map(|(symbol, exchange, _, _)| (symbol, exchange))
+        .collect())
+}
+
+///Records the result of an asset update, last update only moves on success and last attempt
+///on every update
+async fn record_update_status(
+    pool: &Pool<Sqlite>,
+    symbol: &str,
+    now: i64,
+    res: &Result<()>,
+) -> Result<()> {
+    let q = match res {
+        Ok(_) => sqlx::query(
+            "UPDATE assets_dl SET [Last Update ms] = ?, [Last Attempt ms] = ?, [Update Status] = 'Ok' WHERE Asset = ?",
+        )
+        .bind(now)
+        .bind(now)
+        .bind(symbol),
+        Err(e) => sqlx::query(
+            "UPDATE assets_dl SET [Last Attempt ms] = ?, [Update Status] = ? WHERE Asset = ?",
+        )
+        .bind(now)
+        .bind(format!["Failed: {}", e])
+        .bind(symbol),
+    };
+    q.execute(pool).await?;
+    Ok(())
+}
+
+///Scheduled update interval in minutes, None if scheduled updates are off
+pub async fn load_update_schedule(pool: &Pool<Sqlite>) -> Result<Option<u64>> {
+    let res: Option<(i64,)> = sqlx::query_as("SELECT [Interval min] FROM update_schedule LIMIT 1")
+        .fetch_optional(pool)
+        .await?;
+    Ok(res.map(|(m,)| m).filter(|m| *m > 0).map(|m| m as u64))
+}
+
+async fn save_update_schedule(pool: &Pool<Sqlite>, interval_min: Option<u64>) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM update_schedule")
+        .execute(&mut *tx)
+        .await?;
+    if let Some(m) = interval_min {
+        sqlx::query("INSERT INTO update_schedule ([Interval min]) VALUES (?)")
+            .bind(m as i64)
+            .execute(&mut *tx)
+            .await?;
+    };
+    tx.commit().await?;
+    Ok(())
+}
+
 pub enum Exchange {
     Binance,
     Yahoo,
+    Local,
+    ///USDⓈ-M futures, stored under the .P name of the symbol
+    BinanceFutures,
 }
 
-impl From<&str> for Exchange {
-    fn from(input: &str) -> Self {
+impl std::str::FromStr for Exchange {
+    type Err = anyhow::Error;
+    fn from_str(input: &str) -> Result<Self> {
         match input {
-            "Binance" => Exchange::Binance,
-            "Yahoo" => Exchange::Yahoo,
-            "0" => Exchange::Binance,
-            "1" => Exchange::Yahoo,
-            _ => panic!["Unable to parse exchange name"],
+            "Binance" => Ok(Exchange::Binance),
+            "Yahoo" => Ok(Exchange::Yahoo),
+            "Local" => Ok(Exchange::Local),
+            "Binance Futures" => Ok(Exchange::BinanceFutures),
+            "0" => Ok(Exchange::Binance),
+            "1" => Ok(Exchange::Yahoo),
+            "2" => Ok(Exchange::Local),
+            "3" => Ok(Exchange::BinanceFutures),
+            _ => Err(anyhow!["Unknown exchange: {}", input]),
         }
     }
 }
 
-async fn download_asset_data(symbol: &str, exchange: &Exchange, start_time: i64) -> Result<()> {
+///With derive only 1m is downloaded and the other intervals are built from it
+async fn download_asset_data(
+    symbol: &str,
+    exchange: &Exchange,
+    start_time: i64,
+    derive: bool,
+    job: &DlJob,
+) -> Result<()> {
     match exchange {
-        Exchange::Binance => single_asset_dl(symbol, start_time).await,
-        Exchange::Yahoo => {
-            todo!()
+        Exchange::Binance => {
+            binance_download(&BinanceSource::new(), symbol, start_time, derive, job).await
+        }
+        Exchange::BinanceFutures => {
+            let source = BinanceSource::futures();
+            binance_download(&source, symbol, start_time, derive, job).await?;
+            let pool = connect_sqlite(asset_db_path(symbol)).await?;
+            let end_time = Utc::now().timestamp_millis();
+            let res = funding_asset_dl(&source, &pool, symbol, start_time, end_time).await;
+            pool.close().await;
+            tracing::debug!["Stored {} funding rates for {}", res?, symbol];
+            Ok(())
         }
+        Exchange::Yahoo => yahoo_download(symbol, start_time, job).await,
+        //NOTE imported data, nothing to download
+        Exchange::Local => Ok(()),
     }?;
     Ok(())
 }
 
-#[derive(Debug)]
-pub struct SQLConn {
-    db_path: String,
-    hist_asset_data: Arc<Mutex<AssetData>>,
-}
-//TODO hist data arc mutex will be stored here. Make a semiphore for max number of data
-impl Default for SQLConn {
-    fn default() -> Self {
-        Self {
-            db_path: "./databases".to_string(),
-            hist_asset_data: Arc::new(Mutex::new(AssetData::new(666))),
+async fn binance_download(
+    source: &BinanceSource,
+    symbol: &str,
+    start_time: i64,
+    derive: bool,
+    job: &DlJob,
+) -> Result<()> {
+    match derive {
+        true => {
+            single_asset_dl(source, symbol, start_time, &[Intv::Min1], job).await?;
+            let pool = connect_sqlite(asset_db_path(symbol)).await?;
+            let res = derive_intervals(&pool, start_time, true, false).await;
+            pool.close().await;
+            let report = res?;
+            if !report.mismatched.is_empty() {
+                tracing::warn![
+                    "{} downloaded candles of {} didn't match the 1m data and were kept",
+                    report.mismatched.len(),
+                    symbol
+                ];
+            };
+            Ok(())
+        }
+        false => {
+            let intvs: Vec<Intv> = Intv::iter().collect();
+            single_asset_dl(source, symbol, start_time, &intvs, job).await
         }
     }
 }
 
-async fn create_db(db_path: &str) -> Result<()> {
-    if !Sqlite::database_exists(&db_path).await.unwrap_or(false) {
-        let result = Sqlite::create_database(&db_path).await?;
-        let pool = connect_sqlite(&db_path).await?;
-        tracing::trace!("Creating database {:?}", result);
-        let q = format!("PRAGMA foreign_keys=ON");
-        exec_query(&pool, &q).await?;
-        Ok(())
-    } else {
-        tracing::trace!("Database already exists");
-        Ok(())
+///Downloads the funding rates of a perpetual after the last stored one (or from start_time) up
+///to end_time into its asset DB, returns the number stored. Events without a mark price get the
+///open of the 1h mark price candle they fall in.
+pub async fn funding_asset_dl(
+    source: &BinanceSource,
+    pool: &Pool<Sqlite>,
+    symbol: &str,
+    start_time: i64,
+    end_time: i64,
+) -> Result<usize> {
+    let (last,): (Option<i64>,) = sqlx::query_as("SELECT MAX([Timestamp MS]) FROM funding_rate;")
+        .fetch_one(pool)
+        .await?;
+    let mut from = last.map(|t| t + 1).unwrap_or(start_time).max(start_time);
+    let mut rates: Vec<FundingRate> = vec![];
+    while from <= end_time {
+        let page = source.fetch_funding(symbol, (from, end_time)).await?;
+        let Some(last) = page.last().map(|r| r.time) else {
+            break;
+        };
+        let full = page.len() == FUT_PAGE_LIMIT;
+        rates.extend(page);
+        if !full {
+            break;
+        };
+        from = last + 1;
     }
+    let hour = Intv::Hour1.to_ms();
+    let mut marks: HashMap<i64, f64> = HashMap::new();
+    let missing: Vec<i64> = rates
+        .iter()
+        .filter(|r| r.mark_price.is_none())
+        .map(|r| r.time - r.time % hour)
+        .collect();
+    for t in missing.iter() {
+        if marks.contains_key(t) {
+            continue;
+        };
+        let range = (*t, t + hour * (FUT_PAGE_LIMIT as i64 - 1));
+        let k = source.fetch_mark_klines(symbol, range, Intv::Hour1).await?;
+        if k.kline.is_empty() {
+            //NOTE no mark price that far back, don't ask again for every event
+            marks.insert(*t, f64::NAN);
+        };
+        marks.extend(k.kline.iter().map(|k| (k.0, k.2)));
+    }
+    for r in rates.iter_mut().filter(|r| r.mark_price.is_none()) {
+        r.mark_price = marks
+            .get(&(r.time - r.time % hour))
+            .copied()
+            .filter(|p| p.is_finite());
+    }
+    store_funding(pool, &rates).await?;
+    Ok(rates.len())
 }
 
-async fn cr_kl_tables(pool: &Pool<Sqlite>) -> Result<()> {
-    for i in Intv::iter() {
-        let q = format!(
-            "CREATE TABLE kline_{} ( [Timestamp MS] INTEGER, [Open Time] datetime,  Open REAL,  High REAL,  Low REAL,  Close REAL,  Volume REAL, [Close Timestamp MS] INTEGER, [Close Time] INTEGER,  [Quote Asset Volume] REAL,  [Number of Trades] INTEGER,  [Taker Buy Base Asset Volume] REAL,  [Taker Buy Quote Asset Volume] REAL,[Ignore], [Pattern hash] REAL )",
-            i.to_str()
-        );
-        exec_query(&pool, &q).await?;
-        let q = format!(
-            "CREATE UNIQUE INDEX time_index_{}
-            ON kline_{} ( [Timestamp MS] )",
-            i.to_str(),
-            i.to_str()
-        );
-        exec_query(&pool, &q).await?;
+async fn store_funding(pool: &Pool<Sqlite>, rates: &[FundingRate]) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    for r in rates.iter() {
+        sqlx::query(
+            "INSERT OR REPLACE INTO funding_rate ([Timestamp MS], [Funding rate], [Mark price]) VALUES (?, ?, ?)",
+        )
+        .bind(r.time)
+        .bind(r.rate)
+        .bind(r.mark_price)
+        .execute(&mut *tx)
+        .await?;
     }
-    let q = format!(
-        "CREATE TABLE colstats ( Column TEXT, Type INTEGER, Mean REAL, [Standard Deviaton] REAL , No INTEGER )",
-    );
-    exec_query(&pool, &q).await?;
-    let q = format!(
-        "CREATE UNIQUE INDEX col_id
-        ON colstats ( Column )"
-    );
-    exec_query(&pool, &q).await?;
-    let q = format!(
-        "CREATE TABLE metadata (Symbol TEXT, Exchange INTEGER, [Start Time] INTEGER, [End Time] INTEGER, [Key asset class 1] INTEGER, [Sub asset class 1] INTEGER ) "
-    );
-    exec_query(&pool, &q).await?;
-    let q = format!(
-        "CREATE UNIQUE INDEX asset_id
-        ON metadata ( Symbol )"
-    );
-    exec_query(&pool, &q).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+///Funding rates stored in an asset DB sorted by time, t is an optional (start, end) range in ms
+pub async fn load_funding_db(
+    pool: &Pool<Sqlite>,
+    t: Option<(i64, i64)>,
+) -> Result<Vec<FundingRate>> {
+    let (start, end) = t.unwrap_or((i64::MIN, i64::MAX));
+    let rows: Vec<(i64, f64, Option<f64>)> = sqlx::query_as(
+        "SELECT [Timestamp MS], [Funding rate], [Mark price] FROM funding_rate WHERE [Timestamp MS] >= ? AND [Timestamp MS] <= ? ORDER BY [Timestamp MS];",
+    )
+    .bind(start)
+    .bind(end)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(time, rate, mark_price)| FundingRate {
+            time,
+            rate,
+            mark_price,
+        })
+        .collect())
+}
+
+#[cfg(feature = "yfinance")]
+async fn yahoo_download(symbol: &str, start_time: i64, job: &DlJob) -> Result<()> {
+    let source = YahooSource::new();
+    let pool = connect_sqlite(asset_db_path(symbol)).await?;
+    let end_time = Utc::now().timestamp_millis();
+    let res = yahoo_asset_dl(&source, &pool, symbol, start_time, end_time, job).await;
+    pool.close().await;
+    tracing::info!["Stored {} Yahoo candles for {}", res?, symbol];
+    Ok(())
+}
+
+#[cfg(not(feature = "yfinance"))]
+async fn yahoo_download(_symbol: &str, _start_time: i64, _job: &DlJob) -> Result<()> {
+    Err(anyhow!["Yahoo assets need the yfinance feature"])
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CsvTimeFormat {
+    UnixMs,
+    UnixSec,
+    UnixUs,
+    ///chrono strftime format e.g. "%Y-%m-%d %H:%M:%S", dates without a time start at midnight
+    Format(String),
+}
+
+///Column mapping for kline CSV imports. Columns are either 0 based indexes or header names.
+///timezone is "UTC", "Local" or a fixed offset like "+02:00" and only applies to times without
+///an offset in them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CsvImportConfig {
+    pub delimiter: char,
+    pub has_header: bool,
+    pub time: String,
+    pub open: String,
+    pub high: String,
+    pub low: String,
+    pub close: String,
+    pub volume: Option<String>,
+    pub time_format: CsvTimeFormat,
+    pub timezone: String,
+    pub exchange: String,
+}
+impl Default for CsvImportConfig {
+    fn default() -> Self {
+        Self {
+            delimiter: ',',
+            has_header: true,
+            time: "0".to_string(),
+            open: "1".to_string(),
+            high: "2".to_string(),
+            low: "3".to_string(),
+            close: "4".to_string(),
+            volume: Some("5".to_string()),
+            time_format: CsvTimeFormat::UnixMs,
+            timezone: "UTC".to_string(),
+            exchange: "Local".to_string(),
+        }
+    }
+}
+
+fn split_csv_line(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => {
+                fields.push(field.trim().to_string());
+                field.clear();
+            }
+            c => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_string());
+    fields
+}
+
+fn csv_column(col: &str, header: Option<&[String]>) -> Result<usize> {
+    if let Ok(i) = col.parse::<usize>() {
+        return Ok(i);
+    };
+    match header {
+        Some(h) => h
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(col.trim()))
+            .ok_or(anyhow!["CSV column {} not found in header {:?}", col, h]),
+        None => Err(anyhow!["CSV column {} needs a header row", col]),
+    }
+}
+
+fn parse_csv_time(input: &str, format: &CsvTimeFormat, timezone: &str) -> Result<DateTime<Utc>> {
+    let naive = match format {
+        CsvTimeFormat::UnixMs | CsvTimeFormat::UnixSec | CsvTimeFormat::UnixUs => {
+            let t: i64 = input.parse::<f64>()? as i64;
+            let t_ms = match format {
+                CsvTimeFormat::UnixSec => t * 1000,
+                CsvTimeFormat::UnixUs => t / 1000,
+                _ => t,
+            };
+            return DateTime::<Utc>::from_timestamp_millis(t_ms)
+                .ok_or(anyhow!["Timestamp out of range: {}", input]);
+        }
+        CsvTimeFormat::Format(f) => {
+            if let Ok(t) = DateTime::parse_from_str(input, f) {
+                return Ok(t.with_timezone(&Utc));
+            };
+            match chrono::NaiveDateTime::parse_from_str(input, f) {
+                Ok(t) => t,
+                Err(e) => match chrono::NaiveDate::parse_from_str(input, f) {
+                    Ok(d) => d
+                        .and_hms_opt(0, 0, 0)
+                        .ok_or(anyhow!["Invalid date {}", input])?,
+                    Err(_) => return Err(anyhow!["Unable to parse time {}: {}", input, e]),
+                },
+            }
+        }
+    };
+    let t = match timezone {
+        "UTC" | "utc" | "Z" => Some(naive.and_utc()),
+        "Local" | "local" => chrono::Local
+            .from_local_datetime(&naive)
+            .earliest()
+            .map(|t| t.with_timezone(&Utc)),
+        offset => {
+            let tz: chrono::FixedOffset = offset
+                .parse()
+                .map_err(|e| anyhow!["Invalid timezone {}: {:?}", offset, e])?;
+            tz.from_local_datetime(&naive)
+                .single()
+                .map(|t| t.with_timezone(&Utc))
+        }
+    };
+    t.ok_or(anyhow![
+        "Time {} doesn't exist in timezone {}",
+        input,
+        timezone
+    ])
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ArchiveImport {
+    pub files: usize,
+    pub verified: usize,
+    pub rows: usize,
+    pub symbols: Vec<String>,
+    pub failed: Vec<(String, String)>,
+}
+
+///"BTCUSDT-1m-2024-01.zip" or "BTCUSDT-1m-2024-01-31.zip" -> (BTCUSDT, Min1)
+fn parse_archive_name(name: &str) -> Option<(String, Intv)> {
+    let stem = name.strip_suffix(".zip")?;
+    let parts: Vec<&str> = stem.split('-').collect();
+    if parts.len() < 4 || parts.len() > 5 || parts[2..].iter().any(|p| p.parse::<u32>().is_err()) {
+        return None;
+    };
+    let intv = match parts[1] {
+        "1mo" => Intv::Month1,
+        i => Intv::iter().find(|intv| intv.to_bin_str() == i)?,
+    };
+    Some((parts[0].to_uppercase(), intv))
+}
+
+fn read_binance_archive(path: &Path) -> Result<(Vec<FatKlineRow>, bool)> {
+    let bytes = std::fs::read(path)?;
+    let mut checksum_path = path.as_os_str().to_owned();
+    checksum_path.push(".CHECKSUM");
+    let verified = match std::fs::read_to_string(&checksum_path) {
+        Ok(checksum) => {
+            let expected = checksum
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_lowercase();
+            let actual: String = Sha256::digest(&bytes)
+                .iter()
+                .map(|b| format!["{:02x}", b])
+                .collect();
+            if expected != actual {
+                return Err(anyhow![
+                    "Checksum mismatch, expected {} got {}",
+                    expected,
+                    actual
+                ]);
+            };
+            true
+        }
+        Err(_) => false,
+    };
+    let mut zip = zip::ZipArchive::new(std::io::Cursor::new(bytes))?;
+    let mut rows = vec![];
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i)?;
+        if !file.name().ends_with(".csv") {
+            continue;
+        };
+        let mut input = String::new();
+        file.read_to_string(&mut input)?;
+        rows.append(&mut parse_binance_archive_csv(&input)?);
+    }
+    Ok((rows, verified))
+}
+
+///Parses the 12 column kline CSV used by data.binance.vision, the optional header row is skipped.
+///Spot files from 2025 on use microsecond timestamps, these are converted to ms.
+pub fn parse_binance_archive_csv(input: &str) -> Result<Vec<FatKlineRow>> {
+    let to_ms = |t: i64| if t > 100_000_000_000_000 { t / 1000 } else { t };
+    let mut out = vec![];
+    for (n, line) in input.lines().enumerate() {
+        let f: Vec<&str> = line.trim().split(',').collect();
+        if f.len() < 11 || (n == 0 && f[0].parse::<i64>().is_err()) {
+            continue;
+        };
+        let row = || -> Result<_> {
+            let t = to_ms(f[0].parse::<i64>()?);
+            let ct = to_ms(f[6].parse::<i64>()?);
+            Ok((
+                t,
+                DateTime::<Utc>::from_timestamp_millis(t).ok_or(anyhow!["Invalid time {}", t])?,
+                f[1].parse::<f64>()?,
+                f[2].parse::<f64>()?,
+                f[3].parse::<f64>()?,
+                f[4].parse::<f64>()?,
+                f[5].parse::<f64>()?,
+                ct,
+                DateTime::<Utc>::from_timestamp_millis(ct).ok_or(anyhow!["Invalid time {}", ct])?,
+                f[7].parse::<f64>()?,
+                f[8].parse::<u64>()?,
+                f[9].parse::<f64>()?,
+                f[10].parse::<f64>()?,
+            ))
+        };
+        out.push(row().with_context(|| format!["Archive CSV line {}", n + 1])?);
+    }
+    Ok(out)
+}
+
+///Parses OHLCV CSV text into the full kline table layout, sorted and deduplicated by open time.
+///Columns that aren't in the CSV (quote volume, trades...) are stored as 0.
+pub fn parse_kline_csv(
+    input: &str,
+    intv: &Intv,
+    config: &CsvImportConfig,
+) -> Result<Vec<FatKlineRow>> {
+    let mut lines = input.lines().filter(|l| !l.trim().is_empty());
+    let header = if config.has_header {
+        lines.next().map(|l| split_csv_line(l, config.delimiter))
+    } else {
+        None
+    };
+    let header = header.as_deref();
+    let t_col = csv_column(&config.time, header)?;
+    let o_col = csv_column(&config.open, header)?;
+    let h_col = csv_column(&config.high, header)?;
+    let l_col = csv_column(&config.low, header)?;
+    let c_col = csv_column(&config.close, header)?;
+    let v_col = match &config.volume {
+        Some(v) => Some(csv_column(v, header)?),
+        None => None,
+    };
+
+    let mut out = vec![];
+    for (n, line) in lines.enumerate() {
+        let fields = split_csv_line(line, config.delimiter);
+        let field = |i: usize| -> Result<&str> {
+            fields.get(i).map(|f| f.as_str()).ok_or(anyhow![
+                "CSV row {} has no column {}",
+                n + 1,
+                i
+            ])
+        };
+        let num = |i: usize| -> Result<f64> {
+            field(i)?
+                .parse::<f64>()
+                .with_context(|| format!["CSV row {} column {}", n + 1, i])
+        };
+        let open_time = parse_csv_time(field(t_col)?, &config.time_format, &config.timezone)
+            .with_context(|| format!["CSV row {}", n + 1])?;
+        let (o, h, l, c) = (num(o_col)?, num(h_col)?, num(l_col)?, num(c_col)?);
+        let v = match v_col {
+            Some(i) => num(i)?,
+            None => 0.0,
+        };
+        let t = open_time.timestamp_millis();
+        let close_t = intv.next_open(t) - 1;
+        let close_time = DateTime::<Utc>::from_timestamp_millis(close_t).unwrap_or(open_time);
+        out.push((
+            t, open_time, o, h, l, c, v, close_t, close_time, 0.0, 0, 0.0, 0.0,
+        ));
+    }
+    out.sort_by_key(|k| k.0);
+    out.dedup_by_key(|k| k.0);
+    Ok(out)
+}
+
+#[derive(Debug)]
+pub struct SQLConn {
+    db_path: String,
+    hist_asset_data: Arc<Mutex<AssetData>>,
+    ///Only download 1m and derive the other intervals from it
+    pub derive_intervals: bool,
+    ///Minutes between scheduled updates, None if they're off
+    pub update_schedule: Option<u64>,
+    ///Running downloads, clones share them so cancel/pause reach any SQLConn's downloads
+    pub dl_jobs: DlJobs,
+    ///Historical klines loaded around viewed times, shared like dl_jobs
+    pub cache: Arc<Mutex<KlineCache>>,
+}
+impl Default for SQLConn {
+    fn default() -> Self {
+        Self {
+            db_path: databases_dir().to_string_lossy().to_string(),
+            hist_asset_data: Arc::new(Mutex::new(AssetData::new(666))),
+            derive_intervals: false,
+            update_schedule: None,
+            dl_jobs: DlJobs::default(),
+            cache: Arc::new(Mutex::new(KlineCache::default())),
+        }
+    }
+}
+
+pub(crate) async fn create_db(db_path: &str) -> Result<()> {
+    if !Sqlite::database_exists(db_path).await.unwrap_or(false) {
+        Sqlite::create_database(db_path).await?;
+        let pool = connect_sqlite(db_path).await?;
+        tracing::trace!("Created database {}", db_path);
+        exec_query(&pool, "PRAGMA foreign_keys=ON").await?;
+        Ok(())
+    } else {
+        tracing::trace!("Database already exists");
+        Ok(())
+    }
+}
+
+pub(crate) async fn cr_kl_tables(pool: &Pool<Sqlite>) -> Result<()> {
+    migrate_db(pool, &asset_migrations()).await?;
     Ok(())
 }
 
+///Ordered metadata.db migrations, a DB's schema version is the number of them applied
+fn metadata_migrations() -> Vec<Vec<String>> {
+    vec![
+        //1 tables as they were before versioning
+        vec![
+            "CREATE TABLE IF NOT EXISTS assets ( [Asset] TEXT, [Exchange] TEXT, [Status] TEXT, [BaseAsset] TEXT, [QouteAsset] TEXT, [Extras] TEXT, [Extras Multi] TEXT, [Start Time ms] INTEGER, [End Time ms] INTEGER, [Market cap] REAL )".to_string(),
+            "CREATE UNIQUE INDEX IF NOT EXISTS Asset ON assets ( [Asset] )".to_string(),
+            "CREATE TABLE IF NOT EXISTS assets_fut ( [Asset] TEXT, [Exchange] TEXT, [Status] TEXT, [BaseAsset] TEXT, [QouteAsset] TEXT, [Extras] TEXT, [Extras Multi] TEXT, [onboardDate] INTEGER, [deliveryDate] INTEGER, [Market cap] REAL )".to_string(),
+            "CREATE UNIQUE INDEX IF NOT EXISTS AssetFut ON assets_fut ( [Asset] )".to_string(),
+            //TODO make "default asset list for release version"
+            "CREATE TABLE IF NOT EXISTS assets_dl ( [Asset] TEXT, [Exchange] TEXT, [Start Time] INTEGER, [End Time] INTEGER )".to_string(),
+            "CREATE UNIQUE INDEX IF NOT EXISTS Asset_DL ON assets_dl ( [Asset] )".to_string(),
+        ],
+        //2 Market cap was added to the asset lists after the first release
+        vec![
+            "ALTER TABLE assets ADD COLUMN [Market cap] REAL".to_string(),
+            "ALTER TABLE assets_fut ADD COLUMN [Market cap] REAL".to_string(),
+        ],
+        //3 scheduled updates
+        vec![
+            "ALTER TABLE assets_dl ADD COLUMN [Last Update ms] INTEGER".to_string(),
+            "ALTER TABLE assets_dl ADD COLUMN [Update Status] TEXT".to_string(),
+            "CREATE TABLE IF NOT EXISTS update_schedule ( [Interval min] INTEGER )".to_string(),
+        ],
+        //4 failed scheduled updates wait for the next interval
+        vec!["ALTER TABLE assets_dl ADD COLUMN [Last Attempt ms] INTEGER".to_string()],
+    ]
+}
+
+///Ordered Asset*.db migrations, a DB's schema version is the number of them applied.
+///NOTE new intervals need a migration creating their kline table, 1 only runs once
+fn asset_migrations() -> Vec<Vec<String>> {
+    let kline_tables = Intv::iter()
+        .flat_map(|i| {
+            [
+                format!(
+                    "CREATE TABLE IF NOT EXISTS kline_{} ( [Timestamp MS] INTEGER, [Open Time] datetime,  Open REAL,  High REAL,  Low REAL,  Close REAL,  Volume REAL, [Close Timestamp MS] INTEGER, [Close Time] INTEGER,  [Quote Asset Volume] REAL,  [Number of Trades] INTEGER,  [Taker Buy Base Asset Volume] REAL,  [Taker Buy Quote Asset Volume] REAL,[Ignore], [Pattern hash] REAL )",
+                    i.to_str()
+                ),
+                format!(
+                    "CREATE UNIQUE INDEX IF NOT EXISTS time_index_{} ON kline_{} ( [Timestamp MS] )",
+                    i.to_str(),
+                    i.to_str()
+                ),
+            ]
+        })
+        .collect::<Vec<String>>();
+    let tables = vec![
+        "CREATE TABLE IF NOT EXISTS colstats ( Column TEXT, Type INTEGER, Mean REAL, [Standard Deviaton] REAL , No INTEGER )".to_string(),
+        "CREATE UNIQUE INDEX IF NOT EXISTS col_id ON colstats ( Column )".to_string(),
+        "CREATE TABLE IF NOT EXISTS metadata (Symbol TEXT, Exchange INTEGER, [Start Time] INTEGER, [End Time] INTEGER, [Key asset class 1] INTEGER, [Sub asset class 1] INTEGER ) ".to_string(),
+        "CREATE UNIQUE INDEX IF NOT EXISTS asset_id ON metadata ( Symbol )".to_string(),
+    ];
+    let pattern_hash = Intv::iter()
+        .map(|i| {
+            format![
+                "ALTER TABLE kline_{} ADD COLUMN [Pattern hash] REAL",
+                i.to_str()
+            ]
+        })
+        .collect();
+    vec![
+        //1 tables as they were before versioning
+        [kline_tables, tables].concat(),
+        //2 Pattern hash was added to the kline tables after the first release
+        pattern_hash,
+        //3 colstats per interval and rolling window
+        vec![
+            "DROP INDEX IF EXISTS col_id".to_string(),
+            "ALTER TABLE colstats ADD COLUMN Intv TEXT".to_string(),
+            "ALTER TABLE colstats ADD COLUMN [Window] INTEGER".to_string(),
+            "ALTER TABLE colstats ADD COLUMN Min REAL".to_string(),
+            "ALTER TABLE colstats ADD COLUMN Max REAL".to_string(),
+            "ALTER TABLE colstats ADD COLUMN [Last] REAL".to_string(),
+            "ALTER TABLE colstats ADD COLUMN [Updated ms] INTEGER".to_string(),
+            "CREATE UNIQUE INDEX IF NOT EXISTS col_intv_window ON colstats ( Column, Intv, [Window] )".to_string(),
+        ],
+        //4 funding rate history of futures
+        vec![
+            "CREATE TABLE IF NOT EXISTS funding_rate ( [Timestamp MS] INTEGER, [Funding rate] REAL, [Mark price] REAL )".to_string(),
+            "CREATE UNIQUE INDEX IF NOT EXISTS funding_time ON funding_rate ( [Timestamp MS] )".to_string(),
+        ],
+    ]
+}
+
+///Schema version of the DB, 0 if it has never been migrated
+pub async fn schema_version(pool: &Pool<Sqlite>) -> Result<usize> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS schema_version ( Version INTEGER PRIMARY KEY, [Applied ms] INTEGER )",
+    )
+    .execute(pool)
+    .await?;
+    let (v,): (Option<i64>,) = sqlx::query_as("SELECT MAX(Version) FROM schema_version")
+        .fetch_one(pool)
+        .await?;
+    Ok(v.unwrap_or(0) as usize)
+}
+
+///Runs the migrations the DB hasn't had yet, each in its own transaction, and returns the new
+///schema version. Adding a column that's already there is skipped, DBs from before versioning
+///have some of them.
+async fn migrate_db(pool: &Pool<Sqlite>, migrations: &[Vec<String>]) -> Result<usize> {
+    let version = schema_version(pool).await?;
+    if version > migrations.len() {
+        return Err(anyhow![
+            "DB schema version {} is newer than this build supports ({})",
+            version,
+            migrations.len()
+        ]);
+    };
+    for (n, migration) in migrations.iter().enumerate().skip(version) {
+        let mut tx = pool.begin().await?;
+        for q in migration {
+            let res = sqlx::query(q).execute(&mut *tx).await;
+            match res {
+                Ok(_) => (),
+                Err(e) if e.to_string().contains("duplicate column name") => {
+                    tracing::trace!["Migration {} column already exists: {}", n + 1, q];
+                }
+                Err(e) => {
+                    return Err(anyhow!["Migration {} failed on {}: {}", n + 1, q, e]);
+                }
+            };
+        }
+        sqlx::query("INSERT INTO schema_version (Version, [Applied ms]) VALUES (?, ?)")
+            .bind((n + 1) as i64)
+            .bind(Utc::now().timestamp_millis())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        tracing::debug!["Applied DB migration {}", n + 1];
+    }
+    Ok(migrations.len())
+}
+
 async fn update_asset_metadata_time(
     asset_pool: &Pool<Sqlite>,
     metadata_pool: &Pool<Sqlite>,
@@ -1323,6 +2945,8 @@ async fn update_asset_metadata_time(
     let ex = match exchange {
         "Binance" => Ok(0),
         "Yahoo" => Ok(1),
+        "Local" => Ok(2),
+        "Binance Futures" => Ok(3),
         _ => Err(anyhow!["Invalid exchange string"]),
     }?;
     let q = format!(
@@ -1334,16 +2958,27 @@ async fn update_asset_metadata_time(
     exec_query(&asset_pool, &q).await?;
     let q2 = format!(
         "
-    INSERT OR REPLACE INTO assets_dl ( Asset, Exchange, [End Time], [Start Time] )
-    VALUES('{}', '{}', {}, {});",
+    INSERT INTO assets_dl ( Asset, Exchange, [End Time], [Start Time] )
+    VALUES('{}', '{}', {}, {})
+    ON CONFLICT(Asset) DO UPDATE SET Exchange = excluded.Exchange,
+    [End Time] = excluded.[End Time], [Start Time] = excluded.[Start Time];",
         symbol, exchange, et, st,
     );
     exec_query(&metadata_pool, &q2).await?;
     Ok(())
 }
 
+///Whether the futures symbol (local or Binance name) is in the USDⓈ-M asset list
+async fn validate_asset_binance_fut(meta_pool: &Pool<Sqlite>, symbol: &str) -> Result<bool> {
+    let res: Option<(String,)> = sqlx::query_as("SELECT [Asset] FROM assets_fut WHERE Asset = ?;")
+        .bind(exchange_symbol(symbol))
+        .fetch_optional(meta_pool)
+        .await?;
+    Ok(res.is_some())
+}
+
 pub async fn validate_asset_dl(symbol: &str) -> Result<bool> {
-    let meta_pool = SqlitePool::connect(&METADATA_DB_PATH)
+    let meta_pool = SqlitePool::connect(&metadata_db_path())
         .await
         .context(anyhow!("SQL::Unable to metadata connect to db"))?;
     let res: (Option<String>,) = sqlx::query_as(
@@ -1363,7 +2998,7 @@ pub async fn validate_asset_dl(symbol: &str) -> Result<bool> {
     }
 }
 pub async fn validate_asset_binance(symbol: &str) -> Result<bool> {
-    let meta_pool = SqlitePool::connect(&METADATA_DB_PATH)
+    let meta_pool = SqlitePool::connect(&metadata_db_path())
         .await
         .context(anyhow!("SQL::Unable to metadata connect to db"))?;
     let res: (Option<String>,) = sqlx::query_as(
@@ -1390,8 +3025,11 @@ pub async fn get_asset_bases_binance2(
         "SELECT [BaseAsset], [QouteAsset] FROM assets WHERE Asset = '{}';",
         symbol
     ];
-    let (base, qoute): (Option<String>, Option<String>) =
-        sqlx::query_as(q).fetch_one(meta_pool).await?;
+    //NOTE Yahoo and imported assets aren't in the Binance asset list
+    let (base, qoute): (Option<String>, Option<String>) = sqlx::query_as(q)
+        .fetch_optional(meta_pool)
+        .await?
+        .unwrap_or((None, None));
     let res = match (base, qoute) {
         (Some(bb), Some(qq)) => Some((bb, qq)),
         _ => None,
@@ -1400,7 +3038,7 @@ pub async fn get_asset_bases_binance2(
 }
 
 pub async fn get_asset_bases_binance(symbol: &str) -> Result<Option<(String, String)>> {
-    let meta_pool = SqlitePool::connect(&METADATA_DB_PATH)
+    let meta_pool = SqlitePool::connect(&metadata_db_path())
         .await
         .context(anyhow!("SQL::Unable to metadata connect to db"))?;
 
@@ -1437,7 +3075,7 @@ pub async fn get_asset_timestamps(
     };
     let qf = &format![
         "SELECT [onboardDate], [deliveryDate] FROM assets_fut WHERE Asset = '{}';",
-        symbol
+        exchange_symbol(symbol)
     ];
     let timestamps_fut: (Option<i64>, Option<i64>) =
         sqlx::query_as(qf).fetch_one(metadata_db).await?;
@@ -1446,8 +3084,14 @@ pub async fn get_asset_timestamps(
 
 impl SQLConn {
     pub fn new(hist_asset_data: Arc<Mutex<AssetData>>) -> Self {
+        let dl_jobs = hist_asset_data
+            .lock()
+            .expect("Posioned AD mutex! (DATA)")
+            .dl_jobs
+            .clone();
         Self {
             hist_asset_data,
+            dl_jobs,
             ..Default::default()
         }
     }
@@ -1468,6 +3112,7 @@ impl SQLConn {
         symbol: &str,
         trade_time: &i64,
         backload_wicks: &i64,
+        intv: &Intv,
     ) -> Result<()> {
         let now = Instant::now();
 
@@ -1475,7 +3120,7 @@ impl SQLConn {
             .await
             .context("SQL : unable to connect to db")?;
 
-        let meta_pool = SqlitePool::connect(&METADATA_DB_PATH)
+        let meta_pool = SqlitePool::connect(&metadata_db_path())
             .await
             .context(anyhow!("SQL::Unable to metadata connect to db"))?;
 
@@ -1485,8 +3130,9 @@ impl SQLConn {
         tracing::trace!("load_part_data2 Elapsed pool connect: {:?}", elapsed);
 
         let mut klines = Klines::new_empty();
-        for intv in Intv::iter() {
-            let s: &str = intv.to_str();
+        let custom = Some(*intv).filter(|i| i.is_custom());
+        for intv in Intv::iter().chain(custom) {
+            let s = intv.label();
             tracing::trace!("load_part_data2 Loading data for:{} interval:{}", symbol, s);
             let offset = match &intv {
                 Intv::Min1 => 0,
@@ -1499,6 +3145,7 @@ impl SQLConn {
                 &intv,
                 *trade_time - offset,
                 *backload_wicks as usize,
+                &self.cache,
             )
             .await
             .context("SQL : unable to connect to db")?;
@@ -1508,23 +3155,24 @@ impl SQLConn {
                 tracing::trace![
                     "KLINE WSCHECK empty for SYMBOL:{} INTERVAL: {}",
                     symbol,
-                    intv.to_str()
+                    intv.label()
                 ];
             };
         }
+        //NOTE all of it, a few rows a day, so the Hist Trade pane can charge it
+        if is_fut_symbol(symbol) {
+            klines.funding = load_funding_db(&pool, None).await?;
+        };
         pool.close().await;
         meta_pool.close().await;
+        self.publish_cache_usage();
         let ad_a = Arc::clone(&mut self.hist_asset_data);
         let mut ad = ad_a.lock().expect("Posioned AD mutex! (DATA)");
-        if let Some(_) = ad.kline_data.get(symbol) {
-            ad.kline_data.remove(symbol);
-        };
-        if let Some((s1, s2)) = bases {
-            klines.asset_pair = symbol.to_string();
-            klines.s1_string = s1;
-            klines.s2_string = s2;
-        };
-        ad.kline_data.insert(symbol.to_string(), klines);
+        let (s1, s2) = bases.unwrap_or((symbol.to_string(), "Cash".to_string()));
+        klines.asset_pair = symbol.to_string();
+        klines.s1_string = s1;
+        klines.s2_string = s2;
+        ad.insert_loaded(symbol, klines);
         let elapsed = now.elapsed();
         tracing::trace!("load_part_data2 Elapsed total: {:?}", elapsed);
         Ok(())
@@ -1561,21 +3209,11 @@ impl SQLConn {
         let pool = connect_sqlite(format!["{}/Asset{}.db", &self.db_path, &symbol])
             .await
             .context("SQL : unable to connect to db")?;
-        let mut klines = Klines::new_empty();
-        for intv in Intv::iter() {
-            let s: &str = intv.to_str();
-            tracing::trace!("Loading data for:{} interval:{}", symbol, s);
-            let k: Kline = kfrom_sql(&pool, &format!["kline_{}", &s], None)
-                .await
-                .context("SQL : unable to connect to db")?;
-
-            tracing::trace![
-                "LOAD ALL DATA - START:{} END:{}",
-                k.kline[0].0,
-                k.kline[k.kline.len() - 1].0
-            ];
-            klines.dat.insert(intv, k);
-        }
+        //NOTE only the newest candles, through the cache so a symbol can't take more than its limit
+        let res = load_latest_cached(&self.cache, &pool, symbol, CACHE_CHUNK_CANDLES).await;
+        pool.close().await;
+        let klines = res?;
+        self.publish_cache_usage();
         let ad_a = Arc::clone(&mut self.hist_asset_data);
         let mut ad = ad_a.lock().expect("Posioned AD mutex! (DATA)");
         let search = ad.kline_data.get(symbol);
@@ -1583,7 +3221,7 @@ impl SQLConn {
             Some(_) => {
                 return Err(anyhow!["Data for {} already present", symbol]);
             }
-            None => ad.kline_data.insert(symbol.to_string(), klines),
+            None => ad.insert_loaded(symbol, klines),
         };
         tracing::trace!["Asset Data SQL CONNECT = {}", ad.debug()];
         Ok(())
@@ -1599,7 +3237,7 @@ impl SQLConn {
             asset_symbol
         );
         exec_query(&meta_pool, &q).await?;
-        let db_path = format!["./databases/Asset{}.db", asset_symbol];
+        let db_path = asset_db_path(asset_symbol);
         let file_path = std::path::Path::new(&db_path);
         let fs_path = format!["{}-shm", &db_path];
         let ws_path = format!["{}-wal", &db_path];
@@ -1608,60 +3246,516 @@ impl SQLConn {
         std::fs::remove_file(file_path)?;
         let _res = std::fs::remove_file(file_path_shm);
         let _res = std::fs::remove_file(file_path_wal);
+        {
+            let _guard = lock_dl_errors(asset_symbol).await;
+            let _res = std::fs::remove_file(dl_errors_path(asset_symbol));
+        };
+        self.invalidate_cache(asset_symbol);
+        let mut ad = self
+            .hist_asset_data
+            .lock()
+            .expect("Posioned AD mutex! (DATA)");
+        ad.col_stats.remove(asset_symbol);
         Ok(())
     }
-    pub async fn del_all_assets(&self) -> Result<()> {
-        todo!()
+    ///Audits the asset DB of symbol, with repair the gaps are downloaded again. Gaps Binance has
+    ///no data for stay in the report, chunks that fail to download are saved like in updates.
+    pub async fn audit_asset(&self, symbol: &str, repair: bool) -> Result<Option<AuditReport>> {
+        let db_path = format!["{}/Asset{}.db", self.db_path, symbol];
+        if !Sqlite::database_exists(&db_path).await? {
+            return Ok(None);
+        };
+        let pool = connect_sqlite(&db_path).await?;
+        let mut report = audit_asset_db(&pool, symbol).await?;
+        if repair && report.intvs.iter().any(|a| !a.gaps.is_empty()) {
+            let source = BinanceSource::for_symbol(symbol);
+            let mut errors: Vec<(Intv, Vec<(u64, u64)>)> = vec![];
+            for a in report.intvs.iter() {
+                let mut failed = vec![];
+                for (st, et) in a.gaps.iter() {
+                    self.set_dl_status(format![
+                        "Repairing {} {} gap {} to {}",
+                        symbol,
+                        a.intv.to_str(),
+                        st,
+                        et
+                    ]);
+                    let job = DlJob::default();
+                    let res =
+                        get_data_source(&source, symbol, a.intv, &pool, st - 1, *et, &job).await;
+                    match res {
+                        Ok(Some(mut e)) => failed.append(&mut e),
+                        Ok(None) => (),
+                        Err(e) => {
+                            tracing::error!["Repairing gap {} to {}: {:?}", st, et, e];
+                            failed.push(((st - 1) as u64, *et as u64));
+                        }
+                    };
+                }
+                if !failed.is_empty() {
+                    errors.push((a.intv, failed));
+                };
+            }
+            if !errors.is_empty() {
+                RemErrors::merge_saved(symbol, errors).await?;
+            };
+            self.invalidate_cache(symbol);
+            self.refresh_derived(symbol, false).await;
+            report = audit_asset_db(&pool, symbol).await?;
+        };
+        pool.close().await;
+        self.set_dl_status(report.summary());
+        let mut ad = self
+            .hist_asset_data
+            .lock()
+            .expect("Posioned AD mutex! (DATA)");
+        ad.audit_reports.insert(symbol.to_string(), report.clone());
+        Ok(Some(report))
     }
-    pub async fn dl_single_asset_bin_wrap(&self, asset_symbol: &str) -> Result<()> {
-        let exchange = "Binance";
-        let meta_pool = SqlitePool::connect(&METADATA_DB_PATH)
+    ///Rebuilds all intervals of symbol from its 1m data, with repair candles that differ from the
+    ///1m data are replaced
+    pub async fn derive_asset_intervals(
+        &self,
+        symbol: &str,
+        repair: bool,
+    ) -> Result<Option<DeriveReport>> {
+        let db_path = format!["{}/Asset{}.db", self.db_path, symbol];
+        if !Sqlite::database_exists(&db_path).await? {
+            return Ok(None);
+        };
+        let pool = connect_sqlite(&db_path).await?;
+        self.set_dl_status(format!["Deriving intervals for {} from 1m", symbol]);
+        let res = derive_intervals(&pool, 0, false, repair).await;
+        pool.close().await;
+        self.invalidate_cache(symbol);
+        let report = res?;
+        self.refresh_derived(symbol, true).await;
+        self.set_dl_status(format![
+            "Derived {} candles for {}, {} of {} existing candles didn't match",
+            report.written,
+            symbol,
+            report.mismatched.len(),
+            report.compared
+        ]);
+        Ok(Some(report))
+    }
+    ///Retries the saved failed chunks of a symbol, returns true if nothing is missing anymore
+    pub async fn retry_dl_errors(&self, symbol: &str, job: &DlJob) -> Result<bool> {
+        //NOTE held for the whole retry, errors saved meanwhile would be overwritten below
+        let _guard = lock_dl_errors(symbol).await;
+        let Some(rem_errors) = RemErrors::load(symbol)? else {
+            return Ok(true);
+        };
+        let path = dl_errors_path(symbol);
+        if !Sqlite::database_exists(&asset_db_path(symbol)).await? {
+            //NOTE the asset will be downloaded from scratch anyway
+            std::fs::remove_file(&path)?;
+            return Ok(true);
+        };
+        let mut errors = rem_errors.errors;
+        let mut retries: usize = 0;
+        while retries < 3 && !job.is_cancelled() {
+            let chunks: usize = errors.iter().map(|(_, e)| e.len()).sum();
+            self.set_dl_status(format![
+                "Retrying {} missing chunks for {} ({}/3)",
+                chunks,
+                symbol,
+                retries + 1
+            ]);
+            let source = BinanceSource::for_symbol(symbol);
+            let db_path = asset_db_path(symbol);
+            match iterate_over_remaining_errors(&source, &db_path, errors, symbol, job).await? {
+                Some(e) => {
+                    errors = e;
+                    retries += 1;
+                }
+                None => {
+                    std::fs::remove_file(&path)?;
+                    self.set_dl_status(format!["All missing chunks for {} downloaded", symbol]);
+                    return Ok(true);
+                }
+            };
+        }
+        let rem_errors = RemErrors {
+            symbol: symbol.to_string(),
+            errors,
+        };
+        self.set_dl_status(format![
+            "{} is incomplete, {} chunks still missing",
+            symbol,
+            rem_errors.chunks()
+        ]);
+        rem_errors.save_to_file(&path)?;
+        Ok(false)
+    }
+    ///Retries the failed chunks of every downloaded asset, used on app start
+    pub async fn retry_all_dl_errors(&mut self) -> Result<()> {
+        ensure_metadata_db().await?;
+        let meta_pool = SqlitePool::connect(&metadata_db_path())
             .await
             .context(anyhow!("SQL::Unable to metadata connect to db"))?;
-        let current_timestamp: i64 = match SystemTime::now().duration_since(UNIX_EPOCH) {
-            Ok(n) => {
-                let r = n.as_millis().try_into();
-                match r {
-                    Ok(n) => Ok(n),
-                    Err(e) => Err(anyhow!["{:?}", e]),
+        let asset_list = dl_load_asset_list(&meta_pool).await?;
+        meta_pool.close().await;
+        let mut retried = false;
+        for (symbol, exchange) in asset_list.iter() {
+            let binance = matches!(exchange.as_str(), "Binance" | "Binance Futures");
+            if !binance || RemErrors::load(symbol)?.is_none() {
+                continue;
+            };
+            retried = true;
+            let job = match self.dl_jobs.start(symbol) {
+                Ok(j) => j,
+                Err(e) => {
+                    tracing::error!["{:?}", e];
+                    continue;
                 }
-            }
-            Err(e) => Err(anyhow!["{:?}", e]),
-        }?;
-        self.download_single_asset(asset_symbol, exchange, &meta_pool, current_timestamp)
-            .await?;
+            };
+            let res = self.retry_dl_errors(symbol, &job).await.map(|_| ());
+            job.finish(&res);
+            self.dl_jobs.remove(symbol);
+            match res {
+                Ok(_) => (),
+                Err(e) => tracing::error!["Error retrying failed chunks for {}: {:?}", symbol, e],
+            };
+        }
+        if retried {
+            self.load_asset_list().await?;
+        };
         Ok(())
     }
-    pub async fn download_single_asset(
-        &self,
-        asset_symbol: &str,
-        exchange: &str,
-        meta_pool: &Pool<Sqlite>,
-        current_timestamp: i64,
-    ) -> Result<()> {
-        let db_path = format!["./databases/Asset{}.db", asset_symbol];
-        let exch = Exchange::from(exchange);
-
-        let start_time = if Sqlite::database_exists(&db_path).await? == false {
-            let (start_time, _) = get_asset_timestamps(&asset_symbol, &meta_pool).await?;
-            let init_time = match start_time {
-                Some(st) => st,
-                None => BIN_TIMESTAMP,
+    ///Incrementally updates every downloaded asset that wasn't updated in the last interval_min
+    ///minutes, returns the number of assets updated
+    pub async fn scheduled_update(&mut self, interval_min: u64) -> Result<usize> {
+        ensure_metadata_db().await?;
+        let meta_pool = SqlitePool::connect(&metadata_db_path())
+            .await
+            .context(anyhow!("SQL::Unable to metadata connect to db"))?;
+        let now = Utc::now().timestamp_millis();
+        let due = due_assets(&meta_pool, now, interval_min as i64 * 60_000).await?;
+        if due.is_empty() {
+            meta_pool.close().await;
+            tracing::debug!["Scheduled update: all assets are current"];
+            return Ok(0);
+        };
+        let mut failed = 0;
+        for (n, (symbol, exchange)) in due.iter().enumerate() {
+            self.set_dl_status(format![
+                "Scheduled update: {} ({}/{})",
+                symbol,
+                n + 1,
+                due.len()
+            ]);
+            let res = self
+                .download_single_asset(symbol, exchange, &meta_pool, now)
+                .await;
+            if let Err(e) = &res {
+                tracing::error!["Scheduled update of {} failed: {:?}", symbol, e];
+                failed += 1;
             };
-            tracing::debug![
-                "download_single_asset initial_time {}",
-                DateTime::<Utc>::from_timestamp_millis(init_time).unwrap()
-            ];
-            create_db(&db_path).await?;
-            let apool = connect_sqlite(&db_path).await?;
-            cr_kl_tables(&apool).await?;
-            tracing::debug!["Created database {} and created tables", &db_path];
-            apool.close().await;
-            let _res = download_asset_data(&asset_symbol, &exch, init_time).await?;
-            init_time
+            record_update_status(&meta_pool, symbol, now, &res).await?;
+        }
+        meta_pool.close().await;
+        self.load_asset_list().await?;
+        self.set_dl_status(format![
+            "Scheduled update done: {} updated, {} failed",
+            due.len() - failed,
+            failed
+        ]);
+        Ok(due.len() - failed)
+    }
+    ///Saves the scheduled update interval, None turns scheduled updates off
+    pub async fn set_update_schedule(&mut self, interval_min: Option<u64>) -> Result<()> {
+        ensure_metadata_db().await?;
+        let meta_pool = SqlitePool::connect(&metadata_db_path())
+            .await
+            .context(anyhow!("SQL::Unable to metadata connect to db"))?;
+        let res = save_update_schedule(&meta_pool, interval_min).await;
+        meta_pool.close().await;
+        res?;
+        self.update_schedule = interval_min;
+        let mut ad = self
+            .hist_asset_data
+            .lock()
+            .expect("Posioned AD mutex! (DATA)");
+        ad.update_schedule = interval_min;
+        Ok(())
+    }
+    ///Update interval as last set by any SQLConn sharing hist_asset_data
+    pub fn shared_update_schedule(&self) -> Option<u64> {
+        let ad = self
+            .hist_asset_data
+            .lock()
+            .expect("Posioned AD mutex! (DATA)");
+        ad.update_schedule
+    }
+    ///Loads the saved update interval, called when the SQL task starts
+    pub async fn load_update_schedule(&mut self) -> Result<Option<u64>> {
+        ensure_metadata_db().await?;
+        let meta_pool = SqlitePool::connect(&metadata_db_path())
+            .await
+            .context(anyhow!("SQL::Unable to metadata connect to db"))?;
+        let res = load_update_schedule(&meta_pool).await;
+        meta_pool.close().await;
+        self.update_schedule = res?;
+        let mut ad = self
+            .hist_asset_data
+            .lock()
+            .expect("Posioned AD mutex! (DATA)");
+        ad.update_schedule = self.update_schedule;
+        Ok(self.update_schedule)
+    }
+    ///Updates and saves the column statistics of symbol with its new candles, full recomputes
+    ///them from every candle (after rewriting existing ones). None if it has no asset DB.
+    pub async fn update_col_stats(&self, symbol: &str, full: bool) -> Result<Option<Vec<ColStat>>> {
+        let db_path = format!["{}/Asset{}.db", self.db_path, symbol];
+        if !Sqlite::database_exists(&db_path).await? {
+            return Ok(None);
+        };
+        let pool = connect_sqlite(&db_path).await?;
+        let saved = match full {
+            true => Ok(vec![]),
+            false => load_col_stats(&pool).await,
+        };
+        let res = match saved {
+            Ok(saved) => compute_col_stats(&pool, &saved).await,
+            Err(e) => Err(e),
+        };
+        let res = match res {
+            Ok(stats) => save_col_stats(&pool, &stats).await.map(|_| stats),
+            Err(e) => Err(e),
+        };
+        pool.close().await;
+        let stats = res?;
+        self.publish_col_stats(symbol, &stats);
+        Ok(Some(stats))
+    }
+    ///Saved column statistics of symbol, computed first if the asset has none yet
+    pub async fn load_col_stats(&self, symbol: &str) -> Result<Vec<ColStat>> {
+        let db_path = format!["{}/Asset{}.db", self.db_path, symbol];
+        if !Sqlite::database_exists(&db_path).await? {
+            return Err(anyhow!["{} has no downloaded data", symbol]);
+        };
+        let pool = connect_sqlite(&db_path).await?;
+        let res = load_col_stats(&pool).await;
+        pool.close().await;
+        let stats = res?;
+        if stats.is_empty() {
+            return Ok(self
+                .update_col_stats(symbol, true)
+                .await?
+                .unwrap_or_default());
+        };
+        self.publish_col_stats(symbol, &stats);
+        Ok(stats)
+    }
+    ///Hashes the candles of symbol that don't have a Pattern hash yet
+    pub async fn update_pattern_hashes(&self, symbol: &str) -> Result<usize> {
+        let db_path = format!["{}/Asset{}.db", self.db_path, symbol];
+        if !Sqlite::database_exists(&db_path).await? {
+            return Ok(0);
+        };
+        let pool = connect_sqlite(&db_path).await?;
+        let res = update_pattern_hashes(&pool).await;
+        pool.close().await;
+        res
+    }
+    ///Statistics and pattern hashes are a side product of writing klines, failing them doesn't
+    ///fail the write. full_stats after rewriting existing candles.
+    async fn refresh_derived(&self, symbol: &str, full_stats: bool) {
+        let res = self.update_col_stats(symbol, full_stats).await;
+        match res {
+            Ok(_) => (),
+            Err(e) => tracing::error!["Error updating column statistics of {}: {:?}", symbol, e],
+        };
+        let res = self.update_pattern_hashes(symbol).await;
+        match res {
+            Ok(n) => tracing::debug!["Hashed {} candle patterns of {}", n, symbol],
+            Err(e) => tracing::error!["Error updating pattern hashes of {}: {:?}", symbol, e],
+        };
+    }
+    ///The PATTERN_MATCHES windows of every downloaded asset most similar to the candles of
+    ///symbol closed by time
+    pub async fn find_similar_patterns(
+        &self,
+        symbol: &str,
+        intv: &Intv,
+        time: i64,
+    ) -> Result<Vec<PatternMatch>> {
+        if intv.is_custom() {
+            return Err(anyhow![
+                "Patterns are only hashed for stored intervals, not {}",
+                intv.label()
+            ]);
+        };
+        let db_path = format!["{}/Asset{}.db", self.db_path, symbol];
+        if !Sqlite::database_exists(&db_path).await? {
+            return Err(anyhow!["{} has no downloaded data", symbol]);
+        };
+        let pool = connect_sqlite(&db_path).await?;
+        let res = query_pattern(&pool, intv, time).await;
+        pool.close().await;
+        let (query, query_time) = res?;
+
+        ensure_metadata_db().await?;
+        let meta_pool = SqlitePool::connect(&metadata_db_path())
+            .await
+            .context(anyhow!("SQL::Unable to metadata connect to db"))?;
+        let asset_list = dl_load_asset_list(&meta_pool).await;
+        meta_pool.close().await;
+        let mut matches = vec![];
+        for (asset, _) in asset_list?.iter() {
+            let db_path = format!["{}/Asset{}.db", self.db_path, asset];
+            if !Sqlite::database_exists(&db_path).await? {
+                continue;
+            };
+            let pool = connect_sqlite(&db_path).await?;
+            //NOTE assets from before pattern hashes get them on their first search
+            let res = match update_pattern_hashes_intv(&pool, intv).await {
+                Ok(_) => {
+                    let exclude = (asset == symbol).then_some(query_time);
+                    scan_patterns(&pool, asset, intv, query, exclude, PATTERN_MATCHES).await
+                }
+                Err(e) => Err(e),
+            };
+            pool.close().await;
+            match res {
+                Ok(mut m) => matches.append(&mut m),
+                Err(e) => tracing::error!["Pattern search in {} failed: {:?}", asset, e],
+            };
+        }
+        matches.sort_by_key(|m| m.distance);
+        matches.truncate(PATTERN_MATCHES);
+        let mut ad = self
+            .hist_asset_data
+            .lock()
+            .expect("Posioned AD mutex! (DATA)");
+        ad.pattern_matches = matches.clone();
+        Ok(matches)
+    }
+    fn publish_col_stats(&self, symbol: &str, stats: &[ColStat]) {
+        let mut ad = self
+            .hist_asset_data
+            .lock()
+            .expect("Posioned AD mutex! (DATA)");
+        ad.col_stats.insert(symbol.to_string(), stats.to_vec());
+    }
+    fn invalidate_cache(&self, symbol: &str) {
+        self.cache
+            .lock()
+            .expect("Poisoned kline cache mutex")
+            .invalidate(symbol);
+        self.publish_cache_usage();
+    }
+    fn publish_cache_usage(&self) {
+        let usage = self
+            .cache
+            .lock()
+            .expect("Poisoned kline cache mutex")
+            .usage();
+        let mut ad = self
+            .hist_asset_data
+            .lock()
+            .expect("Posioned AD mutex! (DATA)");
+        ad.cache_usage = usage;
+    }
+    pub fn set_cache_limit(&self, limit_mb: u64) {
+        self.cache
+            .lock()
+            .expect("Poisoned kline cache mutex")
+            .set_limit(limit_mb as usize * 1024 * 1024);
+        self.publish_cache_usage();
+    }
+    fn set_dl_status(&self, status: String) {
+        tracing::info!["{}", status];
+        let mut ad = self
+            .hist_asset_data
+            .lock()
+            .expect("Posioned AD mutex! (DATA)");
+        ad.dl_status = status;
+    }
+    pub async fn del_all_assets(&self) -> Result<()> {
+        todo!()
+    }
+    pub async fn dl_single_asset_bin_wrap(&self, asset_symbol: &str) -> Result<()> {
+        let exchange = match is_fut_symbol(asset_symbol) {
+            true => "Binance Futures",
+            false => "Binance",
+        };
+        let meta_pool = SqlitePool::connect(&metadata_db_path())
+            .await
+            .context(anyhow!("SQL::Unable to metadata connect to db"))?;
+        let current_timestamp: i64 = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(n) => {
+                let r = n.as_millis().try_into();
+                match r {
+                    Ok(n) => Ok(n),
+                    Err(e) => Err(anyhow!["{:?}", e]),
+                }
+            }
+            Err(e) => Err(anyhow!["{:?}", e]),
+        }?;
+        self.download_single_asset(asset_symbol, exchange, &meta_pool, current_timestamp)
+            .await?;
+        Ok(())
+    }
+    ///Downloads/updates one asset as a job that can be paused and cancelled
+    pub async fn download_single_asset(
+        &self,
+        asset_symbol: &str,
+        exchange: &str,
+        meta_pool: &Pool<Sqlite>,
+        current_timestamp: i64,
+    ) -> Result<()> {
+        let job = self.dl_jobs.start(asset_symbol)?;
+        let res = self
+            .download_single_asset_job(asset_symbol, exchange, meta_pool, current_timestamp, &job)
+            .await;
+        job.finish(&res);
+        self.dl_jobs.remove(asset_symbol);
+        //NOTE even failed downloads can have written some chunks
+        self.invalidate_cache(asset_symbol);
+        self.refresh_derived(asset_symbol, false).await;
+        res
+    }
+    async fn download_single_asset_job(
+        &self,
+        asset_symbol: &str,
+        exchange: &str,
+        meta_pool: &Pool<Sqlite>,
+        current_timestamp: i64,
+        job: &DlJob,
+    ) -> Result<()> {
+        let db_path = asset_db_path(asset_symbol);
+        let exch: Exchange = exchange.parse()?;
+        if let Exchange::Local = exch {
+            tracing::debug![
+                "download_single_asset {} is imported data, skipping",
+                asset_symbol
+            ];
+            return Ok(());
+        };
+
+        let start_time = if !Sqlite::database_exists(&db_path).await? {
+            let (start_time, _) = get_asset_timestamps(asset_symbol, meta_pool).await?;
+            let init_time = match (start_time, &exch) {
+                (Some(st), _) => st,
+                (None, Exchange::Yahoo) => YF_TIMESTAMP,
+                (None, _) => BIN_TIMESTAMP,
+            };
+            tracing::debug![
+                "download_single_asset initial_time {}",
+                DateTime::<Utc>::from_timestamp_millis(init_time).unwrap()
+            ];
+            create_db(&db_path).await?;
+            let apool = connect_sqlite(&db_path).await?;
+            cr_kl_tables(&apool).await?;
+            tracing::debug!["Created database {} and created tables", &db_path];
+            apool.close().await;
+            download_asset_data(asset_symbol, &exch, init_time, self.derive_intervals, job).await?;
+            init_time
         } else {
             let (start_time_ms, end_time_ms) =
-                get_asset_timestamps(&asset_symbol, &meta_pool).await?;
+                get_asset_timestamps(asset_symbol, meta_pool).await?;
             tracing::debug![
                 "start_time_ms {:?} end_time_ms {:?} ",
                 start_time_ms,
@@ -1681,17 +3775,31 @@ impl SQLConn {
                 }
                 None => BIN_TIMESTAMP,
             };
+            if let Exchange::Binance | Exchange::BinanceFutures = exch {
+                let res = self.retry_dl_errors(asset_symbol, job).await;
+                match res {
+                    Ok(_) => (),
+                    Err(e) => tracing::error!["Error retrying failed chunks: {:?}", e],
+                };
+            };
             tracing::debug!["download_single_asset_ get_from_time {}", get_from_time];
-            let _res = download_asset_data(&asset_symbol, &exch, get_from_time).await?;
+            download_asset_data(
+                asset_symbol,
+                &exch,
+                get_from_time,
+                self.derive_intervals,
+                job,
+            )
+            .await?;
             start_time
         };
 
         let apool = connect_sqlite(&db_path).await?;
         let res = update_asset_metadata_time(
             &apool,
-            &meta_pool,
+            meta_pool,
             asset_symbol,
-            "Binance",
+            exchange,
             start_time,
             current_timestamp - 6000 * 30, //ROllback start time by 30 minutes and set
                                            //current_timestamp as END_TIME for the data.
@@ -1708,11 +3816,11 @@ impl SQLConn {
     }
     pub async fn update_data(&mut self) -> Result<()> {
         tracing::trace!["Update data called!"];
-        if Sqlite::database_exists(&METADATA_DB_PATH).await? == false {
+        if !Sqlite::database_exists(&metadata_db_path()).await? {
             create_metadata_db().await?;
             tracing::trace!["Metadata DB created"];
         };
-        let meta_pool = SqlitePool::connect(&METADATA_DB_PATH)
+        let meta_pool = SqlitePool::connect(&metadata_db_path())
             .await
             .context(anyhow!("SQL::Unable to metadata connect to db"))?;
 
@@ -1737,15 +3845,17 @@ impl SQLConn {
         //doesn't have it... idk
         meta_pool.close().await;
 
-        let meta_pool = SqlitePool::connect(&METADATA_DB_PATH)
+        let meta_pool = SqlitePool::connect(&metadata_db_path())
             .await
             .context(anyhow!("SQL::Unable to metadata connect to db"))?;
 
         for (asset_symbol, exchange) in asset_list {
-            let _ = self
+            let res = self
                 .download_single_asset(&asset_symbol, &exchange, &meta_pool, current_timestamp_ms)
                 .await;
+            record_update_status(&meta_pool, &asset_symbol, current_timestamp_ms, &res).await?;
         }
+        meta_pool.close().await;
         self.load_asset_list().await?;
         tracing::trace!["Data update ran successfully!"];
         Ok(())
@@ -1753,6 +3863,7 @@ impl SQLConn {
     async fn unload_data(&mut self, symbol: &str) -> Result<()> {
         let ad_a = Arc::clone(&mut self.hist_asset_data);
         let mut ad = ad_a.lock().expect("Posioned AD mutex! (DATA)");
+        ad.load_order.retain(|s| s != symbol);
         let result = ad.kline_data.get(symbol);
         match result {
             Some(_) => {
@@ -1764,7 +3875,7 @@ impl SQLConn {
     }
     async fn load_asset_list(&mut self) -> Result<()> {
         let ad_a = Arc::clone(&mut self.hist_asset_data);
-        let meta_pool = SqlitePool::connect(&METADATA_DB_PATH)
+        let meta_pool = SqlitePool::connect(&metadata_db_path())
             .await
             .context(anyhow!("SQL::Unable to metadata connect to db"))?;
         let dl_list = load_asset_list_ad(&meta_pool).await?;
@@ -1774,18 +3885,35 @@ impl SQLConn {
     }
     async fn insert_dl_asset(&self, symbol: &str, exchange: &str) -> Result<()> {
         tracing::debug!["Insert DL asset called!"];
-        let meta_pool = SqlitePool::connect(&METADATA_DB_PATH)
+        let exch: Exchange = exchange.parse()?;
+        let meta_pool = SqlitePool::connect(&metadata_db_path())
             .await
             .context(anyhow!("SQL::Unable to metadata connect to db"))?;
+        let symbol = &match exch {
+            Exchange::BinanceFutures => fut_symbol(symbol),
+            _ => symbol.to_string(),
+        };
 
-        let validate = self
-            .validate_asset_binance(&meta_pool, symbol, exchange)
-            .await?;
+        let validate = match exch {
+            Exchange::Binance => {
+                self.validate_asset_binance(&meta_pool, symbol, exchange)
+                    .await?
+            }
+            Exchange::BinanceFutures => validate_asset_binance_fut(&meta_pool, symbol).await?,
+            #[cfg(feature = "yfinance")]
+            Exchange::Yahoo => validate_asset_yahoo(&YfClient::default(), symbol).await?,
+            #[cfg(not(feature = "yfinance"))]
+            Exchange::Yahoo => {
+                meta_pool.close().await;
+                return Err(anyhow!["Yahoo assets need the yfinance feature"]);
+            }
+            Exchange::Local => true,
+        };
         tracing::debug!["Asset validated! {:?}", validate];
         match validate {
             true => (),
             false => {
-                tracing::error!["Asset symbol:{} not found on Binance!", symbol];
+                tracing::error!["Asset symbol:{} not found on {}!", symbol, exchange];
                 meta_pool.close().await;
                 return Ok(());
             }
@@ -1837,6 +3965,293 @@ impl SQLConn {
             None => Ok(false),
         }
     }
+    ///Headless download: refreshes the Binance asset list, adds the symbol to assets_dl and
+    ///downloads/updates it. Returns false if the symbol is not listed on Binance.
+    pub async fn add_and_download(&mut self, symbol: &str) -> Result<bool> {
+        ensure_metadata_db().await?;
+        let meta_pool = SqlitePool::connect(&metadata_db_path())
+            .await
+            .context(anyhow!("SQL::Unable to metadata connect to db"))?;
+        download_asset_list_binance(&meta_pool).await?;
+        let (listed, exchange) = match is_fut_symbol(symbol) {
+            true => (
+                validate_asset_binance_fut(&meta_pool, symbol).await?,
+                "Binance Futures",
+            ),
+            false => {
+                let res: Option<(String,)> =
+                    sqlx::query_as("SELECT [Asset] FROM assets WHERE Asset = ?;")
+                        .bind(symbol)
+                        .fetch_optional(&meta_pool)
+                        .await?;
+                (res.is_some(), "Binance")
+            }
+        };
+        if !listed {
+            meta_pool.close().await;
+            return Ok(false);
+        };
+        let q = format!(
+            "
+        INSERT OR IGNORE INTO assets_dl (Asset, Exchange)
+        VALUES('{}', '{}');",
+            symbol, exchange
+        );
+        exec_query(&meta_pool, &q).await?;
+        meta_pool.close().await;
+        self.dl_single_asset_bin_wrap(symbol).await?;
+        Ok(true)
+    }
+    pub async fn list_dl_assets(&self) -> Result<Vec<(String, String, Option<i64>, Option<i64>)>> {
+        ensure_metadata_db().await?;
+        let meta_pool = SqlitePool::connect(&metadata_db_path())
+            .await
+            .context(anyhow!("SQL::Unable to metadata connect to db"))?;
+        let out: Vec<(String, String, Option<i64>, Option<i64>)> = sqlx::query_as(
+            "SELECT [Asset], [Exchange], [Start Time], [End Time] FROM assets_dl ORDER BY [Asset];",
+        )
+        .fetch_all(&meta_pool)
+        .await?;
+        meta_pool.close().await;
+        Ok(out)
+    }
+    ///Loads a single interval of a downloaded asset, t is an optional (start, end) range in ms
+    pub async fn load_kline(
+        &self,
+        symbol: &str,
+        intv: &Intv,
+        t: Option<(i64, i64)>,
+    ) -> Result<Option<Kline>> {
+        let db_path = format!["{}/Asset{}.db", self.db_path, symbol];
+        if !Sqlite::database_exists(&db_path).await? {
+            return Ok(None);
+        };
+        let pool = connect_sqlite(&db_path).await?;
+        let kline = kfrom_sql_intv(&pool, intv, t).await;
+        pool.close().await;
+        Ok(Some(kline?))
+    }
+    ///Stored funding rates of a perpetual, t is an optional (start, end) range in ms. Empty for
+    ///spot assets.
+    pub async fn load_funding(
+        &self,
+        symbol: &str,
+        t: Option<(i64, i64)>,
+    ) -> Result<Vec<FundingRate>> {
+        let db_path = format!["{}/Asset{}.db", self.db_path, symbol];
+        if !is_fut_symbol(symbol) || !Sqlite::database_exists(&db_path).await? {
+            return Ok(vec![]);
+        };
+        let pool = connect_sqlite(&db_path).await?;
+        let rates = load_funding_db(&pool, t).await;
+        pool.close().await;
+        rates
+    }
+    pub async fn load_fat_kline(
+        &self,
+        symbol: &str,
+        intv: &Intv,
+        t: Option<(i64, i64)>,
+    ) -> Result<Option<FatKline>> {
+        let db_path = format!["{}/Asset{}.db", self.db_path, symbol];
+        if !Sqlite::database_exists(&db_path).await? {
+            return Ok(None);
+        };
+        let pool = connect_sqlite(&db_path).await?;
+        let kline = fat_kfrom_sql_intv(&pool, intv, t).await;
+        pool.close().await;
+        Ok(Some(kline?))
+    }
+    ///Exports symbol/intv to a Parquet or Arrow IPC file, returns the number of rows written
+    pub async fn export_columnar<P: AsRef<Path>>(
+        &self,
+        symbol: &str,
+        intv: &Intv,
+        t: Option<(i64, i64)>,
+        format: &ExportFormat,
+        path: P,
+    ) -> Result<usize> {
+        let kline = match self.load_fat_kline(symbol, intv, t).await? {
+            Some(k) if !k.kline.is_empty() => k,
+            _ => return Err(anyhow!["No {} data found for {}", intv.label(), symbol]),
+        };
+        if let Some(dir) = path.as_ref().parent()
+            && !dir.as_os_str().is_empty()
+        {
+            std::fs::create_dir_all(dir)?;
+        };
+        let f = std::io::BufWriter::new(std::fs::File::create(path.as_ref())?);
+        let rows = write_kline_columnar(&kline, format, f)?;
+        tracing::info![
+            "Exported {} {} rows for {} to {}",
+            rows,
+            &intv.label(),
+            symbol,
+            path.as_ref().display()
+        ];
+        Ok(rows)
+    }
+    ///Imports an OHLCV CSV file into kline_<intv> of the asset DB, the asset is added to
+    ///assets_dl if needed and its start/end times are widened to cover the imported rows.
+    ///Returns the number of rows imported.
+    pub async fn import_csv<P: AsRef<Path>>(
+        &self,
+        symbol: &str,
+        intv: &Intv,
+        csv_path: P,
+        config: &CsvImportConfig,
+    ) -> Result<usize> {
+        if intv.is_custom() {
+            return Err(anyhow![
+                "{} is resampled on load and can't be imported",
+                intv.label()
+            ]);
+        };
+        let input = tokio::fs::read_to_string(csv_path.as_ref())
+            .await
+            .with_context(|| format!["Unable to read {}", csv_path.as_ref().display()])?;
+        let rows = parse_kline_csv(&input, intv, config)?;
+        self.store_imported_kline(symbol, intv, &rows, &config.exchange)
+            .await?;
+        Ok(rows.len())
+    }
+    ///Writes imported rows to the asset DB and widens the assets_dl/metadata times to cover them,
+    ///exchange is only used for assets that aren't in assets_dl yet
+    async fn store_imported_kline(
+        &self,
+        symbol: &str,
+        intv: &Intv,
+        rows: &[FatKlineRow],
+        exchange: &str,
+    ) -> Result<()> {
+        let (Some(first), Some(last)) = (rows.first(), rows.last()) else {
+            return Ok(());
+        };
+        let (first_t, last_t) = (first.0, last.7);
+
+        ensure_metadata_db().await?;
+        let meta_pool = SqlitePool::connect(&metadata_db_path())
+            .await
+            .context(anyhow!("SQL::Unable to metadata connect to db"))?;
+        let db_path = format!["{}/Asset{}.db", self.db_path, symbol];
+        if !Sqlite::database_exists(&db_path).await? {
+            create_db(&db_path).await?;
+            let apool = connect_sqlite(&db_path).await?;
+            cr_kl_tables(&apool).await?;
+            apool.close().await;
+        };
+        let apool = connect_sqlite(&db_path).await?;
+        let table = intv.table()?;
+        for chunk in rows.chunks(APPEND_KLINE_MAX_ROWS) {
+            append_kline(&apool, &table, chunk).await?;
+        }
+
+        let existing: Option<(String, Option<i64>, Option<i64>)> = sqlx::query_as(
+            "SELECT [Exchange], [Start Time], [End Time] FROM assets_dl WHERE Asset = ?;",
+        )
+        .bind(symbol)
+        .fetch_optional(&meta_pool)
+        .await?;
+        let (exchange, st, et) = match existing {
+            Some((exchange, st, et)) => (
+                exchange,
+                st.map_or(first_t, |st| st.min(first_t)),
+                et.map_or(last_t, |et| et.max(last_t)),
+            ),
+            None => (exchange.to_string(), first_t, last_t),
+        };
+        update_asset_metadata_time(&apool, &meta_pool, symbol, &exchange, st, et).await?;
+        apool.close().await;
+        meta_pool.close().await;
+        tracing::info![
+            "Imported {} {} rows for {}",
+            rows.len(),
+            intv.to_str(),
+            symbol
+        ];
+        Ok(())
+    }
+    ///Imports the zipped kline CSVs from data.binance.vision (e.g. BTCUSDT-1m-2024-01.zip, monthly
+    ///or daily) found in dir and its subdirectories. If a .CHECKSUM file is next to a zip the
+    ///SHA256 has to match or the file is skipped.
+    pub async fn import_binance_archive<P: AsRef<Path>>(&self, dir: P) -> Result<ArchiveImport> {
+        let mut files = vec![];
+        let mut dirs = vec![dir.as_ref().to_path_buf()];
+        while let Some(d) = dirs.pop() {
+            let mut entries = tokio::fs::read_dir(&d)
+                .await
+                .with_context(|| format!["Unable to read directory {}", d.display()])?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if entry.file_type().await?.is_dir() {
+                    dirs.push(path);
+                } else if path.extension().is_some_and(|e| e == "zip") {
+                    files.push(path);
+                }
+            }
+        }
+        files.sort();
+
+        let mut report = ArchiveImport::default();
+        for path in files.into_iter() {
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let Some((symbol, intv)) = parse_archive_name(&name) else {
+                tracing::debug!["import_binance_archive skipping {}", name];
+                continue;
+            };
+            let res = tokio::task::spawn_blocking({
+                let path = path.clone();
+                move || read_binance_archive(&path)
+            })
+            .await?;
+            let (rows, verified) = match res {
+                Ok(r) => r,
+                Err(e) => {
+                    tracing::error!["import_binance_archive {}: {:?}", name, e];
+                    report.failed.push((name, format!["{}", e]));
+                    continue;
+                }
+            };
+            //NOTE a DB error only fails this file, the symbols already written still get refreshed
+            let res = self
+                .store_imported_kline(&symbol, &intv, &rows, "Binance")
+                .await;
+            if let Err(e) = res {
+                tracing::error!["import_binance_archive {}: {:?}", name, e];
+                report.failed.push((name, format!["{}", e]));
+                continue;
+            };
+            report.files += 1;
+            report.rows += rows.len();
+            if verified {
+                report.verified += 1;
+            };
+            if !report.symbols.contains(&symbol) {
+                report.symbols.push(symbol);
+            };
+        }
+        Ok(report)
+    }
+    ///Writes the kline as CSV, returns the number of rows written
+    pub fn write_kline_csv<W: Write>(kline: &Kline, out: &mut W) -> Result<usize> {
+        writeln![out, "open_time_ms,open,high,low,close,volume"]?;
+        for (t, o, h, l, c, v) in kline.kline.iter() {
+            writeln![
+                out,
+                "{},{},{},{},{},{}",
+                t.timestamp_millis(),
+                o,
+                h,
+                l,
+                c,
+                v
+            ]?;
+        }
+        Ok(kline.kline.len())
+    }
     pub fn update_settings(&mut self, _settings: &Settings) -> Result<()> {
         //TODO
         Ok(())
@@ -1858,7 +4273,7 @@ impl SQLConn {
             }
             SQLInstructs::LoadHistData { symbol: ref s } => {
                 tracing::trace!("Loading historical data for:{}", s);
-                let meta_pool = SqlitePool::connect(&METADATA_DB_PATH)
+                let meta_pool = SqlitePool::connect(&metadata_db_path())
                     .await
                     .context(anyhow!("SQL::Unable to metadata connect to db"));
                 let meta_pool = match meta_pool {
@@ -1902,10 +4317,11 @@ impl SQLConn {
                 symbol: ref s,
                 backload_wicks: ref st,
                 trade_time: ref et,
+                ref intv,
             } => {
                 tracing::trace!("Loading historical data for:{}", s);
 
-                let meta_pool = SqlitePool::connect(&METADATA_DB_PATH)
+                let meta_pool = SqlitePool::connect(&metadata_db_path())
                     .await
                     .context(anyhow!("SQL::Unable to metadata connect to db"));
                 let meta_pool = match meta_pool {
@@ -1930,7 +4346,7 @@ impl SQLConn {
                     tracing::error!("{}", err_string);
                     return SQLResponse::Failure((err_string, GeneralError::Generic));
                 };
-                let resp = match self.load_part_data2(&s, &et, &st).await {
+                let resp = match self.load_part_data2(s, et, st, intv).await {
                     Ok(_) => SQLResponse::Success,
                     Err(e) => {
                         let err_string = format!["{}", e];
@@ -1950,7 +4366,7 @@ impl SQLConn {
             } => {
                 tracing::debug!("Loading part data: {}", s);
 
-                let meta_pool = SqlitePool::connect(&METADATA_DB_PATH)
+                let meta_pool = SqlitePool::connect(&metadata_db_path())
                     .await
                     .context(anyhow!("SQL::Unable to metadata connect to db"));
                 let meta_pool = match meta_pool {
@@ -2007,8 +4423,17 @@ impl SQLConn {
                 todo!()
             }
             SQLInstructs::None => SQLResponse::None,
+            SQLInstructs::SetCacheLimit { limit_mb } => {
+                self.set_cache_limit(limit_mb);
+                SQLResponse::Success
+            }
+            SQLInstructs::CancelDownload { .. } | SQLInstructs::PauseDownload { .. } => {
+                //NOTE normally handled by the SQL task while the download runs
+                self.dl_jobs.control(&i);
+                SQLResponse::Success
+            }
             SQLInstructs::DelAsset { ref symbol } => {
-                let meta_pool = SqlitePool::connect(&METADATA_DB_PATH)
+                let meta_pool = SqlitePool::connect(&metadata_db_path())
                     .await
                     .context(anyhow!("SQL::Unable to metadata connect to db"));
                 let meta_pool = match meta_pool {
@@ -2114,17 +4539,1280 @@ impl SQLConn {
                 };
                 resp
             }
-            SQLInstructs::ValidateDLAsset { .. } => todo!(),
-            SQLInstructs::ValidateBinanceAsset { .. } => todo!(),
-        }
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[tokio::test]
-    //TODO make more api tests, chrono tests
-    async fn exmpl() {}
+            SQLInstructs::ImportBinanceArchive { ref dir } => {
+                let res = self.import_binance_archive(dir).await;
+                match res {
+                    Ok(report) => {
+                        tracing::info!["Binance archive import: {:?}", report];
+                        for symbol in report.symbols.iter() {
+                            self.invalidate_cache(symbol);
+                            self.refresh_derived(symbol, false).await;
+                        }
+                        self.load_asset_list().await.ok();
+                        SQLResponse::Success
+                    }
+                    Err(e) => {
+                        let err_string = format!["{}", e];
+                        tracing::error!(
+                            "{}",
+                            anyhow![
+                                "{:?} SQL::import_binance_archive:{:?}",
+                                i,
+                                e.context(ERR_CTX)
+                            ]
+                        );
+                        SQLResponse::Failure((err_string, GeneralError::Generic))
+                    }
+                }
+            }
+            SQLInstructs::ExportKline {
+                ref symbol,
+                ref intv,
+                ref range,
+                ref format,
+                ref path,
+            } => {
+                let res = self
+                    .export_columnar(symbol, intv, *range, format, path)
+                    .await;
+                match res {
+                    Ok(_) => SQLResponse::Success,
+                    Err(e) => {
+                        let err_string = format!["{}", e];
+                        tracing::error!(
+                            "{}",
+                            anyhow!["{:?} SQL::export_columnar:{:?}", i, e.context(ERR_CTX)]
+                        );
+                        SQLResponse::Failure((err_string, GeneralError::Generic))
+                    }
+                }
+            }
+            SQLInstructs::AuditAsset { ref symbol, repair } => {
+                let res = self.audit_asset(symbol, repair).await;
+                match res {
+                    Ok(_) => SQLResponse::Success,
+                    Err(e) => {
+                        let err_string = format!["{}", e];
+                        tracing::error!(
+                            "{}",
+                            anyhow!["{:?} SQL::audit_asset:{:?}", i, e.context(ERR_CTX)]
+                        );
+                        SQLResponse::Failure((err_string, GeneralError::Generic))
+                    }
+                }
+            }
+            SQLInstructs::SetDeriveIntervals { derive } => {
+                self.derive_intervals = derive;
+                SQLResponse::Success
+            }
+            SQLInstructs::SetUpdateSchedule { interval_min } => {
+                let res = self.set_update_schedule(interval_min).await;
+                match res {
+                    Ok(_) => SQLResponse::Success,
+                    Err(e) => {
+                        let err_string = format!["{}", e];
+                        tracing::error!(
+                            "{}",
+                            anyhow!["{:?} SQL::set_update_schedule:{:?}", i, e.context(ERR_CTX)]
+                        );
+                        SQLResponse::Failure((err_string, GeneralError::Generic))
+                    }
+                }
+            }
+            SQLInstructs::DeriveIntervals { ref symbol, repair } => {
+                let res = self.derive_asset_intervals(symbol, repair).await;
+                match res {
+                    Ok(_) => SQLResponse::Success,
+                    Err(e) => {
+                        let err_string = format!["{}", e];
+                        tracing::error!(
+                            "{}",
+                            anyhow![
+                                "{:?} SQL::derive_asset_intervals:{:?}",
+                                i,
+                                e.context(ERR_CTX)
+                            ]
+                        );
+                        SQLResponse::Failure((err_string, GeneralError::Generic))
+                    }
+                }
+            }
+            SQLInstructs::LoadColStats { ref symbol } => {
+                let res = self.load_col_stats(symbol).await;
+                match res {
+                    Ok(_) => SQLResponse::Success,
+                    Err(e) => {
+                        let err_string = format!["{}", e];
+                        tracing::error!(
+                            "{}",
+                            anyhow!["{:?} SQL::load_col_stats:{:?}", i, e.context(ERR_CTX)]
+                        );
+                        SQLResponse::Failure((err_string, GeneralError::Generic))
+                    }
+                }
+            }
+            SQLInstructs::UpdateColStats { ref symbol } => {
+                let res = self.update_col_stats(symbol, true).await;
+                match res {
+                    Ok(_) => SQLResponse::Success,
+                    Err(e) => {
+                        let err_string = format!["{}", e];
+                        tracing::error!(
+                            "{}",
+                            anyhow!["{:?} SQL::update_col_stats:{:?}", i, e.context(ERR_CTX)]
+                        );
+                        SQLResponse::Failure((err_string, GeneralError::Generic))
+                    }
+                }
+            }
+            SQLInstructs::FindSimilarPatterns {
+                ref symbol,
+                ref intv,
+                time,
+            } => {
+                let res = self.find_similar_patterns(symbol, intv, time).await;
+                match res {
+                    Ok(_) => SQLResponse::Success,
+                    Err(e) => {
+                        let err_string = format!["{}", e];
+                        tracing::error!(
+                            "{}",
+                            anyhow![
+                                "{:?} SQL::find_similar_patterns:{:?}",
+                                i,
+                                e.context(ERR_CTX)
+                            ]
+                        );
+                        SQLResponse::Failure((err_string, GeneralError::Generic))
+                    }
+                }
+            }
+            SQLInstructs::ValidateDLAsset { .. } => todo!(),
+            SQLInstructs::ValidateBinanceAsset { .. } => todo!(),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_db {
+    use super::*;
+
+    ///DB in the temp dir, deleted on drop so a failing test doesn't leave it behind
+    pub struct TempDb {
+        pub path: String,
+        pub pool: Pool<Sqlite>,
+    }
+    impl TempDb {
+        ///DB without tables, for the metadata tests
+        pub async fn empty(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!["bintrade_{}_{}.db", name, std::process::id()]);
+            let path = path.to_str().unwrap().to_string();
+            //NOTE left behind by a killed run
+            remove_db(&path);
+            create_db(&path).await.unwrap();
+            let pool = connect_sqlite(&path).await.unwrap();
+            Self { path, pool }
+        }
+        ///Asset DB with the kline tables
+        pub async fn asset(name: &str) -> Self {
+            let db = Self::empty(name).await;
+            cr_kl_tables(&db.pool).await.unwrap();
+            db
+        }
+    }
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            remove_db(&self.path);
+        }
+    }
+    ///The DB and its WAL files
+    fn remove_db(path: &str) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _res = std::fs::remove_file(format!["{}{}", path, suffix]);
+        }
+    }
+
+    ///Candle around p (o = c = p, h/l 1 away) with volume v, half of it taker bought
+    pub fn fat_row(t: i64, intv: &Intv, p: f64, v: f64) -> FatKlineRow {
+        let ct = t + intv.to_ms() - 1;
+        (
+            t,
+            DateTime::<Utc>::from_timestamp_millis(t).unwrap(),
+            p,
+            p + 1.0,
+            p - 1.0,
+            p,
+            v,
+            ct,
+            DateTime::<Utc>::from_timestamp_millis(ct).unwrap(),
+            v * p,
+            1,
+            v / 2.0,
+            v * p / 2.0,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_db::{TempDb, fat_row};
+    use super::*;
+
+    #[tokio::test]
+    //TODO make more api tests, chrono tests
+    async fn exmpl() {}
+
+    #[test]
+    fn intv_parse() {
+        for i in Intv::iter() {
+            assert_eq!(Intv::parse(i.to_str()).unwrap(), i);
+            assert_eq!(Intv::parse(i.to_bin_str()).unwrap(), i);
+        }
+        assert_eq!(Intv::parse("7min").unwrap(), Intv::Custom(7));
+        assert_eq!(Intv::parse("90m").unwrap(), Intv::Custom(90));
+        assert_eq!(Intv::parse("2D").unwrap(), Intv::Custom(2 * 1440));
+        assert_eq!(Intv::parse("60m").unwrap(), Intv::Hour1);
+        assert!(Intv::parse("7x").is_err());
+        assert!(Intv::parse("0m").is_err());
+        assert!(Intv::parse("m").is_err());
+        assert!(Intv::iter().all(|i| !i.is_custom()));
+        assert_eq!(Intv::Custom(10).base(), Intv::Min5);
+        assert_eq!(Intv::Custom(45).base(), Intv::Min15);
+        assert_eq!(Intv::Custom(90).base(), Intv::Min30);
+        assert_eq!(Intv::Custom(2 * 1440).base(), Intv::Day1);
+        assert_eq!(Intv::Custom(90).label(), "90m");
+        assert_eq!(Intv::Custom(2 * 1440).label(), "2D");
+        assert_eq!(Intv::Hour1.table().unwrap(), "kline_1hour");
+        assert!(Intv::Custom(90).table().is_err());
+    }
+
+    ///Serves the recorded chart responses in tests/fixtures, only AAPL daily candles exist
+    #[cfg(feature = "yfinance")]
+    async fn yahoo_fixture_server() -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let dir = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let found = std::fs::read_to_string(dir.join("yahoo_chart_AAPL_1d.json")).unwrap();
+        let not_found = std::fs::read_to_string(dir.join("yahoo_chart_not_found.json")).unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buf = vec![0; 4096];
+                let n = socket.read(&mut buf).await.unwrap_or(0);
+                let req = String::from_utf8_lossy(&buf[..n]);
+                let path = req.split_whitespace().nth(1).unwrap_or("");
+                let (status, body) = match path.starts_with("/v8/finance/chart/AAPL?")
+                    && path.contains("interval=1d")
+                {
+                    true => ("200 OK", &found),
+                    false => ("404 Not Found", &not_found),
+                };
+                let resp = format![
+                    "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                ];
+                let _res = socket.write_all(resp.as_bytes()).await;
+            }
+        });
+        format!["http://{}/v8/finance/chart/", addr]
+    }
+
+    #[cfg(feature = "yfinance")]
+    #[tokio::test]
+    async fn yahoo_download_from_fixtures() {
+        let base = yahoo_fixture_server().await;
+        let client = YfClient::builder()
+            .base_chart(reqwest::Url::parse(&base).unwrap())
+            .retry_enabled(false)
+            .build()
+            .unwrap();
+        assert!(validate_asset_yahoo(&client, "AAPL").await.unwrap());
+        assert!(!validate_asset_yahoo(&client, "NOPE").await.unwrap());
+        let source = YahooSource::with_client(client);
+
+        let db = TempDb::asset("yahoo").await;
+        //2024-01-01 to 2024-01-10
+        let job = DlJob::default();
+        let stored = yahoo_asset_dl(
+            &source,
+            &db.pool,
+            "AAPL",
+            1704067200000,
+            1704844800000,
+            &job,
+        )
+        .await
+        .unwrap();
+        let day = fat_kfrom_sql(&db.pool, "kline_1day", None)
+            .await
+            .unwrap()
+            .kline;
+        let day3 = kfrom_sql(&db.pool, "kline_3day", None).await.unwrap().kline;
+        let min1 = kfrom_sql(&db.pool, "kline_1min", None).await.unwrap().kline;
+        assert!(
+            yahoo_asset_dl(
+                &source,
+                &db.pool,
+                "NOPE",
+                1704067200000,
+                1704844800000,
+                &job
+            )
+            .await
+            .is_err()
+        );
+        db.pool.close().await;
+
+        //the candle without prices is dropped
+        assert_eq!(stored, 4);
+        assert_eq!(day.len(), 4);
+        assert_eq!(day[0].0, 1704205800000);
+        assert_eq!((day[0].2, day[0].5, day[0].6), (187.15, 185.64, 82488700.0));
+        assert_eq!(day[0].7, day[0].0 + Intv::Day1.to_ms() - 1);
+        assert!(min1.is_empty());
+        //3 day candles are epoch aligned, Jan 2 is in the one starting Dec 31 and Jan 3-5 in the next
+        assert_eq!(day3.len(), 2);
+        assert_eq!(day3[1].0.timestamp_millis(), 1704240000000);
+        assert_eq!(
+            (day3[1].1, day3[1].2, day3[1].3, day3[1].4),
+            (184.22, 185.88, 180.17, 181.18)
+        );
+    }
+
+    #[tokio::test]
+    async fn custom_intv_resample() {
+        //2024-01-01 00:00 UTC, aligned to 10m and 90m
+        let start = 1704067200000;
+        let min1: Vec<_> = (0..180)
+            .map(|m| {
+                let t = DateTime::<Utc>::from_timestamp_millis(start + m * 60_000).unwrap();
+                let p = m as f64;
+                (t, p, p + 2.0, p - 1.0, p + 1.0, 1.0)
+            })
+            .collect();
+        let m90 = resample_kline(&min1, &Intv::Custom(90)).kline;
+        assert_eq!(m90.len(), 2);
+        assert_eq!(m90[1].0.timestamp_millis(), start + 90 * 60_000);
+        assert_eq!(
+            (m90[1].1, m90[1].2, m90[1].3, m90[1].4),
+            (90.0, 181.0, 89.0, 180.0)
+        );
+        assert_eq!(m90[1].5, 90.0);
+        //unsorted and partial input, the last 10m candle only has 5 minutes
+        let mut part: Vec<_> = min1[..25].to_vec();
+        part.reverse();
+        let m10 = resample_kline(&part, &Intv::Custom(10)).kline;
+        assert_eq!(m10.len(), 3);
+        assert_eq!((m10[2].1, m10[2].4, m10[2].5), (20.0, 25.0, 5.0));
+
+        let db = TempDb::asset("custom").await;
+        let min5: Vec<_> = resample_kline(&min1, &Intv::Min5)
+            .kline
+            .iter()
+            .map(|k| {
+                let t = k.0.timestamp_millis();
+                let ct = t + Intv::Min5.to_ms() - 1;
+                let ctt = DateTime::<Utc>::from_timestamp_millis(ct).unwrap();
+                (t, k.0, k.1, k.2, k.3, k.4, k.5, ct, ctt, 0.0, 0, 0.0, 0.0)
+            })
+            .collect();
+        append_kline(&db.pool, "kline_5min", &min5).await.unwrap();
+        //range starts and ends inside a 10m candle, both are loaded complete
+        let range = Some((start + 15 * 60_000, start + 42 * 60_000));
+        let m10 = kfrom_sql_intv(&db.pool, &Intv::Custom(10), range)
+            .await
+            .unwrap()
+            .kline;
+        let fat = fat_kfrom_sql_intv(&db.pool, &Intv::Custom(10), range)
+            .await
+            .unwrap()
+            .kline;
+        db.pool.close().await;
+        assert_eq!(m10.len(), 4);
+        assert_eq!(m10[0].0.timestamp_millis(), start + 10 * 60_000);
+        assert_eq!(
+            (m10[0].1, m10[0].2, m10[0].4, m10[0].5),
+            (10.0, 21.0, 20.0, 10.0)
+        );
+        assert_eq!(fat.len(), 4);
+        assert_eq!(fat[3].7, start + 50 * 60_000 - 1);
+    }
+
+    #[test]
+    fn intv_buckets() {
+        //2024-01-03 12:34 UTC, a Wednesday, 2024-01-01 is a Monday
+        let t = 1704285240000;
+        assert_eq!(Intv::Hour1.bucket_start(t), 1704283200000);
+        assert_eq!(Intv::Week1.bucket_start(t), 1704067200000);
+        assert_eq!(Intv::Week1.bucket_start(1704067200000 - 1), 1703462400000);
+        assert_eq!(Intv::Month1.bucket_start(t), 1704067200000);
+        assert_eq!(Intv::Month1.next_open(t), 1706745600000);
+        //leap year february
+        assert_eq!(Intv::Month1.next_open(1706745600000), 1709251200000);
+    }
+
+    #[tokio::test]
+    async fn audit_finds_gaps_bad_ohlc_and_mismatches() {
+        let db = TempDb::asset("audit").await;
+
+        let row = |t: i64, intv: &Intv, o: f64, h: f64, l: f64, c: f64| {
+            let mut row = fat_row(t, intv, o, 1.0);
+            (row.3, row.4, row.5) = (h, l, c);
+            row
+        };
+        let start = 1704067200000;
+        //two full hours of 1m candles, minutes 70..=72 missing and one bad candle
+        let min1: Vec<_> = (0..120)
+            .filter(|m| !(70..=72).contains(m))
+            .map(|m| {
+                let t = start + m * 60_000;
+                match m {
+                    100 => row(t, &Intv::Min1, 1.0, 0.5, 2.0, 1.0),
+                    _ => row(t, &Intv::Min1, 1.0, 2.0, 0.5, 1.5),
+                }
+            })
+            .collect();
+        append_kline(&db.pool, "kline_1min", &min1).await.unwrap();
+        let mut hour1 = vec![
+            row(start, &Intv::Hour1, 1.0, 2.0, 0.5, 1.5),
+            row(start + 3_600_000, &Intv::Hour1, 1.0, 2.0, 0.5, 1.5),
+        ];
+        hour1[0].6 = 60.0;
+        append_kline(&db.pool, "kline_1hour", &hour1).await.unwrap();
+        let hour2 = vec![row(start, &Intv::Hour2, 1.0, 3.0, 0.5, 1.5)];
+        append_kline(&db.pool, "kline_2hour", &hour2).await.unwrap();
+        let mut min3 = vec![row(start, &Intv::Min3, 1.0, 2.5, 0.5, 1.5)];
+        min3[0].6 = 3.0;
+        append_kline(&db.pool, "kline_3min", &min3).await.unwrap();
+
+        let report = audit_asset_db(&db.pool, "TEST").await.unwrap();
+        db.pool.close().await;
+
+        let min1 = &report.intvs[0];
+        assert_eq!(min1.rows, 117);
+        assert_eq!(min1.gaps, vec![(start + 70 * 60_000, start + 72 * 60_000)]);
+        assert_eq!(min1.missing(), 3);
+        assert_eq!(min1.bad_ohlc, vec![start + 100 * 60_000]);
+        let get = |intv: Intv| report.intvs.iter().find(|a| a.intv == intv).unwrap();
+        //first hour is complete and matches, the second one has gaps so it isn't compared
+        assert!(get(Intv::Hour1).is_ok());
+        //2h candle can't be compared either, the 1m data isn't complete
+        assert!(get(Intv::Hour2).mismatched.is_empty());
+        //3m high doesn't match the 1m candles
+        assert_eq!(get(Intv::Min3).mismatched, vec![start]);
+        assert!(!report.is_ok());
+    }
+
+    #[tokio::test]
+    async fn mock_source_pipeline_retries_failed_chunks() {
+        let db = TempDb::asset("mock").await;
+
+        let start = 1704067200000;
+        let end = start + 240 * Intv::Hour1.to_ms();
+        //3 of the 8 parallel chunks fail on the first try
+        let source = crate::source::MockSource::new("MOCK", start, 3);
+        let job = DlJob::new("MOCK");
+        let errors =
+            download_chunks(&source, &db.path, "MOCK", start, end, &[Intv::Hour1], &job).await;
+        let failed: usize = errors.iter().map(|(_, e)| e.len()).sum();
+        let partial = kfrom_sql(&db.pool, "kline_1hour", None)
+            .await
+            .unwrap()
+            .kline;
+        let errors = retry_chunks(&source, &db.path, "MOCK", errors, &job)
+            .await
+            .unwrap();
+        let rows = fat_kfrom_sql(&db.pool, "kline_1hour", None)
+            .await
+            .unwrap()
+            .kline;
+        let progress = job.progress();
+        //a cancelled job doesn't fetch anything, the whole range is left to retry later
+        let cancelled = DlJob::new("MOCK");
+        cancelled.cancel();
+        let skipped = download_chunks(
+            &source,
+            &db.path,
+            "MOCK",
+            start,
+            end,
+            &[Intv::Hour2],
+            &cancelled,
+        )
+        .await;
+        let skipped_rows = kfrom_sql(&db.pool, "kline_2hour", None)
+            .await
+            .unwrap()
+            .kline;
+        db.pool.close().await;
+
+        assert_eq!(failed, 3);
+        assert_eq!(partial.len(), 150);
+        assert!(errors.is_empty());
+        assert_eq!(source.calls(), 11);
+        assert_eq!(
+            (progress.done, progress.total, progress.errors),
+            (11, 11, 3)
+        );
+        assert_eq!(skipped.iter().map(|(_, e)| e.len()).sum::<usize>(), 8);
+        assert!(skipped_rows.is_empty());
+        //chunks start after their start time, the candle opening at start isn't downloaded
+        assert_eq!(rows.len(), 240);
+        assert_eq!(rows[0].0, start + Intv::Hour1.to_ms());
+        assert_eq!(rows[239].0, end);
+        assert!(
+            rows.windows(2)
+                .all(|w| w[1].0 - w[0].0 == Intv::Hour1.to_ms())
+        );
+        assert_eq!(rows[0].10, 1);
+
+        assert!(matches!("Yahoo".parse::<Exchange>(), Ok(Exchange::Yahoo)));
+        assert!(matches!("2".parse::<Exchange>(), Ok(Exchange::Local)));
+        assert!("Kraken".parse::<Exchange>().is_err());
+    }
+
+    #[tokio::test]
+    async fn binance_source_backs_off_and_retries() {
+        let db = TempDb::asset("binsrc").await;
+
+        let start = 1704067200000;
+        let klines: Vec<String> = (0..3)
+            .map(|n| {
+                let t = start + n * 3_600_000;
+                format![
+                    "[{},\"1.0\",\"2.0\",\"0.5\",\"1.5\",\"10.0\",{},\"15.0\",7,\"4.0\",\"6.0\",\"0\"]",
+                    t,
+                    t + 3_599_999
+                ]
+            })
+            .collect();
+        let limited = (
+            429,
+            vec![("retry-after", "1".to_string())],
+            "{\"code\":-1003}".to_string(),
+        );
+        let ok = (
+            200,
+            vec![("x-mbx-used-weight-1m", "12".to_string())],
+            format!["[{}]", klines.join(",")],
+        );
+        let (base, served) = crate::ratelimit::mock_http_server(vec![limited, ok]).await;
+        let limiter = Arc::new(crate::ratelimit::RateLimiter::new(6000));
+        let source = BinanceSource::with_base_url(&base, limiter.clone());
+        let end = start + 3 * 3_600_000;
+
+        let errors = get_data_source(
+            &source,
+            "BTCUSDT",
+            Intv::Hour1,
+            &db.pool,
+            start - 1,
+            end,
+            &DlJob::default(),
+        )
+        .await
+        .unwrap();
+        let blocked = limiter.blocked_until().is_some();
+        let errors = vec![(Intv::Hour1, errors.unwrap())];
+        let t = std::time::Instant::now();
+        let left =
+            iterate_over_remaining_errors(&source, &db.path, errors, "BTCUSDT", &DlJob::default())
+                .await
+                .unwrap();
+        let waited = t.elapsed();
+        let rows = fat_kfrom_sql(&db.pool, "kline_1hour", None)
+            .await
+            .unwrap()
+            .kline;
+        db.pool.close().await;
+
+        assert!(blocked);
+        assert!(waited >= std::time::Duration::from_millis(900));
+        assert!(left.is_none());
+        assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[2].0, start + 2 * 3_600_000);
+        assert_eq!((rows[0].2, rows[0].5, rows[0].6), (1.0, 1.5, 10.0));
+        assert_eq!((rows[0].9, rows[0].10, rows[0].12), (15.0, 7, 6.0));
+    }
+
+    #[tokio::test]
+    async fn futures_funding_is_stored_with_mark_prices() {
+        let db = TempDb::asset("funding").await;
+
+        let t0 = 1704067200000;
+        let eight = 8 * 3_600_000;
+        //the oldest events have no mark price, fundingTime is a few ms after the hour
+        let funding = format![
+            "[{{\"symbol\":\"BTCUSDT\",\"fundingTime\":{},\"fundingRate\":\"0.00010000\",\"markPrice\":\"\"}},\
+             {{\"symbol\":\"BTCUSDT\",\"fundingTime\":{},\"fundingRate\":\"-0.00020000\",\"markPrice\":\"42500.5\"}}]",
+            t0 + 3,
+            t0 + eight
+        ];
+        let mark = format![
+            "[[{},\"42000.0\",\"42100.0\",\"41900.0\",\"42050.0\",\"0\",{},\"0\",60,\"0\",\"0\",\"0\"]]",
+            t0,
+            t0 + 3_599_999
+        ];
+        let (base, served) = crate::ratelimit::mock_http_server(vec![
+            (200, vec![], funding),
+            (200, vec![], mark),
+            (200, vec![], "[]".to_string()),
+        ])
+        .await;
+        let limiter = Arc::new(crate::ratelimit::RateLimiter::new(2400));
+        let source = BinanceSource::futures_with_base_url(&base, limiter);
+        let spot =
+            BinanceSource::with_base_url(&base, Arc::new(crate::ratelimit::RateLimiter::new(6000)));
+
+        let stored = funding_asset_dl(&source, &db.pool, "BTCUSDT.P", t0, t0 + 2 * eight)
+            .await
+            .unwrap();
+        //an update only asks for what's after the last stored event
+        let again = funding_asset_dl(&source, &db.pool, "BTCUSDT.P", t0, t0 + 2 * eight)
+            .await
+            .unwrap();
+        let rates = load_funding_db(&db.pool, None).await.unwrap();
+        let later = load_funding_db(&db.pool, Some((t0 + 4, t0 + eight)))
+            .await
+            .unwrap();
+        let spot_funding = spot.fetch_funding("BTCUSDT", (t0, t0 + eight)).await;
+        db.pool.close().await;
+
+        assert_eq!((stored, again), (2, 0));
+        assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 3);
+        assert_eq!(
+            rates,
+            vec![
+                FundingRate {
+                    time: t0 + 3,
+                    rate: 0.0001,
+                    mark_price: Some(42000.0),
+                },
+                FundingRate {
+                    time: t0 + eight,
+                    rate: -0.0002,
+                    mark_price: Some(42500.5),
+                },
+            ]
+        );
+        assert_eq!(later, rates[1..]);
+        assert!(spot_funding.is_err());
+        assert!(source.is_futures() && !spot.is_futures());
+        assert_eq!(source.name(), "Binance Futures");
+        assert_eq!(fut_symbol("BTCUSDT"), "BTCUSDT.P");
+        assert_eq!(fut_symbol("BTCUSDT.P"), "BTCUSDT.P");
+        assert_eq!(exchange_symbol("BTCUSDT.P"), "BTCUSDT");
+        assert!("Binance Futures".parse::<Exchange>().is_ok());
+    }
+
+    #[tokio::test]
+    async fn scheduled_update_skips_current_assets() {
+        let db = TempDb::empty("sched").await;
+        assert_eq!(
+            migrate_db(&db.pool, &metadata_migrations()).await.unwrap(),
+            4
+        );
+        for (symbol, exchange) in [
+            ("TEST", "Binance"),
+            ("BTCUSDT", "Binance"),
+            ("ETHUSDT", "Binance"),
+            ("AAPL", "Yahoo"),
+            ("MYCSV", "Local"),
+        ] {
+            sqlx::query("INSERT INTO assets_dl (Asset, Exchange) VALUES (?, ?)")
+                .bind(symbol)
+                .bind(exchange)
+                .execute(&db.pool)
+                .await
+                .unwrap();
+        }
+        let hour = 3_600_000;
+        let now = 1704067200000;
+        let never = due_assets(&db.pool, now, hour).await.unwrap();
+        record_update_status(&db.pool, "BTCUSDT", now - hour / 2, &Ok(()))
+            .await
+            .unwrap();
+        record_update_status(&db.pool, "ETHUSDT", now - 2 * hour, &Ok(()))
+            .await
+            .unwrap();
+        record_update_status(&db.pool, "AAPL", now - hour / 2, &Err(anyhow!["timeout"]))
+            .await
+            .unwrap();
+        //the row is updated in place, the status columns stay
+        update_asset_metadata_time(&db.pool, &db.pool, "BTCUSDT", "Binance", 1, 2)
+            .await
+            .unwrap();
+        let due = due_assets(&db.pool, now, hour).await.unwrap();
+        let retry = due_assets(&db.pool, now + hour / 2, hour).await.unwrap();
+        let list = load_asset_list_ad(&db.pool).await.unwrap();
+        let schedule_off = load_update_schedule(&db.pool).await.unwrap();
+        save_update_schedule(&db.pool, Some(30)).await.unwrap();
+        save_update_schedule(&db.pool, Some(90)).await.unwrap();
+        let schedule = load_update_schedule(&db.pool).await.unwrap();
+        db.pool.close().await;
+
+        let names = |v: &[(String, String)]| v.iter().map(|(s, _)| s.clone()).collect::<Vec<_>>();
+        assert_eq!(names(&never), vec!["BTCUSDT", "ETHUSDT", "AAPL"]);
+        //BTCUSDT is current, AAPL failed and is retried an interval after the attempt
+        assert_eq!(names(&due), vec!["ETHUSDT"]);
+        assert_eq!(names(&retry), vec!["BTCUSDT", "ETHUSDT", "AAPL"]);
+        let btc = list.iter().find(|a| a.asset == "BTCUSDT").unwrap();
+        assert_eq!((btc.last_update, btc.dat_end_t), (now - hour / 2, 2));
+        assert_eq!(btc.update_status, "Ok");
+        let aapl = list.iter().find(|a| a.asset == "AAPL").unwrap();
+        assert_eq!(
+            (aapl.last_update, aapl.update_status.as_str()),
+            (0, "Failed: timeout")
+        );
+        assert_eq!(schedule_off, None);
+        assert_eq!(schedule, Some(90));
+    }
+
+    #[tokio::test]
+    async fn migrate_db_from_unversioned_schema() {
+        let db = TempDb::empty("migrate").await;
+        //asset DB from before Pattern hash and colstats
+        sqlx::query("CREATE TABLE kline_1min ( [Timestamp MS] INTEGER, [Open Time] datetime,  Open REAL,  High REAL,  Low REAL,  Close REAL,  Volume REAL, [Close Timestamp MS] INTEGER, [Close Time] INTEGER,  [Quote Asset Volume] REAL,  [Number of Trades] INTEGER,  [Taker Buy Base Asset Volume] REAL,  [Taker Buy Quote Asset Volume] REAL,[Ignore] )")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO kline_1min ([Timestamp MS], Open) VALUES (1704067200000, 1.5)")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(schema_version(&db.pool).await.unwrap(), 0);
+
+        let migrations = asset_migrations();
+        assert_eq!(migrate_db(&db.pool, &migrations).await.unwrap(), 4);
+        //already migrated, nothing runs
+        assert_eq!(migrate_db(&db.pool, &migrations).await.unwrap(), 4);
+        let (open, hash): (f64, Option<f64>) =
+            sqlx::query_as("SELECT Open, [Pattern hash] FROM kline_1min")
+                .fetch_one(&db.pool)
+                .await
+                .unwrap();
+        let (colstats,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM colstats")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        let (applied,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM schema_version")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        //newer DB than the build
+        let too_new = migrate_db(&db.pool, &migrations[..1]).await;
+        db.pool.close().await;
+
+        assert_eq!((open, hash), (1.5, None));
+        assert_eq!(colstats, 0);
+        assert_eq!(applied, 4);
+        assert!(too_new.is_err());
+    }
+
+    #[tokio::test]
+    async fn derive_intervals_week_and_month_boundaries() {
+        let db = TempDb::asset("derive").await;
+
+        //2024-01-29 (Monday) to 2024-02-06, crosses a month and a week boundary
+        let start = 1706486400000;
+        let min1: Vec<_> = (0..8 * 1440)
+            .map(|m| fat_row(start + m * 60_000, &Intv::Min1, 100.0 + m as f64, 1.0))
+            .collect();
+        for chunk in min1.chunks(APPEND_KLINE_MAX_ROWS) {
+            append_kline(&db.pool, "kline_1min", chunk).await.unwrap();
+        }
+        //a wrong downloaded 1h candle and a correct 1d one
+        append_kline(
+            &db.pool,
+            "kline_1hour",
+            &[fat_row(start, &Intv::Hour1, 5.0, 1.0)],
+        )
+        .await
+        .unwrap();
+        let mut day = fat_row(start, &Intv::Day1, 100.0, 1440.0);
+        (day.3, day.4, day.5) = (1540.0, 99.0, 1539.0);
+        append_kline(&db.pool, "kline_1day", &[day]).await.unwrap();
+
+        let report = derive_intervals(&db.pool, start, false, true)
+            .await
+            .unwrap();
+        assert_eq!(report.compared, 2);
+        assert_eq!(report.mismatched, vec![(Intv::Hour1, start)]);
+
+        let week = fat_kfrom_sql(&db.pool, "kline_1week", None)
+            .await
+            .unwrap()
+            .kline;
+        assert_eq!(week.len(), 2);
+        assert_eq!((week[0].0, week[1].0), (start, start + 7 * 86_400_000));
+        assert_eq!(week[0].6, 7.0 * 1440.0);
+        assert_eq!(week[0].10, 7 * 1440);
+        assert_eq!(week[0].7, start + 7 * 86_400_000 - 1);
+        let month = fat_kfrom_sql(&db.pool, "kline_1month", None)
+            .await
+            .unwrap()
+            .kline;
+        //january starts at the 1st even though the data starts on the 29th
+        assert_eq!(month[0].0, 1704067200000);
+        assert_eq!(month[1].0, 1706745600000);
+        assert_eq!(month[1].7, 1709251200000 - 1);
+        assert_eq!(month[0].6 + month[1].6, 8.0 * 1440.0);
+        assert_eq!(month[1].2, 100.0 + 3.0 * 1440.0);
+        let hour = fat_kfrom_sql(&db.pool, "kline_1hour", None)
+            .await
+            .unwrap()
+            .kline;
+        assert_eq!(hour.len(), 8 * 24);
+        assert_eq!((hour[0].2, hour[0].5), (100.0, 159.0));
+
+        //the derived data passes the audit
+        let audit = audit_asset_db(&db.pool, "TEST").await.unwrap();
+        db.pool.close().await;
+        assert!(
+            audit
+                .intvs
+                .iter()
+                .all(|a| a.mismatched.is_empty() && a.bad_ohlc.is_empty())
+        );
+    }
+
+    #[tokio::test]
+    async fn derive_intervals_skips_gaps_and_keeps_downloaded() {
+        let db = TempDb::asset("derive_gap").await;
+
+        //2024-01-01, four hours of 1m with 10 minutes missing in the second hour
+        let start = 1704067200000;
+        let min1: Vec<_> = (0..4 * 60)
+            .filter(|m| !(70..80).contains(m))
+            .map(|m| fat_row(start + m * 60_000, &Intv::Min1, 100.0, 1.0))
+            .collect();
+        append_kline(&db.pool, "kline_1min", &min1).await.unwrap();
+        //the downloaded 1h candles over the gap and over complete 1m data
+        let hour = 3_600_000;
+        let downloaded = [
+            fat_row(start + hour, &Intv::Hour1, 50.0, 1.0),
+            fat_row(start + 2 * hour, &Intv::Hour1, 50.0, 1.0),
+        ];
+        append_kline(&db.pool, "kline_1hour", &downloaded)
+            .await
+            .unwrap();
+
+        let report = derive_intervals(&db.pool, start, false, false)
+            .await
+            .unwrap();
+        let hours = fat_kfrom_sql(&db.pool, "kline_1hour", None)
+            .await
+            .unwrap()
+            .kline;
+        let min15 = fat_kfrom_sql(&db.pool, "kline_15min", None)
+            .await
+            .unwrap()
+            .kline;
+        let repaired = derive_intervals(&db.pool, start, false, true)
+            .await
+            .unwrap();
+        let hours_repaired = fat_kfrom_sql(&db.pool, "kline_1hour", None)
+            .await
+            .unwrap()
+            .kline;
+        db.pool.close().await;
+
+        assert_eq!(report.mismatched, vec![(Intv::Hour1, start + 2 * hour)]);
+        let opens: Vec<_> = hours.iter().map(|k| (k.0, k.2)).collect();
+        assert_eq!(
+            opens,
+            vec![
+                (start, 100.0),
+                (start + hour, 50.0),
+                (start + 2 * hour, 50.0),
+                (start + 3 * hour, 100.0)
+            ]
+        );
+        //the two 15m candles with the gap aren't derived
+        assert_eq!(min15.len(), 14);
+        assert!(
+            !min15
+                .iter()
+                .any(|k| k.0 == start + hour || k.0 == start + hour + 900_000)
+        );
+        assert_eq!(repaired.mismatched, report.mismatched);
+        assert_eq!((hours_repaired[1].2, hours_repaired[2].2), (50.0, 100.0));
+    }
+
+    #[tokio::test]
+    async fn derive_update_rewrites_the_candles_stored_while_open() {
+        let db = TempDb::asset("derive_update").await;
+
+        //2024-01-01, an update after 90 minutes of 1m and another one after 3 hours
+        let start = 1704067200000;
+        let hour = 3_600_000;
+        let first: Vec<_> = (0..90)
+            .map(|m| fat_row(start + m * 60_000, &Intv::Min1, m as f64, 1.0))
+            .collect();
+        append_kline(&db.pool, "kline_1min", &first).await.unwrap();
+        derive_intervals(&db.pool, start, true, false)
+            .await
+            .unwrap();
+        let open = fat_kfrom_sql(&db.pool, "kline_1hour", None)
+            .await
+            .unwrap()
+            .kline;
+        let next = start + 90 * 60_000;
+        let second: Vec<_> = (90..180)
+            .map(|m| fat_row(start + m * 60_000, &Intv::Min1, m as f64, 1.0))
+            .collect();
+        append_kline(&db.pool, "kline_1min", &second).await.unwrap();
+        let report = derive_intervals(&db.pool, next, true, false).await.unwrap();
+        let hours = fat_kfrom_sql(&db.pool, "kline_1hour", None)
+            .await
+            .unwrap()
+            .kline;
+        let day = fat_kfrom_sql(&db.pool, "kline_1day", None)
+            .await
+            .unwrap()
+            .kline;
+        db.pool.close().await;
+
+        //the second hour was stored with 30 minutes
+        assert_eq!(
+            (open[1].0, open[1].5, open[1].6),
+            (start + hour, 89.0, 30.0)
+        );
+        assert!(report.mismatched.is_empty());
+        let closes: Vec<_> = hours.iter().map(|k| (k.0, k.5, k.6)).collect();
+        assert_eq!(
+            closes,
+            vec![
+                (start, 59.0, 60.0),
+                (start + hour, 119.0, 60.0),
+                (start + 2 * hour, 179.0, 60.0)
+            ]
+        );
+        assert_eq!((day[0].5, day[0].6), (179.0, 180.0));
+    }
+
+    #[test]
+    fn aggregator_compares_full_candles_only() {
+        let mut agg = KlineAggregator::new(Intv::Min3);
+        assert_eq!(agg.push((0, 1.0, 2.0, 0.5, 1.5, 1.0)), None);
+        assert_eq!(agg.push((60_000, 1.5, 3.0, 1.0, 2.0, 2.0)), None);
+        assert_eq!(agg.push((120_000, 2.0, 2.5, 0.2, 1.0, 3.0)), None);
+        assert_eq!(
+            agg.push((180_000, 1.0, 1.0, 1.0, 1.0, 1.0)),
+            Some((0, 1.0, 3.0, 0.2, 1.0, 6.0))
+        );
+        assert_eq!(agg.finish(), None);
+        let mut audit = SeriesAudit::new(Intv::Min3);
+        audit.push((0, 1.0, 2.0, 0.5, 1.5, 1.0));
+        audit.push((0, 1.0, 2.0, 0.5, 1.5, 1.0));
+        audit.push((540_000, 1.0, 2.0, 0.5, 1.5, f64::NAN));
+        assert_eq!(audit.audit.duplicates, 1);
+        assert_eq!(audit.audit.gaps, vec![(180_000, 360_000)]);
+        assert_eq!(audit.audit.bad_ohlc, vec![540_000]);
+    }
+
+    #[tokio::test]
+    async fn dl_errors_lock_per_symbol() {
+        let guard = lock_dl_errors("LOCKTESTA").await;
+        let wait = std::time::Duration::from_millis(50);
+        assert!(
+            tokio::time::timeout(wait, lock_dl_errors("LOCKTESTA"))
+                .await
+                .is_err()
+        );
+        //NOTE other symbols aren't blocked
+        let _other = lock_dl_errors("LOCKTESTB").await;
+        drop(guard);
+        assert!(
+            tokio::time::timeout(wait, lock_dl_errors("LOCKTESTA"))
+                .await
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn rem_errors_merge_and_persist() {
+        let mut rem = RemErrors::new("TESTUSDT");
+        rem.merge(vec![(Intv::Min1, vec![(10, 20), (0, 5)])]);
+        rem.merge(vec![
+            (Intv::Min1, vec![(10, 20), (30, 40)]),
+            (Intv::Hour1, vec![(0, 100)]),
+            (Intv::Day1, vec![]),
+        ]);
+        assert_eq!(
+            rem.errors,
+            vec![
+                (Intv::Min1, vec![(0, 5), (10, 20), (30, 40)]),
+                (Intv::Hour1, vec![(0, 100)])
+            ]
+        );
+        assert_eq!(rem.chunks(), 4);
+
+        let path =
+            std::env::temp_dir().join(format!["bintrade_dl_errors_{}.bin", std::process::id()]);
+        let path = path.to_str().unwrap();
+        rem.save_to_file(path).unwrap();
+        assert_eq!(RemErrors::load_from_file(path).unwrap(), rem);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn csv_import_parse() {
+        let config = CsvImportConfig {
+            delimiter: ';',
+            time: "Date".to_string(),
+            open: "Open".to_string(),
+            high: "High".to_string(),
+            low: "Low".to_string(),
+            close: "Close".to_string(),
+            volume: None,
+            time_format: CsvTimeFormat::Format("%Y-%m-%d %H:%M".to_string()),
+            timezone: "+02:00".to_string(),
+            ..Default::default()
+        };
+        let input = "Date;Open;High;Low;Close\n\
+            \"2024-01-01 02:01\";2;3;1;2.5\n\
+            2024-01-01 02:00;1;2;0.5;2\n\
+            2024-01-01 02:00;1;2;0.5;2\n";
+        let rows = parse_kline_csv(input, &Intv::Min1, &config).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].0, 1704067200000);
+        assert_eq!(rows[0].7, 1704067200000 + 59_999);
+        assert_eq!(
+            (rows[1].2, rows[1].3, rows[1].4, rows[1].5),
+            (2.0, 3.0, 1.0, 2.5)
+        );
+
+        let config = CsvImportConfig {
+            has_header: false,
+            time_format: CsvTimeFormat::UnixSec,
+            ..Default::default()
+        };
+        let rows = parse_kline_csv("1704067200,1,2,0.5,2,10", &Intv::Min1, &config).unwrap();
+        assert_eq!((rows[0].0, rows[0].6), (1704067200000, 10.0));
+        assert!(parse_kline_csv("1704067200,1,2", &Intv::Min1, &config).is_err());
+
+        //NOTE January has 31 days, the candle closes the ms before February 1st
+        let rows = parse_kline_csv("1704067200,1,2,0.5,2,10", &Intv::Month1, &config).unwrap();
+        assert_eq!(rows[0].7, 1706745600000 - 1);
+        assert_eq!(rows[0].8.to_rfc3339(), "2024-01-31T23:59:59.999+00:00");
+    }
+
+    #[test]
+    fn binance_archive_parse() {
+        assert_eq!(
+            parse_archive_name("BTCUSDT-1m-2024-01.zip"),
+            Some(("BTCUSDT".to_string(), Intv::Min1))
+        );
+        assert_eq!(
+            parse_archive_name("ethusdt-1mo-2024-01-31.zip"),
+            Some(("ETHUSDT".to_string(), Intv::Month1))
+        );
+        assert_eq!(parse_archive_name("BTCUSDT-trades-2024-01.zip"), None);
+        assert_eq!(parse_archive_name("BTCUSDT-1m-2024-01.csv"), None);
+
+        let input = "open_time,open,high,low,close,volume,close_time,quote_volume,count,taker_buy_volume,taker_buy_quote_volume,ignore
+1704067200000,42283.58,42298.62,42261.02,42298.61,35.92724,1704067259999,1519243.35,1327,17.29,731188.1,0
+1735689600000000,93576.0,93610.93,93537.5,93610.93,8.21827,1735689659999999,769002.3,1134,3.91,366165.3,0";
+        let rows = parse_binance_archive_csv(input).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(
+            (rows[0].0, rows[0].7, rows[0].10),
+            (1704067200000, 1704067259999, 1327)
+        );
+        assert_eq!((rows[1].0, rows[1].7), (1735689600000, 1735689659999));
+        assert_eq!(rows[1].5, 93610.93);
+        assert!(parse_binance_archive_csv("1704067200000,x,1,1,1,1,1,1,1,1,1,0").is_err());
+    }
+
+    #[test]
+    fn columnar_export_roundtrip() {
+        let rows = parse_binance_archive_csv(
+            "1704067200000,1,2,0.5,1.5,10,1704067259999,15,3,5,7.5,0
+1704067260000,1.5,2.5,1,2,20,1704067319999,40,4,10,20,0",
+        )
+        .unwrap();
+        let kline = FatKline { kline: rows };
+
+        let path =
+            std::env::temp_dir().join(format!["bintrade_export_{}.parquet", std::process::id()]);
+        let f = std::fs::File::create(&path).unwrap();
+        assert_eq!(
+            write_kline_columnar(&kline, &ExportFormat::Parquet, f).unwrap(),
+            2
+        );
+        let reader = parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(
+            std::fs::File::open(&path).unwrap(),
+        )
+        .unwrap()
+        .build()
+        .unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|b| b.unwrap()).collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(batches[0].num_rows(), 2);
+        assert_eq!(batches[0].schema().field(0).name(), "open_time");
+        let close = batches[0]
+            .column_by_name("close")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(close.values().to_vec(), vec![1.5, 2.0]);
+
+        let mut ipc_out = vec![];
+        write_kline_columnar(&kline, &ExportFormat::ArrowIpc, &mut ipc_out).unwrap();
+        let reader =
+            arrow_ipc::reader::FileReader::try_new(std::io::Cursor::new(ipc_out), None).unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|b| b.unwrap()).collect();
+        let trades = batches[0]
+            .column_by_name("trades")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap();
+        assert_eq!(trades.values().to_vec(), vec![3, 4]);
+    }
+
+    #[test]
+    fn binance_archive_checksum() {
+        let dir = std::env::temp_dir().join(format!["bintrade_archive_{}", std::process::id()]);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("BTCUSDT-1m-2024-01.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+        zip.start_file(
+            "BTCUSDT-1m-2024-01.csv",
+            zip::write::SimpleFileOptions::default(),
+        )
+        .unwrap();
+        zip.write_all(b"1704067200000,1,2,0.5,1.5,10,1704067259999,15,3,5,7.5,0\n")
+            .unwrap();
+        zip.finish().unwrap();
+
+        let (rows, verified) = read_binance_archive(&path).unwrap();
+        assert_eq!((rows.len(), verified), (1, false));
+
+        let sum: String = Sha256::digest(std::fs::read(&path).unwrap())
+            .iter()
+            .map(|b| format!["{:02x}", b])
+            .collect();
+        let checksum_path = dir.join("BTCUSDT-1m-2024-01.zip.CHECKSUM");
+        std::fs::write(&checksum_path, format!["{}  BTCUSDT-1m-2024-01.zip\n", sum]).unwrap();
+        let (rows, verified) = read_binance_archive(&path).unwrap();
+        assert_eq!((rows.len(), verified), (1, true));
+
+        std::fs::write(
+            &checksum_path,
+            format!["{}  BTCUSDT-1m-2024-01.zip\n", "0".repeat(64)],
+        )
+        .unwrap();
+        assert!(read_binance_archive(&path).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn gapped_asset_data(times: &[i64]) -> AssetData {
+        let kline = times
+            .iter()
+            .map(|t| {
+                let time = DateTime::<Utc>::from_timestamp_millis(*t).unwrap();
+                (time, 1.0, 2.0, 0.5, 1.5, *t as f64)
+            })
+            .collect();
+        let mut klines = Klines::new_empty();
+        klines.insert(&Intv::Hour1, Kline { kline });
+        let mut ad = AssetData::new(1);
+        ad.kline_data.insert("GAP".to_string(), klines);
+        ad
+    }
+
+    #[test]
+    fn find_slice_skips_gaps_and_rejects_out_of_range() {
+        let h = Intv::Hour1.to_ms();
+        let t0 = 1704067200000;
+        //0-9h, a 40h gap, 50-59h and a 1h gap before 61h
+        let times: Vec<i64> = (0..10)
+            .chain(50..60)
+            .chain(61..62)
+            .map(|n| t0 + n * h)
+            .collect();
+        let ad = gapped_asset_data(&times);
+        let dt = |n: i64| DateTime::<Utc>::from_timestamp_millis(t0 + n * h).unwrap();
+        let opens = |s: &[KlineRow]| {
+            s.iter()
+                .map(|k| (k.0.timestamp_millis() - t0) / h)
+                .collect::<Vec<i64>>()
+        };
+
+        let across = ad.find_slice("GAP", &Intv::Hour1, &dt(8), &dt(51)).unwrap();
+        assert_eq!(opens(across), vec![8, 9, 50, 51]);
+        //bounds between candles
+        let between = ad
+            .find_slice(
+                "GAP",
+                &Intv::Hour1,
+                &(dt(9) + chrono::Duration::minutes(30)),
+                &dt(60),
+            )
+            .unwrap();
+        assert_eq!(opens(between), (50..60).collect::<Vec<i64>>());
+        let in_gap = ad
+            .find_slice("GAP", &Intv::Hour1, &dt(20), &dt(30))
+            .unwrap();
+        assert!(in_gap.is_empty());
+        let clipped = ad.find_slice("GAP", &Intv::Hour1, &dt(-5), &dt(1)).unwrap();
+        assert_eq!(opens(clipped), vec![0, 1]);
+        assert!(
+            ad.find_slice("GAP", &Intv::Hour1, &dt(-5), &dt(-1))
+                .is_err()
+        );
+        assert!(
+            ad.find_slice("GAP", &Intv::Hour1, &dt(62), &dt(70))
+                .is_err()
+        );
+        assert!(ad.find_slice("GAP", &Intv::Hour1, &dt(5), &dt(4)).is_err());
+        assert!(ad.find_slice("GAP", &Intv::Day1, &dt(0), &dt(4)).is_err());
+        assert!(ad.find_slice("NOPE", &Intv::Hour1, &dt(0), &dt(4)).is_err());
+
+        let n = ad
+            .find_slice_n("GAP", &Intv::Hour1, &(t0 + 7 * h), 5)
+            .unwrap();
+        assert_eq!(opens(n), vec![7, 8, 9, 50, 51]);
+        let from_gap = ad
+            .find_slice_n("GAP", &Intv::Hour1, &(t0 + 30 * h), 2)
+            .unwrap();
+        assert_eq!(opens(from_gap), vec![50, 51]);
+        let tail = ad
+            .find_slice_n("GAP", &Intv::Hour1, &(t0 + 59 * h), 10)
+            .unwrap();
+        assert_eq!(opens(tail), vec![59, 61]);
+        assert!(ad.find_slice_n("GAP", &Intv::Hour1, &(t0 - h), 2).is_err());
+        assert!(
+            ad.find_slice_n("GAP", &Intv::Hour1, &(t0 + 62 * h), 2)
+                .is_err()
+        );
+        assert!(
+            gapped_asset_data(&[])
+                .find_slice_n("GAP", &Intv::Hour1, &t0, 2)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn loaded_symbols_are_dropped_least_recently_loaded_first() {
+        let mut ad = AssetData::new(0);
+        for n in 0..LOADED_SYMBOLS_MAX {
+            ad.insert_loaded(&format!["S{}", n], Klines::new_empty());
+        }
+        //reloading S0 makes S1 the oldest
+        ad.insert_loaded("S0", Klines::new_empty());
+        ad.insert_loaded("NEW", Klines::new_empty());
+
+        assert_eq!(ad.kline_data.len(), LOADED_SYMBOLS_MAX);
+        assert!(ad.kline_data.contains_key("S0") && ad.kline_data.contains_key("NEW"));
+        assert!(!ad.kline_data.contains_key("S1"));
+        assert_eq!(ad.load_order.first().map(|s| s.as_str()), Some("S2"));
+    }
 }
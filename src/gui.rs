@@ -26,13 +26,20 @@ use egui_tiles::{Tile, TileId, Tiles};
 use epaint::Stroke;
 
 use bincode::{Decode, Encode, config};
-use chrono::{DateTime, Datelike, Local, Utc};
+use chrono::{DateTime, Datelike, Local, Timelike, Utc};
 use derive_debug::Dbg;
 use magic_crypt::{MagicCryptTrait, new_magic_crypt};
 
+use crate::cache::{CacheUsage, DEFAULT_CACHE_LIMIT_MB};
 use crate::conn::{KlineTick, SymbolOutput};
-use crate::data::{AssetData, DLAsset, Intv, Klines};
-use crate::trade::{EvalMode, HistTrade, LimitStatus, Order, Quant, StopStatus};
+use crate::data::{AssetData, AuditReport, DLAsset, ExportFormat, Intv, KlineRow, Klines};
+use crate::jobs::{DlProgress, DlState};
+use crate::pattern::{PATTERN_CANDLES, PatternMatch};
+use crate::replay::{REPLAY_SPEEDS, speed_str};
+use crate::stats::{ColStat, STAT_WINDOWS, StatCol, find_col_stat};
+use crate::trade::{
+    EvalMode, HistJournal, HistSnapshot, HistTrade, LimitStatus, Order, Quant, StopStatus,
+};
 use crate::{BinInstructs, ClientInstruct, ClientResponse, ProcResp, SQLInstructs, SQLResponse};
 
 const WICKS_VISIBLE: usize = 90;
@@ -40,10 +47,14 @@ const NAVI_WICKS_DEFAULT: u16 = 30;
 const CHART_FORWARD: u16 = 40;
 const DEFAULT_TRADE_WICKS: u16 = 30;
 const BACKLOAD_WICKS: i64 = 720;
+///Most candles per interval the Hist Trade pane loads when scrolled back
+const BACKLOAD_WICKS_MAX: i64 = 20 * BACKLOAD_WICKS;
+#[cfg(feature = "yfinance")]
+const DL_EXCHANGES: &[&str] = &["Binance", "Binance Futures", "Yahoo"];
+#[cfg(not(feature = "yfinance"))]
+const DL_EXCHANGES: &[&str] = &["Binance", "Binance Futures"];
 const MAX_PLOT_WIDTH: f32 = 800.0;
 
-const SETTINGS_SAVE_PATH: &str = "./Settings.bin";
-
 #[derive(Dbg, Default, Clone)]
 pub struct OrderMarkers {
     pub buy_markers: Vec<(i64, f64)>,
@@ -108,7 +119,7 @@ pub struct KlinePlot {
     pub l_tick_boxplot: Vec<BoxElem>,
     pub l_tick_barchart: Vec<Bar>,
 
-    pub tick_kline: Option<(DateTime<Utc>, f64, f64, f64, f64, f64)>,
+    pub tick_kline: Option<KlineRow>,
     pub markers: bool,
 
     pub points: OrderMarkers,
@@ -139,6 +150,8 @@ pub struct KlinePlot {
     pub y_offset_s: String,
     pub y_increment: f64,
     pub x_bounds_set: bool,
+    ///Candles shown if more than the view window of intv, set by panes that load more
+    pub backload: usize,
 
     pub live_asset_changed: bool,
 }
@@ -178,6 +191,7 @@ impl Default for KlinePlot {
             y_offset: 0,
             y_increment: 0.001,
             x_bounds_set: false,
+            backload: 0,
 
             live_asset_changed: false,
         }
@@ -205,7 +219,7 @@ impl KlinePlot {
         return_wicks: Option<usize>,
         last_price_hist: Option<&mut f64>,
         hist_symbol_info: Option<&mut (String, String, String)>,
-    ) -> Option<Vec<(DateTime<Utc>, f64, f64, f64, f64, f64)>> {
+    ) -> Option<Vec<KlineRow>> {
         let ad = live_ad.lock().expect("Live AD mutex locked");
         if let Some(live_inf) = live_info {
             live_inf.live_asset_symbol_changed = ad.live_asset_symbol_changed.clone();
@@ -228,7 +242,7 @@ impl KlinePlot {
                             None => {
                                 tracing::error![
                                     "Unable to find interval: {} in ad for ret_wicks",
-                                    &self.intv.to_str()
+                                    &self.intv.label()
                                 ];
                                 None
                             }
@@ -267,7 +281,7 @@ impl KlinePlot {
                 let _res = self.live_live_from_ad(
                     &ad,
                     self.intv.clone(),
-                    self.intv.to_view_window(),
+                    self.intv.to_view_window().max(self.backload),
                     &data,
                 );
             };
@@ -275,7 +289,7 @@ impl KlinePlot {
                 &ad,
                 &symbol,
                 self.intv.clone(),
-                self.intv.to_view_window(),
+                self.intv.to_view_window().max(self.backload),
                 None,
                 false,
             );
@@ -284,7 +298,7 @@ impl KlinePlot {
                 &ad,
                 &symbol,
                 self.intv.clone(),
-                self.intv.to_view_window(),
+                self.intv.to_view_window().max(self.backload),
                 None,
                 true,
             );
@@ -358,13 +372,7 @@ impl KlinePlot {
             &self.name, self.intv, y_lower, y_higher, x_lower, x_higher, v_higher,
         )
     }
-    pub fn add_live(
-        &mut self,
-        kline_input: &[(DateTime<Utc>, f64, f64, f64, f64, f64)],
-        divider: &f64,
-        width: &f64,
-        tick: bool,
-    ) {
+    pub fn add_live(&mut self, kline_input: &[KlineRow], divider: &f64, width: &f64, tick: bool) {
         if tick == false {
             self.l_boxplot = vec![];
             self.l_barchart = vec![];
@@ -576,14 +584,10 @@ impl KlinePlot {
     ) -> Result<()> {
         tracing::trace!["GUI Live from AD called!"];
         let k = match timestamps {
-            Some((start, end)) => ad.find_slice(symbol, &intv, &start, &end).ok_or(anyhow![
-                "Unable to find slice in the period:{} to {}",
-                &start,
-                &end
-            ])?,
+            Some((start, end)) => ad.find_slice(symbol, &intv, &start, &end)?,
             None => ad.load_full_intv(symbol, &intv)?,
         };
-        tracing::trace!["Kline intv (live_from_ad) {}", intv.to_str()];
+        tracing::trace!["Kline intv (live_from_ad) {}", intv.label()];
         let (div, width) = get_chart_params(&intv);
         self.chart_params = (div, width);
         if k.len() <= max_load_points {
@@ -613,11 +617,7 @@ impl KlinePlot {
     }
 }
 
-fn box_element(
-    slice: &(DateTime<Utc>, f64, f64, f64, f64, f64),
-    divider: &f64,
-    width: &f64,
-) -> (BoxElem, Bar) {
+fn box_element(slice: &KlineRow, divider: &f64, width: &f64) -> (BoxElem, Bar) {
     let (time, open, high, low, close, volume) = *slice;
     let a1 = (high + low) / 2.0;
     let red = Color32::from_rgb(255, 0, 0);
@@ -851,6 +851,19 @@ fn make_plot(
             x_higher,
             v_higher
         ),
+        Intv::Custom(m) => {
+            let x_format_custom = x_format_div(m as i64 * M1_DIV);
+            make_p2!(
+                name,
+                x_format_custom,
+                grid_spacer_1d,
+                y_lower,
+                y_higher,
+                x_lower,
+                x_higher,
+                v_higher
+            )
+        }
     }
 }
 
@@ -906,6 +919,14 @@ fn get_chart_params(intv: &Intv) -> (f64, f64) {
         Intv::Day3 => (D3_DIV as f64, D3_GAP),
         Intv::Week1 => (W1_DIV as f64, W1_GAP),
         Intv::Month1 => (MO1_DIV as f64, MO1_GAP), //with reference to 1970 1,1 00:00 perhaps?
+        Intv::Custom(m) => {
+            let div = (*m as i64 * M1_DIV) as f64;
+            let gap = match *m as i64 * M1_DIV > D1_DIV {
+                true => EXTRA_GAP * GAP,
+                false => GAP,
+            };
+            (div, (gap * (*m as f64)) / div)
+        }
     }
 }
 #[allow(unused)]
@@ -969,6 +990,25 @@ fn grid_spacer_1mo(_input: GridInput) -> [f64; 3] {
     [1.0, 1.0, 1.0]
 }
 
+///x axis formatter for custom intervals, div is the interval in seconds
+fn x_format_div(div: i64) -> impl Fn(GridMark, &RangeInclusive<f64>) -> String + Copy {
+    move |gridmark: GridMark, _range: &RangeInclusive<f64>| {
+        let fixed_gridmark = (gridmark.value as i64) * div;
+        let res = DateTime::<Utc>::from_timestamp(fixed_gridmark, 0);
+        let date_time = match res {
+            Some(dt) => {
+                let d: DateTime<Local> = dt.into();
+                d
+            }
+            None => {
+                tracing::error!["Unable to format datetime, setting 0 "];
+                DateTime::default()
+            }
+        };
+        format!["{}", date_time]
+    }
+}
+
 fn x_format_1min(gridmark: GridMark, _range: &RangeInclusive<f64>) -> String {
     let fixed_gridmark = (gridmark.value as i64) * M1_DIV;
     let res = DateTime::<Utc>::from_timestamp(fixed_gridmark, 0);
@@ -1401,6 +1441,12 @@ impl egui_tiles::Behavior<Pane> for DesktopApp {
                     Some(&t_slice),
                     Some(&hist_extras.symbol_info),
                 );
+                let chan = self.send_to_cli.clone().expect("Cli comm channel none!");
+                HistPlot::show_journal(h_plot, man_orders, chan, ui);
+                let chan = self.send_to_cli.clone().expect("Cli comm channel none!");
+                if let Some(m) = HistPlot::show_patterns(h_plot, chan, ui) {
+                    self.open_hist_at = Some(m);
+                };
             }
             PaneType::ManageData => {
                 match self.resp_buff.as_ref() {
@@ -1411,6 +1457,13 @@ impl egui_tiles::Behavior<Pane> for DesktopApp {
 
                 let data_manager_l = self.data_manager.clone();
                 let mut data_manager = data_manager_l.lock().expect("Data manager mutex posoned!");
+                let event = match self.last_resp.as_ref() {
+                    Some(ClientResponse::ProcResp(ProcResp::SQLResp(SQLResponse::DlProgress(
+                        p,
+                    )))) => Some(p),
+                    _ => None,
+                };
+                data_manager.update_downloads(event);
 
                 DataManager::show(&mut data_manager, chan, ui);
             }
@@ -1500,7 +1553,7 @@ fn create_tree() -> egui_tiles::Tree<Pane> {
 pub enum PlotExtras {
     None,
     OrderHlines(Vec<HLine>),
-    TradeSlice(Vec<(DateTime<Utc>, f64, f64, f64, f64, f64)>),
+    TradeSlice(Vec<KlineRow>),
 }
 
 #[derive(PartialEq, Debug, Clone, Default)]
@@ -1513,16 +1566,24 @@ pub struct LiveInfo {
     pub live_orders: HashMap<u64, (Order, bool, f64)>,
     pub keys_status: KeysStatus,
     pub live_info_changed: bool,
+    ///aggTrades of the live symbols are being written to ticks/
+    pub tick_recording: bool,
+    ///File the raw WS messages are being recorded to
+    pub ws_recording: Option<String>,
+    ///Frames replayed out of the frames of the WS session being replayed
+    pub ws_replay: Option<(usize, usize)>,
 }
 
 #[derive(Dbg)]
 pub struct DesktopApp {
-    pub trade_slice: Rc<Mutex<Vec<(DateTime<Utc>, f64, f64, f64, f64, f64)>>>,
+    pub trade_slice: Rc<Mutex<Vec<KlineRow>>>,
 
     pub simplification_options: egui_tiles::SimplificationOptions,
     pub tab_bar_height: f32,
     pub gap_width: f32,
     pub add_child_to: Option<egui_tiles::TileId>,
+    ///Pattern search result to open in a new Hist Trade pane once the tree is drawn
+    pub open_hist_at: Option<PatternMatch>,
 
     pub pane_number: usize,
     #[dbg(skip)]
@@ -1571,6 +1632,8 @@ impl DesktopApp {
         if &resp != last_resp {
             match resp {
                 ClientResponse::None => {}
+                //NOTE one per chunk, the Data Manager reads the latest from last_resp
+                ClientResponse::ProcResp(ProcResp::SQLResp(SQLResponse::DlProgress(_))) => {}
                 ClientResponse::Success | ClientResponse::Failure(_) => {
                     let res = buff.get(&ProcResp::Client);
                     match res {
@@ -1631,6 +1694,7 @@ impl Default for DesktopApp {
             tab_bar_height: 24.0,
             gap_width: 2.0,
             add_child_to: None,
+            open_hist_at: None,
 
             tree: Rc::new(Mutex::new(create_tree())),
             pane_number: 1,
@@ -1715,7 +1779,8 @@ impl DesktopApp {
             });
         if next_panel_type != PaneType::None {
             if let Some(parent) = self.add_child_to.take() {
-                let mut tree = self.tree.lock().expect("Posoned mutex on pane tree!");
+                let tt = self.tree.clone();
+                let mut tree = tt.lock().expect("Posoned mutex on pane tree!");
                 let mut new_child = tree
                     .tiles
                     .insert_pane(Pane::new(self.pane_number, PaneType::None));
@@ -1731,24 +1796,7 @@ impl DesktopApp {
                         self.pane_number += 1;
                     }
                     PaneType::HistTrade => {
-                        self.man_orders
-                            .insert(self.pane_number + 1, ManualOrders::default());
-                        let settings = self.settings.lock().expect("Unable to unlock settings");
-                        self.hist_plot.insert(
-                            self.pane_number + 1,
-                            HistPlot::new(
-                                Arc::clone(&self.hist_asset_data),
-                                &settings.default_intv,
-                                settings.defalt_next_wicks,
-                            ),
-                        );
-                        self.hist_extras
-                            .insert(self.pane_number + 1, HistExtras::default());
-
-                        new_child = tree
-                            .tiles
-                            .insert_pane(Pane::new(self.pane_number + 1, PaneType::HistTrade));
-                        self.pane_number += 1;
+                        new_child = self.insert_hist_pane(&mut tree);
                     }
                     PaneType::ManageData => {
                         new_child = tree
@@ -1772,6 +1820,64 @@ impl DesktopApp {
             }
         }
     }
+
+    fn insert_hist_pane(&mut self, tree: &mut egui_tiles::Tree<Pane>) -> TileId {
+        self.man_orders
+            .insert(self.pane_number + 1, ManualOrders::default());
+        let settings = self.settings.lock().expect("Unable to unlock settings");
+        self.hist_plot.insert(
+            self.pane_number + 1,
+            HistPlot::new(
+                Arc::clone(&self.hist_asset_data),
+                &settings.default_intv,
+                settings.defalt_next_wicks,
+            ),
+        );
+        self.hist_extras
+            .insert(self.pane_number + 1, HistExtras::default());
+
+        let new_child = tree
+            .tiles
+            .insert_pane(Pane::new(self.pane_number + 1, PaneType::HistTrade));
+        self.pane_number += 1;
+        new_child
+    }
+    ///Opens a Hist Trade pane with the last candle of the match as the last closed one
+    fn open_hist_pane(&mut self, tree: &mut egui_tiles::Tree<Pane>, m: &PatternMatch) {
+        let new_child = self.insert_hist_pane(tree);
+        let trade_time = m.time + m.intv.to_ms();
+        if let Some(hist_plot) = self.hist_plot.get_mut(&self.pane_number) {
+            hist_plot.intv = m.intv;
+            hist_plot.last_intv = m.intv;
+            hist_plot.kline_plot.intv = m.intv;
+            hist_plot.search_load_string = m.symbol.clone();
+            hist_plot.kline_plot.symbol = m.symbol.clone();
+            hist_plot.trade_time = trade_time;
+            if let Some(t) = DateTime::<Utc>::from_timestamp_millis(trade_time) {
+                hist_plot.picked_date_end = t.date_naive();
+                hist_plot.trade_h_s = format!["{:02}", t.hour()];
+                hist_plot.trade_min_s = format!["{:02}", t.minute()];
+            };
+        };
+        if let Some(chan) = self.send_to_cli.as_ref() {
+            let msg = ClientInstruct::SendSQLInstructs(SQLInstructs::LoadHistDataPart2 {
+                symbol: m.symbol.clone(),
+                trade_time,
+                backload_wicks: BACKLOAD_WICKS,
+                intv: m.intv,
+            });
+            let _res = chan.send(msg);
+        };
+        let root = tree.root();
+        match root.and_then(|r| tree.tiles.get_mut(r)) {
+            Some(egui_tiles::Tile::Container(egui_tiles::Container::Tabs(tabs))) => {
+                tabs.add_child(new_child);
+                tabs.set_active(new_child);
+            }
+            Some(egui_tiles::Tile::Container(c)) => c.add_child(new_child),
+            _ => tracing::error!["No pane container to open {} in", m.symbol],
+        };
+    }
 }
 
 impl eframe::App for DesktopApp {
@@ -1813,6 +1919,9 @@ impl eframe::App for DesktopApp {
             let tt = self.tree.clone();
             let mut tree = tt.lock().expect("Posoned mutex on pane tree!");
             tree.ui(self, ui);
+            if let Some(m) = self.open_hist_at.take() {
+                self.open_hist_pane(&mut tree, &m);
+            };
         });
     }
 }
@@ -2145,8 +2254,9 @@ impl SingleOrderMode {
             self.order_placed = false;
             self.order = Order::None;
         };
-        if self.order_active && self.order != Order::None {
-            let buy = self.order.get_side();
+        if self.order_active
+            && let Some(buy) = self.order.get_side()
+        {
             if buy {
                 self.locked_qnt = man_orders.asset2;
             } else {
@@ -2228,9 +2338,10 @@ impl SingleOrderMode {
             };
             self.parse_ks = false;
         };
-        if self.place_order && self.order != Order::None {
+        if self.place_order
+            && let Some(buy) = self.order.get_side()
+        {
             tracing::trace!["hotkeys place_order called"];
-            let buy = self.order.get_side();
             if buy {
                 self.locked_qnt = man_orders.asset2;
             } else {
@@ -2257,8 +2368,10 @@ impl SingleOrderMode {
             self.place_order = false;
             self.order_placed = true;
         };
-        if self.order_adjusted && self.order_placed && self.order != Order::None {
-            let buy = self.order.get_side();
+        if self.order_adjusted
+            && self.order_placed
+            && let Some(buy) = self.order.get_side()
+        {
             if buy {
                 self.locked_qnt = man_orders.asset2;
             } else {
@@ -2626,12 +2739,11 @@ impl ManualOrders {
         asset2: &f64,
         locked_qnt: &f64,
     ) -> (f64, f64, f64, f64) {
-        let buy = o.get_side();
         tracing::trace!["LOCKED QNT:{}", locked_qnt];
-        let (unlocked_a1, unlocked_a2) = if buy {
-            (0.0, *locked_qnt)
-        } else {
-            (*locked_qnt, 0.0)
+        let (unlocked_a1, unlocked_a2) = match o.get_side() {
+            Some(true) => (0.0, *locked_qnt),
+            Some(false) => (*locked_qnt, 0.0),
+            None => (0.0, 0.0),
         };
         tracing::trace!["Unlocked A1:{}, Unlocked A2:{}", unlocked_a1, unlocked_a2];
 
@@ -2648,13 +2760,15 @@ impl ManualOrders {
         asset_1_locked: &f64,
         asset_2_locked: &f64,
     ) -> Option<(f64, f64, f64, f64, f64)> {
-        let price = o.get_price();
-        if *price <= 0.0 {
+        let (Some(price), Some(side)) = (o.get_price(), o.get_side()) else {
+            tracing::error!["Order::None should not be passed here"];
+            return None;
+        };
+        if price <= 0.0 {
             tracing::error!["Order price less than or equal to 0"];
             return None;
         };
         //1. Get orde price and check if it's valid
-        let side = o.get_side();
         match side {
             true => {
                 if *asset2 <= 0.0 {
@@ -3125,7 +3239,7 @@ impl ManualOrders {
                                     ui.label(format!["{:.3}", locked_qnt]);
                                 });
                                 row.col(|ui| {
-                                    ui.label(format!["{}", order.get_price()]);
+                                    ui.label(format!["{}", order.get_price().unwrap_or_default()]);
                                 });
                                 row.col(|ui| {
                                     ui.label(order.to_str());
@@ -3200,7 +3314,7 @@ impl ManualOrders {
         ui: &mut egui::Ui,
         hlines: Option<&mut Vec<HLine>>,
         live_info: Option<&LiveInfo>,
-        trade_slice: Option<&[(DateTime<Utc>, f64, f64, f64, f64, f64)]>,
+        trade_slice: Option<&[KlineRow]>,
         symbol_info: Option<&(String, String, String)>,
     ) {
         let hh = hist_trade.is_some();
@@ -3243,6 +3357,10 @@ impl ManualOrders {
                             .map(|(id, (order, _active, locked_qnt))| (*id, *order, *locked_qnt))
                             .collect();
                         tracing::trace!["inactive_orders:{}", inactive_orders.len()];
+                        let funding_paid = h_trade.forward_funding(t_slice);
+                        if funding_paid != 0.0 {
+                            tracing::debug!["Hist funding paid: {}", funding_paid];
+                        };
                         let remaining_active_orders =
                             h_trade.trade_forward(t_slice, &man_orders.eval_mode, active_orders);
                         tracing::trace![
@@ -3265,14 +3383,14 @@ impl ManualOrders {
                         let a2 = h_trade.asset2;
                         let a1_locked = remaining_active_orders
                             .iter()
-                            .filter(|(_, o, _)| o.get_side() == false)
-                            .map(|(_, order, _)| order.get_qnt() * a1)
+                            .filter(|(_, o, _)| o.get_side() == Some(false))
+                            .map(|(_, order, _)| order.get_qnt().unwrap_or_default() * a1)
                             .sum::<f64>()
                             .abs();
                         let a2_locked = remaining_active_orders
                             .iter()
-                            .filter(|(_, o, _)| o.get_side() == true)
-                            .map(|(_, order, _)| order.get_qnt() * a2)
+                            .filter(|(_, o, _)| o.get_side() == Some(true))
+                            .map(|(_, order, _)| order.get_qnt().unwrap_or_default() * a2)
                             .sum::<f64>()
                             .abs();
                         //FIXME .... hmhmmm.....
@@ -3434,6 +3552,8 @@ pub struct LivePlot {
     pub lines: Vec<HLine>,
     pub reload: bool,
     pub last_symbol: String,
+    pub ws_session_path_s: String,
+    pub ws_replay_speed: u32,
 }
 
 impl Default for LivePlot {
@@ -3450,6 +3570,8 @@ impl Default for LivePlot {
             reload: false,
             default_symbol: "BTCUSDT".to_string(),
             last_symbol: "BTCUSDT".to_string(),
+            ws_session_path_s: String::default(),
+            ws_replay_speed: 1,
         }
     }
 }
@@ -3529,6 +3651,47 @@ impl LivePlot {
                 let _res = cli_chan.send(msg);
             };
         });
+        ui.collapsing("WS session", |ui| {
+            ui.horizontal(|ui| {
+                let mut record = live_info.ws_recording.is_some();
+                if ui.checkbox(&mut record, "Record WS").changed() {
+                    let msg = ClientInstruct::SendBinInstructs(BinInstructs::RecordWS { record });
+                    let _res = cli_chan.send(msg);
+                };
+                if let Some(path) = &live_info.ws_recording {
+                    ui.label(path.as_str());
+                };
+            });
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::TextEdit::singleline(&mut live_plot.ws_session_path_s)
+                        .hint_text("WS session file"),
+                );
+                ComboBox::from_id_salt("WS replay speed")
+                    .selected_text(speed_str(live_plot.ws_replay_speed))
+                    .show_ui(ui, |ui| {
+                        for s in REPLAY_SPEEDS {
+                            ui.selectable_value(&mut live_plot.ws_replay_speed, s, speed_str(s));
+                        }
+                    });
+                if ui.button("Replay WS").clicked() {
+                    let msg = ClientInstruct::SendBinInstructs(BinInstructs::ReplayWS {
+                        path: live_plot.ws_session_path_s.trim().to_string(),
+                        speed: live_plot.ws_replay_speed,
+                    });
+                    let _res = cli_chan.send(msg);
+                };
+            });
+            if let Some((n, total)) = live_info.ws_replay {
+                ui.horizontal(|ui| {
+                    ui.label(format!["Replaying {}/{}", n, total]);
+                    if ui.button("Stop replay").clicked() {
+                        let msg = ClientInstruct::SendBinInstructs(BinInstructs::StopReplayWS);
+                        let _res = cli_chan.send(msg);
+                    };
+                });
+            };
+        });
     }
 }
 
@@ -3550,6 +3713,7 @@ pub struct HistPlot {
     pub search_load_string: String,
     pub intv: Intv,
     pub last_intv: Intv,
+    pub custom_intv_s: String,
     pub picked_date: chrono::NaiveDate,
     pub picked_date_end: chrono::NaiveDate,
 
@@ -3575,6 +3739,14 @@ pub struct HistPlot {
     pub all_loaded: bool,
 
     pub trade_slice_loaded: bool,
+    ///Candles per interval loaded before the trade time, grows when scrolled back
+    pub backload_wicks: i64,
+    ///Symbol whose klines were shown, reloaded once AssetData dropped them
+    pub shown_symbol: String,
+
+    pub journal: HistJournal,
+    pub replay: Option<(HistJournal, usize)>,
+    pub journal_path_s: String,
 }
 
 impl Default for HistPlot {
@@ -3599,6 +3771,7 @@ impl Default for HistPlot {
 
             intv: Intv::Min1,
             last_intv: Intv::Min1,
+            custom_intv_s: String::default(),
 
             picked_date: chrono::NaiveDate::from_ymd_opt(current_year - 1, 1, 1)
                 .expect("Unable to get current year"),
@@ -3634,6 +3807,12 @@ impl Default for HistPlot {
             all_loaded: false,
 
             trade_slice_loaded: false,
+            backload_wicks: BACKLOAD_WICKS,
+            shown_symbol: String::default(),
+
+            journal: HistJournal::default(),
+            replay: None,
+            journal_path_s: String::default(),
         }
     }
 }
@@ -3862,6 +4041,17 @@ impl Settings {
                 );
                 ui.checkbox(&mut settings.enc_api_keys, "Encrypt api keys w password");
                 ui.end_row();
+                //NOTE not saved in Settings.bin, recording starts off every run
+                let mut record = live_info.tick_recording;
+                if ui
+                    .checkbox(&mut record, "Record aggTrades to ticks/")
+                    .changed()
+                {
+                    let msg =
+                        ClientInstruct::SendBinInstructs(BinInstructs::RecordTicks { record });
+                    let _res = cli_chan.send(msg);
+                };
+                ui.end_row();
             });
         egui::Grid::new("Account_balances")
             .min_col_width(30.0)
@@ -3986,22 +4176,29 @@ impl Settings {
         }
         let config = config::standard();
         let res = bincode::encode_to_vec(self.clone(), config)?;
-        let mut file = std::fs::File::create(SETTINGS_SAVE_PATH)?;
+        let mut file = Settings::create_file()?;
         file.write_all(&res)?;
         Ok(())
     }
+    fn create_file() -> Result<std::fs::File> {
+        let path = crate::paths::get().settings();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        };
+        Ok(std::fs::File::create(path)?)
+    }
     pub fn save_default_file() -> Result<()> {
         let config = config::standard();
         let s = Settings::new();
         tracing::trace!["{:?}", s];
         let res = bincode::encode_to_vec(s, config)?;
-        let mut file = std::fs::File::create(SETTINGS_SAVE_PATH)?;
+        let mut file = Settings::create_file()?;
         file.write_all(&res)?;
         Ok(())
     }
     pub fn load_settings_enc() -> Result<Option<Self>> {
         let config = config::standard();
-        let res = std::fs::File::open(SETTINGS_SAVE_PATH);
+        let res = std::fs::File::open(crate::paths::get().settings());
         let mut file = match res {
             Ok(file) => file,
             Err(_e) => {
@@ -4016,7 +4213,7 @@ impl Settings {
     }
     pub fn load_settings_file(password: Option<String>) -> Result<Option<Self>> {
         let config = config::standard();
-        let res = std::fs::File::open(SETTINGS_SAVE_PATH);
+        let res = std::fs::File::open(crate::paths::get().settings());
         let mut file = match res {
             Ok(file) => file,
             Err(e) => {
@@ -4045,6 +4242,15 @@ pub struct DataManager {
     pub downloaded_coin_shortlist: Vec<String>,
 
     pub coin_search_string: String,
+    pub dl_exchange: String,
+    pub archive_dir: String,
+
+    pub export_dir: String,
+    pub export_intv: Intv,
+    pub export_format: ExportFormat,
+    pub export_range: bool,
+    pub export_start: chrono::NaiveDate,
+    pub export_end: chrono::NaiveDate,
     pub shortlist_max: usize,
 
     pub selected_coin: String,
@@ -4054,14 +4260,31 @@ pub struct DataManager {
     pub max_backdate_months: usize,
 
     pub autoupdate_on_start: bool,
+    pub derive_intervals: bool,
+    ///Scheduled updates, synced from the SQL task once it has loaded them
+    pub scheduled_update: bool,
+    pub scheduled_update_min: u64,
+    pub schedule_synced: bool,
     pub update_success: bool,
     pub update_ran: bool,
     pub update_status: String,
+    pub dl_status: String,
 
     pub asset_list_loaded: bool,
     pub asset_list_imported: bool,
 
     pub asset_list: Vec<DLAsset>,
+    pub audit_reports: HashMap<String, AuditReport>,
+    ///Latest progress of each download by symbol
+    pub dl_jobs: BTreeMap<String, DlProgress>,
+    ///Last streamed progress event, it stays in last_resp until the next response
+    pub last_dl_event: Option<DlProgress>,
+    pub cache_usage: CacheUsage,
+    pub cache_limit_mb: u64,
+    ///Asset whose column statistics are shown
+    pub stats_symbol: Option<String>,
+    pub stats_intv: Intv,
+    pub col_stats: Vec<ColStat>,
 
     pub hist_asset_data: Arc<Mutex<AssetData>>,
 }
@@ -4071,14 +4294,210 @@ impl DataManager {
         DataManager {
             shortlist_max: 10,
             max_backdate_months: 120,
+            scheduled_update_min: 60,
+            cache_limit_mb: DEFAULT_CACHE_LIMIT_MB,
+            stats_intv: Intv::Day1,
             update_status: "Not ran".to_string(),
             update_ran: false,
             asset_list_loaded: false,
             asset_list_imported: false,
             hist_asset_data,
+            dl_exchange: DL_EXCHANGES[0].to_string(),
+            export_dir: "./exports".to_string(),
+            export_start: Utc::now().date_naive(),
+            export_end: Utc::now().date_naive(),
             ..Default::default()
         }
     }
+    ///Takes a new progress event of the SQL task's downloads. The channel only keeps the
+    ///latest response, so the other jobs (and a Done overwritten by the instruct's response)
+    ///are polled from the shared DlJobs.
+    pub fn update_downloads(&mut self, event: Option<&DlProgress>) {
+        let event = event.filter(|p| self.last_dl_event.as_ref() != Some(*p));
+        if let Some(p) = event {
+            self.on_progress(p);
+            self.last_dl_event = Some(p.clone());
+        };
+        let polled = {
+            let ad = self
+                .hist_asset_data
+                .lock()
+                .expect("Posioned AD mutex! (DATA)");
+            ad.dl_jobs.progress()
+        };
+        for p in polled.iter() {
+            if event.is_none_or(|e| e.symbol != p.symbol) {
+                self.on_progress(p);
+            };
+        }
+    }
+    pub fn on_progress(&mut self, p: &DlProgress) {
+        let known = self.dl_jobs.get(&p.symbol);
+        //NOTE the cancel button already set the job cancelled, late events don't revive it
+        if known.is_some_and(|k| k == p || k.state == DlState::Cancelled) {
+            return;
+        };
+        if p.state.finished() {
+            self.asset_list_loaded = false;
+        };
+        self.dl_jobs.insert(p.symbol.clone(), p.clone());
+    }
+    fn show_downloads(
+        data_manager: &mut DataManager,
+        cli_chan: &watch::Sender<ClientInstruct>,
+        ui: &mut egui::Ui,
+    ) {
+        let mut dismissed = vec![];
+        egui::Grid::new("Data Manager downloads")
+            .min_col_width(30.0)
+            .show(ui, |ui| {
+                for (symbol, p) in data_manager.dl_jobs.iter_mut() {
+                    ui.label(symbol);
+                    let intv = p.intv.as_ref().map(|i| i.to_str()).unwrap_or("");
+                    ui.add(
+                        egui::ProgressBar::new(p.percent() / 100.0)
+                            .desired_width(200.0)
+                            .text(format!["{} {:.0}% {}", intv, p.percent(), p.state.to_str()]),
+                    );
+                    match p.errors {
+                        0 => ui.label(""),
+                        n => ui
+                            .label(RichText::new(format!["{} errors", n]).color(Color32::RED))
+                            .on_hover_text(p.last_error.clone().unwrap_or_default()),
+                    };
+                    if p.state.finished() {
+                        if ui.button("Dismiss").clicked() {
+                            dismissed.push(symbol.clone());
+                        };
+                    } else {
+                        let paused = p.state == DlState::Paused;
+                        let label = match paused {
+                            true => "Resume",
+                            false => "Pause",
+                        };
+                        if ui.button(label).clicked() {
+                            let msg =
+                                ClientInstruct::SendSQLInstructs(SQLInstructs::PauseDownload {
+                                    symbol: symbol.clone(),
+                                    pause: !paused,
+                                });
+                            let _res = cli_chan.send(msg);
+                            p.state = match paused {
+                                true => DlState::Running,
+                                false => DlState::Paused,
+                            };
+                        };
+                        if ui.button("Cancel").clicked() {
+                            let msg =
+                                ClientInstruct::SendSQLInstructs(SQLInstructs::CancelDownload {
+                                    symbol: symbol.clone(),
+                                });
+                            let _res = cli_chan.send(msg);
+                            p.state = DlState::Cancelled;
+                        };
+                    };
+                    ui.end_row();
+                }
+            });
+        if !dismissed.is_empty() {
+            let ad = data_manager
+                .hist_asset_data
+                .lock()
+                .expect("Posioned AD mutex! (DATA)");
+            for symbol in dismissed {
+                ad.dl_jobs.dismiss(&symbol);
+                data_manager.dl_jobs.remove(&symbol);
+            }
+        };
+        if data_manager
+            .dl_jobs
+            .values()
+            .any(|p| p.state == DlState::Running)
+        {
+            ui.ctx()
+                .request_repaint_after(std::time::Duration::from_millis(250));
+        };
+    }
+    fn show_col_stats(
+        data_manager: &mut DataManager,
+        cli_chan: &watch::Sender<ClientInstruct>,
+        ui: &mut egui::Ui,
+    ) {
+        let Some(symbol) = data_manager.stats_symbol.clone() else {
+            return;
+        };
+        ui.horizontal(|ui| {
+            ui.label(RichText::new(format!["{} statistics", symbol]).color(Color32::WHITE));
+            ComboBox::from_id_salt("stats_intv")
+                .selected_text(data_manager.stats_intv.to_str())
+                .show_ui(ui, |ui| {
+                    for i in Intv::iter() {
+                        ui.selectable_value(&mut data_manager.stats_intv, i, i.to_str());
+                    }
+                });
+            if ui
+                .button("Recompute")
+                .on_hover_text("Statistics are updated after every download of the asset")
+                .clicked()
+            {
+                let msg = ClientInstruct::SendSQLInstructs(SQLInstructs::UpdateColStats {
+                    symbol: symbol.clone(),
+                });
+                let _res = cli_chan.send(msg);
+            };
+            if ui.button("Close").clicked() {
+                data_manager.stats_symbol = None;
+            };
+        });
+        ui.end_row();
+        if data_manager.col_stats.is_empty() {
+            ui.label("No statistics yet");
+            ui.end_row();
+            return;
+        };
+        egui::Grid::new("Data Manager column statistics")
+            .min_col_width(30.0)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong("Column");
+                for w in STAT_WINDOWS {
+                    match w {
+                        0 => ui.strong("All"),
+                        w => ui.strong(format!["Last {}", w]),
+                    };
+                }
+                ui.end_row();
+                for column in StatCol::iter() {
+                    ui.label(column.to_str());
+                    for w in STAT_WINDOWS {
+                        let stat = find_col_stat(
+                            &data_manager.col_stats,
+                            column,
+                            data_manager.stats_intv,
+                            w,
+                        );
+                        let Some(s) = stat else {
+                            ui.label("-");
+                            continue;
+                        };
+                        let fmt = |x: f64| match column.is_ratio() {
+                            true => format!["{:.3}%", x * 100.0],
+                            false => format!["{:.2}", x],
+                        };
+                        ui.label(format!["{} ± {}", fmt(s.mean), fmt(s.std_dev)])
+                            .on_hover_text(format![
+                                "Min {}, max {}, last {} over {} candles",
+                                fmt(s.min),
+                                fmt(s.max),
+                                fmt(s.last),
+                                s.no
+                            ]);
+                    }
+                    ui.end_row();
+                }
+            });
+        ui.end_row();
+    }
     pub fn show(
         data_manager: &mut DataManager,
         cli_chan: watch::Sender<ClientInstruct>,
@@ -4090,13 +4509,20 @@ impl DataManager {
                 ui.add_sized(
                     egui::vec2(250.0, 20.0),
                     egui::TextEdit::singleline(&mut data_manager.coin_search_string)
-                        .hint_text("Add asset to download list for binance"),
+                        .hint_text("Add asset to download list"),
                 );
+                ComboBox::from_id_salt("dl_exchange")
+                    .selected_text(data_manager.dl_exchange.as_str())
+                    .show_ui(ui, |ui| {
+                        for e in DL_EXCHANGES {
+                            ui.selectable_value(&mut data_manager.dl_exchange, e.to_string(), *e);
+                        }
+                    });
                 if ui.button("Add").clicked() {
                     tracing::debug!["Add clicked: {:?}", data_manager.coin_search_string];
                     let msg = ClientInstruct::SendSQLInstructs(SQLInstructs::InsertDLAsset {
                         symbol: data_manager.coin_search_string.clone(),
-                        exchange: "Binance".to_string(),
+                        exchange: data_manager.dl_exchange.clone(),
                     });
                     let res = cli_chan.send(msg);
                     match res {
@@ -4114,6 +4540,66 @@ impl DataManager {
                     data_manager.asset_list_loaded = false;
                 };
                 ui.end_row();
+                ui.add_sized(
+                    egui::vec2(250.0, 20.0),
+                    egui::TextEdit::singleline(&mut data_manager.archive_dir)
+                        .hint_text("Directory with data.binance.vision kline zips"),
+                );
+                if ui.button("Import").clicked() {
+                    let msg =
+                        ClientInstruct::SendSQLInstructs(SQLInstructs::ImportBinanceArchive {
+                            dir: data_manager.archive_dir.clone(),
+                        });
+                    let _res = cli_chan.send(msg);
+                    data_manager.asset_list_loaded = false;
+                };
+                ui.end_row();
+                ui.add_sized(
+                    egui::vec2(250.0, 20.0),
+                    egui::TextEdit::singleline(&mut data_manager.export_dir)
+                        .hint_text("Export directory"),
+                );
+                ui.horizontal(|ui| {
+                    ComboBox::from_id_salt("export_intv")
+                        .selected_text(data_manager.export_intv.to_str())
+                        .show_ui(ui, |ui| {
+                            for i in Intv::iter() {
+                                ui.selectable_value(&mut data_manager.export_intv, i, i.to_str());
+                            }
+                        });
+                    ComboBox::from_id_salt("export_format")
+                        .selected_text(data_manager.export_format.to_str())
+                        .show_ui(ui, |ui| {
+                            for f in ExportFormat::iter() {
+                                ui.selectable_value(&mut data_manager.export_format, f, f.to_str());
+                            }
+                        });
+                    ui.checkbox(&mut data_manager.export_range, "Range");
+                    if data_manager.export_range {
+                        ui.add(
+                            egui_extras::DatePickerButton::new(&mut data_manager.export_start)
+                                .id_salt("export_start"),
+                        );
+                        ui.add(
+                            egui_extras::DatePickerButton::new(&mut data_manager.export_end)
+                                .id_salt("export_end"),
+                        );
+                    };
+                });
+                ui.end_row();
+                if ui
+                    .checkbox(&mut data_manager.derive_intervals, "Download 1m only")
+                    .on_hover_text(
+                        "Build the other intervals from the 1m data instead of downloading them",
+                    )
+                    .changed()
+                {
+                    let msg = ClientInstruct::SendSQLInstructs(SQLInstructs::SetDeriveIntervals {
+                        derive: data_manager.derive_intervals,
+                    });
+                    let _res = cli_chan.send(msg);
+                };
+                ui.end_row();
                 if ui.button("Update all data").clicked() {
                     let msg = ClientInstruct::SendSQLInstructs(SQLInstructs::UpdateDataAll);
                     let _res = cli_chan.send(msg);
@@ -4123,6 +4609,27 @@ impl DataManager {
                     //data_manager.update_status = "Ran".to_string();
                     data_manager.asset_list_loaded = false;
                 };
+                let toggled = ui
+                    .checkbox(&mut data_manager.scheduled_update, "Update every")
+                    .on_hover_text("Update all downloaded assets in the background")
+                    .changed();
+                let changed = ui
+                    .add(
+                        egui::DragValue::new(&mut data_manager.scheduled_update_min)
+                            .range(5..=10_080)
+                            .suffix(" min"),
+                    )
+                    .lost_focus();
+                if toggled || (changed && data_manager.scheduled_update) {
+                    let interval_min = match data_manager.scheduled_update {
+                        true => Some(data_manager.scheduled_update_min),
+                        false => None,
+                    };
+                    let msg = ClientInstruct::SendSQLInstructs(SQLInstructs::SetUpdateSchedule {
+                        interval_min,
+                    });
+                    let _res = cli_chan.send(msg);
+                };
             });
         ui.end_row();
         /*
@@ -4149,6 +4656,28 @@ impl DataManager {
             },
         };
         */
+        {
+            let ad = data_manager
+                .hist_asset_data
+                .lock()
+                .expect("Unable to unlock mutex: DATA MANAGER");
+            if !data_manager.schedule_synced
+                && let Some(m) = ad.update_schedule
+            {
+                data_manager.scheduled_update = true;
+                data_manager.scheduled_update_min = m;
+                data_manager.schedule_synced = true;
+            };
+            data_manager.cache_usage = ad.cache_usage;
+            if let Some(symbol) = &data_manager.stats_symbol {
+                data_manager.col_stats = ad.col_stats.get(symbol).cloned().unwrap_or_default();
+            };
+            if ad.dl_status != data_manager.dl_status {
+                //NOTE a background download/retry changed something, reload the list
+                data_manager.dl_status = ad.dl_status.clone();
+                data_manager.asset_list_loaded = false;
+            };
+        }
         if data_manager.asset_list_loaded == false {
             let ad = data_manager
                 .hist_asset_data
@@ -4159,6 +4688,7 @@ impl DataManager {
                 let _res = cli_chan.send(msg);
             } else {
                 data_manager.asset_list = ad.downloaded_assets.clone();
+                data_manager.audit_reports = ad.audit_reports.clone();
                 data_manager.asset_list_loaded = true;
             }
         };
@@ -4169,6 +4699,40 @@ impl DataManager {
         };
         //NOTE add this but not clickable toggle_ui_compact(ui,&mut data_manager.update_success);
         ui.end_row();
+        if !data_manager.dl_status.is_empty() {
+            ui.label(RichText::new(&data_manager.dl_status).color(Color32::ORANGE));
+            ui.end_row();
+        };
+        if !data_manager.dl_jobs.is_empty() {
+            DataManager::show_downloads(data_manager, &cli_chan, ui);
+        };
+        ui.horizontal(|ui| {
+            let usage = &data_manager.cache_usage;
+            let mb = 1024.0 * 1024.0;
+            ui.label(format![
+                "Kline cache: {:.1} MB in {} chunks, {:.0}% hits",
+                usage.bytes as f64 / mb,
+                usage.chunks,
+                usage.hit_rate()
+            ])
+            .on_hover_text(format!["{} chunks evicted", usage.evicted]);
+            ui.label("Limit");
+            let changed = ui
+                .add(
+                    egui::DragValue::new(&mut data_manager.cache_limit_mb)
+                        .range(64..=65_536)
+                        .suffix(" MB"),
+                )
+                .lost_focus();
+            if changed {
+                let msg = ClientInstruct::SendSQLInstructs(SQLInstructs::SetCacheLimit {
+                    limit_mb: data_manager.cache_limit_mb,
+                });
+                let _res = cli_chan.send(msg);
+            };
+        });
+        ui.end_row();
+        DataManager::show_col_stats(data_manager, &cli_chan, ui);
 
         ui.label(RichText::new(format!["All assets"]).color(Color32::WHITE));
         ui.end_row();
@@ -4182,6 +4746,9 @@ impl DataManager {
                 .column(Column::auto().resizable(false))
                 .column(Column::auto().resizable(false))
                 .column(Column::auto().resizable(false))
+                .column(Column::auto().resizable(false))
+                .column(Column::auto().resizable(false))
+                .column(Column::auto().resizable(false))
                 .min_scrolled_height(0.0)
                 .max_scroll_height(available_height);
             table
@@ -4198,6 +4765,9 @@ impl DataManager {
                     header.col(|ui| {
                         ui.strong("Data End Time");
                     });
+                    header.col(|ui| {
+                        ui.strong("Last Update");
+                    });
                 })
                 .body(|mut body| {
                     for asset in data_manager.asset_list.iter() {
@@ -4206,6 +4776,13 @@ impl DataManager {
                             body.row(row_height, |mut row| {
                                 row.col(|ui| {
                                     ui.label(format!["{}", asset.asset]);
+                                    if asset.missing_chunks > 0 {
+                                        ui.label(RichText::new("incomplete").color(Color32::ORANGE))
+                                            .on_hover_text(format![
+                                                "{} chunks failed to download, they are retried on the next update or app start",
+                                                asset.missing_chunks
+                                            ]);
+                                    };
                                 });
                                 row.col(|ui| {
                                     ui.label(format!["{}", asset.exchange]);
@@ -4232,6 +4809,21 @@ impl DataManager {
                                         ui.label(format!["NaN"]);
                                     };
                                 });
+                                row.col(|ui| {
+                                    let last = match time_conversion(asset.last_update) {
+                                        Some(t) if asset.last_update != 0 => format!["{}", t],
+                                        _ => "Never".to_string(),
+                                    };
+                                    match asset.update_status.starts_with("Failed") {
+                                        true => {
+                                            ui.label(RichText::new(last).color(Color32::RED))
+                                                .on_hover_text(&asset.update_status);
+                                        }
+                                        false => {
+                                            ui.label(last);
+                                        }
+                                    };
+                                });
                                 row.col(|ui| {
                                     if ui.button("Delete").clicked() {
                                         let msg = ClientInstruct::SendSQLInstructs(
@@ -4243,6 +4835,95 @@ impl DataManager {
                                         data_manager.asset_list_loaded = false;
                                     }
                                 });
+                                row.col(|ui| {
+                                    if ui.button("Audit").clicked() {
+                                        let msg = ClientInstruct::SendSQLInstructs(
+                                            SQLInstructs::AuditAsset {
+                                                symbol: asset.asset.clone(),
+                                                repair: false,
+                                            },
+                                        );
+                                        let _res = cli_chan.send(msg);
+                                    }
+                                    if ui
+                                        .button("Derive")
+                                        .on_hover_text(
+                                            "Build missing intervals from 1m, downloaded candles are kept",
+                                        )
+                                        .clicked()
+                                    {
+                                        let msg = ClientInstruct::SendSQLInstructs(
+                                            SQLInstructs::DeriveIntervals {
+                                                symbol: asset.asset.clone(),
+                                                repair: false,
+                                            },
+                                        );
+                                        let _res = cli_chan.send(msg);
+                                    }
+                                    if ui.button("Stats").clicked() {
+                                        let msg = ClientInstruct::SendSQLInstructs(
+                                            SQLInstructs::LoadColStats {
+                                                symbol: asset.asset.clone(),
+                                            },
+                                        );
+                                        let _res = cli_chan.send(msg);
+                                        data_manager.stats_symbol = Some(asset.asset.clone());
+                                    }
+                                    if let Some(report) = data_manager.audit_reports.get(&asset.asset) {
+                                        match report.is_ok() {
+                                            true => {
+                                                ui.label(RichText::new("ok").color(Color32::GREEN));
+                                            }
+                                            false => {
+                                                ui.label(RichText::new("problems").color(Color32::RED))
+                                                    .on_hover_text(report.summary());
+                                                if ui.button("Repair").clicked() {
+                                                    let msg = ClientInstruct::SendSQLInstructs(
+                                                        SQLInstructs::AuditAsset {
+                                                            symbol: asset.asset.clone(),
+                                                            repair: true,
+                                                        },
+                                                    );
+                                                    let _res = cli_chan.send(msg);
+                                                }
+                                            }
+                                        };
+                                    };
+                                });
+                                row.col(|ui| {
+                                    if ui.button("Export").clicked() {
+                                        let day_ms = |d: chrono::NaiveDate| {
+                                            d.and_hms_opt(0, 0, 0)
+                                                .unwrap_or_default()
+                                                .and_utc()
+                                                .timestamp_millis()
+                                        };
+                                        let range = match data_manager.export_range {
+                                            true => Some((
+                                                day_ms(data_manager.export_start),
+                                                day_ms(data_manager.export_end) + 86_400_000 - 1,
+                                            )),
+                                            false => None,
+                                        };
+                                        let path = format![
+                                            "{}/{}_{}.{}",
+                                            data_manager.export_dir,
+                                            asset.asset,
+                                            data_manager.export_intv.to_str(),
+                                            data_manager.export_format.extension()
+                                        ];
+                                        let msg = ClientInstruct::SendSQLInstructs(
+                                            SQLInstructs::ExportKline {
+                                                symbol: asset.asset.clone(),
+                                                intv: data_manager.export_intv,
+                                                range,
+                                                format: data_manager.export_format,
+                                                path,
+                                            },
+                                        );
+                                        let _res = cli_chan.send(msg);
+                                    }
+                                });
                             });
                         };
                     }
@@ -4290,7 +4971,9 @@ pub const SELL_INACTIVE: LineState = LineState::InactiveColor(Color32::from_rgb(
 
 impl HlineType {
     pub fn hline_order(o: &Order, active: bool) -> Vec<HLine> {
-        let side = o.get_side();
+        let Some(side) = o.get_side() else {
+            return vec![];
+        };
         let line_state = match (side, active) {
             (true, true) => BUY_ACTIVE,
             (true, false) => BUY_INACTIVE,
@@ -4417,14 +5100,68 @@ impl HistPlot {
             ..Default::default()
         }
     }
+    ///Only a window before the trade time is loaded, scrolling before it or AssetData dropping
+    ///the symbol loads it again (through the SQL task's cache). Takes the funding of a loaded
+    ///perpetual into hist_trade.
+    fn load_missing(
+        hist_plot: &mut HistPlot,
+        cli_chan: &watch::Sender<ClientInstruct>,
+        hist_ad: &Arc<Mutex<AssetData>>,
+    ) {
+        let symbol = &hist_plot.kline_plot.symbol;
+        if symbol.is_empty() {
+            return;
+        };
+        let ad = hist_ad.lock().expect("Posioned AD mutex! (GUI)");
+        let loaded = match ad.kline_data.get(symbol) {
+            Some(klines) => {
+                //NOTE funding isn't journaled, it comes with the loaded klines and a new symbol
+                //starts charging over
+                if hist_plot.hist_trade.asset_pair != *symbol {
+                    hist_plot.hist_trade.asset_pair = symbol.clone();
+                    hist_plot.hist_trade.funded_to = 0;
+                };
+                if hist_plot.hist_trade.funding != klines.funding {
+                    hist_plot.hist_trade.funding = klines.funding.clone();
+                };
+                true
+            }
+            None => false,
+        };
+        drop(ad);
+        let dropped = !loaded && hist_plot.shown_symbol == *symbol;
+        if loaded {
+            hist_plot.shown_symbol = symbol.clone();
+        } else if dropped {
+            hist_plot.shown_symbol.clear();
+        };
+        let needed = WICKS_VISIBLE as i64 + 1 - hist_plot.kline_plot.offset;
+        let scrolled =
+            needed > hist_plot.backload_wicks && hist_plot.backload_wicks < BACKLOAD_WICKS_MAX;
+        if scrolled {
+            hist_plot.backload_wicks =
+                ((needed / BACKLOAD_WICKS + 1) * BACKLOAD_WICKS).min(BACKLOAD_WICKS_MAX);
+            hist_plot.kline_plot.backload = hist_plot.backload_wicks as usize;
+        };
+        if dropped || (scrolled && loaded) {
+            let msg = ClientInstruct::SendSQLInstructs(SQLInstructs::LoadHistDataPart2 {
+                symbol: symbol.clone(),
+                trade_time: hist_plot.trade_time,
+                backload_wicks: hist_plot.backload_wicks,
+                intv: hist_plot.intv,
+            });
+            let _res = cli_chan.send(msg);
+        };
+    }
     pub fn show(
         hist_plot: &mut HistPlot,
         cli_chan: watch::Sender<ClientInstruct>,
         hist_ad: Arc<Mutex<AssetData>>,
         ui: &mut egui::Ui,
-        trade_slice: Option<&mut Vec<(DateTime<Utc>, f64, f64, f64, f64, f64)>>,
+        trade_slice: Option<&mut Vec<KlineRow>>,
         hist_extras: &mut HistExtras,
     ) {
+        HistPlot::load_missing(hist_plot, &cli_chan, &hist_ad);
         if hist_plot.trade_slice_loaded == true {
             let res = hist_plot.kline_plot.show_live(
                 ui,
@@ -4467,17 +5204,37 @@ impl HistPlot {
             //FIXME test dis..FIXME... not working....
             hist_plot.kline_plot.points.buy_markers = hist_plot.hist_trade.buy_points.clone();
             hist_plot.kline_plot.points.sell_markers = hist_plot.hist_trade.sell_points.clone();
+            //NOTE custom intervals aren't part of the regular load, they're resampled on request
+            if hist_plot.intv.is_custom() && !hist_plot.kline_plot.symbol.is_empty() {
+                let msg = ClientInstruct::SendSQLInstructs(SQLInstructs::LoadHistDataPart2 {
+                    symbol: hist_plot.kline_plot.symbol.clone(),
+                    trade_time: hist_plot.trade_time,
+                    backload_wicks: hist_plot.backload_wicks,
+                    intv: hist_plot.intv,
+                });
+                let _res = cli_chan.send(msg);
+            };
         };
 
         ui.end_row();
         egui::Grid::new("Hplot order assets:").show(ui, |ui| {
             egui::ComboBox::from_label("")
-                .selected_text(format!("{}", hist_plot.intv.to_str()))
+                .selected_text(hist_plot.intv.label())
                 .show_ui(ui, |ui| {
                     for i in Intv::iter() {
                         ui.selectable_value(&mut hist_plot.intv, i, i.to_str());
                     }
                 });
+            let custom = ui.add_sized(
+                egui::vec2(60.0, 20.0),
+                egui::TextEdit::singleline(&mut hist_plot.custom_intv_s).hint_text("10m, 2D"),
+            );
+            if custom.lost_focus() && !hist_plot.custom_intv_s.trim().is_empty() {
+                match Intv::parse(hist_plot.custom_intv_s.trim()) {
+                    Ok(i) => hist_plot.intv = i,
+                    Err(e) => tracing::error!["Custom interval: {}", e],
+                };
+            };
             ui.add_sized(
                 egui::vec2(100.0, 20.0),
                 egui::TextEdit::singleline(&mut hist_plot.search_load_string)
@@ -4538,7 +5295,8 @@ impl HistPlot {
                 let msg = ClientInstruct::SendSQLInstructs(SQLInstructs::LoadHistDataPart2 {
                     symbol: hist_plot.search_load_string.clone(),
                     trade_time: trade_time,
-                    backload_wicks: BACKLOAD_WICKS,
+                    backload_wicks: hist_plot.backload_wicks,
+                    intv: hist_plot.intv,
                 });
                 hist_plot.kline_plot.symbol = hist_plot.search_load_string.clone();
                 hist_plot.trade_time = trade_time;
@@ -4570,7 +5328,8 @@ impl HistPlot {
                 let msg = ClientInstruct::SendSQLInstructs(SQLInstructs::LoadHistDataPart2 {
                     symbol: hist_plot.search_load_string.clone(),
                     trade_time: new_trade_time,
-                    backload_wicks: BACKLOAD_WICKS,
+                    backload_wicks: hist_plot.backload_wicks,
+                    intv: hist_plot.intv,
                 });
                 let _res = cli_chan.send(msg);
                 hist_plot.trade_time = new_trade_time;
@@ -4582,4 +5341,274 @@ impl HistPlot {
             ui.end_row();
         });
     }
+    ///Pattern search from the candles up to the trade time, returns the match to open
+    pub fn show_patterns(
+        hist_plot: &mut HistPlot,
+        cli_chan: watch::Sender<ClientInstruct>,
+        ui: &mut egui::Ui,
+    ) -> Option<PatternMatch> {
+        let mut open = None;
+        ui.collapsing("Similar patterns", |ui| {
+            let symbol = hist_plot.kline_plot.symbol.clone();
+            let find = ui
+                .add_enabled(!symbol.is_empty(), egui::Button::new("Find similar"))
+                .on_hover_text(format![
+                    "Searches all downloaded assets for the {} candles before the trade time",
+                    PATTERN_CANDLES
+                ]);
+            if find.clicked() {
+                let msg = ClientInstruct::SendSQLInstructs(SQLInstructs::FindSimilarPatterns {
+                    symbol,
+                    intv: hist_plot.intv,
+                    time: hist_plot.trade_time,
+                });
+                let _res = cli_chan.send(msg);
+            };
+            let matches = hist_plot
+                .hist_asset_data
+                .lock()
+                .expect("Posioned AD mutex! (GUI)")
+                .pattern_matches
+                .clone();
+            egui::Grid::new("Hist pattern matches")
+                .min_col_width(30.0)
+                .striped(true)
+                .show(ui, |ui| {
+                    for m in matches.iter() {
+                        ui.label(&m.symbol);
+                        ui.label(m.intv.to_str());
+                        match time_conversion(m.time) {
+                            Some(t) => ui.label(format!["{}", t]),
+                            None => ui.label("NaN"),
+                        };
+                        ui.label(format!["Distance {}", m.distance]);
+                        if ui.button("Open").clicked() {
+                            open = Some(m.clone());
+                        };
+                        ui.end_row();
+                    }
+                });
+        });
+        open
+    }
+    fn hist_snapshot(hist_plot: &HistPlot, man_orders: &ManualOrders) -> HistSnapshot {
+        HistSnapshot::new(
+            hist_plot.trade_time,
+            man_orders.last_slice_time.timestamp_millis(),
+            hist_plot.intv,
+            &hist_plot.hist_trade,
+            man_orders.orders.iter().map(|(id, o)| (*id, *o)).collect(),
+        )
+    }
+    fn restore_snapshot(
+        snapshot: &HistSnapshot,
+        hist_trade: HistTrade,
+        symbol: &str,
+        hist_plot: &mut HistPlot,
+        man_orders: &mut ManualOrders,
+        cli_chan: &watch::Sender<ClientInstruct>,
+    ) {
+        //NOTE snapshots have no funding, keep the loaded one of the same symbol
+        let funding = match hist_plot.kline_plot.symbol == symbol {
+            true => std::mem::take(&mut hist_plot.hist_trade.funding),
+            false => vec![],
+        };
+        hist_plot.hist_trade = HistTrade {
+            asset_pair: symbol.to_string(),
+            funding,
+            ..hist_trade
+        };
+        hist_plot.intv = snapshot.intv;
+        hist_plot.trade_time = snapshot.trade_time;
+        hist_plot.kline_plot.points.buy_markers = hist_plot.hist_trade.buy_points.clone();
+        hist_plot.kline_plot.points.sell_markers = hist_plot.hist_trade.sell_points.clone();
+
+        man_orders.orders = snapshot.orders.iter().map(|(id, o)| (*id, *o)).collect();
+        man_orders.last_id = man_orders
+            .orders
+            .keys()
+            .copied()
+            .max()
+            .unwrap_or(0)
+            .max(man_orders.last_id);
+        man_orders.last_slice_time =
+            DateTime::<Utc>::from_timestamp_millis(snapshot.slice_time).unwrap_or_default();
+        let (a1_locked, a2_locked) = man_orders
+            .orders
+            .values()
+            .filter(|(o, active, _)| *active && *o != Order::None)
+            .fold((0.0, 0.0), |(a1, a2), (o, _, locked_qnt)| {
+                if o.get_side() == Some(true) {
+                    (a1, a2 + locked_qnt)
+                } else {
+                    (a1 + locked_qnt, a2)
+                }
+            });
+        man_orders.asset1_locked = a1_locked;
+        man_orders.asset2_locked = a2_locked;
+        man_orders.asset1 = snapshot.hist_trade.asset1 - a1_locked;
+        man_orders.asset2 = snapshot.hist_trade.asset2 - a2_locked;
+        man_orders.order_set = a1_locked > 0.0 || a2_locked > 0.0;
+        man_orders.refresh_hist_balance = false;
+
+        hist_plot.search_load_string = symbol.to_string();
+        hist_plot.kline_plot.symbol = symbol.to_string();
+        let msg = ClientInstruct::SendSQLInstructs(SQLInstructs::LoadHistDataPart2 {
+            symbol: symbol.to_string(),
+            trade_time: snapshot.trade_time,
+            backload_wicks: hist_plot.backload_wicks,
+            intv: snapshot.intv,
+        });
+        let _res = cli_chan.send(msg);
+    }
+    pub fn show_journal(
+        hist_plot: &mut HistPlot,
+        man_orders: &mut ManualOrders,
+        cli_chan: watch::Sender<ClientInstruct>,
+        ui: &mut egui::Ui,
+    ) {
+        //NOTE while replaying a loaded session nothing is journaled
+        if hist_plot.replay.is_none() && !man_orders.current_symbol.is_empty() {
+            if hist_plot.journal.symbol != man_orders.current_symbol {
+                let sessions = crate::paths::get().sessions();
+                let path = sessions.join(format![
+                    "hist_{}_{}.jsonl",
+                    man_orders.current_symbol,
+                    Utc::now().timestamp_millis()
+                ]);
+                let _res = std::fs::create_dir_all(&sessions);
+                hist_plot.journal = HistJournal::new(&man_orders.current_symbol, Some(path));
+            };
+            //NOTE only snapshot once an action changed the hist state
+            let unchanged = hist_plot.journal.current().is_some_and(|c| {
+                c.is_state_of(
+                    man_orders.last_slice_time.timestamp_millis(),
+                    hist_plot.intv,
+                    &hist_plot.hist_trade,
+                    &man_orders.orders,
+                )
+            });
+            if !unchanged {
+                let snapshot = HistPlot::hist_snapshot(hist_plot, man_orders);
+                let res = hist_plot.journal.observe(snapshot, &hist_plot.hist_trade);
+                match res {
+                    Ok(_) => (),
+                    Err(e) => tracing::error!["Unable to journal hist action: {}", e],
+                };
+            };
+        };
+
+        let hk_active = &true;
+        let undo_h = make_hotkey_shift![Key, H, ui, hk_active];
+        let undo_z = make_hotkey_shift![Key, Z, ui, hk_active];
+        let redo_y = make_hotkey_shift![Key, Y, ui, hk_active];
+
+        ui.collapsing("Journal", |ui| {
+            ui.horizontal(|ui| {
+                let undo = ui
+                    .add_enabled(hist_plot.journal.can_undo(), egui::Button::new("Undo"))
+                    .clicked();
+                let redo = ui
+                    .add_enabled(hist_plot.journal.can_redo(), egui::Button::new("Redo"))
+                    .clicked();
+                if hist_plot.replay.is_none() {
+                    let res = if undo || undo_h || undo_z {
+                        hist_plot.journal.undo()
+                    } else if redo || redo_y {
+                        hist_plot.journal.redo()
+                    } else {
+                        Ok(None)
+                    };
+                    let last = hist_plot.journal.entries.len();
+                    match res.map(|s| s.zip(hist_plot.journal.hist_trade_at(last))) {
+                        Ok(Some((snapshot, hist_trade))) => {
+                            let symbol = hist_plot.journal.symbol.clone();
+                            HistPlot::restore_snapshot(
+                                &snapshot, hist_trade, &symbol, hist_plot, man_orders, &cli_chan,
+                            );
+                        }
+                        Ok(None) => (),
+                        Err(e) => tracing::error!["Journal undo/redo failed: {}", e],
+                    };
+                };
+                ui.label(format!["Steps: {}", hist_plot.journal.entries.len()]);
+                if let Some(path) = hist_plot.journal.path() {
+                    ui.label(format!["{}", path.display()]);
+                };
+            });
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::TextEdit::singleline(&mut hist_plot.journal_path_s)
+                        .hint_text("Session journal file"),
+                );
+                if ui.button("Replay session").clicked() {
+                    let res = HistJournal::load_from_file(std::path::Path::new(
+                        &hist_plot.journal_path_s,
+                    ));
+                    match res {
+                        Ok(journal) => {
+                            if let (Some(initial), Some(hist_trade)) =
+                                (journal.initial.clone(), journal.hist_trade_at(0))
+                            {
+                                let symbol = journal.symbol.clone();
+                                HistPlot::restore_snapshot(
+                                    &initial, hist_trade, &symbol, hist_plot, man_orders, &cli_chan,
+                                );
+                            };
+                            hist_plot.replay = Some((journal, 0));
+                        }
+                        Err(e) => tracing::error!["Unable to load session journal: {}", e],
+                    };
+                };
+            });
+            if let Some((journal, step)) = hist_plot.replay.take() {
+                let mut step = step;
+                let last_step = step;
+                let mut stop = false;
+                ui.horizontal(|ui| {
+                    if ui.button("<").clicked() && step > 0 {
+                        step -= 1;
+                    };
+                    ui.add(egui::Slider::new(&mut step, 0..=journal.entries.len()));
+                    if ui.button(">").clicked() && step < journal.entries.len() {
+                        step += 1;
+                    };
+                    if ui.button("Stop replay").clicked() {
+                        stop = true;
+                    };
+                });
+                if step > 0 {
+                    let entry = &journal.entries[step - 1];
+                    for action in entry.actions.iter() {
+                        ui.label(action.to_str());
+                    }
+                };
+                if step != last_step {
+                    let snapshot = match step {
+                        0 => journal.initial.clone(),
+                        n => Some(journal.entries[n - 1].after.clone()),
+                    };
+                    if let (Some(snapshot), Some(hist_trade)) =
+                        (snapshot, journal.hist_trade_at(step))
+                    {
+                        let symbol = journal.symbol.clone();
+                        HistPlot::restore_snapshot(
+                            &snapshot, hist_trade, &symbol, hist_plot, man_orders, &cli_chan,
+                        );
+                    };
+                };
+                if stop {
+                    //NOTE start a fresh journal from wherever the replay was stopped
+                    hist_plot.journal = HistJournal::default();
+                } else {
+                    hist_plot.replay = Some((journal, step));
+                };
+            } else {
+                for entry in hist_plot.journal.entries.iter().rev().take(10) {
+                    let actions: Vec<String> = entry.actions.iter().map(|a| a.to_str()).collect();
+                    ui.label(format!["{}: {}", entry.step, actions.join(", ")]);
+                }
+            };
+        });
+    }
 }
@@ -0,0 +1,310 @@
+use anyhow::{Result, anyhow};
+use bincode::{Decode, Encode};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use crate::data::Intv;
+use crate::{SQLInstructs, SQLResponse};
+
+pub type ProgressSink = Arc<watch::Sender<SQLResponse>>;
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash, Encode, Decode, Default)]
+pub enum DlState {
+    #[default]
+    Running,
+    Paused,
+    Cancelled,
+    Done,
+    Failed,
+}
+impl DlState {
+    pub fn to_str(&self) -> &str {
+        match self {
+            DlState::Running => "Running",
+            DlState::Paused => "Paused",
+            DlState::Cancelled => "Cancelled",
+            DlState::Done => "Done",
+            DlState::Failed => "Failed",
+        }
+    }
+    pub fn finished(&self) -> bool {
+        matches!(self, DlState::Cancelled | DlState::Done | DlState::Failed)
+    }
+}
+
+///Full state of a download job, every event replaces the last one so dropped events don't matter
+#[derive(Eq, PartialEq, Debug, Clone, Hash, Encode, Decode, Default)]
+pub struct DlProgress {
+    pub symbol: String,
+    ///Interval of the last chunk
+    pub intv: Option<Intv>,
+    ///Chunks downloaded or failed out of total
+    pub done: u32,
+    pub total: u32,
+    pub errors: u32,
+    pub last_error: Option<String>,
+    pub state: DlState,
+}
+impl DlProgress {
+    pub fn percent(&self) -> f32 {
+        match self.total {
+            0 => 0.0,
+            t => self.done.min(t) as f32 / t as f32 * 100.0,
+        }
+    }
+}
+
+///Handle of one running download, clones share the state. Download loops call checkpoint
+///between chunks which waits while the job is paused and errors once it's cancelled.
+#[derive(Clone, Debug, Default)]
+pub struct DlJob {
+    cancel: CancellationToken,
+    paused: Arc<AtomicBool>,
+    progress: Arc<Mutex<DlProgress>>,
+    sink: Option<ProgressSink>,
+}
+impl DlJob {
+    pub fn new(symbol: &str) -> Self {
+        let job = Self::default();
+        job.update(|p| p.symbol = symbol.to_string());
+        job
+    }
+    pub fn progress(&self) -> DlProgress {
+        self.progress
+            .lock()
+            .expect("Poisoned download progress mutex")
+            .clone()
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+        self.update(|p| p.state = DlState::Cancelled);
+    }
+    pub fn set_paused(&self, pause: bool) {
+        if self.is_cancelled() {
+            return;
+        };
+        self.paused.store(pause, Ordering::SeqCst);
+        self.update(|p| {
+            p.state = match pause {
+                true => DlState::Paused,
+                false => DlState::Running,
+            }
+        });
+    }
+    pub async fn checkpoint(&self) -> Result<()> {
+        loop {
+            if self.is_cancelled() {
+                return Err(anyhow!["Download of {} cancelled", self.progress().symbol]);
+            };
+            if !self.paused.load(Ordering::SeqCst) {
+                return Ok(());
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_millis(200)) => {}
+                _ = self.cancel.cancelled() => {}
+            }
+        }
+    }
+    pub fn add_chunks(&self, n: u32) {
+        self.update(|p| p.total += n);
+    }
+    pub fn chunk_done(&self, intv: Intv) {
+        self.update(|p| {
+            p.intv = Some(intv);
+            p.done += 1;
+        });
+    }
+    pub fn chunk_failed(&self, intv: Intv, err: &anyhow::Error) {
+        self.update(|p| {
+            p.intv = Some(intv);
+            p.done += 1;
+            p.errors += 1;
+            p.last_error = Some(format!["{}", err]);
+        });
+    }
+    ///Sets the final state from the result of the download
+    pub fn finish(&self, res: &Result<()>) {
+        self.update(|p| match (res, self.cancel.is_cancelled()) {
+            (_, true) => p.state = DlState::Cancelled,
+            (Ok(_), false) => {
+                p.done = p.total;
+                p.state = DlState::Done;
+            }
+            (Err(e), false) => {
+                p.last_error = Some(format!["{}", e]);
+                p.state = DlState::Failed;
+            }
+        });
+    }
+    fn update<F: FnOnce(&mut DlProgress)>(&self, f: F) {
+        let (progress, logged) = {
+            let mut p = self
+                .progress
+                .lock()
+                .expect("Poisoned download progress mutex");
+            let before = p.percent() as u32 / 10;
+            f(&mut p);
+            (p.clone(), before != p.percent() as u32 / 10)
+        };
+        match &self.sink {
+            Some(s) => {
+                let _res = s.send(SQLResponse::DlProgress(progress));
+            }
+            //NOTE headless runs have no GUI to stream to
+            None if logged || progress.state.finished() => tracing::info![
+                "Downloading {}: {:.0}% ({} errors) {}",
+                progress.symbol,
+                progress.percent(),
+                progress.errors,
+                progress.state.to_str()
+            ],
+            None => (),
+        };
+    }
+}
+
+///Running download jobs by symbol, shared by the SQL task, the update scheduler and the Data
+///Manager (through AssetData)
+#[derive(Clone, Debug, Default)]
+pub struct DlJobs {
+    jobs: Arc<Mutex<HashMap<String, DlJob>>>,
+    ///Last progress of removed jobs until they're dismissed
+    finished: Arc<Mutex<HashMap<String, DlProgress>>>,
+    ///Where the progress of new jobs is streamed to, the GUI's SQLResponse channel
+    sink: Arc<Mutex<Option<ProgressSink>>>,
+}
+impl DlJobs {
+    pub fn stream_to(&self, sink: ProgressSink) {
+        *self.sink.lock().expect("Poisoned download jobs mutex") = Some(sink);
+    }
+    pub fn start(&self, symbol: &str) -> Result<DlJob> {
+        let mut jobs = self.jobs.lock().expect("Poisoned download jobs mutex");
+        if jobs.contains_key(symbol) {
+            return Err(anyhow!["{} is already being downloaded", symbol]);
+        };
+        let sink = self
+            .sink
+            .lock()
+            .expect("Poisoned download jobs mutex")
+            .clone();
+        let job = DlJob {
+            sink,
+            ..DlJob::new(symbol)
+        };
+        jobs.insert(symbol.to_string(), job.clone());
+        self.dismiss(symbol);
+        Ok(job)
+    }
+    pub fn remove(&self, symbol: &str) {
+        let mut jobs = self.jobs.lock().expect("Poisoned download jobs mutex");
+        if let Some(job) = jobs.remove(symbol) {
+            self.finished
+                .lock()
+                .expect("Poisoned download jobs mutex")
+                .insert(symbol.to_string(), job.progress());
+        };
+    }
+    ///Forgets the progress of a removed job
+    pub fn dismiss(&self, symbol: &str) {
+        self.finished
+            .lock()
+            .expect("Poisoned download jobs mutex")
+            .remove(symbol);
+    }
+    ///Progress of the running and the removed jobs, for the events the GUI missed
+    pub fn progress(&self) -> Vec<DlProgress> {
+        let mut out: Vec<DlProgress> = self
+            .finished
+            .lock()
+            .expect("Poisoned download jobs mutex")
+            .values()
+            .cloned()
+            .collect();
+        let jobs = self.jobs.lock().expect("Poisoned download jobs mutex");
+        out.extend(jobs.values().map(|j| j.progress()));
+        out
+    }
+    pub fn get(&self, symbol: &str) -> Option<DlJob> {
+        let jobs = self.jobs.lock().expect("Poisoned download jobs mutex");
+        jobs.get(symbol).cloned()
+    }
+    ///Handles cancel/pause instructs, returns false for everything else
+    pub fn control(&self, i: &SQLInstructs) -> bool {
+        match i {
+            SQLInstructs::CancelDownload { symbol } => {
+                match self.get(symbol) {
+                    Some(job) => job.cancel(),
+                    None => tracing::debug!["No download of {} to cancel", symbol],
+                };
+                true
+            }
+            SQLInstructs::PauseDownload { symbol, pause } => {
+                match self.get(symbol) {
+                    Some(job) => job.set_paused(*pause),
+                    None => tracing::debug!["No download of {} to pause", symbol],
+                };
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn paused_job_waits_and_cancel_streams_state() {
+        let (s, mut r) = watch::channel(SQLResponse::None);
+        let jobs = DlJobs::default();
+        jobs.stream_to(Arc::new(s));
+        let job = jobs.start("BTCUSDT").unwrap();
+        let dup = jobs.start("BTCUSDT").is_err();
+        job.add_chunks(4);
+        job.chunk_done(Intv::Min1);
+        let streamed = r.borrow_and_update().clone();
+        let polled = jobs.clone().progress();
+
+        jobs.control(&SQLInstructs::PauseDownload {
+            symbol: "BTCUSDT".to_string(),
+            pause: true,
+        });
+        let waiting = job.clone();
+        let waiter = tokio::spawn(async move { waiting.checkpoint().await });
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let blocked = !waiter.is_finished();
+        jobs.control(&SQLInstructs::CancelDownload {
+            symbol: "BTCUSDT".to_string(),
+        });
+        let res = waiter.await.unwrap();
+        job.finish(&Ok(()));
+        jobs.remove("BTCUSDT");
+        let removed = jobs.progress();
+        jobs.dismiss("BTCUSDT");
+
+        assert!(dup);
+        let SQLResponse::DlProgress(p) = streamed else {
+            panic!["Expected a progress event, got {:?}", streamed];
+        };
+        assert_eq!(polled, vec![p.clone()]);
+        assert_eq!((p.done, p.total, p.intv), (1, 4, Some(Intv::Min1)));
+        assert_eq!(p.percent(), 25.0);
+        assert!(blocked);
+        assert!(res.is_err());
+        //NOTE finish doesn't turn a cancelled job into a finished one
+        assert_eq!(job.progress().state, DlState::Cancelled);
+        assert!(jobs.get("BTCUSDT").is_none());
+        assert_eq!(*r.borrow(), SQLResponse::DlProgress(job.progress()));
+        //the Data Manager can still poll the final state until it's dismissed
+        assert_eq!(removed[0].state, DlState::Cancelled);
+        assert!(jobs.progress().is_empty());
+    }
+}
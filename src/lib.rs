@@ -1,4 +1,6 @@
+use crate::data::{ExportFormat, Intv};
 use crate::gui::Settings;
+use crate::jobs::DlProgress;
 use crate::trade::Order;
 use bincode::{Decode, Encode};
 use std::collections::HashMap;
@@ -38,6 +40,8 @@ pub enum SQLInstructs {
         symbol: String,
         backload_wicks: i64,
         trade_time: i64,
+        ///custom intervals are only resampled when requested
+        intv: Intv,
     },
     UnloadHistData {
         symbol: String,
@@ -64,6 +68,58 @@ pub enum SQLInstructs {
     ValidateBinanceAsset {
         symbol: String,
     },
+    ImportBinanceArchive {
+        dir: String,
+    },
+    ExportKline {
+        symbol: String,
+        intv: Intv,
+        range: Option<(i64, i64)>,
+        format: ExportFormat,
+        path: String,
+    },
+    AuditAsset {
+        symbol: String,
+        repair: bool,
+    },
+    SetDeriveIntervals {
+        derive: bool,
+    },
+    ///With repair downloaded candles that differ from the 1m data are replaced
+    DeriveIntervals {
+        symbol: String,
+        repair: bool,
+    },
+    ///Minutes between scheduled updates of all downloaded assets, None turns them off
+    SetUpdateSchedule {
+        interval_min: Option<u64>,
+    },
+    ///Memory limit of the historical kline cache
+    SetCacheLimit {
+        limit_mb: u64,
+    },
+    ///Saved column statistics of a downloaded asset, computed if there are none
+    LoadColStats {
+        symbol: String,
+    },
+    UpdateColStats {
+        symbol: String,
+    },
+    ///Searches all downloaded assets for the windows most similar to the intv candles of symbol
+    ///closed by time
+    FindSimilarPatterns {
+        symbol: String,
+        intv: Intv,
+        time: i64,
+    },
+    ///Handled while a download is running, the other instructs wait for it
+    CancelDownload {
+        symbol: String,
+    },
+    PauseDownload {
+        symbol: String,
+        pause: bool,
+    },
 }
 impl SQLInstructs {
     pub fn to_str(&self) -> &str {
@@ -80,6 +136,7 @@ impl SQLInstructs {
                 symbol: _,
                 trade_time: _,
                 backload_wicks: _,
+                intv: _,
             } => "SQLInstructs: Load Hist Data partially2",
             SQLInstructs::UnloadHistData { symbol: _ } => "SQLInstructs: Unload Hist Data",
             SQLInstructs::LoadTradeRecord { id: _ } => "SQLInstructs: Load Trade Record",
@@ -98,6 +155,22 @@ impl SQLInstructs {
             SQLInstructs::ValidateBinanceAsset { symbol: _ } => {
                 "SQLInstructs: Validate symbol for download"
             }
+            SQLInstructs::ImportBinanceArchive { dir: _ } => {
+                "SQLInstructs: Import binance archive files"
+            }
+            SQLInstructs::ExportKline { .. } => "SQLInstructs: Export kline data",
+            SQLInstructs::AuditAsset { .. } => "SQLInstructs: Audit asset data",
+            SQLInstructs::SetDeriveIntervals { .. } => {
+                "SQLInstructs: Derive intervals from 1m on download"
+            }
+            SQLInstructs::DeriveIntervals { .. } => "SQLInstructs: Derive intervals from 1m",
+            SQLInstructs::SetUpdateSchedule { .. } => "SQLInstructs: Set scheduled update interval",
+            SQLInstructs::SetCacheLimit { .. } => "SQLInstructs: Set kline cache memory limit",
+            SQLInstructs::LoadColStats { .. } => "SQLInstructs: Load column statistics",
+            SQLInstructs::UpdateColStats { .. } => "SQLInstructs: Update column statistics",
+            SQLInstructs::FindSimilarPatterns { .. } => "SQLInstructs: Find similar patterns",
+            SQLInstructs::CancelDownload { .. } => "SQLInstructs: Cancel a running download",
+            SQLInstructs::PauseDownload { .. } => "SQLInstructs: Pause/resume a running download",
         }
     }
 }
@@ -107,6 +180,7 @@ pub enum SQLResponse {
     None,
     Success,
     Failure((String, GeneralError)),
+    DlProgress(DlProgress),
 }
 
 #[derive(Eq, PartialEq, Debug, Clone, Hash, Encode, Decode)]
@@ -170,6 +244,20 @@ pub enum BinInstructs {
     ChangeLiveAsset2 {
         symbol: String,
     },
+    ///Starts or stops recording the aggTrades of every subscribed symbol
+    RecordTicks {
+        record: bool,
+    },
+    ///Starts or stops recording every raw WS message to ws_sessions/
+    RecordWS {
+        record: bool,
+    },
+    ///Replays a recorded WS session into the live chart, speed 0 replays without waiting
+    ReplayWS {
+        path: String,
+        speed: u32,
+    },
+    StopReplayWS,
 }
 impl BinInstructs {
     pub fn to_str(&self) -> &str {
@@ -207,6 +295,10 @@ impl BinInstructs {
                 defualt_symbol: _,
             } => "BinInstruct: Change symbol",
             BinInstructs::ChangeLiveAsset2 { symbol: _ } => "BinInstruct: Change symbol2",
+            BinInstructs::RecordTicks { record: _ } => "BinInstruct: Record ticks",
+            BinInstructs::RecordWS { record: _ } => "BinInstruct: Record WS",
+            BinInstructs::ReplayWS { path: _, speed: _ } => "BinInstruct: Replay WS",
+            BinInstructs::StopReplayWS => "BinInstruct: Stop WS replay",
         }
     }
 }
@@ -271,8 +363,17 @@ impl std::fmt::Display for ClientInstruct {
         write!(f, "{}", self.to_str())
     }
 }
+pub mod cache;
 pub mod client;
 pub mod conn;
 pub mod data;
 pub mod gui;
+pub mod jobs;
+pub mod paths;
+pub mod pattern;
+pub mod ratelimit;
+pub mod replay;
+pub mod source;
+pub mod stats;
+pub mod ticks;
 pub mod trade;
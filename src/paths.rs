@@ -0,0 +1,276 @@
+use anyhow::{Context, Result, anyhow};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+///Overrides the XDG dirs, everything (databases, sessions, Settings.bin) is kept in it
+pub const DATA_DIR_ENV: &str = "BINTRADE_DATA_DIR";
+pub const DATA_DIR_ARG: &str = "--data-dir";
+const APP_DIR: &str = "bintrade";
+///Written to the data dir once the legacy files were migrated
+const LEGACY_MARKER: &str = ".legacy_migrated";
+
+static PATHS: OnceLock<Paths> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Paths {
+    ///databases/, sessions/, ticks/ and ws_sessions/
+    pub data_dir: PathBuf,
+    ///Settings.bin
+    pub config_dir: PathBuf,
+}
+impl Paths {
+    ///--data-dir, then BINTRADE_DATA_DIR, then the XDG data/config dirs (or their
+    ///platform equivalents), the working directory if there are none
+    pub fn resolve(data_dir: Option<PathBuf>) -> Self {
+        let dir = data_dir.or_else(|| {
+            std::env::var_os(DATA_DIR_ENV)
+                .filter(|d| !d.is_empty())
+                .map(PathBuf::from)
+        });
+        match dir {
+            Some(d) => Self {
+                data_dir: d.clone(),
+                config_dir: d,
+            },
+            None => Self {
+                data_dir: dirs::data_dir()
+                    .map(|d| d.join(APP_DIR))
+                    .unwrap_or(PathBuf::from(".")),
+                config_dir: dirs::config_dir()
+                    .map(|d| d.join(APP_DIR))
+                    .unwrap_or(PathBuf::from(".")),
+            },
+        }
+    }
+    pub fn databases(&self) -> PathBuf {
+        self.data_dir.join("databases")
+    }
+    pub fn sessions(&self) -> PathBuf {
+        self.data_dir.join("sessions")
+    }
+    ///Recorded aggTrades, ticks/<SYMBOL>/<YYYY-MM-DD>.bin
+    pub fn ticks(&self) -> PathBuf {
+        self.data_dir.join("ticks")
+    }
+    ///Raw WS sessions, ws_<start ms>.jsonl
+    pub fn ws_sessions(&self) -> PathBuf {
+        self.data_dir.join("ws_sessions")
+    }
+    pub fn settings(&self) -> PathBuf {
+        self.config_dir.join("Settings.bin")
+    }
+    ///Moves ./databases (if it has a metadata.db), the journals in ./sessions and ./Settings.bin
+    ///from before configurable paths to their new places, anything that's already there is left
+    ///alone. Only runs once per data dir, returns what was moved.
+    pub fn migrate_legacy(&self, legacy_dir: &Path) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let marker = self.data_dir.join(LEGACY_MARKER);
+        if marker.exists() {
+            return Ok(vec![]);
+        };
+        let mut moves = vec![(legacy_dir.join("Settings.bin"), self.settings())];
+        if legacy_dir.join("databases").join("metadata.db").is_file() {
+            moves.push((legacy_dir.join("databases"), self.databases()));
+        };
+        //NOTE only the files the app wrote, ./sessions can be anything in the working directory
+        if let Ok(dir) = std::fs::read_dir(legacy_dir.join("sessions")) {
+            for entry in dir.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if name.starts_with("hist_") && name.ends_with(".jsonl") {
+                    moves.push((entry.path(), self.sessions().join(&name)));
+                };
+            }
+        };
+        let mut moved = vec![];
+        for (from, to) in moves {
+            if !from.exists() || to.exists() || same_path(&from, &to) {
+                continue;
+            };
+            if let Some(parent) = to.parent() {
+                std::fs::create_dir_all(parent)
+                    .with_context(|| format!["Unable to create {}", parent.display()])?;
+            };
+            move_path(&from, &to).with_context(|| {
+                format!["Unable to move {} to {}", from.display(), to.display()]
+            })?;
+            tracing::info!["Moved {} to {}", from.display(), to.display()];
+            moved.push((from, to));
+        }
+        std::fs::create_dir_all(&self.data_dir)
+            .with_context(|| format!["Unable to create {}", self.data_dir.display()])?;
+        std::fs::write(&marker, b"")
+            .with_context(|| format!["Unable to write {}", marker.display()])?;
+        Ok(moved)
+    }
+}
+
+fn same_path(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn move_path(from: &Path, to: &Path) -> Result<()> {
+    match std::fs::rename(from, to) {
+        Ok(_) => Ok(()),
+        //NOTE rename fails across filesystems
+        Err(_) => copy_move(from, to),
+    }
+}
+
+///Copies from to a temporary path next to to, which is renamed once the copy is complete, from
+///is only removed after that
+fn copy_move(from: &Path, to: &Path) -> Result<()> {
+    let name = to.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = to.with_file_name(format![".{}.partial", name]);
+    remove_all(&tmp)?;
+    let copied =
+        copy_all(from, &tmp).and_then(|_| match size_of_all(from)? == size_of_all(&tmp)? {
+            true => Ok(()),
+            false => Err(anyhow!["Copy of {} is incomplete", from.display()]),
+        });
+    if let Err(e) = copied {
+        let _res = remove_all(&tmp);
+        return Err(e);
+    };
+    std::fs::rename(&tmp, to)?;
+    remove_all(from)
+}
+
+fn remove_all(path: &Path) -> Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(m) if m.is_dir() => std::fs::remove_dir_all(path)?,
+        Ok(_) => std::fs::remove_file(path)?,
+        Err(_) => (),
+    };
+    Ok(())
+}
+
+fn size_of_all(path: &Path) -> Result<u64> {
+    match path.is_dir() {
+        true => {
+            std::fs::read_dir(path)?.try_fold(0, |n, entry| Ok(n + size_of_all(&entry?.path())?))
+        }
+        false => Ok(std::fs::metadata(path)?.len()),
+    }
+}
+
+fn copy_all(from: &Path, to: &Path) -> Result<()> {
+    if from.is_dir() {
+        std::fs::create_dir_all(to)?;
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            copy_all(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else {
+        std::fs::copy(from, to)?;
+    };
+    Ok(())
+}
+
+///Sets the paths for the rest of the run, only the first call has an effect
+pub fn init(data_dir: Option<PathBuf>) -> &'static Paths {
+    PATHS.get_or_init(|| Paths::resolve(data_dir))
+}
+
+pub fn get() -> &'static Paths {
+    init(None)
+}
+
+///Removes --data-dir <dir> from the command line args
+pub fn take_data_dir_arg(args: &mut Vec<String>) -> Option<PathBuf> {
+    let n = args.iter().position(|a| a == DATA_DIR_ARG)?;
+    if n + 1 >= args.len() {
+        return None;
+    };
+    let dir = args.remove(n + 1);
+    args.remove(n);
+    Some(PathBuf::from(dir))
+}
+
+pub fn databases_dir() -> PathBuf {
+    get().databases()
+}
+
+pub fn metadata_db_path() -> String {
+    databases_dir()
+        .join("metadata.db")
+        .to_string_lossy()
+        .to_string()
+}
+
+pub fn asset_db_path(symbol: &str) -> String {
+    databases_dir()
+        .join(format!["Asset{}.db", symbol])
+        .to_string_lossy()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_dirs_are_moved_once() {
+        let root = std::env::temp_dir().join(format!["bintrade_paths_{}", std::process::id()]);
+        let legacy = root.join("cwd");
+        std::fs::create_dir_all(legacy.join("databases")).unwrap();
+        std::fs::write(legacy.join("databases").join("metadata.db"), b"meta").unwrap();
+        std::fs::write(legacy.join("Settings.bin"), b"settings").unwrap();
+        std::fs::create_dir_all(legacy.join("sessions")).unwrap();
+        std::fs::write(legacy.join("sessions").join("hist_BTCUSDT_1.jsonl"), b"{}").unwrap();
+        std::fs::write(legacy.join("sessions").join("notes.txt"), b"not ours").unwrap();
+        let paths = Paths {
+            data_dir: root.join("data"),
+            config_dir: root.join("config"),
+        };
+        let moved = paths.migrate_legacy(&legacy).unwrap().len();
+        let meta = std::fs::read(paths.databases().join("metadata.db")).unwrap();
+        let settings = std::fs::read(paths.settings()).unwrap();
+        let gone = !legacy.join("databases").exists() && !legacy.join("Settings.bin").exists();
+        let journal = paths.sessions().join("hist_BTCUSDT_1.jsonl").exists();
+        let notes = legacy.join("sessions").join("notes.txt").exists()
+            && !paths.sessions().join("notes.txt").exists();
+        //the migration only runs once, a new ./databases isn't moved
+        std::fs::create_dir_all(legacy.join("databases")).unwrap();
+        std::fs::write(legacy.join("databases").join("metadata.db"), b"new").unwrap();
+        let again = paths.migrate_legacy(&legacy).unwrap();
+        let kept = std::fs::read(paths.databases().join("metadata.db")).unwrap();
+        //a ./databases without a metadata.db isn't ours
+        let other = Paths {
+            data_dir: root.join("other"),
+            config_dir: root.join("other"),
+        };
+        std::fs::remove_file(legacy.join("databases").join("metadata.db")).unwrap();
+        let other_moved = other.migrate_legacy(&legacy).unwrap();
+        //cross filesystem fallback
+        std::fs::create_dir_all(root.join("copy").join("sub")).unwrap();
+        std::fs::write(root.join("copy").join("sub").join("file.bin"), b"file").unwrap();
+        copy_move(&root.join("copy"), &root.join("copied")).unwrap();
+        let file = std::fs::read(root.join("copied").join("sub").join("file.bin")).unwrap();
+        let copy_gone = !root.join("copy").exists() && !root.join(".copied.partial").exists();
+        let mut args = vec![
+            "list".to_string(),
+            DATA_DIR_ARG.to_string(),
+            "/tmp/bt".to_string(),
+        ];
+        let dir = take_data_dir_arg(&mut args);
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(meta, b"meta");
+        assert_eq!(settings, b"settings");
+        assert_eq!(moved, 3);
+        assert!(gone && journal && notes);
+        assert!(again.is_empty());
+        assert_eq!(kept, b"meta");
+        assert!(other_moved.is_empty());
+        assert_eq!(file, b"file");
+        assert!(copy_gone);
+        assert_eq!(dir, Some(PathBuf::from("/tmp/bt")));
+        assert_eq!(args, vec!["list".to_string()]);
+        assert_eq!(
+            Paths::resolve(Some(PathBuf::from("/tmp/bt"))).settings(),
+            PathBuf::from("/tmp/bt/Settings.bin")
+        );
+    }
+}
@@ -0,0 +1,286 @@
+use anyhow::{Result, anyhow};
+use futures::TryStreamExt;
+use sqlx::{Pool, Sqlite};
+use std::collections::BinaryHeap;
+use strum::IntoEnumIterator;
+
+use crate::data::Intv;
+
+///Candles in a pattern, a candle's hash is of the window ending with it
+pub const PATTERN_CANDLES: usize = 16;
+///Levels each close is quantized to, 2 bits per candle so a hash fits in 32 bits
+pub const PATTERN_LEVELS: u32 = 4;
+///Hash of the first candles, they don't have a full window before them
+pub const NO_PATTERN: f64 = -1.0;
+pub const PATTERN_MATCHES: usize = 20;
+const HASH_BATCH: i64 = 50_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternMatch {
+    pub symbol: String,
+    pub intv: Intv,
+    ///Open time of the last candle of the window
+    pub time: i64,
+    ///Summed level differences, 0 is the same shape
+    pub distance: u32,
+}
+
+///The closes of a window normalised to its own range and quantized, the first candle in the
+///highest bits. Scale and offset of the prices don't change it.
+pub fn pattern_hash(closes: &[f64]) -> Option<u32> {
+    if closes.len() != PATTERN_CANDLES || closes.iter().any(|c| !c.is_finite()) {
+        return None;
+    };
+    let min = closes.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = closes.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let range = max - min;
+    let hash = closes.iter().fold(0u32, |hash, c| {
+        let level = match range > 0.0 {
+            true => (((c - min) / range) * PATTERN_LEVELS as f64) as u32,
+            false => 0,
+        };
+        (hash << 2) | level.min(PATTERN_LEVELS - 1)
+    });
+    Some(hash)
+}
+
+pub fn pattern_distance(a: u32, b: u32) -> u32 {
+    (0..PATTERN_CANDLES)
+        .map(|n| {
+            let (la, lb) = ((a >> (2 * n)) & 3, (b >> (2 * n)) & 3);
+            la.abs_diff(lb)
+        })
+        .sum()
+}
+
+///Fills the Pattern hash of every candle of intv from the first one without a hash, returns the
+///number of candles hashed. Updates replace candles with ones without a hash.
+pub async fn update_pattern_hashes_intv(pool: &Pool<Sqlite>, intv: &Intv) -> Result<usize> {
+    let table = intv.table()?;
+    let q = format![
+        "SELECT MIN([Timestamp MS]) FROM {} WHERE [Pattern hash] IS NULL;",
+        table
+    ];
+    let (first,): (Option<i64>,) = sqlx::query_as(&q).fetch_one(pool).await?;
+    let Some(first) = first else {
+        return Ok(0);
+    };
+    let q = format![
+        "SELECT Close FROM {} WHERE [Timestamp MS] < ? ORDER BY [Timestamp MS] DESC LIMIT ?;",
+        table
+    ];
+    let before: Vec<(f64,)> = sqlx::query_as(&q)
+        .bind(first)
+        .bind((PATTERN_CANDLES - 1) as i64)
+        .fetch_all(pool)
+        .await?;
+    let mut window: Vec<f64> = before.into_iter().rev().map(|(c,)| c).collect();
+    let select = format![
+        "SELECT [Timestamp MS], Close FROM {} WHERE [Timestamp MS] >= ? ORDER BY [Timestamp MS] LIMIT ?;",
+        table
+    ];
+    let update = format![
+        "UPDATE {} SET [Pattern hash] = ? WHERE [Timestamp MS] = ?;",
+        table
+    ];
+    let mut from = first;
+    let mut hashed = 0;
+    loop {
+        let rows: Vec<(i64, f64)> = sqlx::query_as(&select)
+            .bind(from)
+            .bind(HASH_BATCH)
+            .fetch_all(pool)
+            .await?;
+        let Some(last) = rows.last().map(|r| r.0) else {
+            break;
+        };
+        let mut tx = pool.begin().await?;
+        for (t, close) in rows.iter() {
+            window.push(*close);
+            if window.len() > PATTERN_CANDLES {
+                window.remove(0);
+            };
+            let hash = pattern_hash(&window)
+                .map(|h| h as f64)
+                .unwrap_or(NO_PATTERN);
+            sqlx::query(&update)
+                .bind(hash)
+                .bind(t)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        hashed += rows.len();
+        from = last + 1;
+    }
+    Ok(hashed)
+}
+
+pub async fn update_pattern_hashes(pool: &Pool<Sqlite>) -> Result<usize> {
+    let mut hashed = 0;
+    for intv in Intv::iter() {
+        hashed += update_pattern_hashes_intv(pool, &intv).await?;
+    }
+    Ok(hashed)
+}
+
+///Hash of the PATTERN_CANDLES candles of intv closed by time and the open time of the last one
+pub async fn query_pattern(pool: &Pool<Sqlite>, intv: &Intv, time: i64) -> Result<(u32, i64)> {
+    let q = format![
+        "SELECT [Timestamp MS], Close FROM {} WHERE [Timestamp MS] <= ? ORDER BY [Timestamp MS] DESC LIMIT ?;",
+        intv.table()?
+    ];
+    let rows: Vec<(i64, f64)> = sqlx::query_as(&q)
+        .bind(time - intv.to_ms())
+        .bind(PATTERN_CANDLES as i64)
+        .fetch_all(pool)
+        .await?;
+    let closes: Vec<f64> = rows.iter().rev().map(|r| r.1).collect();
+    match (pattern_hash(&closes), rows.first()) {
+        (Some(hash), Some((last, _))) => Ok((hash, *last)),
+        _ => Err(anyhow![
+            "Not enough {} candles before {} for a pattern of {}",
+            intv.label(),
+            time,
+            PATTERN_CANDLES
+        ]),
+    }
+}
+
+///The limit windows of intv most similar to query in one asset DB, windows overlap neither
+///each other nor exclude (the open time of the last candle of the query window)
+pub async fn scan_patterns(
+    pool: &Pool<Sqlite>,
+    symbol: &str,
+    intv: &Intv,
+    query: u32,
+    exclude: Option<i64>,
+    limit: usize,
+) -> Result<Vec<PatternMatch>> {
+    let q = format![
+        "SELECT [Timestamp MS], [Pattern hash] FROM {} WHERE [Pattern hash] >= 0;",
+        intv.table()?
+    ];
+    let span = intv.to_ms() * PATTERN_CANDLES as i64;
+    let overlaps = |a: i64, b: i64| (a - b).abs() < span;
+    //NOTE overlapping windows of a match are similar too, keep enough to skip them
+    let keep = limit * PATTERN_CANDLES;
+    let mut best: BinaryHeap<(u32, i64)> = BinaryHeap::with_capacity(keep + 1);
+    let mut rows = sqlx::query_as::<_, (i64, f64)>(&q).fetch(pool);
+    while let Some((t, hash)) = rows.try_next().await? {
+        if exclude.is_some_and(|e| overlaps(e, t)) {
+            continue;
+        };
+        best.push((pattern_distance(query, hash as u32), t));
+        if best.len() > keep {
+            best.pop();
+        };
+    }
+    let mut matches: Vec<PatternMatch> = vec![];
+    for (distance, time) in best.into_sorted_vec() {
+        if matches.len() == limit {
+            break;
+        };
+        if matches.iter().any(|m| overlaps(m.time, time)) {
+            continue;
+        };
+        matches.push(PatternMatch {
+            symbol: symbol.to_string(),
+            intv: *intv,
+            time,
+            distance,
+        });
+    }
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::test_db::TempDb;
+    use chrono::{DateTime, Utc};
+
+    #[test]
+    fn pattern_hash_ignores_price_scale() {
+        let ramp: Vec<f64> = (0..PATTERN_CANDLES).map(|n| n as f64).collect();
+        let scaled: Vec<f64> = ramp.iter().map(|c| c * 250.0 + 30_000.0).collect();
+        let down: Vec<f64> = ramp.iter().rev().copied().collect();
+        let (up_h, down_h) = (pattern_hash(&ramp).unwrap(), pattern_hash(&down).unwrap());
+
+        assert_eq!(pattern_hash(&scaled), Some(up_h));
+        assert_eq!(pattern_distance(up_h, up_h), 0);
+        //levels 0 0 0 0 1 1 1 1 2 2 2 2 3 3 3 3 against the reverse
+        assert_eq!(pattern_distance(up_h, down_h), 32);
+        assert_eq!(pattern_hash(&[1.0; PATTERN_CANDLES]), Some(0));
+        assert_eq!(pattern_hash(&ramp[1..]), None);
+    }
+
+    #[tokio::test]
+    async fn hashed_patterns_are_found_without_overlaps() {
+        let db = TempDb::asset("pattern").await;
+        let hour = Intv::Hour1.to_ms();
+        let t0 = 1704067200000;
+        let insert = |n: i64, close: f64| {
+            let pool = db.pool.clone();
+            async move {
+                let t = t0 + n * hour;
+                sqlx::query("INSERT OR REPLACE INTO kline_1hour ([Timestamp MS], [Open Time], Open, High, Low, Close, Volume) VALUES (?, ?, ?, ?, ?, ?, ?)")
+                    .bind(t)
+                    .bind(DateTime::<Utc>::from_timestamp_millis(t).unwrap())
+                    .bind(close)
+                    .bind(close)
+                    .bind(close)
+                    .bind(close)
+                    .bind(1.0)
+                    .execute(&pool)
+                    .await
+                    .unwrap();
+            }
+        };
+        //the shape repeats every 13 candles
+        let close = |n: i64| 100.0 + ((n * 7) % 13) as f64;
+        for n in 0..300 {
+            insert(n, close(n)).await;
+        }
+        let hashed = update_pattern_hashes(&db.pool).await.unwrap();
+        let again = update_pattern_hashes(&db.pool).await.unwrap();
+        //an update replaces the last candles
+        insert(299, close(299)).await;
+        let rehashed = update_pattern_hashes_intv(&db.pool, &Intv::Hour1)
+            .await
+            .unwrap();
+        let hashes: Vec<(f64,)> =
+            sqlx::query_as("SELECT [Pattern hash] FROM kline_1hour ORDER BY [Timestamp MS]")
+                .fetch_all(&db.pool)
+                .await
+                .unwrap();
+
+        let time = t0 + 101 * hour;
+        let (query, last) = query_pattern(&db.pool, &Intv::Hour1, time).await.unwrap();
+        let matches = scan_patterns(&db.pool, "MOCK", &Intv::Hour1, query, Some(last), 5)
+            .await
+            .unwrap();
+        let too_early = query_pattern(&db.pool, &Intv::Hour1, t0 + 10 * hour).await;
+        db.pool.close().await;
+
+        assert_eq!((hashed, again, rehashed), (300, 0, 1));
+        assert!(
+            hashes[..PATTERN_CANDLES - 1]
+                .iter()
+                .all(|h| h.0 == NO_PATTERN)
+        );
+        assert!(hashes[PATTERN_CANDLES - 1..].iter().all(|h| h.0 >= 0.0));
+        //the last closed candle is the one before time
+        assert_eq!(last, t0 + 100 * hour);
+        assert_eq!(hashes[100].0, query as f64);
+        assert_eq!(matches.len(), 5);
+        let span = hour * PATTERN_CANDLES as i64;
+        for (n, m) in matches.iter().enumerate() {
+            assert_eq!(m.distance, 0);
+            assert_eq!((m.time - last) % (13 * hour), 0);
+            assert!((m.time - last).abs() >= span);
+            assert!(matches[..n].iter().all(|o| (o.time - m.time).abs() >= span));
+        }
+        assert!(too_early.is_err());
+    }
+}
@@ -0,0 +1,302 @@
+use anyhow::{Result, anyhow};
+use reqwest::StatusCode;
+use reqwest::header::HeaderMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::sleep;
+
+///Binance spot REQUEST_WEIGHT limit per minute
+pub const BINANCE_WEIGHT_LIMIT: u32 = 6000;
+//NOTE request weights from the Binance spot API docs
+pub const WEIGHT_KLINES: u32 = 2;
+pub const WEIGHT_EXCHANGE_INFO: u32 = 20;
+pub const WEIGHT_ACCOUNT: u32 = 20;
+pub const WEIGHT_OPEN_ORDERS_ALL: u32 = 80;
+pub const WEIGHT_ORDER: u32 = 1;
+///Binance USDⓈ-M futures REQUEST_WEIGHT limit per minute, counted apart from spot
+pub const BINANCE_FUT_WEIGHT_LIMIT: u32 = 2400;
+//NOTE futures kline weight goes up with the limit, 5 for 500 to 1000 candles
+pub const WEIGHT_FUT_KLINES: u32 = 5;
+pub const WEIGHT_FUNDING_RATE: u32 = 1;
+pub const WEIGHT_FUT_EXCHANGE_INFO: u32 = 1;
+const USED_WEIGHT_HEADERS: [&str; 2] = ["x-mbx-used-weight-1m", "x-mbx-used-weight"];
+const MAX_BACKOFF_SEC: u64 = 120;
+
+static BINANCE_LIMITER: OnceLock<Arc<RateLimiter>> = OnceLock::new();
+static BINANCE_FUT_LIMITER: OnceLock<Arc<RateLimiter>> = OnceLock::new();
+
+///Limiter shared by every Binance REST call of the process
+pub fn binance_limiter() -> Arc<RateLimiter> {
+    BINANCE_LIMITER
+        .get_or_init(|| Arc::new(RateLimiter::new(BINANCE_WEIGHT_LIMIT)))
+        .clone()
+}
+
+///Limiter shared by every Binance futures REST call of the process
+pub fn binance_fut_limiter() -> Arc<RateLimiter> {
+    BINANCE_FUT_LIMITER
+        .get_or_init(|| Arc::new(RateLimiter::new(BINANCE_FUT_WEIGHT_LIMIT)))
+        .clone()
+}
+
+#[derive(Debug, Default)]
+struct LimiterState {
+    window: u64,
+    used: u32,
+    blocked_until: Option<SystemTime>,
+    backoffs: u32,
+}
+
+///Tracks request weight used in the current window (a wall clock minute like Binance's) and
+///holds requests back when the limit would be exceeded or after a 429/418
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: u32,
+    window: Duration,
+    state: Mutex<LimiterState>,
+}
+impl RateLimiter {
+    pub fn new(limit: u32) -> Self {
+        Self::with_window(limit, Duration::from_secs(60))
+    }
+    pub fn with_window(limit: u32, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            state: Mutex::new(LimiterState::default()),
+        }
+    }
+    fn window_at(&self, t: SystemTime) -> u64 {
+        let ms = t.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        ms / (self.window.as_millis() as u64).max(1)
+    }
+    fn window_end(&self, window: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis((window + 1) * self.window.as_millis() as u64)
+    }
+    ///Weight used in the current window as far as the limiter knows
+    pub fn used(&self) -> u32 {
+        let st = self.state.lock().expect("Rate limiter mutex poisoned");
+        match st.window == self.window_at(SystemTime::now()) {
+            true => st.used,
+            false => 0,
+        }
+    }
+    pub fn blocked_until(&self) -> Option<SystemTime> {
+        let st = self.state.lock().expect("Rate limiter mutex poisoned");
+        st.blocked_until.filter(|t| *t > SystemTime::now())
+    }
+    ///Waits until weight fits in the current window and there's no back off, then counts it
+    pub async fn acquire(&self, weight: u32) {
+        let weight = weight.min(self.limit);
+        loop {
+            let wait = {
+                let mut st = self.state.lock().expect("Rate limiter mutex poisoned");
+                let now = SystemTime::now();
+                let window = self.window_at(now);
+                if st.window != window {
+                    st.window = window;
+                    st.used = 0;
+                };
+                match st.blocked_until {
+                    Some(until) if until > now => until.duration_since(now).unwrap_or_default(),
+                    _ if st.used + weight > self.limit => self
+                        .window_end(window)
+                        .duration_since(now)
+                        .unwrap_or_default(),
+                    _ => {
+                        st.used += weight;
+                        return;
+                    }
+                }
+            };
+            tracing::debug!["Rate limiter holding a request for {:?}", wait];
+            sleep(wait.max(Duration::from_millis(1))).await;
+        }
+    }
+    ///Syncs the used weight with X-MBX-USED-WEIGHT-1M, the server count wins if it's higher
+    pub fn update_from_headers(&self, headers: &HeaderMap) {
+        let used = USED_WEIGHT_HEADERS.iter().find_map(|h| {
+            headers
+                .get(*h)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.trim().parse::<u32>().ok())
+        });
+        let Some(used) = used else {
+            return;
+        };
+        let mut st = self.state.lock().expect("Rate limiter mutex poisoned");
+        let window = self.window_at(SystemTime::now());
+        if st.window != window {
+            st.window = window;
+            st.used = 0;
+        };
+        st.used = st.used.max(used);
+    }
+    ///Backs off on 429 (too many requests) and 418 (IP banned) for Retry-After seconds, or
+    ///exponentially if there isn't one. Returns true if the response was rate limited.
+    pub fn on_status(&self, status: StatusCode, headers: &HeaderMap) -> bool {
+        let retry_after = headers
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<u64>().ok());
+        self.on_status_code(status.as_u16(), retry_after)
+    }
+    fn on_status_code(&self, status: u16, retry_after: Option<u64>) -> bool {
+        let mut st = self.state.lock().expect("Rate limiter mutex poisoned");
+        match status {
+            429 | 418 => {
+                let sec = match retry_after {
+                    Some(s) => s,
+                    None => (1u64 << st.backoffs.min(7)).min(MAX_BACKOFF_SEC),
+                };
+                st.backoffs += 1;
+                let until = SystemTime::now() + Duration::from_secs(sec);
+                st.blocked_until = Some(st.blocked_until.map_or(until, |b| b.max(until)));
+                tracing::warn![
+                    "Binance rate limit hit ({}), backing off for {}s",
+                    status,
+                    sec
+                ];
+                true
+            }
+            _ => {
+                st.backoffs = 0;
+                false
+            }
+        }
+    }
+    ///For calls made through the binance crate, it doesn't expose headers but puts the status
+    ///of unexpected responses in the error message
+    pub fn on_binance_error(&self, err: &binance::errors::Error) {
+        if let binance::errors::Error::Msg(msg) = err {
+            for status in [429, 418] {
+                if msg.contains(&status.to_string()) {
+                    self.on_status_code(status, None);
+                };
+            }
+        };
+    }
+    ///Rate limited GET, returns the body of successful responses
+    pub async fn get(&self, client: &reqwest::Client, url: &str, weight: u32) -> Result<String> {
+        self.acquire(weight).await;
+        let resp = client.get(url).send().await?;
+        self.update_from_headers(resp.headers());
+        let status = resp.status();
+        if self.on_status(status, resp.headers()) {
+            return Err(anyhow!["Rate limited ({}) on {}", status, url]);
+        };
+        let body = resp.text().await?;
+        if !status.is_success() {
+            return Err(anyhow!["{} on {}: {}", status, url, body]);
+        };
+        Ok(body)
+    }
+}
+
+///Runs a binance crate call with the shared limiter
+pub async fn binance_call<T, F>(
+    weight: u32,
+    call: F,
+) -> std::result::Result<T, binance::errors::Error>
+where
+    F: Future<Output = std::result::Result<T, binance::errors::Error>>,
+{
+    let limiter = binance_limiter();
+    limiter.acquire(weight).await;
+    let res = call.await;
+    if let Err(e) = &res {
+        limiter.on_binance_error(e);
+    };
+    res
+}
+
+///(status, extra headers, body)
+#[cfg(test)]
+pub(crate) type MockResponse = (u16, Vec<(&'static str, String)>, String);
+
+///Local HTTP server answering requests in order with (status, extra headers, body), the last
+///response is repeated. Returns the base url and the number of requests served.
+#[cfg(test)]
+pub(crate) async fn mock_http_server(
+    responses: Vec<MockResponse>,
+) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+    use std::sync::atomic::Ordering;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let served = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let count = served.clone();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let mut buf = vec![0; 4096];
+            let _n = socket.read(&mut buf).await.unwrap_or(0);
+            let n = count.fetch_add(1, Ordering::SeqCst);
+            let (status, headers, body) = &responses[n.min(responses.len() - 1)];
+            let headers: String = headers
+                .iter()
+                .map(|(k, v)| format!["{}: {}\r\n", k, v])
+                .collect();
+            let resp = format![
+                "HTTP/1.1 {} X\r\ncontent-type: application/json\r\ncontent-length: {}\r\n{}connection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                headers,
+                body
+            ];
+            let _res = socket.write_all(resp.as_bytes()).await;
+        }
+    });
+    (format!["http://{}", addr], served)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn used_weight_header_and_429_back_off() {
+        let ok = (
+            200,
+            vec![("x-mbx-used-weight-1m", "5990".to_string())],
+            "[]".to_string(),
+        );
+        let limited = (
+            429,
+            vec![("retry-after", "1".to_string())],
+            "{\"code\":-1003}".to_string(),
+        );
+        let (base, served) = mock_http_server(vec![ok.clone(), limited, ok]).await;
+        let client = reqwest::Client::new();
+        let limiter = RateLimiter::new(BINANCE_WEIGHT_LIMIT);
+        let url = format!["{}/api/v3/klines", base];
+
+        assert_eq!(limiter.get(&client, &url, 2).await.unwrap(), "[]");
+        //the server count replaces the local one
+        let used = limiter.used();
+        assert!(limiter.get(&client, &url, 2).await.is_err());
+        let blocked = limiter.blocked_until().is_some();
+        let t = std::time::Instant::now();
+        assert!(limiter.get(&client, &url, 2).await.is_ok());
+
+        //unless the minute rolled over between the requests
+        assert!(used == 5990 || used == 0);
+        assert!(blocked);
+        assert!(t.elapsed() >= Duration::from_millis(900));
+        assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn acquire_waits_for_the_next_window() {
+        let limiter = RateLimiter::with_window(10, Duration::from_millis(300));
+        let t = std::time::Instant::now();
+        for _ in 0..3 {
+            limiter.acquire(6).await;
+        }
+        //6 + 6 doesn't fit in one window, each acquire lands in a new one
+        assert!(t.elapsed() >= Duration::from_millis(300));
+        limiter.on_status_code(418, None);
+        assert!(limiter.blocked_until().is_some());
+        limiter.on_status_code(200, None);
+        assert_eq!(limiter.state.lock().unwrap().backoffs, 0);
+    }
+}
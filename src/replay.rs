@@ -0,0 +1,145 @@
+use anyhow::{Context, Result, anyhow};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+///Replay speed multipliers offered in the Live Trade pane, 0 replays without waiting
+pub const REPLAY_SPEEDS: [u32; 4] = [1, 10, 100, 0];
+
+pub fn speed_str(speed: u32) -> String {
+    match speed {
+        0 => "Max".to_string(),
+        s => format!["{}x", s],
+    }
+}
+
+///One WS message as received, time is when it was received in ms
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WsFrame {
+    pub time: i64,
+    pub msg: Value,
+}
+impl WsFrame {
+    ///Symbol of an aggTrade or kline message
+    pub fn symbol(&self) -> Option<&str> {
+        self.msg.get("s").and_then(|s| s.as_str())
+    }
+}
+
+///Appends every WS message to ws_sessions/ws_<start ms>.jsonl
+#[derive(Debug)]
+pub struct WsRecorder {
+    path: PathBuf,
+    w: BufWriter<File>,
+    recorded: u64,
+}
+impl WsRecorder {
+    pub fn new(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!["Unable to create {}", dir.display()])?;
+        let path = dir.join(format!["ws_{}.jsonl", Utc::now().timestamp_millis()]);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!["Unable to open {}", path.display()])?;
+        Ok(Self {
+            path,
+            w: BufWriter::new(file),
+            recorded: 0,
+        })
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    pub fn recorded(&self) -> u64 {
+        self.recorded
+    }
+    pub fn record(&mut self, msg: &Value) -> Result<()> {
+        let frame = WsFrame {
+            time: Utc::now().timestamp_millis(),
+            msg: msg.clone(),
+        };
+        serde_json::to_writer(&mut self.w, &frame)?;
+        self.w.write_all(b"\n")?;
+        self.recorded += 1;
+        Ok(())
+    }
+    pub fn flush(&mut self) -> Result<()> {
+        self.w.flush()?;
+        Ok(())
+    }
+}
+
+///Frames of a recorded session, a last line cut short by a crash is dropped
+pub fn load_frames(path: &Path) -> Result<Vec<WsFrame>> {
+    let file = std::fs::read_to_string(path)
+        .with_context(|| format!["Unable to read {}", path.display()])?;
+    let lines: Vec<&str> = file.lines().filter(|l| !l.trim().is_empty()).collect();
+    let mut frames = Vec::with_capacity(lines.len());
+    for (n, l) in lines.iter().enumerate() {
+        match serde_json::from_str::<WsFrame>(l) {
+            Ok(f) => frames.push(f),
+            Err(_) if n + 1 == lines.len() && !file.ends_with('\n') => break,
+            Err(e) => {
+                return Err(anyhow![
+                    "Bad frame on line {} of {}: {}",
+                    n + 1,
+                    path.display(),
+                    e
+                ]);
+            }
+        }
+    }
+    Ok(frames)
+}
+
+///Time to wait between two frames at speed, frames received out of order don't wait
+pub fn replay_delay(last: i64, next: i64, speed: u32) -> Duration {
+    match speed {
+        0 => Duration::ZERO,
+        s => Duration::from_millis((next - last).max(0) as u64 / s as u64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn recorded_frames_load_back() {
+        let dir = std::env::temp_dir().join(format!["bintrade_ws_{}", std::process::id()]);
+        let msgs = [
+            json!({"e": "aggTrade", "s": "BTCUSDT", "p": "42000.10"}),
+            json!({"e": "kline", "s": "BTCUSDT", "k": {"i": "1m"}}),
+        ];
+        let mut rec = WsRecorder::new(&dir).unwrap();
+        for m in msgs.iter() {
+            rec.record(m).unwrap();
+        }
+        rec.flush().unwrap();
+        let path = rec.path().to_path_buf();
+        let frames = load_frames(&path).unwrap();
+        //a crash in the middle of a line
+        let mut f = OpenOptions::new().append(true).open(&path).unwrap();
+        f.write_all(b"{\"time\": 1, \"msg\": {\"e\"").unwrap();
+        let cut = load_frames(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(rec.recorded(), 2);
+        assert_eq!(
+            frames.iter().map(|f| &f.msg).collect::<Vec<_>>(),
+            vec![&msgs[0], &msgs[1]]
+        );
+        assert_eq!(frames[0].symbol(), Some("BTCUSDT"));
+        assert_eq!(cut, frames);
+        assert_eq!(replay_delay(1000, 3000, 10), Duration::from_millis(200));
+        assert_eq!(replay_delay(3000, 1000, 1), Duration::ZERO);
+        assert_eq!(replay_delay(1000, 3000, 0), Duration::ZERO);
+    }
+}
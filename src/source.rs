@@ -0,0 +1,494 @@
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Utc};
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::conn::{fut_get_exchange_info, get_exchange_info};
+use crate::data::{CsvImportConfig, FatKline, FatKlineRow, FundingRate, Intv, parse_kline_csv};
+use crate::ratelimit::{
+    RateLimiter, WEIGHT_FUNDING_RATE, WEIGHT_FUT_KLINES, WEIGHT_KLINES, binance_fut_limiter,
+    binance_limiter,
+};
+
+#[cfg(feature = "yfinance")]
+use crate::data::{Kline, get_yfinance_data, thin_to_fat};
+#[cfg(feature = "yfinance")]
+use yfinance_rs::core::models::Interval;
+#[cfg(feature = "yfinance")]
+use yfinance_rs::{HistoryBuilder, Range, YfClient};
+
+///Historical kline provider the download pipeline pulls from, times are in ms
+pub trait DataSource: Send + Sync {
+    fn name(&self) -> &str;
+    fn list_symbols(&self) -> impl Future<Output = Result<Vec<String>>> + Send;
+    ///Open time of the first candle the source has for symbol, None if it has none
+    fn earliest_timestamp(&self, symbol: &str) -> impl Future<Output = Result<Option<i64>>> + Send;
+    ///Candles with open times in range, both ends inclusive
+    fn fetch_klines(
+        &self,
+        symbol: &str,
+        range: (i64, i64),
+        intv: Intv,
+    ) -> impl Future<Output = Result<FatKline>> + Send;
+    ///Longest range fetch_klines returns in one request
+    fn max_range(&self, intv: Intv) -> i64 {
+        intv.to_ms() * 500
+    }
+}
+
+pub const BINANCE_API_URL: &str = "https://api.binance.com";
+pub const BINANCE_FUT_API_URL: &str = "https://fapi.binance.com";
+///Local names of USDⓈ-M futures end with it, BTCUSDT.P doesn't share the asset DB of the spot
+///pair
+pub const FUT_SUFFIX: &str = ".P";
+
+pub fn is_fut_symbol(symbol: &str) -> bool {
+    symbol.ends_with(FUT_SUFFIX)
+}
+///Local name of a USDⓈ-M futures symbol
+pub fn fut_symbol(symbol: &str) -> String {
+    format!["{}{}", exchange_symbol(symbol), FUT_SUFFIX]
+}
+///Name of symbol on Binance
+pub fn exchange_symbol(symbol: &str) -> &str {
+    symbol.strip_suffix(FUT_SUFFIX).unwrap_or(symbol)
+}
+
+///Binance spot or USDⓈ-M futures klines, requests go through the shared rate limiter of the
+///market
+#[derive(Clone)]
+pub struct BinanceSource {
+    client: reqwest::Client,
+    base_url: String,
+    limiter: Arc<RateLimiter>,
+    futures: bool,
+}
+impl BinanceSource {
+    pub fn new() -> Self {
+        Self::with_base_url(BINANCE_API_URL, binance_limiter())
+    }
+    pub fn futures() -> Self {
+        Self::futures_with_base_url(BINANCE_FUT_API_URL, binance_fut_limiter())
+    }
+    ///Futures source for .P symbols, spot for the rest
+    pub fn for_symbol(symbol: &str) -> Self {
+        match is_fut_symbol(symbol) {
+            true => Self::futures(),
+            false => Self::new(),
+        }
+    }
+    pub fn with_base_url(base_url: &str, limiter: Arc<RateLimiter>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            limiter,
+            futures: false,
+        }
+    }
+    pub fn futures_with_base_url(base_url: &str, limiter: Arc<RateLimiter>) -> Self {
+        Self {
+            futures: true,
+            ..Self::with_base_url(base_url, limiter)
+        }
+    }
+    pub fn is_futures(&self) -> bool {
+        self.futures
+    }
+    async fn get_klines(
+        &self,
+        symbol: &str,
+        intv: &str,
+        start: i64,
+        end: Option<i64>,
+        limit: u16,
+    ) -> Result<FatKline> {
+        let (path, weight) = match self.futures {
+            true => ("fapi/v1/klines", WEIGHT_FUT_KLINES),
+            false => ("api/v3/klines", WEIGHT_KLINES),
+        };
+        self.get_kline_rows(path, weight, symbol, intv, (start, end), limit)
+            .await
+    }
+    async fn get_kline_rows(
+        &self,
+        path: &str,
+        weight: u32,
+        symbol: &str,
+        intv: &str,
+        (start, end): (i64, Option<i64>),
+        limit: u16,
+    ) -> Result<FatKline> {
+        let mut url = format![
+            "{}/{}?symbol={}&interval={}&startTime={}&limit={}",
+            self.base_url,
+            path,
+            exchange_symbol(symbol),
+            intv,
+            start,
+            limit
+        ];
+        if let Some(e) = end {
+            url.push_str(&format!["&endTime={}", e]);
+        };
+        let body = self.limiter.get(&self.client, &url, weight).await?;
+        let rows: Vec<BinanceKlineRow> = serde_json::from_str(&body)
+            .with_context(|| format!["Unable to parse klines of {}", symbol])?;
+        let kline = rows
+            .iter()
+            .map(BinanceKlineRow::to_row)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(FatKline { kline })
+    }
+    ///Mark price candles of a futures symbol with open times in range, at most
+    ///FUT_PAGE_LIMIT of them. Only OHLC and the times are set, the rest is 0.
+    pub async fn fetch_mark_klines(
+        &self,
+        symbol: &str,
+        range: (i64, i64),
+        intv: Intv,
+    ) -> Result<FatKline> {
+        if !self.futures {
+            return Err(anyhow![
+                "Mark price klines are only on futures, not {}",
+                symbol
+            ]);
+        };
+        self.get_kline_rows(
+            "fapi/v1/markPriceKlines",
+            WEIGHT_FUT_KLINES,
+            symbol,
+            intv.to_bin_str(),
+            (range.0, Some(range.1)),
+            FUT_PAGE_LIMIT as u16,
+        )
+        .await
+    }
+    ///Funding events of a perpetual with times in range sorted by time, at most FUT_PAGE_LIMIT of
+    ///them. Old events have no mark price.
+    pub async fn fetch_funding(&self, symbol: &str, range: (i64, i64)) -> Result<Vec<FundingRate>> {
+        if !self.futures {
+            return Err(anyhow!["Funding rates are only on futures, not {}", symbol]);
+        };
+        let url = format![
+            "{}/fapi/v1/fundingRate?symbol={}&startTime={}&endTime={}&limit={}",
+            self.base_url,
+            exchange_symbol(symbol),
+            range.0,
+            range.1,
+            FUT_PAGE_LIMIT
+        ];
+        let body = self
+            .limiter
+            .get(&self.client, &url, WEIGHT_FUNDING_RATE)
+            .await?;
+        let rows: Vec<BinanceFundingRow> = serde_json::from_str(&body)
+            .with_context(|| format!["Unable to parse funding rates of {}", symbol])?;
+        let mut rates = rows
+            .iter()
+            .map(BinanceFundingRow::to_rate)
+            .collect::<Result<Vec<_>>>()?;
+        rates.sort_by_key(|r| r.time);
+        Ok(rates)
+    }
+}
+impl Default for BinanceSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl DataSource for BinanceSource {
+    fn name(&self) -> &str {
+        match self.futures {
+            true => "Binance Futures",
+            false => "Binance",
+        }
+    }
+    async fn list_symbols(&self) -> Result<Vec<String>> {
+        match self.futures {
+            true => {
+                let info = fut_get_exchange_info().await?;
+                Ok(info.iter().map(|s| fut_symbol(&s.symbol)).collect())
+            }
+            false => {
+                let info = get_exchange_info().await?;
+                Ok(info.into_iter().map(|s| s.symbol).collect())
+            }
+        }
+    }
+    async fn earliest_timestamp(&self, symbol: &str) -> Result<Option<i64>> {
+        let k = self.get_klines(symbol, "1M", 0, None, 1).await?;
+        Ok(k.kline.first().map(|k| k.0))
+    }
+    async fn fetch_klines(&self, symbol: &str, range: (i64, i64), intv: Intv) -> Result<FatKline> {
+        //NOTE 500 candles is max_range, it's also the default limit and costs 2 weight (5 on
+        //futures)
+        self.get_klines(symbol, intv.to_bin_str(), range.0, Some(range.1), 500)
+            .await
+    }
+}
+
+///Most rows a futures funding rate or mark price kline request returns
+pub const FUT_PAGE_LIMIT: usize = 1000;
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BinanceFundingRow {
+    funding_time: i64,
+    funding_rate: String,
+    #[serde(default)]
+    mark_price: String,
+}
+impl BinanceFundingRow {
+    fn to_rate(&self) -> Result<FundingRate> {
+        let rate = self
+            .funding_rate
+            .parse::<f64>()
+            .with_context(|| format!["Invalid funding rate {}", self.funding_rate])?;
+        Ok(FundingRate {
+            time: self.funding_time,
+            rate,
+            mark_price: self.mark_price.parse::<f64>().ok().filter(|p| *p > 0.0),
+        })
+    }
+}
+
+///[open time, o, h, l, c, volume, close time, quote volume, trades, taker buy base volume,
+///taker buy quote volume, ignore]
+#[derive(serde::Deserialize)]
+struct BinanceKlineRow(
+    i64,
+    String,
+    String,
+    String,
+    String,
+    String,
+    i64,
+    String,
+    u64,
+    String,
+    String,
+    serde::de::IgnoredAny,
+);
+impl BinanceKlineRow {
+    fn to_row(&self) -> Result<FatKlineRow> {
+        let num = |s: &str| -> Result<f64> {
+            s.parse::<f64>()
+                .with_context(|| format!["Invalid kline value {}", s])
+        };
+        let (Some(time), Some(ctime)) = (
+            DateTime::<Utc>::from_timestamp_millis(self.0),
+            DateTime::<Utc>::from_timestamp_millis(self.6),
+        ) else {
+            return Err(anyhow!["Invalid kline times {} {}", self.0, self.6]);
+        };
+        Ok((
+            self.0,
+            time,
+            num(&self.1)?,
+            num(&self.2)?,
+            num(&self.3)?,
+            num(&self.4)?,
+            num(&self.5)?,
+            self.6,
+            ctime,
+            num(&self.7)?,
+            self.8,
+            num(&self.9)?,
+            num(&self.10)?,
+        ))
+    }
+}
+
+///Yahoo Finance, only the intervals yfintv_conv maps are served
+#[cfg(feature = "yfinance")]
+#[derive(Clone, Default)]
+pub struct YahooSource {
+    pub client: YfClient,
+}
+#[cfg(feature = "yfinance")]
+impl YahooSource {
+    pub fn new() -> Self {
+        Self {
+            client: YfClient::default(),
+        }
+    }
+    pub fn with_client(client: YfClient) -> Self {
+        Self { client }
+    }
+}
+#[cfg(feature = "yfinance")]
+impl DataSource for YahooSource {
+    fn name(&self) -> &str {
+        "Yahoo"
+    }
+    //NOTE Yahoo has no symbol listing, symbols are validated one at a time
+    async fn list_symbols(&self) -> Result<Vec<String>> {
+        Ok(vec![])
+    }
+    async fn earliest_timestamp(&self, symbol: &str) -> Result<Option<i64>> {
+        let k = HistoryBuilder::new(&self.client, symbol)
+            .interval(Interval::M1)
+            .range(Range::Max)
+            .actions(false)
+            .fetch()
+            .await?;
+        Ok(k.first().map(|c| c.ts * 1000))
+    }
+    async fn fetch_klines(&self, symbol: &str, range: (i64, i64), intv: Intv) -> Result<FatKline> {
+        let Kline { kline } =
+            get_yfinance_data(&self.client, symbol, intv, range.0, range.1).await?;
+        Ok(FatKline {
+            kline: thin_to_fat(&intv, &kline),
+        })
+    }
+    //NOTE the whole range comes back in one response
+    fn max_range(&self, _intv: Intv) -> i64 {
+        i64::MAX
+    }
+}
+
+///CSV files named <SYMBOL>-<binance interval>.csv in dir e.g. BTCUSDT-1h.csv
+#[derive(Clone)]
+pub struct LocalSource {
+    pub dir: PathBuf,
+    pub config: CsvImportConfig,
+}
+impl LocalSource {
+    pub fn new<P: Into<PathBuf>>(dir: P, config: CsvImportConfig) -> Self {
+        Self {
+            dir: dir.into(),
+            config,
+        }
+    }
+    fn path(&self, symbol: &str, intv: Intv) -> PathBuf {
+        self.dir
+            .join(format!["{}-{}.csv", symbol, intv.to_bin_str()])
+    }
+    async fn read(&self, symbol: &str, intv: Intv) -> Result<FatKline> {
+        let path = self.path(symbol, intv);
+        let input = tokio::fs::read_to_string(&path)
+            .await
+            .with_context(|| format!["Unable to read {}", path.display()])?;
+        let kline = parse_kline_csv(&input, &intv, &self.config)?;
+        Ok(FatKline { kline })
+    }
+}
+impl DataSource for LocalSource {
+    fn name(&self) -> &str {
+        "Local"
+    }
+    async fn list_symbols(&self) -> Result<Vec<String>> {
+        let mut dir = tokio::fs::read_dir(&self.dir)
+            .await
+            .with_context(|| format!["Unable to read {}", self.dir.display()])?;
+        let mut symbols = vec![];
+        while let Some(entry) = dir.next_entry().await? {
+            let name = entry.file_name();
+            let Some((symbol, _)) = name
+                .to_str()
+                .and_then(|n| n.strip_suffix(".csv"))
+                .and_then(|n| n.rsplit_once('-'))
+            else {
+                continue;
+            };
+            symbols.push(symbol.to_string());
+        }
+        symbols.sort();
+        symbols.dedup();
+        Ok(symbols)
+    }
+    async fn earliest_timestamp(&self, symbol: &str) -> Result<Option<i64>> {
+        for intv in [Intv::Min1, Intv::Hour1, Intv::Day1] {
+            if self.path(symbol, intv).exists() {
+                let k = self.read(symbol, intv).await?;
+                return Ok(k.kline.first().map(|k| k.0));
+            };
+        }
+        Ok(None)
+    }
+    async fn fetch_klines(&self, symbol: &str, range: (i64, i64), intv: Intv) -> Result<FatKline> {
+        let mut k = self.read(symbol, intv).await?;
+        k.kline.retain(|k| k.0 >= range.0 && k.0 <= range.1);
+        Ok(k)
+    }
+    fn max_range(&self, _intv: Intv) -> i64 {
+        i64::MAX
+    }
+}
+
+///Offline source for pipeline tests, serves flat candles from start onwards and fails the
+///first fail_first fetch_klines calls
+#[derive(Clone)]
+pub struct MockSource {
+    pub symbol: String,
+    pub start: i64,
+    pub fail_first: usize,
+    calls: Arc<AtomicUsize>,
+}
+impl MockSource {
+    pub fn new(symbol: &str, start: i64, fail_first: usize) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            start,
+            fail_first,
+            calls: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+    ///fetch_klines calls so far, shared between clones
+    pub fn calls(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+}
+impl DataSource for MockSource {
+    fn name(&self) -> &str {
+        "Mock"
+    }
+    async fn list_symbols(&self) -> Result<Vec<String>> {
+        Ok(vec![self.symbol.clone()])
+    }
+    async fn earliest_timestamp(&self, symbol: &str) -> Result<Option<i64>> {
+        Ok((symbol == self.symbol).then_some(self.start))
+    }
+    async fn fetch_klines(&self, symbol: &str, range: (i64, i64), intv: Intv) -> Result<FatKline> {
+        let n = self.calls.fetch_add(1, Ordering::SeqCst);
+        if n < self.fail_first {
+            return Err(anyhow!["Mock failure {}/{}", n + 1, self.fail_first]);
+        };
+        if symbol != self.symbol {
+            return Err(anyhow!["Unknown symbol {}", symbol]);
+        };
+        let step = intv.to_ms();
+        let first = intv.bucket_start(range.0.max(self.start));
+        let first = if first < range.0 { first + step } else { first };
+        let mut kline = vec![];
+        let mut t = first;
+        while t <= range.1 {
+            let (Some(open_time), Some(close_time)) = (
+                DateTime::<Utc>::from_timestamp_millis(t),
+                DateTime::<Utc>::from_timestamp_millis(t + step - 1),
+            ) else {
+                return Err(anyhow!["Invalid time {}", t]);
+            };
+            let p = ((t - self.start) / step) as f64;
+            kline.push((
+                t,
+                open_time,
+                p,
+                p + 2.0,
+                p - 1.0,
+                p + 1.0,
+                1.0,
+                t + step - 1,
+                close_time,
+                p,
+                1,
+                0.5,
+                p / 2.0,
+            ));
+            t += step;
+        }
+        Ok(FatKline { kline })
+    }
+}
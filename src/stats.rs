@@ -0,0 +1,534 @@
+use anyhow::{Result, anyhow};
+use chrono::Utc;
+use futures::TryStreamExt;
+use sqlx::{Pool, Sqlite};
+use std::collections::VecDeque;
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+use crate::data::Intv;
+
+///Trailing candle counts of the rolling statistics, 0 is every candle
+pub const STAT_WINDOWS: [u32; 3] = [0, 30, 365];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EnumIter, Default)]
+pub enum StatCol {
+    ///Close to close, as a fraction of the previous close
+    #[default]
+    Returns,
+    ///High to low range as a fraction of the open
+    Volatility,
+    Volume,
+    Trades,
+}
+impl StatCol {
+    pub fn to_str(&self) -> &str {
+        match self {
+            StatCol::Returns => "Returns",
+            StatCol::Volatility => "Volatility",
+            StatCol::Volume => "Volume",
+            StatCol::Trades => "Trades",
+        }
+    }
+    ///Returns and volatility are fractions, shown as percentages
+    pub fn is_ratio(&self) -> bool {
+        matches!(self, StatCol::Returns | StatCol::Volatility)
+    }
+}
+
+impl std::str::FromStr for StatCol {
+    type Err = anyhow::Error;
+    fn from_str(input: &str) -> Result<Self> {
+        match input {
+            "Returns" => Ok(StatCol::Returns),
+            "Volatility" => Ok(StatCol::Volatility),
+            "Volume" => Ok(StatCol::Volume),
+            "Trades" => Ok(StatCol::Trades),
+            _ => Err(anyhow!["Invalid statistics column {}", input]),
+        }
+    }
+}
+
+///One row of colstats, the statistics of a column over the last window candles of intv
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ColStat {
+    pub column: StatCol,
+    pub intv: Intv,
+    pub window: u32,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub min: f64,
+    pub max: f64,
+    ///Value of the last candle
+    pub last: f64,
+    ///Candles the statistics are over
+    pub no: u64,
+    ///Open time of the last candle in the statistics, later ones are added on the next update
+    pub updated: i64,
+}
+
+///Mean and variance in one pass (Welford)
+#[derive(Debug, Clone, Default)]
+struct RunningStats {
+    no: u64,
+    mean: f64,
+    m2: f64,
+    min: f64,
+    max: f64,
+    last: f64,
+}
+impl RunningStats {
+    fn resume(s: &ColStat) -> Self {
+        let m2 = match s.no {
+            0 | 1 => 0.0,
+            n => s.std_dev * s.std_dev * (n - 1) as f64,
+        };
+        Self {
+            no: s.no,
+            mean: s.mean,
+            m2,
+            min: s.min,
+            max: s.max,
+            last: s.last,
+        }
+    }
+    fn push(&mut self, x: f64) {
+        if self.no == 0 {
+            (self.min, self.max) = (x, x);
+        };
+        self.no += 1;
+        let d = x - self.mean;
+        self.mean += d / self.no as f64;
+        self.m2 += d * (x - self.mean);
+        self.min = self.min.min(x);
+        self.max = self.max.max(x);
+        self.last = x;
+    }
+    fn col_stat(&self, column: StatCol, intv: Intv, window: u32, updated: i64) -> ColStat {
+        let std_dev = match self.no {
+            0 | 1 => 0.0,
+            n => (self.m2 / (n - 1) as f64).sqrt(),
+        };
+        ColStat {
+            column,
+            intv,
+            window,
+            mean: self.mean,
+            std_dev,
+            min: self.min,
+            max: self.max,
+            last: self.last,
+            no: self.no,
+            updated,
+        }
+    }
+}
+
+///Column values of one candle, None where the candle doesn't have it (no previous close,
+///imported data without trade counts)
+type StatRow = [Option<f64>; 4];
+
+fn stat_row(prev_close: Option<f64>, row: &(f64, f64, f64, f64, f64, Option<i64>)) -> StatRow {
+    let (open, high, low, close, volume, trades) = *row;
+    let returns = prev_close.filter(|c| *c != 0.0).map(|c| close / c - 1.0);
+    let volatility = Some((high - low) / open).filter(|_| open != 0.0);
+    [returns, volatility, Some(volume), trades.map(|t| t as f64)]
+}
+
+///Statistics of every StatCol and window of a time ordered series, one candle at a time
+#[derive(Debug, Clone)]
+pub struct SeriesStats {
+    intv: Intv,
+    global: Vec<RunningStats>,
+    tail: VecDeque<StatRow>,
+    max_window: usize,
+    prev_close: Option<f64>,
+}
+impl SeriesStats {
+    pub fn new(intv: Intv) -> Self {
+        let max_window = STAT_WINDOWS.iter().max().copied().unwrap_or(0) as usize;
+        Self {
+            intv,
+            global: vec![RunningStats::default(); 4],
+            tail: VecDeque::with_capacity(max_window),
+            max_window,
+            prev_close: None,
+        }
+    }
+    ///Continues the all candle statistics of saved, prev_close is the close of their last candle
+    pub fn resume(intv: Intv, saved: &[ColStat], prev_close: f64) -> Self {
+        let mut series = Self::new(intv);
+        for (column, s) in StatCol::iter().zip(series.global.iter_mut()) {
+            if let Some(saved) = find_col_stat(saved, column, intv, 0) {
+                *s = RunningStats::resume(saved);
+            };
+        }
+        series.prev_close = Some(prev_close);
+        series
+    }
+    ///(open high low close volume trades) of the next candle
+    pub fn push(&mut self, row: &(f64, f64, f64, f64, f64, Option<i64>)) {
+        let r = stat_row(self.prev_close, row);
+        self.prev_close = Some(row.3);
+        for (s, x) in self.global.iter_mut().zip(r.iter()) {
+            if let Some(x) = x {
+                s.push(*x);
+            };
+        }
+        if self.tail.len() == self.max_window {
+            self.tail.pop_front();
+        };
+        self.tail.push_back(r);
+    }
+    ///Columns without any values are left out, an empty series gives no statistics
+    pub fn finish(&self, updated: i64) -> Vec<ColStat> {
+        let mut stats = self.global(updated);
+        stats.append(&mut self.windows(updated));
+        stats
+    }
+    fn global(&self, updated: i64) -> Vec<ColStat> {
+        StatCol::iter()
+            .zip(self.global.iter())
+            .filter(|(_, s)| s.no > 0)
+            .map(|(column, s)| s.col_stat(column, self.intv, 0, updated))
+            .collect()
+    }
+    ///Statistics of the rolling windows, over the candles pushed last
+    fn windows(&self, updated: i64) -> Vec<ColStat> {
+        let mut stats = vec![];
+        for window in STAT_WINDOWS.into_iter().filter(|w| *w != 0) {
+            let mut windowed = vec![RunningStats::default(); 4];
+            for r in self
+                .tail
+                .iter()
+                .skip(self.tail.len().saturating_sub(window as usize))
+            {
+                for (s, x) in windowed.iter_mut().zip(r.iter()) {
+                    if let Some(x) = x {
+                        s.push(*x);
+                    };
+                }
+            }
+            for (column, s) in StatCol::iter().zip(windowed.iter()) {
+                if s.no > 0 {
+                    stats.push(s.col_stat(column, self.intv, window, updated));
+                };
+            }
+        }
+        stats
+    }
+}
+
+type StatSqlRow = (i64, f64, f64, f64, f64, f64, Option<i64>);
+
+///The saved all candle statistics of intv and the open time of their last candle, None if
+///they're missing or the rows up to them changed (older data was imported)
+async fn resume_col_stats(
+    pool: &Pool<Sqlite>,
+    table: &str,
+    intv: Intv,
+    saved: &[ColStat],
+) -> Result<Option<(i64, SeriesStats)>> {
+    //NOTE every candle has a volume, its count is the number of candles
+    let Some(volume) = find_col_stat(saved, StatCol::Volume, intv, 0) else {
+        return Ok(None);
+    };
+    let q = format![
+        "SELECT COUNT(*), (SELECT Close FROM {} WHERE [Timestamp MS] = ?) FROM {} WHERE [Timestamp MS] <= ?;",
+        table, table
+    ];
+    let (no, prev_close): (i64, Option<f64>) = sqlx::query_as(&q)
+        .bind(volume.updated)
+        .bind(volume.updated)
+        .fetch_one(pool)
+        .await?;
+    Ok(prev_close
+        .filter(|_| no as u64 == volume.no)
+        .map(|c| (volume.updated, SeriesStats::resume(intv, saved, c))))
+}
+
+///Statistics of every interval from the closed candles of an asset DB. The all candle ones
+///continue from saved with the candles after them, the rolling windows are recomputed from the
+///last candles.
+pub async fn compute_col_stats(pool: &Pool<Sqlite>, saved: &[ColStat]) -> Result<Vec<ColStat>> {
+    let now = Utc::now().timestamp_millis();
+    let mut stats = vec![];
+    for intv in Intv::iter() {
+        let table = intv.table()?;
+        let (from, mut series) = resume_col_stats(pool, &table, intv, saved)
+            .await?
+            .unwrap_or_else(|| (i64::MIN, SeriesStats::new(intv)));
+        let q = format![
+            "SELECT [Timestamp MS], Open, High, Low, Close, Volume, [Number of Trades] FROM {} WHERE [Timestamp MS] > ? AND IFNULL([Close Timestamp MS], 0) < ? ORDER BY [Timestamp MS];",
+            table
+        ];
+        let mut updated = from;
+        {
+            //NOTE streamed, a full 1m table doesn't have to fit in memory
+            let mut rows = sqlx::query_as::<_, StatSqlRow>(&q)
+                .bind(from)
+                .bind(now)
+                .fetch(pool);
+            while let Some((t, o, h, l, c, v, trades)) = rows.try_next().await? {
+                series.push(&(o, h, l, c, v, trades));
+                updated = t;
+            }
+        }
+        //one more than the longest window for the returns of its first candle
+        let q = format![
+            "SELECT * FROM (SELECT [Timestamp MS], Open, High, Low, Close, Volume, [Number of Trades] FROM {} WHERE IFNULL([Close Timestamp MS], 0) < ? ORDER BY [Timestamp MS] DESC LIMIT ?) ORDER BY [Timestamp MS];",
+            table
+        ];
+        let last: Vec<StatSqlRow> = sqlx::query_as(&q)
+            .bind(now)
+            .bind(series.max_window as i64 + 1)
+            .fetch_all(pool)
+            .await?;
+        let mut tail = SeriesStats::new(intv);
+        for (_, o, h, l, c, v, trades) in last.iter() {
+            tail.push(&(*o, *h, *l, *c, *v, *trades));
+        }
+        stats.append(&mut series.global(updated));
+        stats.append(&mut tail.windows(updated));
+    }
+    Ok(stats)
+}
+
+///Replaces the statistics saved in the colstats table of an asset DB
+pub async fn save_col_stats(pool: &Pool<Sqlite>, stats: &[ColStat]) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM colstats")
+        .execute(&mut *tx)
+        .await?;
+    for s in stats.iter() {
+        sqlx::query(
+            "INSERT INTO colstats ( Column, Type, Intv, [Window], Mean, [Standard Deviaton], Min, Max, [Last], No, [Updated ms] ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(s.column.to_str())
+        .bind(match s.window {
+            0 => 0,
+            _ => 1,
+        })
+        .bind(s.intv.to_str())
+        .bind(s.window as i64)
+        .bind(s.mean)
+        .bind(s.std_dev)
+        .bind(s.min)
+        .bind(s.max)
+        .bind(s.last)
+        .bind(s.no as i64)
+        .bind(s.updated)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+type ColStatRow = (String, String, i64, f64, f64, f64, f64, f64, i64, i64);
+
+///Statistics saved in an asset DB, ordered by column, interval and window
+pub async fn load_col_stats(pool: &Pool<Sqlite>) -> Result<Vec<ColStat>> {
+    let rows: Vec<ColStatRow> = sqlx::query_as(
+        "SELECT Column, Intv, [Window], Mean, [Standard Deviaton], Min, Max, [Last], No, [Updated ms] FROM colstats WHERE Intv IS NOT NULL",
+    )
+    .fetch_all(pool)
+    .await?;
+    let intvs: Vec<Intv> = Intv::iter().collect();
+    let mut stats = vec![];
+    for (column, intv, window, mean, std_dev, min, max, last, no, updated) in rows {
+        let Some(intv) = intvs.iter().find(|i| i.to_str() == intv) else {
+            tracing::debug!["Skipping colstats row of unknown interval {}", intv];
+            continue;
+        };
+        stats.push(ColStat {
+            column: column.parse()?,
+            intv: *intv,
+            window: window as u32,
+            mean,
+            std_dev,
+            min,
+            max,
+            last,
+            no: no as u64,
+            updated,
+        });
+    }
+    let order = |s: &ColStat| {
+        (
+            StatCol::iter().position(|c| c == s.column),
+            intvs.iter().position(|i| *i == s.intv),
+            s.window,
+        )
+    };
+    stats.sort_by_key(order);
+    Ok(stats)
+}
+
+///Statistic of column over the last window candles of intv, for screening assets on them
+pub fn find_col_stat(
+    stats: &[ColStat],
+    column: StatCol,
+    intv: Intv,
+    window: u32,
+) -> Option<&ColStat> {
+    stats
+        .iter()
+        .find(|s| s.column == column && s.intv == intv && s.window == window)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::get_data_source;
+    use crate::data::test_db::TempDb;
+    use crate::jobs::DlJob;
+    use crate::source::MockSource;
+
+    #[test]
+    fn series_stats_rolling_windows() {
+        let mut series = SeriesStats::new(Intv::Day1);
+        //close doubles every candle after the first, the last 30 candles have no trade counts
+        for n in 0..400 {
+            let close = match n {
+                0 => 1.0,
+                _ => 2.0,
+            };
+            let trades = match n {
+                370.. => None,
+                n => Some(n as i64),
+            };
+            series.push(&(1.0, 1.5, 0.5, close, n as f64, trades));
+        }
+        let stats = series.finish(0);
+        let get = |c, w| find_col_stat(&stats, c, Intv::Day1, w).unwrap();
+
+        let returns = get(StatCol::Returns, 0);
+        assert_eq!(returns.no, 399);
+        assert_eq!((returns.max, returns.min, returns.last), (1.0, 0.0, 0.0));
+        assert!((returns.mean - 1.0 / 399.0).abs() < 1e-12);
+        //the jump isn't in the rolling windows
+        let recent = get(StatCol::Returns, 30);
+        assert_eq!((recent.no, recent.mean, recent.std_dev), (30, 0.0, 0.0));
+        assert_eq!(get(StatCol::Volatility, 365).mean, 1.0);
+        let volume = get(StatCol::Volume, 30);
+        assert_eq!((volume.no, volume.mean, volume.min), (30, 384.5, 370.0));
+        //sample standard deviation of 370..400
+        assert!((volume.std_dev - 8.803408430829505).abs() < 1e-9);
+        assert_eq!(get(StatCol::Trades, 0).no, 370);
+        assert!(find_col_stat(&stats, StatCol::Trades, Intv::Day1, 30).is_none());
+        assert_eq!(get(StatCol::Trades, 365).no, 335);
+        assert!(SeriesStats::new(Intv::Day1).finish(0).is_empty());
+    }
+
+    #[tokio::test]
+    async fn col_stats_are_saved_per_interval_and_window() {
+        let db = TempDb::asset("stats").await;
+        let hour = Intv::Hour1.to_ms();
+        let start = 1704067200000;
+        let source = MockSource::new("MOCK", start, 0);
+        let job = DlJob::default();
+        get_data_source(
+            &source,
+            "MOCK",
+            Intv::Hour1,
+            &db.pool,
+            start - 1,
+            start + 500 * hour,
+            &job,
+        )
+        .await
+        .unwrap();
+
+        let stats = compute_col_stats(&db.pool, &[]).await.unwrap();
+        save_col_stats(&db.pool, &stats).await.unwrap();
+        //saving again replaces the rows
+        save_col_stats(&db.pool, &stats).await.unwrap();
+        let loaded = load_col_stats(&db.pool).await.unwrap();
+        let (rows,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM colstats")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        db.pool.close().await;
+
+        //only 1h has data
+        assert!(stats.iter().all(|s| s.intv == Intv::Hour1));
+        let volume = find_col_stat(&stats, StatCol::Volume, Intv::Hour1, 0).unwrap();
+        assert!(volume.no >= 500);
+        assert_eq!(
+            find_col_stat(&stats, StatCol::Volume, Intv::Hour1, 365)
+                .unwrap()
+                .no,
+            365
+        );
+        assert_eq!(rows as usize, stats.len());
+        assert_eq!(loaded.len(), stats.len());
+        for s in stats.iter() {
+            assert_eq!(Some(s), find_col_stat(&loaded, s.column, s.intv, s.window));
+        }
+    }
+
+    #[tokio::test]
+    async fn col_stats_continue_with_the_new_candles() {
+        let db = TempDb::asset("stats_update").await;
+        let hour = Intv::Hour1.to_ms();
+        let start = 1704067200000;
+        let job = DlJob::default();
+        let source = MockSource::new("MOCK", start, 0);
+        let (from, to) = (start - 1, start + 500 * hour);
+        get_data_source(&source, "MOCK", Intv::Hour1, &db.pool, from, to, &job)
+            .await
+            .unwrap();
+        let stats = compute_col_stats(&db.pool, &[]).await.unwrap();
+        //a saved mean that's off shows which statistics were continued
+        let mut saved = stats.clone();
+        for s in saved.iter_mut() {
+            if (s.column, s.window) == (StatCol::Volume, 0) {
+                s.mean += 600.0;
+            };
+        }
+        let (from, to) = (start + 500 * hour, start + 600 * hour);
+        get_data_source(&source, "MOCK", Intv::Hour1, &db.pool, from, to, &job)
+            .await
+            .unwrap();
+        let updated = compute_col_stats(&db.pool, &saved).await.unwrap();
+        let full = compute_col_stats(&db.pool, &[]).await.unwrap();
+        //older candles than the saved ones
+        let older = MockSource::new("MOCK", start - 50 * hour, 0);
+        let (from, to) = (start - 50 * hour - 1, start - 1);
+        get_data_source(&older, "MOCK", Intv::Hour1, &db.pool, from, to, &job)
+            .await
+            .unwrap();
+        let imported = compute_col_stats(&db.pool, &updated).await.unwrap();
+        let imported_full = compute_col_stats(&db.pool, &[]).await.unwrap();
+        db.pool.close().await;
+
+        let get = |stats: &[ColStat], c| find_col_stat(stats, c, Intv::Hour1, 0).unwrap().clone();
+        let (before, after) = (get(&stats, StatCol::Volume), get(&full, StatCol::Volume));
+        assert_eq!(
+            (before.updated, after.updated),
+            (start + 500 * hour, start + 600 * hour)
+        );
+        let shift = get(&updated, StatCol::Volume).mean - after.mean;
+        assert!((shift - 600.0 * before.no as f64 / after.no as f64).abs() < 1e-6);
+        let close = |x: f64, y: f64| (x - y).abs() <= 1e-9 * x.abs().max(1.0);
+        assert_eq!(updated.len(), full.len());
+        for (u, f) in updated.iter().zip(full.iter()) {
+            if (u.column, u.window) == (StatCol::Volume, 0) {
+                continue;
+            };
+            assert_eq!(
+                (u.column, u.window, u.no, u.min, u.max, u.last, u.updated),
+                (f.column, f.window, f.no, f.min, f.max, f.last, f.updated)
+            );
+            assert!(
+                close(u.mean, f.mean) && close(u.std_dev, f.std_dev),
+                "{:?}",
+                u
+            );
+        }
+        //the rows before the saved statistics changed, they're recomputed
+        assert_eq!(imported, imported_full);
+        assert_eq!(get(&imported, StatCol::Volume).no, after.no + 50);
+    }
+}
@@ -0,0 +1,382 @@
+use anyhow::{Context, Result, anyhow};
+use bincode::error::DecodeError;
+use bincode::{Decode, Encode, config};
+use chrono::{DateTime, NaiveDate, Utc};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+///One aggTrade as received from the WS, times in ms
+#[derive(Debug, Clone, Copy, PartialEq, Default, Encode, Decode)]
+pub struct AggTick {
+    pub id: i64,
+    pub price: f64,
+    pub qty: f64,
+    pub first_id: i64,
+    pub last_id: i64,
+    pub time: i64,
+    ///The buyer was the maker, the trade was a market sell
+    pub buyer_maker: bool,
+}
+impl AggTick {
+    ///UTC day of the trade, ticks are rotated into a new file on it
+    pub fn day(&self) -> Option<NaiveDate> {
+        DateTime::<Utc>::from_timestamp_millis(self.time).map(|t| t.date_naive())
+    }
+}
+
+///ticks/<SYMBOL>/<YYYY-MM-DD>.bin
+pub fn tick_file(dir: &Path, symbol: &str, day: &NaiveDate) -> PathBuf {
+    dir.join(symbol)
+        .join(format!["{}.bin", day.format("%Y-%m-%d")])
+}
+
+#[derive(Debug)]
+struct DayFile {
+    day: NaiveDate,
+    w: BufWriter<File>,
+}
+
+///Appends every aggTrade to a file per symbol and UTC day. Records are bincode encoded back to
+///back so a file can be read without an index.
+#[derive(Debug)]
+pub struct TickRecorder {
+    dir: PathBuf,
+    files: HashMap<String, DayFile>,
+    recorded: u64,
+}
+impl TickRecorder {
+    pub fn new(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!["Unable to create {}", dir.display()])?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            files: HashMap::new(),
+            recorded: 0,
+        })
+    }
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+    pub fn recorded(&self) -> u64 {
+        self.recorded
+    }
+    pub fn record(&mut self, symbol: &str, tick: &AggTick) -> Result<()> {
+        let day = tick.day().ok_or(anyhow![
+            "Invalid time {} of aggTrade {}",
+            tick.time,
+            tick.id
+        ])?;
+        if self.files.get(symbol).is_none_or(|f| f.day != day) {
+            if let Some(mut old) = self.files.remove(symbol) {
+                old.w.flush()?;
+            };
+            let path = tick_file(&self.dir, symbol, &day);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            };
+            //NOTE append, a restart on the same day continues the file after its last whole record
+            if path.exists() {
+                truncate_ticks(&path)?;
+            };
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .with_context(|| format!["Unable to open {}", path.display()])?;
+            self.files.insert(
+                symbol.to_string(),
+                DayFile {
+                    day,
+                    w: BufWriter::new(file),
+                },
+            );
+        };
+        let f = self
+            .files
+            .get_mut(symbol)
+            .ok_or(anyhow!["No tick file for {}", symbol])?;
+        bincode::encode_into_std_write(tick, &mut f.w, config::standard())?;
+        self.recorded += 1;
+        Ok(())
+    }
+    pub fn flush(&mut self) -> Result<()> {
+        for f in self.files.values_mut() {
+            f.w.flush()?;
+        }
+        Ok(())
+    }
+}
+
+///Counts the bytes read through it
+struct CountingReader<R> {
+    inner: R,
+    read: u64,
+}
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        Ok(n)
+    }
+}
+
+///Every tick of a day file, a last record cut short by a crash is dropped
+pub fn read_ticks(path: &Path) -> Result<Vec<AggTick>> {
+    let mut ticks = vec![];
+    scan_ticks(path, |t| ticks.push(t))?;
+    Ok(ticks)
+}
+
+///Cuts a day file after its last whole record so appended ticks can be read, returns the new
+///length
+pub fn truncate_ticks(path: &Path) -> Result<u64> {
+    let valid = scan_ticks(path, |_| ())?;
+    let file = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .with_context(|| format!["Unable to open {}", path.display()])?;
+    let len = file.metadata()?.len();
+    if valid < len {
+        tracing::warn![
+            "Dropping {} bytes of a cut short tick record from {}",
+            len - valid,
+            path.display()
+        ];
+        file.set_len(valid)?;
+    };
+    Ok(valid)
+}
+
+///Decodes the ticks of a day file, returns the length of the whole records
+fn scan_ticks<F: FnMut(AggTick)>(path: &Path, mut f: F) -> Result<u64> {
+    let file = File::open(path).with_context(|| format!["Unable to open {}", path.display()])?;
+    let mut r = CountingReader {
+        inner: BufReader::new(file),
+        read: 0,
+    };
+    let mut valid = 0;
+    loop {
+        match bincode::decode_from_std_read::<AggTick, _, _>(&mut r, config::standard()) {
+            Ok(t) => {
+                valid = r.read;
+                f(t);
+            }
+            Err(DecodeError::UnexpectedEnd { .. }) => break,
+            Err(DecodeError::Io { inner, .. })
+                if inner.kind() == std::io::ErrorKind::UnexpectedEof =>
+            {
+                break;
+            }
+            Err(e) => return Err(anyhow!["Unable to read {}: {}", path.display(), e]),
+        }
+    }
+    Ok(valid)
+}
+
+///Recorded ticks of symbol with times between start and end (inclusive) sorted by id, days
+///without a file are skipped
+pub fn load_ticks(dir: &Path, symbol: &str, start: i64, end: i64) -> Result<Vec<AggTick>> {
+    let day = |t: i64| {
+        DateTime::<Utc>::from_timestamp_millis(t)
+            .map(|t| t.date_naive())
+            .ok_or(anyhow!["Invalid time {}", t])
+    };
+    let (first, last) = (day(start)?, day(end)?);
+    let mut ticks = vec![];
+    for d in first.iter_days().take_while(|d| *d <= last) {
+        let path = tick_file(dir, symbol, &d);
+        if !path.exists() {
+            continue;
+        };
+        ticks.extend(
+            read_ticks(&path)?
+                .into_iter()
+                .filter(|t| t.time >= start && t.time <= end),
+        );
+    }
+    ticks.sort_by_key(|t| t.id);
+    ticks.dedup_by_key(|t| t.id);
+    Ok(ticks)
+}
+
+///Parses the aggTrades CSV of data.binance.vision (id, price, qty, first id, last id, time,
+///buyer maker, best match), the optional header row is skipped. Microsecond times are
+///converted to ms.
+pub fn parse_agg_trades_csv(input: &str) -> Result<Vec<AggTick>> {
+    let to_ms = |t: i64| if t > 100_000_000_000_000 { t / 1000 } else { t };
+    let mut out = vec![];
+    for (n, line) in input.lines().enumerate() {
+        let f: Vec<&str> = line.trim().split(',').collect();
+        if f.len() < 7 || (n == 0 && f[0].parse::<i64>().is_err()) {
+            continue;
+        };
+        let tick = || -> Result<AggTick> {
+            Ok(AggTick {
+                id: f[0].parse()?,
+                price: f[1].parse()?,
+                qty: f[2].parse()?,
+                first_id: f[3].parse()?,
+                last_id: f[4].parse()?,
+                time: to_ms(f[5].parse()?),
+                buyer_maker: f[6].eq_ignore_ascii_case("true"),
+            })
+        };
+        out.push(tick().with_context(|| format!["Bad aggTrade on line {}", n + 1])?);
+    }
+    Ok(out)
+}
+
+///Adds the aggTrades of a data.binance.vision CSV, or of every CSV in a zip of them, to the ticks
+///of symbol in dir. Returns the number of ticks imported, ticks already recorded are skipped
+///when loaded.
+pub fn import_agg_trades(path: &Path, symbol: &str, dir: &Path) -> Result<usize> {
+    let mut ticks = vec![];
+    match path.extension().is_some_and(|e| e == "zip") {
+        true => {
+            let file =
+                File::open(path).with_context(|| format!["Unable to open {}", path.display()])?;
+            let mut zip = zip::ZipArchive::new(BufReader::new(file))?;
+            for i in 0..zip.len() {
+                let mut file = zip.by_index(i)?;
+                if !file.name().ends_with(".csv") {
+                    continue;
+                };
+                let mut input = String::new();
+                std::io::Read::read_to_string(&mut file, &mut input)?;
+                ticks.append(&mut parse_agg_trades_csv(&input)?);
+            }
+        }
+        false => {
+            let input = std::fs::read_to_string(path)
+                .with_context(|| format!["Unable to read {}", path.display()])?;
+            ticks = parse_agg_trades_csv(&input)?;
+        }
+    };
+    ticks.sort_by_key(|t| t.id);
+    let mut rec = TickRecorder::new(dir)?;
+    for t in ticks.iter() {
+        rec.record(symbol, t)?;
+    }
+    rec.flush()?;
+    Ok(ticks.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ticks_rotate_by_day_and_read_back() {
+        let dir = std::env::temp_dir().join(format!["bintrade_ticks_{}", std::process::id()]);
+        let day_ms = 86_400_000;
+        let t0 = 1704153600000 - 2;
+        let tick = |n: i64| AggTick {
+            id: n,
+            price: 42_000.0 + n as f64,
+            qty: 0.5,
+            first_id: n * 2,
+            last_id: n * 2 + 1,
+            time: t0 + n,
+            buyer_maker: n % 2 == 0,
+        };
+        let mut rec = TickRecorder::new(&dir).unwrap();
+        for n in 0..5 {
+            rec.record("BTCUSDT", &tick(n)).unwrap();
+        }
+        rec.record("ETHUSDT", &tick(0)).unwrap();
+        rec.flush().unwrap();
+        //a restart appends to the same day
+        let mut rec2 = TickRecorder::new(&dir).unwrap();
+        rec2.record("BTCUSDT", &tick(5)).unwrap();
+        rec2.flush().unwrap();
+        //cut the last record short
+        let second = tick_file(&dir, "BTCUSDT", &tick(2).day().unwrap());
+        let bytes = std::fs::read(&second).unwrap();
+        std::fs::write(&second, &bytes[..bytes.len() - 1]).unwrap();
+
+        let first = read_ticks(&tick_file(&dir, "BTCUSDT", &tick(0).day().unwrap())).unwrap();
+        let all = load_ticks(&dir, "BTCUSDT", t0 - day_ms, t0 + day_ms).unwrap();
+        let some = load_ticks(&dir, "BTCUSDT", t0 + 1, t0 + 3).unwrap();
+        let none = load_ticks(&dir, "SOLUSDT", t0, t0 + day_ms).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(rec.recorded(), 6);
+        //the first 2 ticks are before midnight
+        assert_eq!(first, vec![tick(0), tick(1)]);
+        assert_eq!(all, (0..5).map(tick).collect::<Vec<AggTick>>());
+        assert_eq!(some, vec![tick(1), tick(2), tick(3)]);
+        assert!(none.is_empty());
+    }
+
+    #[test]
+    fn restart_after_a_cut_short_record_appends_readable_ticks() {
+        let dir = std::env::temp_dir().join(format!["bintrade_ticks_cut_{}", std::process::id()]);
+        let tick = |n: i64| AggTick {
+            id: n,
+            price: 42_000.0 + n as f64,
+            qty: 0.25,
+            first_id: n,
+            last_id: n,
+            time: 1704067200000 + n,
+            buyer_maker: false,
+        };
+        let mut rec = TickRecorder::new(&dir).unwrap();
+        rec.record("BTCUSDT", &tick(0)).unwrap();
+        rec.record("BTCUSDT", &tick(1)).unwrap();
+        rec.flush().unwrap();
+        drop(rec);
+        //a crash in the middle of writing the third tick
+        let path = tick_file(&dir, "BTCUSDT", &tick(0).day().unwrap());
+        let whole = std::fs::metadata(&path).unwrap().len();
+        let cut = bincode::encode_to_vec(tick(2), config::standard()).unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes.extend_from_slice(&cut[..cut.len() / 2]);
+        std::fs::write(&path, &bytes).unwrap();
+        let mut rec = TickRecorder::new(&dir).unwrap();
+        rec.record("BTCUSDT", &tick(3)).unwrap();
+        rec.flush().unwrap();
+        let ticks = read_ticks(&path).unwrap();
+        let len = std::fs::metadata(&path).unwrap().len();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(ticks, vec![tick(0), tick(1), tick(3)]);
+        assert_eq!(
+            len,
+            whole
+                + bincode::encode_to_vec(tick(3), config::standard())
+                    .unwrap()
+                    .len() as u64
+        );
+    }
+
+    #[test]
+    fn archive_agg_trades_are_imported() {
+        let dir = std::env::temp_dir().join(format!["bintrade_ticks_csv_{}", std::process::id()]);
+        std::fs::create_dir_all(&dir).unwrap();
+        let csv = dir.join("BTCUSDT-aggTrades-2024-01-01.csv");
+        std::fs::write(
+            &csv,
+            "agg_trade_id,price,quantity,first_trade_id,last_trade_id,transact_time,is_buyer_maker,is_best_match\n\
+             11,42283.58,0.006,20,20,1704067200035,false,true\n\
+             10,42283.57,0.05,18,19,1704067200000000,True,True\n",
+        )
+        .unwrap();
+        let store = dir.join("ticks");
+        let n = import_agg_trades(&csv, "BTCUSDT", &store).unwrap();
+        let ticks = load_ticks(&store, "BTCUSDT", 1704067200000, 1704067200035).unwrap();
+        let bad = parse_agg_trades_csv("1,x,1,1,1,1,true\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(n, 2);
+        assert_eq!(
+            ticks.iter().map(|t| t.id).collect::<Vec<i64>>(),
+            vec![10, 11]
+        );
+        assert_eq!(ticks[0].time, 1704067200000);
+        assert!(ticks[0].buyer_maker && !ticks[1].buyer_maker);
+        assert_eq!((ticks[1].first_id, ticks[1].last_id), (20, 20));
+        assert!(bad.is_err());
+    }
+}
@@ -1,6 +1,11 @@
-use crate::data::Intv;
+use crate::data::{FundingRate, Intv, KlineRow};
+use crate::ticks::AggTick;
+use anyhow::{Result, anyhow};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
 const M_FEE: f64 = 0.0015;
 const T_FEE: f64 = 0.0015;
@@ -66,7 +71,9 @@ const fn eval_stop(stop: f64, h: f64, o: f64, _c: f64, l: f64) -> Option<f64> {
 }
 use strum_macros::EnumIter;
 
-#[derive(EnumIter, Debug, Clone, Copy, PartialEq, Default, Hash, Encode, Decode)]
+#[derive(
+    EnumIter, Debug, Clone, Copy, PartialEq, Default, Hash, Encode, Decode, Serialize, Deserialize,
+)]
 pub enum EvalMode {
     #[default]
     LHTriggerLimit,
@@ -115,6 +122,16 @@ const fn eval_limit(
     }
 }
 
+///A limit that is already marketable at ref_p (the open, or the stop fill for stop limits) fills at
+///ref_p like on the exchange, a buy limit above the open doesn't pay more than the open.
+const fn marketable_fill(limit: f64, ref_p: f64, buy_sell: bool) -> f64 {
+    match buy_sell {
+        true if limit > ref_p => ref_p,
+        false if limit < ref_p => ref_p,
+        _ => limit,
+    }
+}
+
 #[derive(Eq, PartialEq, EnumIter, Debug, Clone, Copy, Hash, Encode, Decode)]
 pub enum OrderCondition {
     Untouched,
@@ -122,7 +139,10 @@ pub enum OrderCondition {
     StopTriggered,
 }
 
-pub const fn eval_order_basic(
+///Evaluates an order against a single wick. Returns None if the order is untouched, otherwise the
+///new condition, balances and the price the order was executed/triggered at.
+#[allow(clippy::too_many_arguments)]
+pub fn eval_order_basic(
     h: f64,
     o: f64,
     c: f64,
@@ -132,8 +152,7 @@ pub const fn eval_order_basic(
     order: Order,
     eval_mode: &EvalMode,
     locked_qnt: f64,
-) -> Option<(OrderCondition, f64, f64, f64)> {
-    let last_order_price;
+) -> Result<Option<(OrderCondition, f64, f64, f64)>> {
     match order {
         Order::Market { buy: b, quant: q } => {
             let quant = q.get_f64();
@@ -141,8 +160,7 @@ pub const fn eval_order_basic(
             let (asset1, asset2) =
                 exec_order(asset1, asset2, o, quant, buy_sell, T_FEE, locked_qnt);
             let condition = OrderCondition::Filled;
-            last_order_price = o;
-            Some((condition, asset1, asset2, last_order_price))
+            Ok(Some((condition, asset1, asset2, o)))
         }
         Order::Limit {
             buy: b,
@@ -153,16 +171,16 @@ pub const fn eval_order_basic(
             let quant = q.get_f64();
             let buy_sell = b;
             let limit = p;
-            let order = eval_limit(limit, h, o, c, l, buy_sell, eval_mode);
+            let order = eval_limit(limit, h, o, c, l, buy_sell, eval_mode)
+                .map(|p| marketable_fill(p, o, buy_sell));
             match order {
                 Some(price) => {
                     let (asset1, asset2) =
                         exec_order(asset1, asset2, price, quant, buy_sell, M_FEE, locked_qnt);
                     let condition = OrderCondition::Filled;
-                    last_order_price = o;
-                    Some((condition, asset1, asset2, last_order_price))
+                    Ok(Some((condition, asset1, asset2, price)))
                 }
-                None => None,
+                None => Ok(None),
             }
         }
         Order::StopLimit {
@@ -181,27 +199,27 @@ pub const fn eval_order_basic(
             //order change
             let order = eval_stop(stop, h, o, c, l);
             match order {
-                Some(price) => {
-                    let (asset1, asset2) =
-                        exec_order(asset1, asset2, price, quant, buy_sell, M_FEE, locked_qnt);
-                    let condition = OrderCondition::StopTriggered;
-                    let limit_order = eval_limit(limit, h, o, c, l, buy_sell, eval_mode);
+                Some(stop_fill) => {
+                    //NOTE the stop only turns the order into a limit, nothing is executed until
+                    //the limit is reached. Executing at the stop as well booked the order twice
+                    //when both were hit by the same wick.
+                    let limit_order = eval_limit(limit, h, o, c, l, buy_sell, eval_mode)
+                        .map(|p| marketable_fill(p, stop_fill, buy_sell));
                     match limit_order {
                         Some(price) => {
                             let (asset1, asset2) = exec_order(
                                 asset1, asset2, price, quant, buy_sell, M_FEE, locked_qnt,
                             );
                             let condition = OrderCondition::Filled;
-                            last_order_price = o;
-                            Some((condition, asset1, asset2, last_order_price))
+                            Ok(Some((condition, asset1, asset2, price)))
                         }
                         None => {
-                            last_order_price = o;
-                            Some((condition, asset1, asset2, last_order_price))
+                            let condition = OrderCondition::StopTriggered;
+                            Ok(Some((condition, asset1, asset2, stop_fill)))
                         }
                     }
                 }
-                None => None,
+                None => Ok(None),
             }
         }
         Order::StopMarket {
@@ -219,13 +237,12 @@ pub const fn eval_order_basic(
                     let (asset1, asset2) =
                         exec_order(asset1, asset2, price, quant, buy_sell, M_FEE, locked_qnt);
                     let condition = OrderCondition::Filled;
-                    last_order_price = o;
-                    Some((condition, asset1, asset2, last_order_price))
+                    Ok(Some((condition, asset1, asset2, price)))
                 }
-                None => None,
+                None => Ok(None),
             }
         }
-        _ => panic!(),
+        Order::None => Err(anyhow!["eval_order_basic: Order::None can't be evaluated"]),
     }
 }
 
@@ -239,15 +256,20 @@ pub enum BasicOrderType {
     StopMarket,
 }
 
-pub const fn eval_basic_condition(
+pub fn eval_basic_condition(
     condition: OrderCondition,
     order_type: Order,
-) -> (OrderCondition, Order) {
-    match order_type {
+) -> Result<(OrderCondition, Order)> {
+    let res = match order_type {
         Order::Market { buy: b, quant: q } => match condition {
-            OrderCondition::Untouched => panic!("Invalid order state!"),
             OrderCondition::Filled => (OrderCondition::Filled, Order::Market { buy: b, quant: q }),
-            OrderCondition::StopTriggered => panic!("Invalid order state!"),
+            _ => {
+                return Err(anyhow![
+                    "Invalid order state! {:?} for {:?}",
+                    condition,
+                    order_type
+                ]);
+            }
         },
         Order::Limit {
             buy: b,
@@ -273,7 +295,13 @@ pub const fn eval_basic_condition(
                     quant: q,
                 },
             ),
-            OrderCondition::StopTriggered => panic!("Invalid order state!"),
+            OrderCondition::StopTriggered => {
+                return Err(anyhow![
+                    "Invalid order state! {:?} for {:?}",
+                    condition,
+                    order_type
+                ]);
+            }
         },
         Order::StopLimit {
             buy: b,
@@ -339,13 +367,26 @@ pub const fn eval_basic_condition(
                     quant: q,
                 },
             ),
-            OrderCondition::StopTriggered => panic!("Invalid order condition!"),
+            OrderCondition::StopTriggered => {
+                return Err(anyhow![
+                    "Invalid order condition! {:?} for {:?}",
+                    condition,
+                    order_type
+                ]);
+            }
         },
-        _ => panic!(),
-    }
+        Order::None => {
+            return Err(anyhow![
+                "eval_basic_condition: Order::None has no condition"
+            ]);
+        }
+    };
+    Ok(res)
 }
 
-#[derive(PartialEq, EnumIter, Debug, Clone, Copy, Default, Encode, Decode)]
+#[derive(
+    PartialEq, EnumIter, Debug, Clone, Copy, Default, Encode, Decode, Serialize, Deserialize,
+)]
 pub enum LimitStatus {
     #[default]
     Untouched,
@@ -355,14 +396,18 @@ pub enum LimitStatus {
     FullyFilled,
 }
 
-#[derive(Eq, PartialEq, EnumIter, Debug, Clone, Copy, Default, Encode, Decode)]
+#[derive(
+    Eq, PartialEq, EnumIter, Debug, Clone, Copy, Default, Encode, Decode, Serialize, Deserialize,
+)]
 pub enum StopStatus {
     #[default]
     Untouched,
     Triggered,
 }
 
-#[derive(PartialEq, EnumIter, Debug, Clone, Copy, Default, Encode, Decode)]
+#[derive(
+    PartialEq, EnumIter, Debug, Clone, Copy, Default, Encode, Decode, Serialize, Deserialize,
+)]
 pub enum Quant {
     #[default]
     Q100,
@@ -388,7 +433,9 @@ impl Quant {
     }
 }
 
-#[derive(PartialEq, EnumIter, Debug, Clone, Copy, Default, Encode, Decode)]
+#[derive(
+    PartialEq, EnumIter, Debug, Clone, Copy, Default, Encode, Decode, Serialize, Deserialize,
+)]
 pub enum Order {
     #[default]
     None,
@@ -418,90 +465,41 @@ pub enum Order {
     },
 }
 impl Order {
-    pub fn get_side(&self) -> bool {
+    ///None for Order::None
+    pub fn get_side(&self) -> Option<bool> {
         match &self {
-            Order::None => panic!["None order should never have this called on it"],
-            Order::Market { buy: b, quant: _ } => return *b,
-            Order::Limit {
-                buy: b,
-                quant: _,
-                price: _,
-                limit_status: _,
-            } => return *b,
-            Order::StopLimit {
-                buy: b,
-                quant: _,
-                price: _,
-                limit_status: _,
-                stop_price: _,
-                stop_status: _,
-            } => return *b,
-            Order::StopMarket {
-                buy: b,
-                quant: _,
-                price: _,
-                stop_status: _,
-            } => return *b,
+            Order::None => None,
+            Order::Market { buy, .. }
+            | Order::Limit { buy, .. }
+            | Order::StopLimit { buy, .. }
+            | Order::StopMarket { buy, .. } => Some(*buy),
         }
     }
     pub fn get_side_str(&self) -> String {
-        let side = self.get_side();
-        if side == true {
-            return "BUY".to_string();
-        } else {
-            return "SELL".to_string();
+        match self.get_side() {
+            Some(true) => "BUY".to_string(),
+            Some(false) => "SELL".to_string(),
+            None => "".to_string(),
         }
     }
-    pub fn get_qnt(&self) -> f64 {
+    ///None for Order::None
+    pub fn get_qnt(&self) -> Option<f64> {
         match &self {
-            Order::None => panic!("None Order has no side..."),
-            Order::Market { buy: _, quant: q } => return q.get_f64(),
-            Order::Limit {
-                buy: _,
-                quant: q,
-                price: _,
-                limit_status: _,
-            } => return q.get_f64(),
-            Order::StopLimit {
-                buy: _,
-                quant: q,
-                price: _,
-                limit_status: _,
-                stop_price: _,
-                stop_status: _,
-            } => return q.get_f64(),
-            Order::StopMarket {
-                buy: _,
-                quant: q,
-                price: _,
-                stop_status: _,
-            } => return q.get_f64(),
+            Order::None => None,
+            Order::Market { quant, .. }
+            | Order::Limit { quant, .. }
+            | Order::StopLimit { quant, .. }
+            | Order::StopMarket { quant, .. } => Some(quant.get_f64()),
         }
     }
-    pub fn get_price(&self) -> &f64 {
+    ///None for Order::None, market orders have no price and return 0.0
+    pub fn get_price(&self) -> Option<f64> {
         match &self {
-            Order::None => panic!("None Order has no side..."),
-            Order::Market { buy: _, quant: _ } => &0.0,
-            Order::Limit {
-                buy: _,
-                quant: _,
-                price: p,
-                limit_status: _,
-            } => return p,
-            Order::StopLimit {
-                buy: _,
-                quant: _,
-                price: p,
-                limit_status: _,
-                stop_price: _,
-                stop_status: _,
-            } => return p,
-            Order::StopMarket {
-                buy: _,
-                quant: _, //Qua
-                price: p,
-                stop_status: _,
-            } => return p,
+            Order::None => None,
+            Order::Market { .. } => Some(0.0),
+            Order::Limit { price, .. }
+            | Order::StopLimit { price, .. }
+            | Order::StopMarket { price, .. } => Some(*price),
         }
     }
     pub fn to_str(&self) -> String {
@@ -557,33 +555,48 @@ enum OrderStatus {
 }
 */
 
+///(transaction time, asset1, asset2, order still active)
+type KlineEval = (DateTime<Utc>, f64, f64, Option<Order>);
+
 fn hist_eval_kline(
-    kline: &[(DateTime<Utc>, f64, f64, f64, f64, f64)],
+    kline: &[KlineRow],
     order: Order,
     asset1: f64,
     asset2: f64,
     eval_mode: &EvalMode,
     locked_qnt: f64,
-) -> Option<(DateTime<Utc>, f64, f64, Option<Order>)> {
+) -> Result<Option<KlineEval>> {
+    let mut order = order;
+    let mut triggered_at = None;
     for k in kline.iter() {
         let (t, o, h, l, c, _) = *k;
-        let result = eval_order_basic(h, o, c, l, asset1, asset2, order, eval_mode, locked_qnt);
+        let result = eval_order_basic(h, o, c, l, asset1, asset2, order, eval_mode, locked_qnt)?;
         match result {
-            Some((order_cond, asset1, asset2, _last_price)) => {
-                let (order_cond, order) = eval_basic_condition(order_cond, order);
+            Some((order_cond, asset1, asset2, _exec_price)) => {
+                let (order_cond, new_order) = eval_basic_condition(order_cond, order)?;
                 match order_cond {
-                    OrderCondition::Untouched => continue,
-                    OrderCondition::Filled => return Some((t, asset1, asset2, None)),
-                    _ => return Some((t, asset1, asset2, Some(order))),
+                    OrderCondition::Untouched => {
+                        //NOTE stop triggered, keep evaluating the remaining wicks as a limit
+                        order = new_order;
+                        triggered_at = Some(t);
+                        continue;
+                    }
+                    OrderCondition::Filled => return Ok(Some((t, asset1, asset2, None))),
+                    OrderCondition::StopTriggered => {
+                        return Ok(Some((t, asset1, asset2, Some(new_order))));
+                    }
                 }
             }
             None => continue,
         }
     }
-    return None;
+    match triggered_at {
+        Some(t) => Ok(Some((t, asset1, asset2, Some(order)))),
+        None => Ok(None),
+    }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct HistTrade {
     pub asset_pair: String,
     pub asset1: f64,
@@ -613,6 +626,13 @@ pub struct HistTrade {
     pub sell_points: Vec<(i64, f64)>,
 
     pub last_completed_order_price_side: Option<(f64, f64, bool)>,
+
+    ///Funding of a perpetual sorted by time, backtests charge it on the asset1 held
+    #[serde(skip)]
+    pub funding: Vec<FundingRate>,
+    ///Funding events up to this time (ms) are charged
+    #[serde(default)]
+    pub funded_to: i64,
 }
 impl Default for HistTrade {
     fn default() -> Self {
@@ -641,6 +661,8 @@ impl Default for HistTrade {
             current_intv: Intv::Min15,
             current_data_end_index: 0,
             current_index: 0,
+            funding: vec![],
+            funded_to: 0,
         }
     }
 }
@@ -651,13 +673,40 @@ impl HistTrade {
             ..Default::default()
         }
     }
+    ///Copy of the balances without the trade record, chart points and funding
+    fn without_fills(&self) -> Self {
+        Self {
+            asset_pair: self.asset_pair.clone(),
+            asset1: self.asset1,
+            asset2: self.asset2,
+            last_ch_a1: self.last_ch_a1,
+            last_ch_a2: self.last_ch_a2,
+            trades_made: self.trades_made,
+            asset1_held: self.asset1_held,
+            last_asset1: self.last_asset1,
+            last_asset2: self.last_asset2,
+            ch1: self.ch1,
+            ch2: self.ch2,
+            start_time: self.start_time,
+            trade_time: self.trade_time,
+            current_intv: self.current_intv,
+            trade_record: vec![],
+            current_data_end_index: self.current_data_end_index,
+            current_index: self.current_index,
+            buy_points: vec![],
+            sell_points: vec![],
+            last_completed_order_price_side: self.last_completed_order_price_side,
+            funding: vec![],
+            funded_to: self.funded_to,
+        }
+    }
     pub fn eval_single_order(
         &mut self,
-        trade_slice: &[(DateTime<Utc>, f64, f64, f64, f64, f64)],
+        trade_slice: &[KlineRow],
         o: Order,
         eval_mode: &EvalMode,
         locked_qnt: f64,
-    ) -> Option<Order> {
+    ) -> Result<Option<Order>> {
         let result = hist_eval_kline(
             trade_slice,
             o,
@@ -665,7 +714,7 @@ impl HistTrade {
             self.asset2,
             eval_mode,
             locked_qnt,
-        );
+        )?;
         match result {
             Some((transaction_time, asset1, asset2, order)) => {
                 tracing::trace![
@@ -675,74 +724,77 @@ impl HistTrade {
                     asset2,
                     order
                 ];
-                if order.is_none() {
-                    let order_price = *o.get_price();
-                    let order_side = o.get_side();
-                    if order_side {
-                        self.buy_points
-                            .push((transaction_time.timestamp_millis(), order_price));
-                        self.last_completed_order_price_side =
-                            Some((order_price, (order_price * (1.0 + M_FEE)), order_side));
-                        //NOTE For buy orders sell at this line or aboce to BEAT THE FEEES
-                    } else {
-                        self.sell_points
-                            .push((transaction_time.timestamp_millis(), order_price));
-                        self.last_completed_order_price_side =
-                            Some((order_price, (order_price * (1.0 - T_FEE)), order_side));
-                        //NOTE FEE line is simply the price at which profit can be calculated
-                    };
+                if order.is_some() {
+                    //NOTE stop triggered but not filled, nothing was traded
+                    return Ok(order);
                 };
-                self.calculate_change();
-                let tr = TradeRecord {
-                    asset_pair: self.asset_pair.clone(),
-                    transaction_time,
-                    trades_made: self.trades_made,
-                    asset1_held: self.asset1_held,
-                    asset1: self.asset1,
-                    asset2: self.asset2,
-                    last_asset1: self.last_asset1,
-                    last_asset2: self.last_asset2,
-                    ch1: self.ch1,
-                    ch2: self.ch1,
-                };
-                self.trade_record.push(tr);
-                self.asset1 = asset1;
-                self.asset2 = asset2;
-                return order;
+                self.record_fill(&o, transaction_time, asset1, asset2)?;
+                Ok(order)
             }
-            None => return Some(o),
+            None => Ok(Some(o)),
         }
     }
+    ///Books a filled order, shared by the candle and tick engines so both produce the same
+    ///trade record
+    fn record_fill(
+        &mut self,
+        o: &Order,
+        transaction_time: DateTime<Utc>,
+        asset1: f64,
+        asset2: f64,
+    ) -> Result<()> {
+        let (Some(order_price), Some(order_side)) = (o.get_price(), o.get_side()) else {
+            return Err(anyhow!["record_fill: Order::None can't be filled"]);
+        };
+        if order_side {
+            self.buy_points
+                .push((transaction_time.timestamp_millis(), order_price));
+            self.last_completed_order_price_side =
+                Some((order_price, (order_price * (1.0 + M_FEE)), order_side));
+            //NOTE For buy orders sell at this line or aboce to BEAT THE FEEES
+        } else {
+            self.sell_points
+                .push((transaction_time.timestamp_millis(), order_price));
+            self.last_completed_order_price_side =
+                Some((order_price, (order_price * (1.0 - T_FEE)), order_side));
+            //NOTE FEE line is simply the price at which profit can be calculated
+        };
+        self.calculate_change();
+        let tr = TradeRecord {
+            asset_pair: self.asset_pair.clone(),
+            transaction_time,
+            trades_made: self.trades_made,
+            asset1_held: self.asset1_held,
+            asset1: self.asset1,
+            asset2: self.asset2,
+            last_asset1: self.last_asset1,
+            last_asset2: self.last_asset2,
+            ch1: self.ch1,
+            ch2: self.ch1,
+        };
+        self.trade_record.push(tr);
+        self.asset1 = asset1;
+        self.asset2 = asset2;
+        Ok(())
+    }
+    ///Orders that fail to evaluate are logged and dropped
     pub fn trade_forward(
         &mut self,
-        trade_slice: &[(DateTime<Utc>, f64, f64, f64, f64, f64)],
+        trade_slice: &[KlineRow],
         eval_mode: &EvalMode,
         active_orders: Vec<(u64, Order, f64)>,
     ) -> Vec<(u64, Order, f64)> {
         tracing::trace!["Active orders{:?}", active_orders];
-        match active_orders.len() {
-            0 => return vec![],
-            1 => {
-                let (id, o, locked_qnt) = active_orders[0];
-                let result = self.eval_single_order(trade_slice, o, eval_mode, locked_qnt);
-                match result {
-                    Some(o) => return vec![(id, o, locked_qnt)],
-                    None => return vec![],
-                }
-            }
-            _ => {
-                let mut remaining_active_orders = vec![];
-                for (id, order, locked_qnt) in active_orders.iter() {
-                    let result =
-                        self.eval_single_order(trade_slice, *order, eval_mode, *locked_qnt);
-                    match result {
-                        Some(o) => remaining_active_orders.push((*id, o, *locked_qnt)),
-                        None => (),
-                    }
-                }
-                remaining_active_orders
+        let mut remaining_active_orders = vec![];
+        for (id, order, locked_qnt) in active_orders.iter() {
+            let result = self.eval_single_order(trade_slice, *order, eval_mode, *locked_qnt);
+            match result {
+                Ok(Some(o)) => remaining_active_orders.push((*id, o, *locked_qnt)),
+                Ok(None) => (),
+                Err(e) => tracing::error!["Order {} {:?} dropped: {}", id, order, e],
             }
         }
+        remaining_active_orders
     }
     pub fn calculate_change(&mut self) {
         self.trades_made += 1;
@@ -771,6 +823,378 @@ impl HistTrade {
             }
         }
     }
+    ///Runs a whole backtest wick by wick, orders are placed once their place_time is reached and
+    ///lock the free balance * quant just like a manually placed hist order.
+    pub fn backtest(
+        &mut self,
+        kline: &[KlineRow],
+        eval_mode: &EvalMode,
+        orders: &[BacktestOrder],
+    ) -> BacktestReport {
+        let mut pending: Vec<BacktestOrder> = orders.to_vec();
+        pending.sort_by_key(|o| o.place_time);
+        let mut pending = pending.into_iter().peekable();
+        let mut active_orders: Vec<(u64, Order, f64)> = vec![];
+        let mut next_id: u64 = 0;
+        let mut rejected_orders: usize = 0;
+        let mut funding_paid = 0.0;
+
+        for (i, k) in kline.iter().enumerate() {
+            let t = k.0.timestamp_millis();
+            funding_paid += self.charge_funding(t, k.1);
+            while let Some(bo) = pending.next_if(|o| o.place_time <= t) {
+                let locked = active_orders.iter().map(|(_, o, l)| (o, *l));
+                match self.backtest_lock(&bo, locked) {
+                    Some(locked_qnt) => {
+                        active_orders.push((next_id, bo.order, locked_qnt));
+                        next_id += 1;
+                    }
+                    None => rejected_orders += 1,
+                };
+            }
+            active_orders = self.trade_forward(&kline[i..i + 1], eval_mode, active_orders);
+        }
+        let last_price = match kline.last() {
+            Some(k) => k.4,
+            None => 0.0,
+        };
+        BacktestReport {
+            asset_pair: self.asset_pair.clone(),
+            wicks: kline.len(),
+            asset1: self.asset1,
+            asset2: self.asset2,
+            last_price,
+            equity: self.asset2 + self.asset1 * last_price,
+            trades_made: self.trades_made,
+            open_orders: active_orders.iter().map(|(_, o, _)| *o).collect(),
+            unplaced_orders: pending.count(),
+            rejected_orders,
+            funding_paid,
+            trade_record: self.trade_record.clone(),
+        }
+    }
+    ///Pays the funding events after funded_to up to time t on the asset1 held out of asset2,
+    ///price is used for events without a mark price. Returns the amount paid, negative if
+    ///received.
+    fn charge_funding(&mut self, t: i64, price: f64) -> f64 {
+        let next = self.funding.partition_point(|f| f.time <= self.funded_to);
+        let paid = self.funding[next..]
+            .iter()
+            .take_while(|f| f.time <= t)
+            .map(|f| self.asset1 * f.mark_price.unwrap_or(price) * f.rate)
+            .sum::<f64>();
+        self.asset2 -= paid;
+        self.funded_to = self.funded_to.max(t);
+        paid
+    }
+    ///Charges the funding of a hist trade slice candle by candle before its orders are
+    ///evaluated, events skipped over by a jump forward are charged on the first candle. A new
+    ///session (funded_to 0) or a slice before funded_to (going back in time) starts at the first
+    ///candle.
+    pub fn forward_funding(&mut self, trade_slice: &[KlineRow]) -> f64 {
+        let (Some(first), Some(last)) = (trade_slice.first(), trade_slice.last()) else {
+            return 0.0;
+        };
+        let (first, last) = (first.0.timestamp_millis(), last.0.timestamp_millis());
+        if self.funded_to == 0 || self.funded_to > last {
+            self.funded_to = first - 1;
+        };
+        trade_slice
+            .iter()
+            .map(|k| self.charge_funding(k.0.timestamp_millis(), k.1))
+            .sum()
+    }
+    ///Balance a new backtest order locks, the free balance of its side * quant. None if it
+    ///can't be placed.
+    fn backtest_lock<'a>(
+        &self,
+        bo: &BacktestOrder,
+        active: impl Iterator<Item = (&'a Order, f64)>,
+    ) -> Option<f64> {
+        let (Some(side), Some(quant)) = (bo.order.get_side(), bo.order.get_qnt()) else {
+            tracing::error!["Backtest: Order::None can't be placed"];
+            return None;
+        };
+        let locked: f64 = active
+            .filter(|(o, _)| o.get_side() == Some(side))
+            .map(|(_, locked_qnt)| locked_qnt)
+            .sum();
+        let free = if side {
+            self.asset2 - locked
+        } else {
+            self.asset1 - locked
+        };
+        let locked_qnt = free * quant;
+        if locked_qnt <= 0.0 {
+            tracing::error!["Backtest: insufficient free balance for {:?}", bo];
+            return None;
+        };
+        Some(locked_qnt)
+    }
+    ///Runs a backtest trade by trade, orders fill against single aggTrades in time order instead
+    ///of candles. Limits resting at a trade's price fill according to queue, wicks in the
+    ///report is the number of trades.
+    pub fn backtest_ticks(
+        &mut self,
+        ticks: &[AggTick],
+        queue: &QueueModel,
+        orders: &[BacktestOrder],
+    ) -> BacktestReport {
+        let mut pending: Vec<BacktestOrder> = orders.to_vec();
+        pending.sort_by_key(|o| o.place_time);
+        let mut pending = pending.into_iter().peekable();
+        let mut active_orders: Vec<TickOrder> = vec![];
+        let mut next_id: u64 = 0;
+        let mut rejected_orders: usize = 0;
+        let mut funding_paid = 0.0;
+
+        for tick in ticks.iter() {
+            funding_paid += self.charge_funding(tick.time, tick.price);
+            while let Some(bo) = pending.next_if(|o| o.place_time <= tick.time) {
+                let locked = active_orders.iter().map(|o| (&o.order, o.locked_qnt));
+                match self.backtest_lock(&bo, locked) {
+                    Some(locked_qnt) => {
+                        active_orders.push(TickOrder::new(next_id, bo.order, locked_qnt, queue));
+                        next_id += 1;
+                    }
+                    None => rejected_orders += 1,
+                };
+            }
+            let time = DateTime::<Utc>::from_timestamp_millis(tick.time).unwrap_or_default();
+            let mut remaining = vec![];
+            for mut o in active_orders.drain(..) {
+                //NOTE booked as the order was before this trade like on a candle, a stop limit
+                //triggered and filled by the same trade is still a stop limit
+                let order = o.order;
+                match eval_tick(&mut o, tick.price, tick.qty, queue) {
+                    Ok(Some((price, fee))) => {
+                        //NOTE eval_tick already rejects Order::None
+                        let (Some(quant), Some(side)) = (order.get_qnt(), order.get_side()) else {
+                            continue;
+                        };
+                        let (asset1, asset2) = exec_order(
+                            self.asset1,
+                            self.asset2,
+                            price,
+                            quant,
+                            side,
+                            fee,
+                            o.locked_qnt,
+                        );
+                        if let Err(e) = self.record_fill(&order, time, asset1, asset2) {
+                            tracing::error!["Order {} {:?} dropped: {}", o.id, order, e];
+                        };
+                    }
+                    Ok(None) => remaining.push(o),
+                    Err(e) => tracing::error!["Order {} {:?} dropped: {}", o.id, order, e],
+                }
+            }
+            active_orders = remaining;
+        }
+        let last_price = match ticks.last() {
+            Some(t) => t.price,
+            None => 0.0,
+        };
+        BacktestReport {
+            asset_pair: self.asset_pair.clone(),
+            wicks: ticks.len(),
+            asset1: self.asset1,
+            asset2: self.asset2,
+            last_price,
+            equity: self.asset2 + self.asset1 * last_price,
+            trades_made: self.trades_made,
+            open_orders: active_orders.iter().map(|o| o.order).collect(),
+            unplaced_orders: pending.count(),
+            rejected_orders,
+            funding_paid,
+            trade_record: self.trade_record.clone(),
+        }
+    }
+}
+
+///Where a resting limit is assumed to be in the queue at its price in tick backtests
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum QueueModel {
+    ///The first trade at the limit price fills it, the order is first in the queue
+    Touch,
+    ///Only a trade through the limit price fills it, the order is last in the queue
+    #[default]
+    TradeThrough,
+    ///ahead (asset1 qty) has to trade at the limit price before the order fills, a trade through
+    ///the price fills it at once
+    Queue { ahead: f64 },
+}
+impl QueueModel {
+    fn ahead(&self) -> f64 {
+        match self {
+            QueueModel::Queue { ahead } => *ahead,
+            _ => 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TickOrder {
+    id: u64,
+    order: Order,
+    locked_qnt: f64,
+    ///Qty still to trade at the limit price before the limit fills
+    ahead: f64,
+    ///Side of the stop the first trade after placing was on
+    above_stop: Option<bool>,
+    ///No trade was evaluated against it yet, a marketable limit takes the trade price
+    fresh: bool,
+}
+impl TickOrder {
+    fn new(id: u64, order: Order, locked_qnt: f64, queue: &QueueModel) -> Self {
+        Self {
+            id,
+            order,
+            locked_qnt,
+            ahead: queue.ahead(),
+            above_stop: None,
+            fresh: true,
+        }
+    }
+}
+
+///A stop triggers on the first trade at or past it coming from the side the first trade was on
+fn tick_stop_hit(above_stop: &mut Option<bool>, stop: f64, p: f64) -> bool {
+    match *above_stop {
+        None if p == stop => true,
+        None => {
+            *above_stop = Some(p > stop);
+            false
+        }
+        Some(true) => p <= stop,
+        Some(false) => p >= stop,
+    }
+}
+
+fn tick_limit_fill(
+    buy: bool,
+    limit: f64,
+    p: f64,
+    qty: f64,
+    fresh: bool,
+    ahead: &mut f64,
+    queue: &QueueModel,
+) -> Option<f64> {
+    let (marketable, through) = match buy {
+        true => (p <= limit, p < limit),
+        false => (p >= limit, p > limit),
+    };
+    if fresh && marketable {
+        return Some(p);
+    };
+    if through {
+        return Some(limit);
+    };
+    if p != limit {
+        return None;
+    };
+    match queue {
+        QueueModel::Touch => Some(limit),
+        QueueModel::TradeThrough => None,
+        QueueModel::Queue { ahead: _ } => {
+            *ahead -= qty;
+            (*ahead < 0.0).then_some(limit)
+        }
+    }
+}
+
+///Evaluates a resting order against a single trade. Returns the fill price and fee if it filled,
+///a triggered stop limit is turned into its limit.
+fn eval_tick(
+    o: &mut TickOrder,
+    p: f64,
+    qty: f64,
+    queue: &QueueModel,
+) -> Result<Option<(f64, f64)>> {
+    let fresh = std::mem::replace(&mut o.fresh, false);
+    match o.order {
+        Order::Market { buy: _, quant: _ } => Ok(Some((p, T_FEE))),
+        Order::Limit {
+            buy: b,
+            quant: _,
+            price: limit,
+            limit_status: _,
+        } => Ok(tick_limit_fill(b, limit, p, qty, fresh, &mut o.ahead, queue).map(|p| (p, M_FEE))),
+        Order::StopMarket {
+            buy: _,
+            quant: _,
+            price: stop,
+            stop_status: _,
+        } => Ok(tick_stop_hit(&mut o.above_stop, stop, p).then_some((p, M_FEE))),
+        Order::StopLimit {
+            buy: b,
+            quant: _,
+            price: limit,
+            limit_status: _,
+            stop_price: sp,
+            stop_status: _,
+        } => {
+            let stop = limit * (sp as f64);
+            if !tick_stop_hit(&mut o.above_stop, stop, p) {
+                return Ok(None);
+            };
+            let (_, limit_order) = eval_basic_condition(OrderCondition::StopTriggered, o.order)?;
+            o.order = limit_order;
+            o.ahead = queue.ahead();
+            //NOTE the limit sees the trigger trade first, like the stop fill on a candle
+            Ok(tick_limit_fill(b, limit, p, qty, true, &mut o.ahead, queue).map(|p| (p, M_FEE)))
+        }
+        Order::None => Err(anyhow!["eval_tick: Order::None can't be evaluated"]),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct BacktestOrder {
+    pub place_time: i64,
+    pub order: Order,
+}
+
+fn default_asset2() -> f64 {
+    10_000.0
+}
+
+///Headless backtest definition, read from a JSON file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BacktestConfig {
+    pub symbol: String,
+    pub intv: String,
+    pub start_time: i64,
+    pub end_time: i64,
+    #[serde(default)]
+    pub asset1: f64,
+    #[serde(default = "default_asset2")]
+    pub asset2: f64,
+    #[serde(default)]
+    pub eval_mode: EvalMode,
+    ///Backtest on the recorded/imported aggTrades of symbol instead of intv candles
+    #[serde(default)]
+    pub ticks: bool,
+    #[serde(default)]
+    pub queue: QueueModel,
+    pub orders: Vec<BacktestOrder>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BacktestReport {
+    pub asset_pair: String,
+    pub wicks: usize,
+    pub asset1: f64,
+    pub asset2: f64,
+    pub last_price: f64,
+    pub equity: f64,
+    pub trades_made: i32,
+    pub open_orders: Vec<Order>,
+    pub unplaced_orders: usize,
+    pub rejected_orders: usize,
+    ///Funding paid on perpetuals, negative if received
+    #[serde(default)]
+    pub funding_paid: f64,
+    pub trade_record: Vec<TradeRecord>,
 }
 
 #[derive(PartialEq, Debug, Clone, Serialize, Deserialize, Default)]
@@ -795,3 +1219,1165 @@ impl TradeRecord {
         }
     }
 }
+
+///Lengths of the trade record, buy points and sell points
+pub type FillLens = (usize, usize, usize);
+
+///Trade record and chart points of a HistTrade. They grow with every fill, so the journal only
+///stores the ones added by each step.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HistFills {
+    pub trade_record: Vec<TradeRecord>,
+    pub buy_points: Vec<(i64, f64)>,
+    pub sell_points: Vec<(i64, f64)>,
+}
+impl HistFills {
+    pub fn lens_of(hist_trade: &HistTrade) -> FillLens {
+        (
+            hist_trade.trade_record.len(),
+            hist_trade.buy_points.len(),
+            hist_trade.sell_points.len(),
+        )
+    }
+    fn lens(&self) -> FillLens {
+        (
+            self.trade_record.len(),
+            self.buy_points.len(),
+            self.sell_points.len(),
+        )
+    }
+    ///Fills of hist_trade past lens
+    fn since(hist_trade: &HistTrade, lens: FillLens) -> Self {
+        let (tr, bp, sp) = lens;
+        Self {
+            trade_record: hist_trade
+                .trade_record
+                .get(tr..)
+                .unwrap_or_default()
+                .to_vec(),
+            buy_points: hist_trade.buy_points.get(bp..).unwrap_or_default().to_vec(),
+            sell_points: hist_trade
+                .sell_points
+                .get(sp..)
+                .unwrap_or_default()
+                .to_vec(),
+        }
+    }
+    ///Applies a journal step, cuts back to the state the step started from and adds its fills
+    fn apply(&mut self, after: FillLens, added: &HistFills) {
+        let (tr, bp, sp) = added.lens();
+        self.trade_record.truncate(after.0.saturating_sub(tr));
+        self.buy_points.truncate(after.1.saturating_sub(bp));
+        self.sell_points.truncate(after.2.saturating_sub(sp));
+        self.trade_record.extend_from_slice(&added.trade_record);
+        self.buy_points.extend_from_slice(&added.buy_points);
+        self.sell_points.extend_from_slice(&added.sell_points);
+    }
+}
+
+///Hist state that the journal snapshots after every step. slice_time is the end of the last
+///evaluated trade slice, trade_time is where the chart is positioned. hist_trade has no trade
+///record or chart points, the journal keeps those per step in JournalEntry::fills.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistSnapshot {
+    pub trade_time: i64,
+    pub slice_time: i64,
+    pub intv: Intv,
+    pub hist_trade: HistTrade,
+    pub fills: FillLens,
+    pub orders: BTreeMap<u64, (Order, bool, f64)>,
+}
+impl HistSnapshot {
+    pub fn new(
+        trade_time: i64,
+        slice_time: i64,
+        intv: Intv,
+        hist_trade: &HistTrade,
+        orders: BTreeMap<u64, (Order, bool, f64)>,
+    ) -> Self {
+        Self {
+            trade_time,
+            slice_time,
+            intv,
+            hist_trade: hist_trade.without_fills(),
+            fills: HistFills::lens_of(hist_trade),
+            orders,
+        }
+    }
+    //NOTE trade_time alone changes before the new slice is loaded, only record once evaluated
+    fn same_state(&self, other: &HistSnapshot) -> bool {
+        self.slice_time == other.slice_time
+            && self.intv == other.intv
+            && self.orders == other.orders
+            && self.fills == other.fills
+            && self.hist_trade == other.hist_trade
+    }
+    ///Cheap check whether the hist state is still this snapshot, without building a new one
+    pub fn is_state_of(
+        &self,
+        slice_time: i64,
+        intv: Intv,
+        hist_trade: &HistTrade,
+        orders: &HashMap<u64, (Order, bool, f64)>,
+    ) -> bool {
+        self.slice_time == slice_time
+            && self.intv == intv
+            && self.fills == HistFills::lens_of(hist_trade)
+            && self.hist_trade.asset1 == hist_trade.asset1
+            && self.hist_trade.asset2 == hist_trade.asset2
+            && self.orders.len() == orders.len()
+            && orders.iter().all(|(id, o)| self.orders.get(id) == Some(o))
+    }
+    ///HistTrade of this state with the fills the journal rebuilt for it
+    pub fn to_hist_trade(&self, fills: HistFills) -> HistTrade {
+        HistTrade {
+            trade_record: fills.trade_record,
+            buy_points: fills.buy_points,
+            sell_points: fills.sell_points,
+            ..self.hist_trade.without_fills()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum HistAction {
+    OrderPlaced {
+        id: u64,
+        order: Order,
+        active: bool,
+    },
+    OrderModified {
+        id: u64,
+        order: Order,
+        active: bool,
+    },
+    OrderCancelled {
+        id: u64,
+    },
+    TradeForward {
+        from: i64,
+        to: i64,
+        filled: Vec<u64>,
+    },
+    IntvChanged {
+        from: Intv,
+        to: Intv,
+    },
+    Undo {
+        step: usize,
+    },
+    Redo {
+        step: usize,
+    },
+}
+impl HistAction {
+    pub fn to_str(&self) -> String {
+        match self {
+            HistAction::OrderPlaced { id, order, .. } => {
+                format!["Placed {} {} #{}", order.to_str(), order.get_side_str(), id]
+            }
+            HistAction::OrderModified { id, order, .. } => {
+                format![
+                    "Modified {} {} #{}",
+                    order.to_str(),
+                    order.get_side_str(),
+                    id
+                ]
+            }
+            HistAction::OrderCancelled { id } => format!["Cancelled #{}", id],
+            HistAction::TradeForward { from, to, filled } => {
+                let fmt_t = |t: &i64| match DateTime::<Utc>::from_timestamp_millis(*t) {
+                    Some(t) => t.format("%Y-%m-%d %H:%M").to_string(),
+                    None => format!["{}", t],
+                };
+                format!["Trade {} -> {} filled {:?}", fmt_t(from), fmt_t(to), filled]
+            }
+            HistAction::IntvChanged { from, to } => {
+                format!["Interval {} -> {}", from.label(), to.label()]
+            }
+            HistAction::Undo { step } => format!["Undo step {}", step],
+            HistAction::Redo { step } => format!["Redo step {}", step],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub step: usize,
+    pub time: i64,
+    pub actions: Vec<HistAction>,
+    pub after: HistSnapshot,
+    ///Fills added by this step, a redo repeats the fills of the redone step
+    pub fills: HistFills,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct JournalHeader {
+    symbol: String,
+    initial: HistSnapshot,
+    initial_fills: HistFills,
+}
+
+///Append-only record of every hist action. Undo and redo are journal entries themselves so a
+///session can be replayed exactly as it was traded.
+#[derive(Debug, Clone, Default)]
+pub struct HistJournal {
+    pub symbol: String,
+    pub initial: Option<HistSnapshot>,
+    pub initial_fills: HistFills,
+    pub entries: Vec<JournalEntry>,
+    applied: Vec<usize>,
+    undone: Vec<usize>,
+    path: Option<PathBuf>,
+}
+
+impl HistJournal {
+    pub fn new(symbol: &str, path: Option<PathBuf>) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            path,
+            ..Default::default()
+        }
+    }
+    pub fn current(&self) -> Option<&HistSnapshot> {
+        match self.applied.last() {
+            Some(i) => Some(&self.entries[*i].after),
+            None => self.initial.as_ref(),
+        }
+    }
+    pub fn can_undo(&self) -> bool {
+        !self.applied.is_empty()
+    }
+    pub fn can_redo(&self) -> bool {
+        !self.undone.is_empty()
+    }
+    pub fn path(&self) -> Option<&PathBuf> {
+        self.path.as_ref()
+    }
+    ///Orders that disappeared are fills if a buy/sell point at their side and price was added in
+    ///the same step, otherwise they were cancelled
+    fn diff(before: &HistSnapshot, after: &HistSnapshot, fills: &HistFills) -> Vec<HistAction> {
+        let mut actions = vec![];
+        if before.intv != after.intv {
+            actions.push(HistAction::IntvChanged {
+                from: before.intv,
+                to: after.intv,
+            });
+        };
+        let mut buy_points = fills.buy_points.clone();
+        let mut sell_points = fills.sell_points.clone();
+        let mut filled = vec![];
+        let mut cancelled = vec![];
+        for (id, (order, _, _)) in before.orders.iter() {
+            if after.orders.contains_key(id) {
+                continue;
+            };
+            let points = match order.get_side() {
+                Some(true) => &mut buy_points,
+                Some(false) => &mut sell_points,
+                None => {
+                    cancelled.push(*id);
+                    continue;
+                }
+            };
+            let price = order.get_price().unwrap_or_default();
+            match points.iter().position(|(_, p)| *p == price) {
+                Some(i) => {
+                    points.remove(i);
+                    filled.push(*id);
+                }
+                None => cancelled.push(*id),
+            };
+        }
+        if before.slice_time != after.slice_time || !filled.is_empty() {
+            actions.push(HistAction::TradeForward {
+                from: before.slice_time,
+                to: after.slice_time,
+                filled,
+            });
+        };
+        for (id, (order, active, _)) in after.orders.iter() {
+            match before.orders.get(id) {
+                None => actions.push(HistAction::OrderPlaced {
+                    id: *id,
+                    order: *order,
+                    active: *active,
+                }),
+                Some((o, a, _)) if o != order || a != active => {
+                    actions.push(HistAction::OrderModified {
+                        id: *id,
+                        order: *order,
+                        active: *active,
+                    })
+                }
+                Some(_) => (),
+            }
+        }
+        for id in cancelled {
+            actions.push(HistAction::OrderCancelled { id });
+        }
+        actions
+    }
+    fn append(
+        &mut self,
+        actions: Vec<HistAction>,
+        after: HistSnapshot,
+        fills: HistFills,
+    ) -> Result<usize> {
+        let time = Utc::now().timestamp_millis();
+        let entry = JournalEntry {
+            step: self.entries.len(),
+            time,
+            actions,
+            after,
+            fills,
+        };
+        if let Some(path) = &self.path {
+            let mut f = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?;
+            writeln![f, "{}", serde_json::to_string(&entry)?]?;
+        };
+        self.entries.push(entry);
+        Ok(self.entries.len() - 1)
+    }
+    ///Compares the current hist state with the last journaled one and records the difference,
+    ///hist_trade is the state the snapshot was taken of. Returns true if a new step was recorded.
+    pub fn observe(&mut self, snapshot: HistSnapshot, hist_trade: &HistTrade) -> Result<bool> {
+        let current = match self.current() {
+            Some(c) => c,
+            None => {
+                let initial_fills = HistFills::since(hist_trade, (0, 0, 0));
+                if let Some(path) = &self.path {
+                    let header = JournalHeader {
+                        symbol: self.symbol.clone(),
+                        initial: snapshot.clone(),
+                        initial_fills: initial_fills.clone(),
+                    };
+                    std::fs::write(path, format!["{}\n", serde_json::to_string(&header)?])?;
+                };
+                self.initial = Some(snapshot);
+                self.initial_fills = initial_fills;
+                return Ok(false);
+            }
+        };
+        if current.same_state(&snapshot) {
+            return Ok(false);
+        };
+        let fills = HistFills::since(hist_trade, current.fills);
+        let actions = HistJournal::diff(current, &snapshot, &fills);
+        let i = self.append(actions, snapshot, fills)?;
+        self.applied.push(i);
+        self.undone.clear();
+        Ok(true)
+    }
+    ///Returns the state to restore, None if there is nothing to undo
+    pub fn undo(&mut self) -> Result<Option<HistSnapshot>> {
+        let Some(i) = self.applied.pop() else {
+            return Ok(None);
+        };
+        self.undone.push(i);
+        let after = match self.current() {
+            Some(s) => s.clone(),
+            None => return Err(anyhow!["Journal has no initial state"]),
+        };
+        let step = self.entries[i].step;
+        self.append(
+            vec![HistAction::Undo { step }],
+            after.clone(),
+            HistFills::default(),
+        )?;
+        Ok(Some(after))
+    }
+    pub fn redo(&mut self) -> Result<Option<HistSnapshot>> {
+        let Some(i) = self.undone.pop() else {
+            return Ok(None);
+        };
+        self.applied.push(i);
+        let after = self.entries[i].after.clone();
+        let step = self.entries[i].step;
+        let fills = self.entries[i].fills.clone();
+        self.append(vec![HistAction::Redo { step }], after.clone(), fills)?;
+        Ok(Some(after))
+    }
+    ///Trade record and chart points after the first n entries, 0 is the initial state
+    pub fn fills_at(&self, n: usize) -> HistFills {
+        let mut fills = self.initial_fills.clone();
+        for entry in self.entries.iter().take(n) {
+            fills.apply(entry.after.fills, &entry.fills);
+        }
+        fills
+    }
+    ///HistTrade after the first n entries, 0 is the initial state
+    pub fn hist_trade_at(&self, n: usize) -> Option<HistTrade> {
+        let snapshot = match n {
+            0 => self.initial.as_ref()?,
+            n => &self.entries.get(n - 1)?.after,
+        };
+        Some(snapshot.to_hist_trade(self.fills_at(n)))
+    }
+    ///Replays the journal from the initial state, returns the state after every entry
+    pub fn replay(&self) -> Result<Vec<HistSnapshot>> {
+        let Some(initial) = &self.initial else {
+            return Ok(vec![]);
+        };
+        let mut applied: Vec<usize> = vec![];
+        let mut undone: Vec<usize> = vec![];
+        let mut orders = initial.orders.clone();
+        let mut intv = initial.intv;
+        let mut states = vec![];
+        for (i, entry) in self.entries.iter().enumerate() {
+            let mut restored = None;
+            for action in entry.actions.iter() {
+                match action {
+                    HistAction::OrderPlaced { id, order, active }
+                    | HistAction::OrderModified { id, order, active } => {
+                        let locked_qnt = match entry.after.orders.get(id) {
+                            Some((_, _, l)) => *l,
+                            None => return Err(anyhow!["Step {}: order {} missing", i, id]),
+                        };
+                        orders.insert(*id, (*order, *active, locked_qnt));
+                    }
+                    HistAction::OrderCancelled { id } => {
+                        orders.remove(id);
+                    }
+                    HistAction::IntvChanged { from, to } => {
+                        if *from != intv {
+                            return Err(anyhow!["Step {}: interval mismatch", i]);
+                        };
+                        intv = *to;
+                    }
+                    //NOTE fills need the kline data, the evaluated orders are taken from the
+                    //snapshot
+                    HistAction::TradeForward { .. } => orders = entry.after.orders.clone(),
+                    HistAction::Undo { step } => {
+                        if applied.pop() != Some(*step) {
+                            return Err(anyhow!["Step {}: undo of {} out of order", i, step]);
+                        };
+                        undone.push(*step);
+                        restored = Some(match applied.last() {
+                            Some(j) => &self.entries[*j].after,
+                            None => initial,
+                        });
+                    }
+                    HistAction::Redo { step } => {
+                        if undone.pop() != Some(*step) {
+                            return Err(anyhow!["Step {}: redo of {} out of order", i, step]);
+                        };
+                        applied.push(*step);
+                        restored = Some(&self.entries[*step].after);
+                    }
+                }
+            }
+            match restored {
+                Some(s) => {
+                    orders = s.orders.clone();
+                    intv = s.intv;
+                }
+                None => {
+                    applied.push(i);
+                    undone.clear();
+                }
+            };
+            if orders != entry.after.orders || intv != entry.after.intv {
+                return Err(anyhow![
+                    "Step {}: replayed state doesn't match the journal",
+                    i
+                ]);
+            };
+            states.push(entry.after.clone());
+        }
+        Ok(states)
+    }
+    pub fn load_from_file(path: &Path) -> Result<Self> {
+        let file = std::fs::read_to_string(path)?;
+        let mut lines = file.lines().filter(|l| !l.trim().is_empty());
+        let header: JournalHeader = match lines.next() {
+            Some(l) => serde_json::from_str(l)?,
+            None => return Err(anyhow!["Empty journal file {}", path.display()]),
+        };
+        let mut journal = HistJournal::new(&header.symbol, Some(path.to_path_buf()));
+        journal.initial = Some(header.initial);
+        journal.initial_fills = header.initial_fills;
+        for l in lines {
+            journal.entries.push(serde_json::from_str(l)?);
+        }
+        //NOTE rebuild the undo/redo stacks so a loaded session can be continued
+        for (i, entry) in journal.entries.iter().enumerate() {
+            match entry.actions.first() {
+                Some(HistAction::Undo { step }) => {
+                    journal.applied.pop();
+                    journal.undone.push(*step);
+                }
+                Some(HistAction::Redo { step }) => {
+                    journal.undone.pop();
+                    journal.applied.push(*step);
+                }
+                _ => {
+                    journal.applied.push(i);
+                    journal.undone.clear();
+                }
+            }
+        }
+        journal.replay()?;
+        Ok(journal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::path::PathBuf;
+
+    const EPS: f64 = 1e-9;
+
+    fn candle() -> impl Strategy<Value = (f64, f64, f64, f64)> {
+        (1.0..1000.0f64, 1.0..1000.0f64, 0.0..50.0f64, 0.0..50.0f64).prop_map(|(o, c, up, down)| {
+            let h = o.max(c) + up;
+            let l = (o.min(c) - down).max(0.01);
+            (o, h, l, c)
+        })
+    }
+
+    fn quant() -> impl Strategy<Value = Quant> {
+        prop_oneof![
+            Just(Quant::Q100),
+            Just(Quant::Q75),
+            Just(Quant::Q50),
+            Just(Quant::Q25),
+            (0.01..1.0f64).prop_map(Quant::from_f64),
+        ]
+    }
+
+    fn order() -> impl Strategy<Value = Order> {
+        (0..4u8, any::<bool>(), quant(), 0.5..1100.0f64, 0.8..1.2f32).prop_map(
+            |(kind, buy, quant, price, sp)| match kind {
+                0 => Order::Market { buy, quant },
+                1 => Order::Limit {
+                    buy,
+                    quant,
+                    price,
+                    limit_status: LimitStatus::Untouched,
+                },
+                2 => Order::StopLimit {
+                    buy,
+                    quant,
+                    price,
+                    limit_status: LimitStatus::Untouched,
+                    stop_price: sp,
+                    stop_status: StopStatus::Untouched,
+                },
+                _ => Order::StopMarket {
+                    buy,
+                    quant,
+                    price,
+                    stop_status: StopStatus::Untouched,
+                },
+            },
+        )
+    }
+
+    fn eval_mode() -> impl Strategy<Value = EvalMode> {
+        prop_oneof![
+            Just(EvalMode::LHTriggerLimit),
+            Just(EvalMode::OCTriggerLimit)
+        ]
+    }
+
+    fn locked(order: &Order, asset1: f64, asset2: f64) -> f64 {
+        if order.get_side().unwrap() {
+            asset2 * order.get_qnt().unwrap()
+        } else {
+            asset1 * order.get_qnt().unwrap()
+        }
+    }
+
+    fn stop_level(order: &Order) -> Option<f64> {
+        match order {
+            Order::StopLimit {
+                price, stop_price, ..
+            } => Some(price * (*stop_price as f64)),
+            Order::StopMarket { price, .. } => Some(*price),
+            _ => None,
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn fills_conserve_value_minus_fees(
+            (o, h, l, c) in candle(),
+            order in order(),
+            mode in eval_mode(),
+            asset1 in 0.0..10.0f64,
+            asset2 in 0.0..10_000.0f64,
+        ) {
+            let locked_qnt = locked(&order, asset1, asset2);
+            let res = eval_order_basic(h, o, c, l, asset1, asset2, order, &mode, locked_qnt).unwrap();
+            if let Some((OrderCondition::Filled, a1, a2, p)) = res {
+                let fee = if let Order::Market { .. } = order { T_FEE } else { M_FEE };
+                let locked_value = if order.get_side().unwrap() { locked_qnt } else { locked_qnt * p };
+                let before = asset1 * p + asset2;
+                let after = a1 * p + a2;
+                prop_assert!((before - locked_value * fee - after).abs() <= EPS * before.max(1.0));
+                prop_assert!(a1 >= -EPS && a2 >= -EPS);
+            }
+        }
+
+        #[test]
+        fn no_fills_outside_candle_range(
+            (o, h, l, c) in candle(),
+            order in order(),
+            mode in eval_mode(),
+        ) {
+            let locked_qnt = locked(&order, 1.0, 1000.0);
+            let res = eval_order_basic(h, o, c, l, 1.0, 1000.0, order, &mode, locked_qnt).unwrap();
+            if let Some((_, _, _, p)) = res {
+                prop_assert!(l <= p && p <= h, "price {} outside {}..{}", p, l, h);
+            }
+        }
+
+        #[test]
+        fn stops_trigger_before_limits(
+            (o, h, l, c) in candle(),
+            order in order(),
+            mode in eval_mode(),
+        ) {
+            let Some(stop) = stop_level(&order) else { return Ok(()) };
+            let locked_qnt = locked(&order, 1.0, 1000.0);
+            let res = eval_order_basic(h, o, c, l, 1.0, 1000.0, order, &mode, locked_qnt).unwrap();
+            match res {
+                None => prop_assert!(eval_stop(stop, h, o, c, l).is_none()),
+                Some((cond, a1, a2, _)) => {
+                    prop_assert!(l <= stop && stop <= h);
+                    if cond == OrderCondition::StopTriggered {
+                        prop_assert_eq!((a1, a2), (1.0, 1000.0));
+                        let (cond, new_order) = eval_basic_condition(cond, order).unwrap();
+                        prop_assert_eq!(cond, OrderCondition::Untouched);
+                        prop_assert!(matches!(new_order, Order::Limit { .. }), "{:?}", new_order);
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn stop_limit_never_fills_before_trigger(
+            wicks in prop::collection::vec(candle(), 1..20),
+            order in order(),
+            mode in eval_mode(),
+        ) {
+            let Some(stop) = stop_level(&order) else { return Ok(()) };
+            let kline: Vec<KlineRow> = wicks
+                .iter()
+                .enumerate()
+                .map(|(i, (o, h, l, c))| {
+                    (DateTime::from_timestamp_millis(i as i64 * 60_000).unwrap(), *o, *h, *l, *c, 1.0)
+                })
+                .collect();
+            let first_trigger = kline.iter().position(|(_, o, h, l, c, _)| eval_stop(stop, *h, *o, *c, *l).is_some());
+            let mut h_trade = HistTrade::new("TEST".to_string());
+            h_trade.asset1 = 1.0;
+            let locked_qnt = locked(&order, h_trade.asset1, h_trade.asset2);
+            let res = h_trade.eval_single_order(&kline, order, &mode, locked_qnt).unwrap();
+            match first_trigger {
+                None => {
+                    prop_assert_eq!(res, Some(order));
+                    prop_assert!(h_trade.trade_record.is_empty());
+                }
+                Some(i) => {
+                    if let Some(tr) = h_trade.trade_record.last() {
+                        prop_assert!(tr.transaction_time >= kline[i].0);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn none_order_is_an_error() {
+        let res = eval_order_basic(
+            2.0,
+            1.0,
+            1.5,
+            0.5,
+            1.0,
+            1.0,
+            Order::None,
+            &EvalMode::default(),
+            1.0,
+        );
+        assert!(res.is_err());
+        assert!(eval_basic_condition(OrderCondition::Filled, Order::None).is_err());
+        let market = Order::Market {
+            buy: true,
+            quant: Quant::Q100,
+        };
+        assert!(eval_basic_condition(OrderCondition::Untouched, market).is_err());
+        assert!(eval_basic_condition(OrderCondition::StopTriggered, market).is_err());
+        assert_eq!(Order::None.get_side(), None);
+        assert_eq!(Order::None.get_qnt(), None);
+        assert_eq!(Order::None.get_price(), None);
+        assert_eq!(Order::None.get_side_str(), "");
+        assert_eq!(market.get_side(), Some(true));
+        assert_eq!(market.get_price(), Some(0.0));
+
+        let mut h_trade = HistTrade::default();
+        let kline = vec![(
+            DateTime::from_timestamp_millis(0).unwrap(),
+            1.0,
+            2.0,
+            0.5,
+            1.5,
+            1.0,
+        )];
+        let res = h_trade.trade_forward(&kline, &EvalMode::default(), vec![(0, Order::None, 1.0)]);
+        assert!(res.is_empty());
+    }
+
+    #[test]
+    fn marketable_limit_fills_at_open() {
+        let order = Order::Limit {
+            buy: true,
+            quant: Quant::Q100,
+            price: 120.0,
+            limit_status: LimitStatus::Untouched,
+        };
+        let res = eval_order_basic(
+            105.0,
+            100.0,
+            101.0,
+            95.0,
+            0.0,
+            100.0,
+            order,
+            &EvalMode::default(),
+            100.0,
+        );
+        let (cond, a1, a2, p) = res.unwrap().unwrap();
+        assert_eq!(cond, OrderCondition::Filled);
+        assert_eq!(p, 100.0);
+        assert!((a1 - (1.0 - M_FEE)).abs() < EPS);
+        assert_eq!(a2, 0.0);
+
+        assert_eq!(marketable_fill(95.0, 100.0, true), 95.0);
+        assert_eq!(marketable_fill(105.0, 100.0, false), 105.0);
+        assert_eq!(marketable_fill(95.0, 100.0, false), 100.0);
+    }
+
+    fn agg_ticks(trades: &[(f64, f64)]) -> Vec<AggTick> {
+        trades
+            .iter()
+            .enumerate()
+            .map(|(n, (price, qty))| AggTick {
+                id: n as i64,
+                price: *price,
+                qty: *qty,
+                first_id: n as i64,
+                last_id: n as i64,
+                time: n as i64 * 60_000,
+                buyer_maker: false,
+            })
+            .collect()
+    }
+
+    fn limit_at(place_time: i64, buy: bool, price: f64) -> BacktestOrder {
+        BacktestOrder {
+            place_time,
+            order: Order::Limit {
+                buy,
+                quant: Quant::Q100,
+                price,
+                limit_status: LimitStatus::Untouched,
+            },
+        }
+    }
+
+    #[test]
+    fn tick_limits_fill_by_queue_model() {
+        //two trades at the limit, then one through it
+        let ticks = agg_ticks(&[(101.0, 1.0), (100.0, 2.0), (100.0, 2.0), (99.5, 1.0)]);
+        let fill_time = |queue: QueueModel| {
+            let mut h_trade = HistTrade::new("BTCUSDT".to_string());
+            let report = h_trade.backtest_ticks(&ticks, &queue, &[limit_at(0, true, 100.0)]);
+            assert_eq!(report.trade_record.len(), 1);
+            h_trade.buy_points[0]
+        };
+        let mut h_trade = HistTrade::new("BTCUSDT".to_string());
+        let marketable =
+            h_trade.backtest_ticks(&ticks, &QueueModel::default(), &[limit_at(0, true, 105.0)]);
+
+        assert_eq!(fill_time(QueueModel::Touch), (60_000, 100.0));
+        assert_eq!(
+            fill_time(QueueModel::Queue { ahead: 3.0 }),
+            (120_000, 100.0)
+        );
+        assert_eq!(
+            fill_time(QueueModel::Queue { ahead: 10.0 }),
+            (180_000, 100.0)
+        );
+        assert_eq!(fill_time(QueueModel::TradeThrough), (180_000, 100.0));
+        //a marketable limit takes the first trade's price
+        assert!((marketable.asset1 - 10_000.0 / 101.0 * (1.0 - M_FEE)).abs() < EPS);
+        assert!(marketable.open_orders.is_empty());
+    }
+
+    #[test]
+    fn tick_stops_fill_on_the_crossing_trade() {
+        let ticks = agg_ticks(&[(100.0, 1.0), (96.0, 1.0), (94.0, 1.0), (97.0, 1.0)]);
+        let run = |order: Order| {
+            let mut h_trade = HistTrade::new("BTCUSDT".to_string());
+            h_trade.asset1 = 1.0;
+            h_trade.asset2 = 0.0;
+            let bo = BacktestOrder {
+                place_time: 0,
+                order,
+            };
+            let report = h_trade.backtest_ticks(&ticks, &QueueModel::default(), &[bo]);
+            (report, h_trade.sell_points)
+        };
+        let (stop_market, sm_points) = run(Order::StopMarket {
+            buy: false,
+            quant: Quant::Q100,
+            price: 95.0,
+            stop_status: StopStatus::Untouched,
+        });
+        //triggered at 94 into a sell limit at 96 that only the trade at 97 fills
+        let (stop_limit, sl_points) = run(Order::StopLimit {
+            buy: false,
+            quant: Quant::Q100,
+            price: 96.0,
+            limit_status: LimitStatus::Untouched,
+            stop_price: (95.0 / 96.0) as f32,
+            stop_status: StopStatus::Untouched,
+        });
+
+        assert!((stop_market.asset2 - 94.0 * (1.0 - M_FEE)).abs() < EPS);
+        assert_eq!(sm_points, vec![(120_000, 95.0)]);
+        assert!((stop_limit.asset2 - 96.0 * (1.0 - M_FEE)).abs() < EPS);
+        assert_eq!(sl_points, vec![(180_000, 96.0)]);
+        assert!(stop_limit.open_orders.is_empty());
+    }
+
+    #[test]
+    fn tick_and_candle_engines_agree_on_flat_candles() {
+        let ticks = agg_ticks(&[
+            (100.0, 1.0),
+            (98.0, 1.0),
+            (101.0, 1.0),
+            (104.0, 1.0),
+            (103.0, 1.0),
+        ]);
+        let kline: Vec<KlineRow> = ticks
+            .iter()
+            .map(|t| {
+                let time = DateTime::from_timestamp_millis(t.time).unwrap();
+                (time, t.price, t.price, t.price, t.price, t.qty)
+            })
+            .collect();
+        let orders = [limit_at(0, true, 98.0), limit_at(120_000, false, 104.0)];
+        let mut candles = HistTrade::new("BTCUSDT".to_string());
+        let by_candle = candles.backtest(&kline, &EvalMode::default(), &orders);
+        let mut trades = HistTrade::new("BTCUSDT".to_string());
+        let by_tick = trades.backtest_ticks(&ticks, &QueueModel::Touch, &orders);
+
+        assert_eq!(by_tick.trade_record.len(), 2);
+        assert_eq!(by_tick.trade_record, by_candle.trade_record);
+        assert_eq!(
+            (by_tick.asset1, by_tick.asset2),
+            (by_candle.asset1, by_candle.asset2)
+        );
+        assert_eq!(by_tick.wicks, 5);
+    }
+
+    #[test]
+    fn funding_is_charged_on_held_asset1() {
+        let ticks = agg_ticks(&[(100.0, 1.0), (100.0, 1.0), (100.0, 1.0)]);
+        let kline: Vec<KlineRow> = ticks
+            .iter()
+            .map(|t| {
+                let time = DateTime::from_timestamp_millis(t.time).unwrap();
+                (time, t.price, t.price, t.price, t.price, t.qty)
+            })
+            .collect();
+        let funding = vec![
+            FundingRate {
+                time: 60_000,
+                rate: 0.001,
+                mark_price: Some(110.0),
+            },
+            //without a mark price the candle open/trade price is used
+            FundingRate {
+                time: 90_000,
+                rate: -0.0005,
+                mark_price: None,
+            },
+        ];
+        let h_trade = || {
+            let mut h = HistTrade::new("BTCUSDT.P".to_string());
+            h.asset1 = 2.0;
+            h.asset2 = 10.0;
+            h.funding = funding.clone();
+            h
+        };
+        let by_candle = h_trade().backtest(&kline, &EvalMode::default(), &[]);
+        let by_tick = h_trade().backtest_ticks(&ticks, &QueueModel::Touch, &[]);
+        let spot =
+            HistTrade::new("BTCUSDT".to_string()).backtest(&kline, &EvalMode::default(), &[]);
+
+        let paid = 2.0 * 110.0 * 0.001 - 2.0 * 100.0 * 0.0005;
+        assert!((by_candle.funding_paid - paid).abs() < 1e-12);
+        assert!((by_candle.asset2 - (10.0 - paid)).abs() < 1e-12);
+        assert_eq!(by_tick.funding_paid, by_candle.funding_paid);
+        assert_eq!(spot.funding_paid, 0.0);
+    }
+
+    #[test]
+    fn hist_trade_forward_charges_funding_once() {
+        let kline: Vec<KlineRow> = (0..12)
+            .map(|n| {
+                let time = DateTime::from_timestamp_millis(n * 60_000).unwrap();
+                (time, 100.0, 100.0, 100.0, 100.0, 1.0)
+            })
+            .collect();
+        let rate = |time: i64| FundingRate {
+            time,
+            rate: 0.001,
+            mark_price: None,
+        };
+        let h_trade = || {
+            let mut h = HistTrade::new("BTCUSDT.P".to_string());
+            h.asset1 = 1.0;
+            h.asset2 = 10.0;
+            //on the first candle of every slice and one skipped over by the jump
+            h.funding = vec![rate(0), rate(180_000), rate(420_000), rate(600_000)];
+            h
+        };
+        //back to back slices like the Hist Trade pane's, then a jump over a slice
+        let mut stepped = h_trade();
+        let paid: Vec<f64> = [0..3, 3..6, 10..12]
+            .into_iter()
+            .map(|r| stepped.forward_funding(&kline[r]))
+            .collect();
+        //overlapping slices are only charged once
+        let mut overlapping = h_trade();
+        let first = overlapping.forward_funding(&kline[0..4]);
+        let again = overlapping.forward_funding(&kline[2..6]);
+        //going back in time starts over
+        let back = stepped.forward_funding(&kline[0..3]);
+
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-12;
+        assert!(close(paid[0], 0.1) && close(paid[1], 0.1) && close(paid[2], 0.2));
+        assert!(close(stepped.asset2, 9.6 - back));
+        assert!(close(first, 0.2) && again == 0.0);
+        assert!(close(back, 0.1));
+    }
+
+    fn snapshot(slice_time: i64, orders: &[(u64, Order)], hist_trade: &HistTrade) -> HistSnapshot {
+        HistSnapshot::new(
+            slice_time,
+            slice_time,
+            Intv::Min15,
+            hist_trade,
+            orders
+                .iter()
+                .map(|(id, o)| (*id, (*o, true, 100.0)))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn journal_undo_redo_replay() {
+        let path =
+            std::env::temp_dir().join(format!["bintrade_journal_{}.jsonl", std::process::id()]);
+        let limit = Order::Limit {
+            buy: true,
+            quant: Quant::Q100,
+            price: 100.0,
+            limit_status: LimitStatus::Untouched,
+        };
+        let moved = Order::Limit {
+            buy: true,
+            quant: Quant::Q100,
+            price: 99.0,
+            limit_status: LimitStatus::Untouched,
+        };
+        let mut t0 = HistTrade::new("BTCUSDT".to_string());
+        t0.asset2 = 1000.0;
+        let mut t3 = t0.clone();
+        let fill_time = DateTime::from_timestamp_millis(60_000).unwrap();
+        t3.record_fill(&moved, fill_time, 1.0, 900.0).unwrap();
+
+        let s0 = snapshot(0, &[], &t0);
+        let s1 = snapshot(0, &[(1, limit)], &t0);
+        let s2 = snapshot(0, &[(1, moved), (2, limit)], &t0);
+        //NOTE order 1 filled, order 2 cancelled and order 3 placed in the same step
+        let s3 = snapshot(60_000, &[(3, limit)], &t3);
+
+        let mut journal = HistJournal::new("BTCUSDT", Some(path.clone()));
+        assert!(!journal.observe(s0.clone(), &t0).unwrap());
+        assert!(journal.observe(s1.clone(), &t0).unwrap());
+        assert!(!journal.observe(s1.clone(), &t0).unwrap());
+        assert!(journal.observe(s2.clone(), &t0).unwrap());
+        assert!(journal.observe(s3.clone(), &t3).unwrap());
+        assert_eq!(
+            journal.entries[2].actions,
+            vec![
+                HistAction::TradeForward {
+                    from: 0,
+                    to: 60_000,
+                    filled: vec![1]
+                },
+                HistAction::OrderPlaced {
+                    id: 3,
+                    order: limit,
+                    active: true
+                },
+                HistAction::OrderCancelled { id: 2 },
+            ]
+        );
+        //NOTE snapshots only hold balances, the trade record is stored once in the step's fills
+        assert!(journal.entries[2].after.hist_trade.trade_record.is_empty());
+        assert_eq!(journal.entries[2].fills.trade_record, t3.trade_record);
+        assert_eq!(
+            journal.hist_trade_at(journal.entries.len()),
+            Some(t3.clone())
+        );
+
+        assert_eq!(journal.undo().unwrap(), Some(s2.clone()));
+        assert_eq!(
+            journal.hist_trade_at(journal.entries.len()),
+            Some(t0.clone())
+        );
+        assert_eq!(journal.redo().unwrap(), Some(s3.clone()));
+        assert_eq!(
+            journal.hist_trade_at(journal.entries.len()),
+            Some(t3.clone())
+        );
+        assert_eq!(journal.undo().unwrap(), Some(s2.clone()));
+        assert_eq!(journal.undo().unwrap(), Some(s1.clone()));
+        assert_eq!(journal.undo().unwrap(), Some(s0.clone()));
+        assert_eq!(journal.undo().unwrap(), None);
+        assert_eq!(journal.redo().unwrap(), Some(s1.clone()));
+        assert_eq!(journal.current(), Some(&s1));
+
+        //NOTE a new action after undo drops the redo history but the journal keeps everything
+        let s4 = snapshot(0, &[(1, limit), (2, moved)], &t0);
+        assert!(journal.observe(s4.clone(), &t0).unwrap());
+        assert!(!journal.can_redo());
+        assert_eq!(journal.entries.len(), 10);
+        assert_eq!(
+            journal.hist_trade_at(journal.entries.len()),
+            Some(t0.clone())
+        );
+
+        let states = journal.replay().unwrap();
+        assert_eq!(states.len(), journal.entries.len());
+        assert_eq!(states.last(), Some(&s4));
+
+        let loaded = HistJournal::load_from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        //NOTE serde_json doesn't round trip every f64, the fee lines of the snapshots may differ
+        let steps = |j: &HistJournal| -> Vec<(Vec<HistAction>, HistFills)> {
+            j.entries
+                .iter()
+                .map(|e| (e.actions.clone(), e.fills.clone()))
+                .collect()
+        };
+        assert_eq!(steps(&loaded), steps(&journal));
+        assert_eq!(loaded.current(), journal.current());
+        assert_eq!(
+            loaded.hist_trade_at(3).map(|t| t.trade_record),
+            Some(t3.trade_record)
+        );
+        let mut loaded = loaded;
+        loaded.path = None;
+        assert_eq!(loaded.undo().unwrap(), Some(s1));
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct GoldenScenario {
+        config: BacktestConfig,
+        kline: Vec<(i64, f64, f64, f64, f64, f64)>,
+    }
+
+    ///serde_json doesn't round trip every f64 exactly, numbers are compared with a tolerance
+    fn json_close(a: &serde_json::Value, b: &serde_json::Value) -> bool {
+        use serde_json::Value;
+        match (a, b) {
+            (Value::Number(x), Value::Number(y)) => {
+                let (x, y) = (x.as_f64().unwrap(), y.as_f64().unwrap());
+                (x - y).abs() <= EPS * x.abs().max(1.0)
+            }
+            (Value::Array(x), Value::Array(y)) => {
+                x.len() == y.len() && x.iter().zip(y).all(|(x, y)| json_close(x, y))
+            }
+            (Value::Object(x), Value::Object(y)) => {
+                x.len() == y.len()
+                    && x.iter()
+                        .all(|(k, x)| y.get(k).is_some_and(|y| json_close(x, y)))
+            }
+            _ => a == b,
+        }
+    }
+
+    ///Runs every tests/golden/*.json scenario and compares it with the *.expected.json report.
+    ///Set UPDATE_GOLDEN=1 to rewrite the expected reports after an intended engine change.
+    #[test]
+    fn golden_backtests() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+        let update = std::env::var("UPDATE_GOLDEN").is_ok();
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| {
+                let name = p.file_name().unwrap().to_string_lossy();
+                name.ends_with(".json") && !name.ends_with(".expected.json")
+            })
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty());
+        for path in paths {
+            let scenario: GoldenScenario =
+                serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+            let kline: Vec<KlineRow> = scenario
+                .kline
+                .iter()
+                .map(|(t, o, h, l, c, v)| {
+                    (
+                        DateTime::from_timestamp_millis(*t).unwrap(),
+                        *o,
+                        *h,
+                        *l,
+                        *c,
+                        *v,
+                    )
+                })
+                .collect();
+            let config = scenario.config;
+            let mut h_trade = HistTrade::new(config.symbol.clone());
+            h_trade.asset1 = config.asset1;
+            h_trade.asset2 = config.asset2;
+            let report = h_trade.backtest(&kline, &config.eval_mode, &config.orders);
+            let report = serde_json::to_value(&report).unwrap();
+
+            let expected_path = path.with_extension("expected.json");
+            if update {
+                std::fs::write(
+                    &expected_path,
+                    serde_json::to_string_pretty(&report).unwrap() + "\n",
+                )
+                .unwrap();
+                continue;
+            }
+            let expected: serde_json::Value =
+                serde_json::from_str(&std::fs::read_to_string(&expected_path).unwrap()).unwrap();
+            assert!(
+                json_close(&report, &expected),
+                "golden mismatch: {}\n{:#}",
+                path.display(),
+                report
+            );
+        }
+    }
+}